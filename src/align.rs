@@ -0,0 +1,265 @@
+//! Pairwise alignment producing CIGARs.
+//!
+//! With the `align` feature enabled, [`Aligner`] performs small affine-gap alignments
+//! (Gotoh's algorithm) of a query against a reference, giving the result directly as a
+//! [`Cigar`]. It is intended for small realignments, such as rescuing soft clipped bases
+//! or placing a consensus against its reference window, and uses time and memory
+//! proportional to the product of the sequence lengths.
+//!
+//! A [global](AlignmentMode::Global) alignment aligns both sequences end to end; a
+//! [glocal](AlignmentMode::Glocal) alignment aligns the whole query to any part of the
+//! reference, without penalising the unaligned ends of the reference.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::align::{Aligner, AlignmentMode, Scoring};
+//!
+//! let aligner = Aligner::new(Scoring::default()).mode(AlignmentMode::Glocal);
+//! let alignment = aligner.align(b"ACGTTGCA", b"TTTACGTAATGCAGGG");
+//! assert_eq!(alignment.cigar.to_string(), "4M2D4M");
+//! assert_eq!((alignment.reference_start, alignment.reference_end), (3, 13));
+//! assert_eq!(alignment.score, 8);
+//! ```
+
+use crate::builder::CigarBuilder;
+pub use crate::score::Scoring;
+use crate::{Cigar, CigarOp};
+
+/// Which ends of the sequences an [`Aligner`] must align.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AlignmentMode {
+    /// Align both sequences end to end.
+    #[default]
+    Global,
+    /// Align the whole query to part of the reference.
+    Glocal,
+}
+
+/// The result of a pairwise alignment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairwiseAlignment {
+    /// The alignment of the query to the reference.
+    pub cigar: Cigar,
+    /// The score of the alignment.
+    pub score: i32,
+    /// The offset in the reference of the first aligned base.
+    pub reference_start: usize,
+    /// The offset in the reference after the last aligned base.
+    pub reference_end: usize,
+}
+
+/// An affine-gap pairwise aligner.
+#[derive(Debug, Clone, Default)]
+pub struct Aligner {
+    scoring: Scoring,
+    mode: AlignmentMode,
+    eqx: bool,
+}
+
+const NEG: i32 = i32::MIN / 2;
+
+// The traceback of a cell: where the best score came from, and whether the best gap
+// ending there extends a gap ending at the previous cell.
+const FROM_DELETION: u8 = 1;
+const FROM_INSERTION: u8 = 2;
+const DELETION_EXTENDS: u8 = 4;
+const INSERTION_EXTENDS: u8 = 8;
+
+/// The state of the traceback.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Best,
+    Deletion,
+    Insertion,
+}
+
+impl Aligner {
+    /// Create a new global aligner with the given scoring scheme.
+    pub fn new(scoring: Scoring) -> Self {
+        Aligner {
+            scoring,
+            mode: AlignmentMode::Global,
+            eqx: false,
+        }
+    }
+
+    /// Set the alignment mode.
+    pub fn mode(mut self, mode: AlignmentMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Report aligned pairs as `=` and `X` rather than `M`.
+    pub fn eqx(mut self) -> Self {
+        self.eqx = true;
+        self
+    }
+
+    /// Align `query` to `reference`. Bases are compared case insensitively.
+    pub fn align(&self, query: &[u8], reference: &[u8]) -> PairwiseAlignment {
+        let Scoring {
+            match_score,
+            mismatch,
+            gap_open,
+            gap_extend,
+        } = self.scoring;
+        let glocal = self.mode == AlignmentMode::Glocal;
+        let (n, m) = (query.len(), reference.len());
+        let width = m + 1;
+        let gap = |k: usize| -(gap_open + k as i32 * gap_extend);
+
+        let mut best = vec![NEG; (n + 1) * width];
+        let mut deletion = vec![NEG; (n + 1) * width];
+        let mut insertion = vec![NEG; (n + 1) * width];
+        let mut traceback = vec![0u8; (n + 1) * width];
+        best[0] = 0;
+        for j in 1..=m {
+            if glocal {
+                best[j] = 0;
+            } else {
+                deletion[j] = gap(j);
+                best[j] = gap(j);
+                traceback[j] = FROM_DELETION | if j > 1 { DELETION_EXTENDS } else { 0 };
+            }
+        }
+        for i in 1..=n {
+            let row = i * width;
+            insertion[row] = gap(i);
+            best[row] = gap(i);
+            traceback[row] = FROM_INSERTION | if i > 1 { INSERTION_EXTENDS } else { 0 };
+            for j in 1..=m {
+                let cell = row + j;
+                let mut tb = 0;
+
+                let opened = best[cell - 1] - gap_open - gap_extend;
+                let extended = deletion[cell - 1] - gap_extend;
+                deletion[cell] = opened.max(extended);
+                if extended > opened {
+                    tb |= DELETION_EXTENDS;
+                }
+
+                let opened = best[cell - width] - gap_open - gap_extend;
+                let extended = insertion[cell - width] - gap_extend;
+                insertion[cell] = opened.max(extended);
+                if extended > opened {
+                    tb |= INSERTION_EXTENDS;
+                }
+
+                let same = query[i - 1].eq_ignore_ascii_case(&reference[j - 1]);
+                let mut score = best[cell - width - 1] + if same { match_score } else { -mismatch };
+                if deletion[cell] > score {
+                    score = deletion[cell];
+                    tb |= FROM_DELETION;
+                }
+                if insertion[cell] > score {
+                    score = insertion[cell];
+                    tb = (tb & !FROM_DELETION) | FROM_INSERTION;
+                }
+                best[cell] = score;
+                traceback[cell] = tb;
+            }
+        }
+
+        let last = n * width;
+        let end = if glocal {
+            (0..=m)
+                .max_by_key(|&j| (best[last + j], std::cmp::Reverse(j)))
+                .unwrap()
+        } else {
+            m
+        };
+        let score = best[last + end];
+
+        let mut ops = Vec::new();
+        let (mut i, mut j) = (n, end);
+        let mut state = State::Best;
+        while i > 0 || (j > 0 && !glocal) {
+            let tb = traceback[i * width + j];
+            match state {
+                State::Best if tb & FROM_DELETION != 0 => state = State::Deletion,
+                State::Best if tb & FROM_INSERTION != 0 => state = State::Insertion,
+                State::Best => {
+                    let op = match (
+                        self.eqx,
+                        query[i - 1].eq_ignore_ascii_case(&reference[j - 1]),
+                    ) {
+                        (false, _) => CigarOp::Match,
+                        (true, true) => CigarOp::Equal,
+                        (true, false) => CigarOp::Diff,
+                    };
+                    ops.push(op);
+                    i -= 1;
+                    j -= 1;
+                }
+                State::Deletion => {
+                    ops.push(CigarOp::Deletion);
+                    j -= 1;
+                    if tb & DELETION_EXTENDS == 0 {
+                        state = State::Best;
+                    }
+                }
+                State::Insertion => {
+                    ops.push(CigarOp::Insertion);
+                    i -= 1;
+                    if tb & INSERTION_EXTENDS == 0 {
+                        state = State::Best;
+                    }
+                }
+            }
+        }
+
+        let mut builder = CigarBuilder::new();
+        for op in ops.into_iter().rev() {
+            builder.push(op, 1).expect("lengths are non-zero");
+        }
+        PairwiseAlignment {
+            cigar: builder.build(),
+            score,
+            reference_start: j,
+            reference_end: end,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_global_alignment() {
+        let aligner = Aligner::new(Scoring::default());
+        let alignment = aligner.align(b"ACGTACGT", b"ACGTACGT");
+        assert_eq!(alignment.cigar.to_string(), "8M");
+        assert_eq!(alignment.score, 16);
+
+        let alignment = aligner.eqx().align(b"ACGTTTACGT", b"acgtacct");
+        assert_eq!(alignment.cigar.to_string(), "3=2I3=1X1=");
+        assert_eq!(alignment.score, 14 - 8 - 4);
+        assert_eq!(
+            crate::score::score(&alignment.cigar, &Scoring::default()).unwrap(),
+            alignment.score
+        );
+        assert_eq!((alignment.reference_start, alignment.reference_end), (0, 8));
+
+        let alignment = Aligner::new(Scoring::default()).align(b"", b"ACG");
+        assert_eq!(alignment.cigar.to_string(), "3D");
+        assert_eq!(alignment.score, -10);
+    }
+
+    #[test]
+    fn test_glocal_alignment() {
+        let aligner = Aligner::new(Scoring::default()).mode(AlignmentMode::Glocal);
+        let alignment = aligner.align(b"GATTACA", b"CCCCGATTACACCCC");
+        assert_eq!(alignment.cigar.to_string(), "7M");
+        assert_eq!(
+            (alignment.reference_start, alignment.reference_end),
+            (4, 11)
+        );
+
+        // The query is aligned end to end even when its ends match poorly.
+        let alignment = aligner.align(b"TTGATTACA", b"CCCCGATTACACCCC");
+        assert_eq!(alignment.cigar.query_length(), 9);
+        assert_eq!(alignment.reference_end, 11);
+    }
+}
@@ -0,0 +1,463 @@
+//! Gapped alignment strings and pretty-printing.
+//!
+//! Render an aligned read as the three classic rows of a pairwise alignment: the
+//! reference with `-` at insertions, a match line with `|` for matching bases and `.`
+//! for mismatches, and the read with `-` at deletions. Match elements are split using
+//! [expansion](crate::expand), so `M` elements are shown base by base.
+//! [`pretty_alignment`] wraps the rows into annotated blocks for logs and terminals, and
+//! [`cigar_from_gapped`] goes the other way, from a pair of aligned rows to a CIGAR.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::alignment::to_alignment_strings;
+//!
+//! let rows = to_alignment_strings(0, "3M1I2M1D2M", b"ACGTACGT", b"ACCTTAGT").unwrap();
+//! assert_eq!(rows.reference, "ACG-TACGT");
+//! assert_eq!(rows.matches, "||. || ||");
+//! assert_eq!(rows.query, "ACCTTA-GT");
+//! ```
+
+use std::fmt::Display;
+
+use crate::builder::CigarBuilder;
+use crate::error::CigarError;
+use crate::expand::ExpandedCigarIterator;
+use crate::{Cigar, CigarOp};
+
+/// The rows of a gapped pairwise alignment, all of the same length.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AlignmentStrings {
+    /// The reference row, with `-` where bases were inserted in the read.
+    pub reference: String,
+    /// The match line, with `|` for a match, `.` for a mismatch and a space for a gap.
+    pub matches: String,
+    /// The read row, with `-` where reference bases were deleted or skipped.
+    pub query: String,
+}
+
+impl AlignmentStrings {
+    fn push(&mut self, reference: u8, matches: u8, query: u8) {
+        self.reference.push(char::from(reference));
+        self.matches.push(char::from(matches));
+        self.query.push(char::from(query));
+    }
+}
+
+impl Display for AlignmentStrings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.reference)?;
+        writeln!(f, "{}", self.matches)?;
+        writeln!(f, "{}", self.query)
+    }
+}
+
+/// A stretch of a rendered alignment.
+enum Segment {
+    /// Aligned rows, with the positions of their first reference and read bases.
+    Aligned {
+        reference_start: usize,
+        read_start: usize,
+        rows: AlignmentStrings,
+    },
+    /// Skipped reference bases `start..start + length`.
+    Skip { start: usize, length: usize },
+}
+
+/// Render an alignment as segments, optionally showing soft clipped bases (in lower
+/// case) and splitting at skipped reference bases rather than showing them as deletions.
+fn render(
+    reference_position: usize,
+    cigar: &str,
+    reference: &[u8],
+    seq: &[u8],
+    clips: bool,
+    split_skips: bool,
+) -> std::result::Result<Vec<Segment>, CigarError> {
+    let reference_bases = |start: usize, length: usize| {
+        let end = start + length;
+        if end > reference.len() {
+            return Err(CigarError::ReferenceOutOfBounds(start as u64, end as u64));
+        }
+        Ok(&reference[start..end])
+    };
+    let read_bases = |start: usize, length: usize| {
+        let end = start + length;
+        if end > seq.len() {
+            return Err(CigarError::SequenceTooShort(end, seq.len()));
+        }
+        Ok(&seq[start..end])
+    };
+
+    let mut segments = Vec::new();
+    let mut rows = AlignmentStrings::default();
+    let mut position = reference_position;
+    let mut read_position = 0;
+    let mut starts = (position, read_position);
+    for elem in ExpandedCigarIterator::new(reference_position, cigar, reference, seq) {
+        let elem = elem?;
+        let length = elem.length as usize;
+        match elem.op {
+            CigarOp::Match | CigarOp::Equal | CigarOp::Diff => {
+                let ref_slice = reference_bases(position, length)?;
+                let read_slice = read_bases(read_position, length)?;
+                for (r, q) in ref_slice.iter().zip(read_slice) {
+                    let m = match elem.op {
+                        CigarOp::Equal => b'|',
+                        CigarOp::Diff => b'.',
+                        _ if r == q => b'|',
+                        _ => b'.',
+                    };
+                    rows.push(*r, m, *q);
+                }
+                position += length;
+                read_position += length;
+            }
+            CigarOp::Insertion => {
+                for q in read_bases(read_position, length)? {
+                    rows.push(b'-', b' ', *q);
+                }
+                read_position += length;
+            }
+            CigarOp::Skip if split_skips => {
+                if !rows.reference.is_empty() {
+                    segments.push(Segment::Aligned {
+                        reference_start: starts.0,
+                        read_start: starts.1,
+                        rows: std::mem::take(&mut rows),
+                    });
+                }
+                segments.push(Segment::Skip {
+                    start: position,
+                    length,
+                });
+                position += length;
+                starts = (position, read_position);
+            }
+            CigarOp::Deletion | CigarOp::Skip => {
+                for r in reference_bases(position, length)? {
+                    rows.push(*r, b' ', b'-');
+                }
+                position += length;
+            }
+            CigarOp::SoftClip => {
+                if clips {
+                    for q in read_bases(read_position, length)? {
+                        rows.push(b' ', b' ', q.to_ascii_lowercase());
+                    }
+                }
+                read_position += length;
+                if rows.reference.is_empty() {
+                    starts.1 = read_position;
+                }
+            }
+            CigarOp::HardClip | CigarOp::Padding => {}
+        }
+    }
+    if !rows.reference.is_empty() || segments.is_empty() {
+        segments.push(Segment::Aligned {
+            reference_start: starts.0,
+            read_start: starts.1,
+            rows,
+        });
+    }
+    Ok(segments)
+}
+
+/// Render a read aligned at `reference_position` as gapped alignment strings.
+///
+/// Skipped reference bases (`N`) are shown like deletions. Clipped bases and padding
+/// are not part of the alignment and are left out.
+pub fn to_alignment_strings<R: AsRef<[u8]>, S: AsRef<[u8]>>(
+    reference_position: usize,
+    cigar: &str,
+    reference: &R,
+    seq: &S,
+) -> std::result::Result<AlignmentStrings, CigarError> {
+    let segments = render(
+        reference_position,
+        cigar,
+        reference.as_ref(),
+        seq.as_ref(),
+        false,
+        false,
+    )?;
+    match segments.into_iter().next() {
+        Some(Segment::Aligned { rows, .. }) => Ok(rows),
+        _ => Ok(AlignmentStrings::default()),
+    }
+}
+
+/// Options controlling the layout of [`pretty_alignment`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrettyOptions {
+    /// The number of alignment columns on each line.
+    pub width: usize,
+    /// Show soft clipped bases, in lower case opposite blank reference.
+    pub show_clips: bool,
+}
+
+impl Default for PrettyOptions {
+    fn default() -> Self {
+        PrettyOptions {
+            width: 60,
+            show_clips: true,
+        }
+    }
+}
+
+/// Format a read aligned at `reference_position` as a BLAST-style alignment.
+///
+/// The alignment is wrapped into blocks of `width` columns, each annotated with the
+/// 1-based reference and read coordinates of its first and last bases. Skipped
+/// reference bases are not shown base by base, but as a line marking the intron.
+///
+/// # Example
+///
+/// ```rust
+/// use cigar_utils::alignment::{PrettyOptions, pretty_alignment};
+///
+/// let options = PrettyOptions {
+///     width: 4,
+///     ..Default::default()
+/// };
+/// let text = pretty_alignment(0, "1S3M100N2M", &b"ACGT".repeat(30), b"TACCTA", &options).unwrap();
+/// assert_eq!(
+///     text,
+///     "Ref    1  ACG 3\n\
+///      \x20         ||.\n\
+///      Read   1 tACC 4\n\
+///      \n\
+///      Ref    4 <intron of 100 bp> 103\n\
+///      \n\
+///      Ref  104 TA 105\n\
+///      \x20        ||\n\
+///      Read   5 TA 6\n"
+/// );
+/// ```
+pub fn pretty_alignment<R: AsRef<[u8]>, S: AsRef<[u8]>>(
+    reference_position: usize,
+    cigar: &str,
+    reference: &R,
+    seq: &S,
+    options: &PrettyOptions,
+) -> std::result::Result<String, CigarError> {
+    let segments = render(
+        reference_position,
+        cigar,
+        reference.as_ref(),
+        seq.as_ref(),
+        options.show_clips,
+        true,
+    )?;
+    let width = options.width.max(1);
+    let digits = segments
+        .iter()
+        .map(|segment| match segment {
+            Segment::Aligned {
+                reference_start,
+                read_start,
+                rows,
+            } => (reference_start + rows.reference.len()).max(read_start + rows.query.len()),
+            Segment::Skip { start, length } => start + length,
+        })
+        .max()
+        .unwrap_or(0)
+        .to_string()
+        .len();
+
+    let mut blocks = Vec::new();
+    for segment in segments {
+        match segment {
+            Segment::Aligned {
+                reference_start,
+                read_start,
+                rows,
+            } => {
+                // Coordinates are 1-based, so the next position doubles as the last one shown.
+                let mut next_reference = reference_start;
+                let mut next_read = read_start;
+                let columns = rows.reference.len();
+                for start in (0..columns).step_by(width) {
+                    let end = (start + width).min(columns);
+                    let reference = &rows.reference[start..end];
+                    let query = &rows.query[start..end];
+                    let reference_first = next_reference + 1;
+                    let read_first = next_read + 1;
+                    next_reference += reference
+                        .bytes()
+                        .filter(|b| *b != b'-' && *b != b' ')
+                        .count();
+                    next_read += query.bytes().filter(|b| *b != b'-').count();
+                    let matches =
+                        format!("{:pad$}{}", "", &rows.matches[start..end], pad = digits + 6);
+                    blocks.push(format!(
+                        "{:<4} {:>digits$} {} {}\n{}\n{:<4} {:>digits$} {} {}\n",
+                        "Ref",
+                        reference_first,
+                        reference,
+                        next_reference,
+                        matches.trim_end(),
+                        "Read",
+                        read_first,
+                        query,
+                        next_read,
+                    ));
+                }
+            }
+            Segment::Skip { start, length } => {
+                blocks.push(format!(
+                    "{:<4} {:>digits$} <intron of {} bp> {}\n",
+                    "Ref",
+                    start + 1,
+                    length,
+                    start + length,
+                ));
+            }
+        }
+    }
+    Ok(blocks.join("\n"))
+}
+
+fn is_gap(base: u8) -> bool {
+    base == b'-' || base == b'.'
+}
+
+fn gapped_to_cigar(
+    ref_row: &[u8],
+    query_row: &[u8],
+    eqx: bool,
+) -> std::result::Result<Cigar, CigarError> {
+    if ref_row.len() != query_row.len() {
+        return Err(CigarError::LengthMismatch(ref_row.len(), query_row.len()));
+    }
+    let mut builder = CigarBuilder::new();
+    for (r, q) in ref_row.iter().zip(query_row) {
+        let op = match (*r, *q) {
+            (r, q) if is_gap(r) && is_gap(q) => continue,
+            (b' ', q) if !is_gap(q) => CigarOp::SoftClip,
+            (r, _) if is_gap(r) => CigarOp::Insertion,
+            (_, q) if is_gap(q) => CigarOp::Deletion,
+            (r, q) if eqx && r.eq_ignore_ascii_case(&q) => CigarOp::Equal,
+            _ if eqx => CigarOp::Diff,
+            _ => CigarOp::Match,
+        };
+        builder.push(op, 1)?;
+    }
+    Ok(builder.build())
+}
+
+/// Infer the CIGAR of a pair of aligned rows, such as those of a multiple sequence
+/// alignment.
+///
+/// Either `-` or `.` marks a gap. Columns which are gaps in both rows are ignored, and a
+/// blank reference opposite a read base, as [`pretty_alignment`] shows soft clips, is a
+/// soft clip. Rows of different lengths give [`CigarError::LengthMismatch`].
+///
+/// # Example
+///
+/// ```rust
+/// use cigar_utils::alignment::cigar_from_gapped;
+///
+/// let cigar = cigar_from_gapped(&"ACG-TA-CGT", &"ACCTTA--GT").unwrap();
+/// assert_eq!(cigar.to_string(), "3M1I2M1D2M");
+/// ```
+pub fn cigar_from_gapped<R: AsRef<[u8]>, Q: AsRef<[u8]>>(
+    ref_row: &R,
+    query_row: &Q,
+) -> std::result::Result<Cigar, CigarError> {
+    gapped_to_cigar(ref_row.as_ref(), query_row.as_ref(), false)
+}
+
+/// Infer the CIGAR of a pair of aligned rows as for [`cigar_from_gapped`], using sequence
+/// match (`=`) and mismatch (`X`) operations for aligned bases. Bases are compared
+/// ignoring case.
+pub fn cigar_from_gapped_eqx<R: AsRef<[u8]>, Q: AsRef<[u8]>>(
+    ref_row: &R,
+    query_row: &Q,
+) -> std::result::Result<Cigar, CigarError> {
+    gapped_to_cigar(ref_row.as_ref(), query_row.as_ref(), true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clips_are_left_out() {
+        let rows = to_alignment_strings(2, "2S2M2N2M1H", b"TTACGTACGT", b"GGACAC").unwrap();
+        assert_eq!(rows.reference, "ACGTAC");
+        assert_eq!(rows.matches, "||  ||");
+        assert_eq!(rows.query, "AC--AC");
+        assert_eq!(rows.to_string(), "ACGTAC\n||  ||\nAC--AC\n");
+    }
+
+    #[test]
+    fn test_eqx_elements_are_trusted() {
+        let rows = to_alignment_strings(0, "2=1X", b"ACG", b"ACG").unwrap();
+        assert_eq!(rows.matches, "||.");
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        assert!(matches!(
+            to_alignment_strings(0, "2M3D", b"ACGT", b"AC"),
+            Err(CigarError::ReferenceOutOfBounds(2, 5))
+        ));
+        assert!(matches!(
+            to_alignment_strings(0, "2M3I", b"ACGT", b"AC"),
+            Err(CigarError::SequenceTooShort(5, 2))
+        ));
+    }
+
+    #[test]
+    fn test_pretty_wraps_with_coordinates() {
+        let options = PrettyOptions {
+            width: 5,
+            show_clips: false,
+        };
+        let text = pretty_alignment(
+            8,
+            "2S3M1I2M2D2M",
+            b"ACGTACGTACGTACGTACGT",
+            b"GGACGTTAGC",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(
+            text,
+            "Ref   9 ACG-T 12\n        ||| |\nRead  3 ACGTT 7\n\n\
+             Ref  13 ACGTA 17\n        |  ..\nRead  8 A--GC 10\n"
+        );
+    }
+
+    #[test]
+    fn test_pretty_gap_only_block() {
+        let options = PrettyOptions {
+            width: 2,
+            ..Default::default()
+        };
+        let text = pretty_alignment(0, "2M2D", b"ACGT", b"AC", &options).unwrap();
+        assert_eq!(
+            text,
+            "Ref  1 AC 2\n       ||\nRead 1 AC 2\n\nRef  3 GT 4\n\nRead 3 -- 2\n"
+        );
+    }
+
+    #[test]
+    fn test_cigar_from_gapped_round_trip() {
+        let rows = to_alignment_strings(0, "3M1I2M1D2M", b"ACGTACGT", b"ACCTTAGT").unwrap();
+        let cigar = cigar_from_gapped_eqx(&rows.reference, &rows.query).unwrap();
+        assert_eq!(cigar.to_string(), "2=1X1I2=1D2=");
+        let cigar = cigar_from_gapped(&"  ACGT..A", &"ttAc-T.-A").unwrap();
+        assert_eq!(cigar.to_string(), "2S2M1D2M");
+    }
+
+    #[test]
+    fn test_cigar_from_gapped_length_mismatch() {
+        assert!(matches!(
+            cigar_from_gapped(&"ACGT", &"ACG"),
+            Err(CigarError::LengthMismatch(4, 3))
+        ));
+    }
+}
@@ -0,0 +1,264 @@
+//! Arrow and Parquet export of collated events.
+//!
+//! With the `arrow` feature enabled, [`EventBatches`] turns per-position summaries of
+//! collated events into Arrow record batches, one row per event, and
+//! [`write_parquet`] writes them to a Parquet file, ready for dataframe libraries.
+//!
+//! The schema ([`event_schema`]) is:
+//!
+//! | column     | type           | contents                                          |
+//! |------------|----------------|---------------------------------------------------|
+//! | `chrom_id` | `UInt32`       | the chromosome ID                                 |
+//! | `chrom`    | `Utf8`, null   | the chromosome name, if names were given          |
+//! | `position` | `UInt64`       | the 0-based reference position of the event       |
+//! | `op`       | `Utf8`         | the operation character, such as `M` or `I`       |
+//! | `length`   | `UInt32`       | the length of the event                           |
+//! | `count`    | `UInt64`       | the number of records with the event              |
+//! | `depth`    | `UInt64`       | the depth of coverage at the position             |
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::arrow::EventBatches;
+//! use cigar_utils::collated::CollatedAugmentedCigarIterator;
+//!
+//! let cigars = vec![
+//!     std::io::Result::Ok(("2M1D2M".to_string(), 0, 100)),
+//!     std::io::Result::Ok(("5M".to_string(), 0, 100)),
+//! ];
+//! let summaries = CollatedAugmentedCigarIterator::new(cigars.into_iter()).summarized();
+//! let batches: Vec<_> = EventBatches::new(summaries, 1024)
+//!     .with_chromosome_names(vec!["chr1".to_string()])
+//!     .collect::<Result<_, _>>()
+//!     .unwrap();
+//! assert_eq!(batches.len(), 1);
+//! assert_eq!(batches[0].num_rows(), 4);
+//! ```
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, RecordBatch, StringArray, UInt32Array, UInt64Array};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+
+use crate::chrom_map::ChromMap;
+use crate::error::CigarError;
+use crate::summary::PositionSummary;
+
+/// The schema of collated event batches.
+pub fn event_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("chrom_id", DataType::UInt32, false),
+        Field::new("chrom", DataType::Utf8, true),
+        Field::new("position", DataType::UInt64, false),
+        Field::new("op", DataType::Utf8, false),
+        Field::new("length", DataType::UInt32, false),
+        Field::new("count", DataType::UInt64, false),
+        Field::new("depth", DataType::UInt64, false),
+    ]))
+}
+
+/// One row of an event batch.
+struct EventRow {
+    chrom_id: u32,
+    position: u64,
+    op: char,
+    length: u32,
+    count: u64,
+    depth: u64,
+}
+
+/// An iterator of Arrow record batches of at most `batch_size` collated events, built
+/// from per-position summaries (see
+/// [`summarized`](crate::collated::CollatedAugmentedCigarIterator::summarized)).
+pub struct EventBatches<I> {
+    summaries: I,
+    batch_size: usize,
+    names: Option<ChromMap>,
+    rows: VecDeque<EventRow>,
+    schema: SchemaRef,
+}
+
+impl<I> EventBatches<I>
+where
+    I: Iterator<Item = std::result::Result<PositionSummary, CigarError>>,
+{
+    /// Create a new iterator of batches of at most `batch_size` rows.
+    pub fn new(summaries: I, batch_size: usize) -> Self {
+        EventBatches {
+            summaries,
+            batch_size: batch_size.max(1),
+            names: None,
+            rows: VecDeque::new(),
+            schema: event_schema(),
+        }
+    }
+
+    /// Fill the `chrom` column with the names of chromosomes, indexed by chromosome ID.
+    pub fn with_chromosome_names(mut self, names: impl Into<ChromMap>) -> Self {
+        self.names = Some(names.into());
+        self
+    }
+
+    /// The schema of the batches.
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn batch(&mut self) -> std::result::Result<RecordBatch, CigarError> {
+        let rows: Vec<EventRow> = self
+            .rows
+            .drain(..self.batch_size.min(self.rows.len()))
+            .collect();
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt32Array::from_iter_values(
+                rows.iter().map(|r| r.chrom_id),
+            )),
+            Arc::new(StringArray::from_iter(
+                rows.iter()
+                    .map(|r| self.names.as_ref().and_then(|n| n.name(r.chrom_id))),
+            )),
+            Arc::new(UInt64Array::from_iter_values(
+                rows.iter().map(|r| r.position),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                rows.iter().map(|r| r.op.to_string()),
+            )),
+            Arc::new(UInt32Array::from_iter_values(rows.iter().map(|r| r.length))),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|r| r.count))),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|r| r.depth))),
+        ];
+        RecordBatch::try_new(self.schema.clone(), columns)
+            .map_err(|e| CigarError::External(Box::new(e)))
+    }
+}
+
+impl<I> Iterator for EventBatches<I>
+where
+    I: Iterator<Item = std::result::Result<PositionSummary, CigarError>>,
+{
+    type Item = std::result::Result<RecordBatch, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.rows.len() < self.batch_size {
+            let summary = match self.summaries.next() {
+                Some(Ok(summary)) => summary,
+                Some(Err(e)) => return Some(Err(e)),
+                None => break,
+            };
+            self.rows.extend(summary.events.iter().map(|e| EventRow {
+                chrom_id: summary.chrom_id,
+                position: summary.position,
+                op: char::from(e.op),
+                length: e.length,
+                count: e.count as u64,
+                depth: summary.depth as u64,
+            }));
+        }
+        if self.rows.is_empty() {
+            return None;
+        }
+        Some(self.batch())
+    }
+}
+
+/// Write record batches to `out` as Parquet, returning the number of rows written.
+///
+/// The batches must all have the `schema` given.
+pub fn write_parquet<W, I>(
+    batches: I,
+    schema: SchemaRef,
+    out: W,
+) -> std::result::Result<usize, CigarError>
+where
+    W: Write + Send,
+    I: IntoIterator<Item = std::result::Result<RecordBatch, CigarError>>,
+{
+    let external = |e: parquet::errors::ParquetError| CigarError::External(Box::new(e));
+    let mut writer = ArrowWriter::try_new(out, schema, None).map_err(external)?;
+    let mut rows = 0;
+    for batch in batches {
+        let batch = batch?;
+        rows += batch.num_rows();
+        writer.write(&batch).map_err(external)?;
+    }
+    writer.close().map_err(external)?;
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collated::CollatedAugmentedCigarIterator;
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn summaries() -> impl Iterator<Item = std::result::Result<PositionSummary, CigarError>> {
+        let cigars = vec![
+            std::io::Result::Ok(("3M1I2M".to_string(), 1, 10)),
+            std::io::Result::Ok(("6M".to_string(), 1, 10)),
+            std::io::Result::Ok(("2M".to_string(), 2, 5)),
+        ];
+        CollatedAugmentedCigarIterator::new(cigars.into_iter()).summarized()
+    }
+
+    #[test]
+    fn test_event_batches() {
+        let batches: Vec<_> = EventBatches::new(summaries(), 2)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let rows: Vec<usize> = batches.iter().map(|b| b.num_rows()).collect();
+        assert_eq!(rows, vec![2, 2, 1]);
+        let chrom = batches[0]
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert!(chrom.is_null(0));
+        let ops = batches[1]
+            .column(3)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(ops.value(0), "M");
+        let depth = batches[0]
+            .column(6)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(depth.value(0), 2);
+    }
+
+    #[test]
+    fn test_write_parquet() {
+        let batches = EventBatches::new(summaries(), 1024).with_chromosome_names(vec![
+            "chr0".into(),
+            "chr1".into(),
+            "chr2".into(),
+        ]);
+        let path = std::env::temp_dir().join(format!("cigar-utils-{}.parquet", std::process::id()));
+        let rows = write_parquet(
+            batches,
+            event_schema(),
+            std::fs::File::create(&path).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(rows, 5);
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(batches[0].schema(), event_schema());
+        let chrom = batches[0]
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(chrom.value(4), "chr2");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
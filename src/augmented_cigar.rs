@@ -3,12 +3,26 @@
 //! Augmented CIGAR operations provide additional context to the standard CIGAR operations by including read and reference positions.
 //!
 //! This module also provides iterators over sequences of them derived from an alignment position and a cigar string.
+//!
+//! Read positions follow a [`ConsumptionPolicy`]. By default only the operations which
+//! consume query bases in the SAM specification (`M`, `I`, `S`, `=` and `X`) advance the
+//! read position, so read positions index SEQ, and hard clips and padding are reported at
+//! the position of the next base.
+//!
+//! [`AugmentedCigarIterator::expanded`] splits alignment match (`M`) elements into
+//! sequence match (`=`) and mismatch (`X`) elements as it goes, by comparing the read to
+//! the reference, so that mismatch events can be collated without first expanding each
+//! CIGAR.
+
+use std::iter::FusedIterator;
 
 use crate::error::CigarError;
-use crate::{CigarElement, CigarIterator, CigarOp};
+use crate::expand::ExpandedCigarIterator;
+use crate::{Cigar, CigarElement, CigarIterator, CigarOp, Strand};
 
 /// An augmented CIGAR operation element.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AugmentedCigarElement {
     /// The length of the CIGAR operation.
     pub length: u32,
@@ -19,7 +33,84 @@ pub struct AugmentedCigarElement {
     /// The chromosome ID for the reference position.
     pub chrom_id: u32,
     /// The reference position of the CIGAR operation.
-    pub reference_position: u32,
+    pub reference_position: u64,
+    /// Whether the element is a soft or hard clip preceding every other operation of its
+    /// alignment, in reference order.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub leading_clip: bool,
+}
+
+impl AugmentedCigarElement {
+    /// Whether the element is a soft or hard clip at the start of its alignment, in
+    /// reference order, rather than at the end.
+    ///
+    /// This does not depend on the read position, so holds whatever the
+    /// [policy](ConsumptionPolicy) and [strand](AugmentedCigarIterator::with_strand) the
+    /// element was produced under.
+    pub fn is_leading_clip(&self) -> bool {
+        self.leading_clip
+    }
+
+    /// The reference position just past the end of the element.
+    pub fn reference_end(&self) -> u64 {
+        if self.op.consumes_reference() {
+            self.reference_position + self.length as u64
+        } else {
+            self.reference_position
+        }
+    }
+
+    /// The read position just past the end of the element.
+    ///
+    /// Elements consume read bases as in the SAM specification, whatever the policy they
+    /// were produced under, so hard clips and padding are empty.
+    pub fn read_end(&self) -> u32 {
+        self.read_position + read_consumed(self)
+    }
+
+    /// The half open interval of reference positions covered by the element.
+    pub fn reference_range(&self) -> std::ops::Range<u64> {
+        self.reference_position..self.reference_end()
+    }
+
+    /// The half open interval of read positions covered by the element.
+    pub fn read_range(&self) -> std::ops::Range<u32> {
+        self.read_position..self.read_end()
+    }
+
+    /// Whether the element overlaps the half open reference interval `start..end`.
+    ///
+    /// Elements which do not consume the reference (such as insertions) are treated as
+    /// occupying their reference position.
+    pub fn overlaps_reference(&self, start: u64, end: u64) -> bool {
+        let elem_end = self.reference_end().max(self.reference_position + 1);
+        self.reference_position < end && start < elem_end
+    }
+
+    /// Restrict the element to the half open reference interval `start..end`.
+    ///
+    /// Elements which straddle a boundary are shortened, with the read position adjusted
+    /// to match. Returns `None` if the element does not overlap the interval.
+    pub fn clip_reference(&self, start: u64, end: u64) -> Option<AugmentedCigarElement> {
+        if !self.overlaps_reference(start, end) {
+            return None;
+        }
+        if !self.op.consumes_reference() {
+            return Some(self.clone());
+        }
+        let clipped_start = self.reference_position.max(start);
+        let clipped_end = self.reference_end().min(end);
+        let offset = (clipped_start - self.reference_position) as u32;
+        let read_offset = if read_consumed(self) > 0 { offset } else { 0 };
+        Some(AugmentedCigarElement {
+            length: (clipped_end - clipped_start) as u32,
+            op: self.op,
+            read_position: self.read_position + read_offset,
+            chrom_id: self.chrom_id,
+            reference_position: clipped_start,
+            leading_clip: self.leading_clip,
+        })
+    }
 }
 
 impl Ord for AugmentedCigarElement {
@@ -28,7 +119,13 @@ impl Ord for AugmentedCigarElement {
             std::cmp::Ordering::Equal => {
                 match self.reference_position.cmp(&other.reference_position) {
                     std::cmp::Ordering::Equal => match self.op.cmp(&other.op) {
-                        std::cmp::Ordering::Equal => self.length.cmp(&other.length),
+                        std::cmp::Ordering::Equal => match self.length.cmp(&other.length) {
+                            std::cmp::Ordering::Equal => self
+                                .read_position
+                                .cmp(&other.read_position)
+                                .then(self.leading_clip.cmp(&other.leading_clip)),
+                            ord => ord,
+                        },
                         ord => ord,
                     },
                     ord => ord,
@@ -45,96 +142,569 @@ impl PartialOrd for AugmentedCigarElement {
     }
 }
 
+/// A set of CIGAR operations to pass through augmented or collated streams.
+///
+/// Filtering inside [`AugmentedCigarIterator`] or the
+/// [collator](crate::collated::CollatedAugmentedCigarIterator::event_filter) drops
+/// unwanted elements before they are buffered, which saves memory and output volume
+/// compared with filtering the results.
+///
+/// ```rust
+/// use cigar_utils::CigarOp;
+/// use cigar_utils::augmented_cigar::EventFilter;
+///
+/// let filter = EventFilter::all().without_clips();
+/// assert!(filter.accepts(CigarOp::Match));
+/// assert!(!filter.accepts(CigarOp::SoftClip));
+/// assert!(EventFilter::indels().accepts(CigarOp::Deletion));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EventFilter {
+    ops: u16,
+}
+
+impl EventFilter {
+    /// A filter passing every operation.
+    pub fn all() -> Self {
+        EventFilter { ops: 0x1ff }
+    }
+
+    /// A filter passing only the given operations.
+    pub fn only<V: IntoIterator<Item = CigarOp>>(ops: V) -> Self {
+        EventFilter {
+            ops: ops.into_iter().fold(0, |bits, op| bits | Self::bit(op)),
+        }
+    }
+
+    /// A filter passing only insertions and deletions.
+    pub fn indels() -> Self {
+        Self::only([CigarOp::Insertion, CigarOp::Deletion])
+    }
+
+    /// A filter passing only the operations which may hold mismatches: alignment matches
+    /// (`M`) and sequence mismatches (`X`).
+    pub fn mismatches() -> Self {
+        Self::only([CigarOp::Match, CigarOp::Diff])
+    }
+
+    /// Also drop the given operations.
+    pub fn excluding<V: IntoIterator<Item = CigarOp>>(self, ops: V) -> Self {
+        EventFilter {
+            ops: self.ops & !Self::only(ops).ops,
+        }
+    }
+
+    /// Also drop soft and hard clips.
+    pub fn without_clips(self) -> Self {
+        self.excluding([CigarOp::SoftClip, CigarOp::HardClip])
+    }
+
+    /// Also drop padding.
+    pub fn without_padding(self) -> Self {
+        self.excluding([CigarOp::Padding])
+    }
+
+    /// Whether the filter passes the operation.
+    pub fn accepts(&self, op: CigarOp) -> bool {
+        self.ops & Self::bit(op) != 0
+    }
+
+    fn bit(op: CigarOp) -> u16 {
+        1 << op as u16
+    }
+}
+
+impl Default for EventFilter {
+    fn default() -> Self {
+        EventFilter::all()
+    }
+}
+
+/// How an [`AugmentedCigarIterator`] advances the read position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ConsumptionPolicy {
+    /// Advance over the operations which consume query bases in the SAM specification:
+    /// `M`, `I`, `S`, `=` and `X`. Read positions are offsets into SEQ.
+    #[default]
+    Sam,
+    /// Also advance over hard clips and padding, as earlier versions of this crate did.
+    /// Read positions count hard clipped bases as well as those in SEQ.
+    Legacy,
+}
+
+impl ConsumptionPolicy {
+    /// Whether the operation advances the read position under this policy.
+    pub fn consumes_read(&self, op: CigarOp) -> bool {
+        match self {
+            ConsumptionPolicy::Sam => op.consumes_query(),
+            ConsumptionPolicy::Legacy => !matches!(op, CigarOp::Deletion | CigarOp::Skip),
+        }
+    }
+
+    fn read_consumed(&self, op: CigarOp, length: u32) -> u32 {
+        if self.consumes_read(op) { length } else { 0 }
+    }
+}
+
+/// The source of CIGAR elements for an augmented iterator.
+#[derive(Clone)]
+enum ElementSource<'a> {
+    Parsed(CigarIterator<'a>),
+    Borrowed(std::slice::Iter<'a, CigarElement>),
+    Owned(std::vec::IntoIter<CigarElement>),
+    Expanded(ExpandedCigarIterator<'a>),
+}
+
+impl<'a> Iterator for ElementSource<'a> {
+    type Item = std::result::Result<CigarElement, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            ElementSource::Parsed(inner) => inner.next(),
+            ElementSource::Borrowed(inner) => inner.next().cloned().map(Ok),
+            ElementSource::Owned(inner) => inner.next().map(Ok),
+            ElementSource::Expanded(inner) => inner.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            ElementSource::Parsed(inner) => inner.size_hint(),
+            ElementSource::Borrowed(inner) => inner.size_hint(),
+            ElementSource::Owned(inner) => inner.size_hint(),
+            ElementSource::Expanded(inner) => inner.size_hint(),
+        }
+    }
+}
+
 /// An iterator over augmented CIGAR elements.
 pub struct AugmentedCigarIterator<'a> {
-    inner: CigarIterator<'a>,
+    inner: ElementSource<'a>,
     read_position: u32,
     chrom_id: u32,
-    reference_position: u32,
+    reference_position: u64,
+    read_length: Option<u32>,
+    filter: EventFilter,
+    policy: ConsumptionPolicy,
+    aligned: bool,
 }
 
-impl<'a> From<(CigarIterator<'a>, u32, u32)> for AugmentedCigarIterator<'a> {
-    fn from(value: (CigarIterator<'a>, u32, u32)) -> Self {
-        let (inner, chrom_id, reference_position) = value;
+impl<'a> AugmentedCigarIterator<'a> {
+    fn with_source(inner: ElementSource<'a>, chrom_id: u32, reference_position: u64) -> Self {
         AugmentedCigarIterator {
             inner,
             read_position: 0,
             chrom_id,
             reference_position,
+            read_length: None,
+            filter: EventFilter::all(),
+            policy: ConsumptionPolicy::default(),
+            aligned: false,
+        }
+    }
+
+    /// An iterator over the augmented elements of `seq` aligned by `cigar` at
+    /// `reference_position`, with alignment match (`M`) elements split into sequence
+    /// match (`=`) and mismatch (`X`) elements by comparing the read to the reference.
+    ///
+    /// This expands the CIGAR as it goes, as an [`ExpandedCigarIterator`] does, so no
+    /// expanded CIGAR is built for the read. Errors in expansion, such as an alignment
+    /// beyond the end of the reference, are produced in place of the elements concerned.
+    /// To compare bases with [options](crate::expand::ExpandOptions) or against a window
+    /// of the reference, augment a configured [`ExpandedCigarIterator`] instead.
+    pub fn expanded(
+        cigar: &'a str,
+        chrom_id: u32,
+        reference_position: u64,
+        reference: &'a [u8],
+        seq: &'a [u8],
+    ) -> Self {
+        let expanded =
+            ExpandedCigarIterator::new(reference_position as usize, cigar, reference, seq);
+        AugmentedCigarIterator::from((expanded, chrom_id, reference_position))
+    }
+
+    /// Advance the read position according to `policy`.
+    pub fn with_consumption_policy(mut self, policy: ConsumptionPolicy) -> Self {
+        self.policy = policy;
+        if self.read_length.is_some() {
+            self.read_length = Some(self.total_read_length());
+        }
+        self
+    }
+
+    /// The read position after the last element.
+    fn total_read_length(&self) -> u32 {
+        self.read_position
+            + self
+                .inner
+                .clone()
+                .filter_map(Result::ok)
+                .map(|e| self.policy.read_consumed(e.op, e.length))
+                .sum::<u32>()
+    }
+
+    /// The elements not yet produced, up to any which fails to parse.
+    fn remaining(&self) -> Cigar {
+        Cigar::from(self.inner.clone().map_while(Result::ok).collect::<Vec<_>>())
+    }
+
+    /// The half open reference interval of the aligned part of the alignment, as for
+    /// [`Cigar::clipped_interval`].
+    ///
+    /// This is computed from the elements not yet produced, so is that of the whole
+    /// alignment on a newly constructed iterator.
+    pub fn clipped_interval(&self) -> (u64, u64) {
+        self.remaining().clipped_interval(self.reference_position)
+    }
+
+    /// The half open reference interval of the alignment extended through its clipped
+    /// bases, as for [`Cigar::unclipped_interval`].
+    ///
+    /// This is computed from the elements not yet produced, so is that of the whole
+    /// alignment on a newly constructed iterator.
+    pub fn unclipped_interval(&self) -> (i64, u64) {
+        self.remaining().unclipped_interval(self.reference_position)
+    }
+
+    /// The reference intervals covered by the elements produced, merged where they touch
+    /// or overlap, as for [`Cigar::covered_intervals`].
+    ///
+    /// With an [event filter](Self::with_event_filter) this gives the positions covered
+    /// by chosen operations, such as the mismatches of an [expanded](Self::expanded)
+    /// alignment. Fails with the first error from the elements.
+    pub fn covered_intervals(self) -> std::result::Result<Vec<(u64, u64)>, CigarError> {
+        let mut intervals: Vec<(u64, u64)> = Vec::new();
+        for elem in self {
+            let elem = elem?;
+            let (start, end) = (elem.reference_position, elem.reference_end());
+            if start == end {
+                continue;
+            }
+            match intervals.last_mut() {
+                Some(last) if last.1 >= start => last.1 = last.1.max(end),
+                _ => intervals.push((start, end)),
+            }
         }
+        Ok(intervals)
+    }
+
+    /// Only produce elements whose operations pass `filter`.
+    ///
+    /// Read and reference positions still account for the elements filtered out.
+    pub fn with_event_filter(mut self, filter: EventFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Report read positions in the orientation of the original read, for a read aligned
+    /// to `strand`.
+    ///
+    /// For reverse strand reads, read positions count from the end of the alignment, as
+    /// for [`reverse_augmented`], so that the first sequenced base (cycle 0) is position
+    /// 0. Elements are still produced in reference order. The length of the read is taken
+    /// from the whole CIGAR, counting hard clips only under the
+    /// [legacy](ConsumptionPolicy::Legacy) policy.
+    pub fn with_strand(mut self, strand: Strand) -> Self {
+        self.read_length = match strand {
+            Strand::Forward => None,
+            Strand::Reverse => Some(self.total_read_length()),
+        };
+        self
+    }
+}
+
+impl<'a> From<(CigarIterator<'a>, u32, u64)> for AugmentedCigarIterator<'a> {
+    fn from(value: (CigarIterator<'a>, u32, u64)) -> Self {
+        let (inner, chrom_id, reference_position) = value;
+        AugmentedCigarIterator::with_source(
+            ElementSource::Parsed(inner),
+            chrom_id,
+            reference_position,
+        )
     }
 }
 
-impl<'a> From<(&'a str, u32, u32)> for AugmentedCigarIterator<'a> {
-    fn from(value: (&'a str, u32, u32)) -> Self {
+/// Augment the elements of an expanded CIGAR. The reference position must be the one the
+/// expanded iterator was created with.
+impl<'a> From<(ExpandedCigarIterator<'a>, u32, u64)> for AugmentedCigarIterator<'a> {
+    fn from(value: (ExpandedCigarIterator<'a>, u32, u64)) -> Self {
+        let (inner, chrom_id, reference_position) = value;
+        AugmentedCigarIterator::with_source(
+            ElementSource::Expanded(inner),
+            chrom_id,
+            reference_position,
+        )
+    }
+}
+
+impl<'a> From<(&'a str, u32, u64)> for AugmentedCigarIterator<'a> {
+    fn from(value: (&'a str, u32, u64)) -> Self {
         let (cigar_str, chrom_id, reference_position) = value;
-        let inner = CigarIterator {
-            chars: cigar_str.chars(),
-        };
-        AugmentedCigarIterator {
-            inner,
-            read_position: 0,
+        let inner = CigarIterator::new(cigar_str);
+        AugmentedCigarIterator::with_source(
+            ElementSource::Parsed(inner),
             chrom_id,
             reference_position,
-        }
+        )
     }
 }
 
-impl<'a> Iterator for AugmentedCigarIterator<'a> {
-    type Item = std::result::Result<AugmentedCigarElement, CigarError>;
+impl<'a> From<(&'a [CigarElement], u32, u64)> for AugmentedCigarIterator<'a> {
+    fn from(value: (&'a [CigarElement], u32, u64)) -> Self {
+        let (elements, chrom_id, reference_position) = value;
+        AugmentedCigarIterator::with_source(
+            ElementSource::Borrowed(elements.iter()),
+            chrom_id,
+            reference_position,
+        )
+    }
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
+impl<'a> From<(&'a Cigar, u32, u64)> for AugmentedCigarIterator<'a> {
+    fn from(value: (&'a Cigar, u32, u64)) -> Self {
+        let (cigar, chrom_id, reference_position) = value;
+        AugmentedCigarIterator::from((cigar.elements(), chrom_id, reference_position))
+    }
+}
+
+impl<'a> From<(Vec<CigarElement>, u32, u64)> for AugmentedCigarIterator<'a> {
+    fn from(value: (Vec<CigarElement>, u32, u64)) -> Self {
+        let (elements, chrom_id, reference_position) = value;
+        AugmentedCigarIterator::with_source(
+            ElementSource::Owned(elements.into_iter()),
+            chrom_id,
+            reference_position,
+        )
+    }
+}
+
+impl<'a> From<(Cigar, u32, u64)> for AugmentedCigarIterator<'a> {
+    fn from(value: (Cigar, u32, u64)) -> Self {
+        let (cigar, chrom_id, reference_position) = value;
+        AugmentedCigarIterator::from((cigar.into_elements(), chrom_id, reference_position))
+    }
+}
+
+impl<'a> AugmentedCigarIterator<'a> {
+    /// Augment the next element, whether or not the filter accepts it.
+    fn step(&mut self) -> Option<std::result::Result<AugmentedCigarElement, CigarError>> {
         let inner_elem = self.inner.next()?;
         match inner_elem {
             Ok(CigarElement { length, op }) => {
-                let read_position = self.read_position;
+                let read_position = match self.read_length {
+                    Some(read_length) => {
+                        read_length - self.read_position - self.policy.read_consumed(op, length)
+                    }
+                    None => self.read_position,
+                };
                 let reference_position = self.reference_position;
+                let clip = matches!(op, CigarOp::SoftClip | CigarOp::HardClip);
                 let elem = AugmentedCigarElement {
                     length,
                     op,
                     read_position,
                     chrom_id: self.chrom_id,
                     reference_position,
+                    leading_clip: clip && !self.aligned,
                 };
-                match op {
-                    CigarOp::Match => {
-                        self.read_position += length;
-                        self.reference_position += length;
-                    }
-                    CigarOp::Insertion => {
-                        self.read_position += length;
-                    }
-                    CigarOp::Deletion => {
-                        self.reference_position += length;
-                    }
-                    CigarOp::Skip => {
-                        self.reference_position += length;
-                    }
-                    CigarOp::SoftClip => {
-                        self.read_position += length;
-                    }
-                    CigarOp::HardClip => {
-                        self.read_position += length;
-                    }
-                    CigarOp::Padding => {
-                        self.read_position += length;
-                    }
-                    CigarOp::Equal => {
-                        self.read_position += length;
-                        self.reference_position += length;
-                    }
-                    CigarOp::Diff => {
-                        self.read_position += length;
-                        self.reference_position += length;
-                    }
+                self.aligned |= !clip;
+                self.read_position += self.policy.read_consumed(op, length);
+                if op.consumes_reference() {
+                    self.reference_position += length as u64;
                 }
                 Some(Ok(elem))
             }
-            Err(e) => Some(Err(e)),
+            Err(e) => match &self.inner {
+                ElementSource::Parsed(inner) => Some(Err(e.with_cigar(inner.as_str()))),
+                _ => Some(Err(e)),
+            },
+        }
+    }
+}
+
+impl<'a> Iterator for AugmentedCigarIterator<'a> {
+    type Item = std::result::Result<AugmentedCigarElement, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.step()? {
+                Ok(elem) if !self.filter.accepts(elem.op) => continue,
+                other => return Some(other),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.inner.size_hint();
+        if self.filter == EventFilter::all() {
+            (lower, upper)
+        } else {
+            (0, upper)
+        }
+    }
+}
+
+impl<'a> FusedIterator for AugmentedCigarIterator<'a> {}
+
+impl<'a> AugmentedCigarIterator<'a> {
+    /// Tag each element produced by this iterator with a caller supplied value, such as a read ID.
+    pub fn tagged<T: Clone>(self, tag: T) -> TaggedAugmentedCigarIterator<'a, T> {
+        TaggedAugmentedCigarIterator::new(self, tag)
+    }
+}
+
+/// An adapter splitting augmented CIGAR elements into elements of length one.
+///
+/// Each base of a split element gets its own read and reference position. Elements
+/// whose operation is not selected are passed through unchanged.
+pub struct PerBaseIterator<I> {
+    inner: I,
+    ops: Option<Vec<CigarOp>>,
+    current: Option<(AugmentedCigarElement, u32)>,
+}
+
+impl<I> PerBaseIterator<I>
+where
+    I: Iterator<Item = std::result::Result<AugmentedCigarElement, CigarError>>,
+{
+    /// Create a new per-base adapter splitting elements of every operation.
+    pub fn new(inner: I) -> Self {
+        PerBaseIterator {
+            inner,
+            ops: None,
+            current: None,
+        }
+    }
+
+    /// Only split elements with the given operations.
+    pub fn only<V: IntoIterator<Item = CigarOp>>(mut self, ops: V) -> Self {
+        self.ops = Some(ops.into_iter().collect());
+        self
+    }
+
+    fn selected(&self, op: CigarOp) -> bool {
+        match &self.ops {
+            Some(ops) => ops.contains(&op),
+            None => true,
+        }
+    }
+}
+
+impl<I> Iterator for PerBaseIterator<I>
+where
+    I: Iterator<Item = std::result::Result<AugmentedCigarElement, CigarError>>,
+{
+    type Item = std::result::Result<AugmentedCigarElement, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((elem, offset)) = &mut self.current
+                && *offset < elem.length
+            {
+                let i = *offset;
+                *offset += 1;
+                let reference_step = if elem.op.consumes_reference() { i } else { 0 };
+                let read_step = if read_consumed(elem) > 0 { i } else { 0 };
+                return Some(Ok(AugmentedCigarElement {
+                    length: 1,
+                    op: elem.op,
+                    read_position: elem.read_position + read_step,
+                    chrom_id: elem.chrom_id,
+                    reference_position: elem.reference_position + reference_step as u64,
+                    leading_clip: elem.leading_clip,
+                }));
+            }
+            self.current = None;
+            match self.inner.next()? {
+                Ok(elem) if elem.length > 1 && self.selected(elem.op) => {
+                    self.current = Some((elem, 0));
+                }
+                other => return Some(other),
+            }
         }
     }
 }
 
+impl<'a> AugmentedCigarIterator<'a> {
+    /// Split the elements produced by this iterator into elements of length one.
+    pub fn per_base(self) -> PerBaseIterator<Self> {
+        PerBaseIterator::new(self)
+    }
+}
+
+/// An augmented CIGAR element tagged with a caller supplied value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaggedAugmentedCigarElement<T> {
+    /// The augmented CIGAR element.
+    pub element: AugmentedCigarElement,
+    /// The tag of the read the element was derived from.
+    pub tag: T,
+}
+
+/// An iterator over augmented CIGAR elements tagged with a caller supplied value.
+pub struct TaggedAugmentedCigarIterator<'a, T: Clone> {
+    inner: AugmentedCigarIterator<'a>,
+    tag: T,
+}
+
+impl<'a, T: Clone> TaggedAugmentedCigarIterator<'a, T> {
+    /// Create a new tagged iterator, attaching `tag` to every element of `inner`.
+    pub fn new(inner: AugmentedCigarIterator<'a>, tag: T) -> Self {
+        TaggedAugmentedCigarIterator { inner, tag }
+    }
+}
+
+impl<'a, T: Clone> Iterator for TaggedAugmentedCigarIterator<'a, T> {
+    type Item = std::result::Result<TaggedAugmentedCigarElement<T>, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let elem = self.inner.next()?;
+        Some(elem.map(|element| TaggedAugmentedCigarElement {
+            element,
+            tag: self.tag.clone(),
+        }))
+    }
+}
+
+/// Parse a CIGAR string for a read aligned at `reference_position` into a caller-owned
+/// buffer of augmented elements, returning the number of elements parsed.
+///
+/// As for [`parse_into`](crate::parse_into), the buffer is cleared first and keeps its
+/// capacity, and on error holds the elements before it.
+pub fn parse_augmented_into(
+    cigar: &str,
+    chrom_id: u32,
+    reference_position: u64,
+    out: &mut Vec<AugmentedCigarElement>,
+) -> std::result::Result<usize, CigarError> {
+    out.clear();
+    for elem in AugmentedCigarIterator::from((cigar, chrom_id, reference_position)) {
+        out.push(elem?);
+    }
+    Ok(out.len())
+}
+
+/// Reverse a sequence of augmented CIGAR elements for the reverse strand of the read.
+///
+/// The order of the elements is reversed and read positions are recomputed so that they
+/// count from the other end of the read. Reference positions are left unchanged.
+pub fn reverse_augmented(elements: &[AugmentedCigarElement]) -> Vec<AugmentedCigarElement> {
+    let read_length = elements.iter().map(|e| e.read_end()).max().unwrap_or(0);
+    elements
+        .iter()
+        .rev()
+        .map(|e| AugmentedCigarElement {
+            read_position: read_length - e.read_end(),
+            ..e.clone()
+        })
+        .collect()
+}
+
+fn read_consumed(elem: &AugmentedCigarElement) -> u32 {
+    ConsumptionPolicy::Sam.read_consumed(elem.op, elem.length)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -142,13 +712,7 @@ mod tests {
     #[test]
     fn test_augmented_cigar_iterator_basic() {
         let cigar = "3M2I4D";
-        let iter = AugmentedCigarIterator::from((
-            CigarIterator {
-                chars: cigar.chars(),
-            },
-            1,
-            100,
-        ));
+        let iter = AugmentedCigarIterator::from((CigarIterator::new(cigar), 1, 100));
         let elems: Vec<_> = iter.collect();
         assert_eq!(elems.len(), 3);
         // 3M
@@ -165,13 +729,7 @@ mod tests {
     #[test]
     fn test_augmented_cigar_iterator_positions() {
         let cigar = "2M1I2D1M";
-        let iter = AugmentedCigarIterator::from((
-            CigarIterator {
-                chars: cigar.chars(),
-            },
-            1,
-            50,
-        ));
+        let iter = AugmentedCigarIterator::from((CigarIterator::new(cigar), 1, 50));
         let elems: Vec<_> = iter.collect();
         assert_eq!(elems.len(), 4);
         assert!(matches!(elems[0], Ok(ref e)
@@ -187,17 +745,16 @@ mod tests {
     #[test]
     fn test_augmented_cigar_iterator_error_propagation() {
         let cigar = "2M1Z";
-        let iter = AugmentedCigarIterator::from((
-            CigarIterator {
-                chars: cigar.chars(),
-            },
-            1,
-            0,
-        ));
+        let iter = AugmentedCigarIterator::from((CigarIterator::new(cigar), 1, 0));
         let elems: Vec<_> = iter.collect();
         assert_eq!(elems.len(), 2);
         assert!(matches!(elems[0], Ok(ref e) if e.length == 2 && e.op == CigarOp::Match));
-        assert!(matches!(elems[1], Err(CigarError::InvalidCharacter('Z'))));
+        let error = elems[1].as_ref().unwrap_err();
+        assert!(matches!(error.root(), CigarError::InvalidCharacter('Z', _)));
+        assert_eq!(
+            error.error_context().and_then(|c| c.cigar.as_deref()),
+            Some("2M1Z")
+        );
     }
 
     #[test]
@@ -211,4 +768,332 @@ mod tests {
         assert!(matches!(elems[1], Ok(ref e)
         if e.length == 2 && e.op == CigarOp::Insertion && e.read_position == 1 && e.reference_position == 11));
     }
+
+    #[test]
+    fn test_augmented_cigar_iterator_size_hint() {
+        let cigar: Cigar = "2S3M1D2M".parse().unwrap();
+        let mut iter = AugmentedCigarIterator::from((&cigar, 1, 10));
+        assert_eq!(iter.size_hint(), (4, Some(4)));
+        iter.next();
+        assert_eq!(iter.size_hint(), (3, Some(3)));
+        let mut iter = AugmentedCigarIterator::from(("3M", 1, 10));
+        assert_eq!(iter.size_hint(), (1, Some(2)));
+        assert!(iter.next().is_some());
+        assert!(iter.next().is_none());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_augmented_cigar_iterator_from_elements() {
+        let cigar: Cigar = "2S3M1D2M".parse().unwrap();
+        let expected: Vec<_> = AugmentedCigarIterator::from(("2S3M1D2M", 1, 10))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let borrowed: Vec<_> = AugmentedCigarIterator::from((cigar.elements(), 1, 10))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let owned: Vec<_> = AugmentedCigarIterator::from((cigar.into_elements(), 1, 10))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(borrowed, expected);
+        assert_eq!(owned, expected);
+    }
+
+    #[test]
+    fn test_parse_augmented_into() {
+        let mut buffer = Vec::new();
+        assert_eq!(
+            parse_augmented_into("2S3M1D2M", 1, 10, &mut buffer).unwrap(),
+            4
+        );
+        let expected: Vec<_> = AugmentedCigarIterator::from(("2S3M1D2M", 1, 10))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(buffer, expected);
+        assert!(parse_augmented_into("4M1Z", 2, 0, &mut buffer).is_err());
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer[0].chrom_id, 2);
+    }
+
+    #[test]
+    fn test_tagged_augmented_cigar_iterator() {
+        let elems: Vec<_> = AugmentedCigarIterator::from(("3M1I2M", 1, 10))
+            .tagged("read1")
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(elems.len(), 3);
+        assert!(elems.iter().all(|e| e.tag == "read1"));
+        assert_eq!(elems[1].element.op, CigarOp::Insertion);
+        assert_eq!(elems[1].element.reference_position, 13);
+    }
+
+    #[test]
+    fn test_per_base_iterator() {
+        let elems: Vec<_> = AugmentedCigarIterator::from(("2M2I2D", 1, 10))
+            .per_base()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let summary: Vec<_> = elems
+            .iter()
+            .map(|e| (e.op, e.length, e.read_position, e.reference_position))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (CigarOp::Match, 1, 0, 10),
+                (CigarOp::Match, 1, 1, 11),
+                (CigarOp::Insertion, 1, 2, 12),
+                (CigarOp::Insertion, 1, 3, 12),
+                (CigarOp::Deletion, 1, 4, 12),
+                (CigarOp::Deletion, 1, 4, 13),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_per_base_iterator_selected_ops() {
+        let elems: Vec<_> = AugmentedCigarIterator::from(("3M2D3M", 1, 10))
+            .per_base()
+            .only([CigarOp::Deletion])
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let lengths: Vec<_> = elems.iter().map(|e| e.length).collect();
+        assert_eq!(lengths, vec![3, 1, 1, 3]);
+    }
+
+    #[test]
+    fn test_augmented_cigar_iterator_large_positions() {
+        let elems: Vec<_> = AugmentedCigarIterator::from(("2M1D1M", 1, 5_000_000_000))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(elems[2].reference_position, 5_000_000_003);
+    }
+
+    #[test]
+    fn test_augmented_cigar_element_intervals() {
+        let elems: Vec<_> = AugmentedCigarIterator::from(("3M2I4D", 1, 100))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(elems[0].reference_range(), 100..103);
+        assert_eq!(elems[0].read_range(), 0..3);
+        assert_eq!(elems[1].reference_range(), 103..103);
+        assert_eq!(elems[1].read_range(), 3..5);
+        assert_eq!(elems[2].reference_range(), 103..107);
+        assert_eq!(elems[2].read_range(), 5..5);
+        assert!(elems[0].overlaps_reference(102, 110));
+        assert!(!elems[0].overlaps_reference(103, 110));
+        assert!(elems[1].overlaps_reference(103, 104));
+        assert!(!elems[1].overlaps_reference(100, 103));
+        assert!(elems[2].overlaps_reference(106, 107));
+        let clipped = elems[0].clip_reference(101, 110).unwrap();
+        assert_eq!(clipped.reference_range(), 101..103);
+        assert_eq!(clipped.read_range(), 1..3);
+        let clipped = elems[2].clip_reference(90, 105).unwrap();
+        assert_eq!(clipped.reference_range(), 103..105);
+        assert_eq!(clipped.read_range(), 5..5);
+        assert!(elems[1].clip_reference(90, 103).is_none());
+    }
+
+    #[test]
+    fn test_reverse_augmented() {
+        let elems: Vec<_> = AugmentedCigarIterator::from(("2S3M1D1I4M", 1, 100))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let reversed = reverse_augmented(&elems);
+        let summary: Vec<_> = reversed
+            .iter()
+            .map(|e| (e.op, e.length, e.read_position, e.reference_position))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (CigarOp::Match, 4, 0, 104),
+                (CigarOp::Insertion, 1, 4, 104),
+                (CigarOp::Deletion, 1, 5, 103),
+                (CigarOp::Match, 3, 5, 100),
+                (CigarOp::SoftClip, 2, 8, 100),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_augmented_with_strand() {
+        let reverse: Vec<_> = AugmentedCigarIterator::from(("2S3M1D1I4M", 1, 100))
+            .with_strand(Strand::Reverse)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let expected = reverse_augmented(
+            &AugmentedCigarIterator::from(("2S3M1D1I4M", 1, 100))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+        );
+        assert_eq!(reverse, expected.into_iter().rev().collect::<Vec<_>>());
+
+        let forward: Vec<_> = AugmentedCigarIterator::from(("3H2M", 0, 10))
+            .with_strand(Strand::Forward)
+            .map(|e| e.unwrap().read_position)
+            .collect();
+        assert_eq!(forward, vec![0, 0]);
+        let reverse: Vec<_> = AugmentedCigarIterator::from(("3H2M", 0, 10))
+            .with_strand(Strand::Reverse)
+            .map(|e| e.unwrap().read_position)
+            .collect();
+        assert_eq!(reverse, vec![2, 0]);
+    }
+
+    #[test]
+    fn test_consumption_policy() {
+        let positions = |policy: ConsumptionPolicy, strand: Strand| -> Vec<(CigarOp, u32)> {
+            AugmentedCigarIterator::from(("2H1S3M1P1I2M3H", 0, 10))
+                .with_strand(strand)
+                .with_consumption_policy(policy)
+                .map(|e| e.map(|e| (e.op, e.read_position)).unwrap())
+                .collect()
+        };
+        use CigarOp::*;
+        assert_eq!(
+            positions(ConsumptionPolicy::Sam, Strand::Forward),
+            vec![
+                (HardClip, 0),
+                (SoftClip, 0),
+                (Match, 1),
+                (Padding, 4),
+                (Insertion, 4),
+                (Match, 5),
+                (HardClip, 7)
+            ]
+        );
+        assert_eq!(
+            positions(ConsumptionPolicy::Legacy, Strand::Forward),
+            vec![
+                (HardClip, 0),
+                (SoftClip, 2),
+                (Match, 3),
+                (Padding, 6),
+                (Insertion, 7),
+                (Match, 8),
+                (HardClip, 10)
+            ]
+        );
+        assert_eq!(
+            positions(ConsumptionPolicy::Sam, Strand::Reverse),
+            vec![
+                (HardClip, 7),
+                (SoftClip, 6),
+                (Match, 3),
+                (Padding, 3),
+                (Insertion, 2),
+                (Match, 0),
+                (HardClip, 0)
+            ]
+        );
+        assert!(ConsumptionPolicy::Legacy.consumes_read(HardClip));
+        assert!(!ConsumptionPolicy::default().consumes_read(Padding));
+    }
+
+    #[test]
+    fn test_augmented_event_filter() {
+        let elems: Vec<_> = AugmentedCigarIterator::from(("2S3M1D1I4M", 1, 100))
+            .with_event_filter(EventFilter::indels())
+            .map(|e| {
+                let e = e.unwrap();
+                (e.op, e.read_position, e.reference_position)
+            })
+            .collect();
+        assert_eq!(
+            elems,
+            vec![(CigarOp::Deletion, 5, 103), (CigarOp::Insertion, 5, 104)]
+        );
+        let filter = EventFilter::all().without_clips().without_padding();
+        assert!(!filter.accepts(CigarOp::HardClip));
+        assert!(!filter.accepts(CigarOp::Padding));
+        assert!(filter.accepts(CigarOp::Skip));
+        assert!(EventFilter::mismatches().accepts(CigarOp::Diff));
+        assert!(!EventFilter::mismatches().accepts(CigarOp::Equal));
+    }
+
+    #[test]
+    fn test_augmented_expanded() {
+        use CigarOp::*;
+        let reference = b"TTACGTACGT";
+        let elems: Vec<_> =
+            AugmentedCigarIterator::expanded("1S4M1D2M", 3, 2, reference, b"GACTTCG")
+                .map(|e| {
+                    let e = e.unwrap();
+                    (e.op, e.length, e.read_position, e.reference_position)
+                })
+                .collect();
+        assert_eq!(
+            elems,
+            vec![
+                (SoftClip, 1, 0, 2),
+                (Equal, 2, 1, 2),
+                (Diff, 1, 3, 4),
+                (Equal, 1, 4, 5),
+                (Deletion, 1, 5, 6),
+                (Equal, 2, 5, 7)
+            ]
+        );
+        let mismatches: Vec<_> = AugmentedCigarIterator::expanded("4M", 0, 2, reference, b"ACTT")
+            .with_event_filter(EventFilter::mismatches())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].reference_position, 4);
+        let mask = AugmentedCigarIterator::expanded("4M1D2M", 0, 2, reference, b"ACTTCC")
+            .with_event_filter(EventFilter::only([Diff, Deletion]))
+            .covered_intervals()
+            .unwrap();
+        assert_eq!(mask, vec![(4, 5), (6, 7), (8, 9)]);
+        let mut beyond = AugmentedCigarIterator::expanded("2M", 0, 9, reference, b"TT");
+        assert!(matches!(
+            beyond.next().unwrap().unwrap_err().root(),
+            CigarError::ReferenceOutOfBounds(9, 11)
+        ));
+        // Padding moves neither the read nor the reference position.
+        let padded: Vec<_> = AugmentedCigarIterator::expanded("2M1P2M", 0, 0, b"ACGT", b"ACGT")
+            .map(|e| {
+                let e = e.unwrap();
+                (e.op, e.length, e.read_position, e.reference_position)
+            })
+            .collect();
+        assert_eq!(
+            padded,
+            vec![(Equal, 2, 0, 0), (Padding, 1, 2, 2), (Equal, 2, 2, 2)]
+        );
+    }
+
+    #[test]
+    fn test_clip_intervals() {
+        let iter = AugmentedCigarIterator::from(("2H3S10M2I4M5S", 1, 100));
+        assert_eq!(iter.clipped_interval(), (100, 114));
+        assert_eq!(iter.unclipped_interval(), (95, 119));
+        let iter = AugmentedCigarIterator::from(("4M1D4M", 1, 7));
+        assert_eq!(iter.unclipped_interval(), (7, 16));
+    }
+
+    #[test]
+    fn test_leading_clips() {
+        let leading = |iter: AugmentedCigarIterator| -> Vec<(CigarOp, bool)> {
+            iter.map(|e| e.unwrap())
+                .filter(|e| matches!(e.op, CigarOp::SoftClip | CigarOp::HardClip))
+                .map(|e| (e.op, e.is_leading_clip()))
+                .collect()
+        };
+        let expected = vec![
+            (CigarOp::HardClip, true),
+            (CigarOp::SoftClip, true),
+            (CigarOp::SoftClip, false),
+            (CigarOp::HardClip, false),
+        ];
+        let cigar = "5H3S10M2S4H";
+        let legacy = AugmentedCigarIterator::from((cigar, 0, 100))
+            .with_consumption_policy(ConsumptionPolicy::Legacy);
+        assert_eq!(leading(legacy), expected);
+        // The trailing clips of a reverse strand read have the first read positions.
+        let reverse = AugmentedCigarIterator::from((cigar, 0, 100)).with_strand(Strand::Reverse);
+        assert_eq!(leading(reverse), expected);
+        let only_clips = AugmentedCigarIterator::from(("5S", 0, 100));
+        assert_eq!(leading(only_clips), vec![(CigarOp::SoftClip, true)]);
+    }
 }
@@ -0,0 +1,213 @@
+//! Breakpoint candidates from clipped alignments.
+//!
+//! Reads spanning a structural variant breakpoint are typically clipped where their
+//! alignment to the reference ends, so many clips sharing a reference boundary point
+//! at a breakpoint. [`BreakpointIterator`] screens a collated stream for such
+//! boundaries, counting the soft and hard clips ending on either side of each.
+//!
+//! Collation keeps clips at the start of alignments apart from those at their end (see
+//! [`AugmentedCigarElement::is_leading_clip`](crate::augmented_cigar::AugmentedCigarElement::is_leading_clip)),
+//! so the clips at each boundary can be split by the side their bases lie on.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::breakpoints::{BreakpointIterator, ClipSide};
+//! use cigar_utils::collated::CollatedAugmentedCigarIterator;
+//!
+//! let cigars = vec![
+//!     std::io::Result::Ok(("10M5S".to_string(), 0, 90)),
+//!     std::io::Result::Ok(("8M7S".to_string(), 0, 92)),
+//!     std::io::Result::Ok(("4H6M".to_string(), 0, 100)),
+//!     std::io::Result::Ok(("3S12M".to_string(), 0, 95)),
+//! ];
+//! let collated = CollatedAugmentedCigarIterator::new(cigars.into_iter());
+//! let candidates: Vec<_> = BreakpointIterator::new(collated, 2)
+//!     .collect::<Result<_, _>>()
+//!     .unwrap();
+//! assert_eq!(candidates.len(), 1);
+//! assert_eq!(candidates[0].position, 100);
+//! assert_eq!((candidates[0].left, candidates[0].right), (1, 2));
+//! assert_eq!(candidates[0].dominant_side(), Some(ClipSide::Right));
+//! ```
+
+use std::iter::Peekable;
+
+use crate::CigarOp;
+use crate::augmented_cigar::AugmentedCigarElement;
+use crate::collated::CollatedEvent;
+use crate::error::CigarError;
+
+/// The side of a breakpoint on which clipped bases lie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClipSide {
+    /// The clipped bases precede the boundary: the read is clipped at its start.
+    Left,
+    /// The clipped bases follow the boundary: the read is clipped at its end.
+    Right,
+}
+
+/// A reference position where clipped alignments end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BreakpointCandidate {
+    /// The chromosome ID of the boundary.
+    pub chrom_id: u32,
+    /// The reference position of the boundary; clipped bases lie to the left of it or
+    /// from it onwards.
+    pub position: u64,
+    /// The number of clips with their bases to the left of the boundary.
+    pub left: usize,
+    /// The number of clips with their bases to the right of the boundary.
+    pub right: usize,
+}
+
+impl BreakpointCandidate {
+    /// The total number of clips at the boundary.
+    pub fn support(&self) -> usize {
+        self.left + self.right
+    }
+
+    /// The side with more clips, or `None` if they are equal.
+    pub fn dominant_side(&self) -> Option<ClipSide> {
+        match self.left.cmp(&self.right) {
+            std::cmp::Ordering::Greater => Some(ClipSide::Left),
+            std::cmp::Ordering::Less => Some(ClipSide::Right),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+}
+
+/// An iterator over the breakpoint candidates of a stream of collated events.
+///
+/// Soft and hard clips are grouped by the reference position of their boundary, and a
+/// candidate is reported for each boundary with at least `min_support` clips.
+pub struct BreakpointIterator<I: Iterator> {
+    inner: Peekable<I>,
+    min_support: usize,
+}
+
+impl<I: Iterator> BreakpointIterator<I> {
+    /// Create a new breakpoint iterator over collated events, reporting boundaries with
+    /// at least `min_support` clips.
+    pub fn new(inner: I, min_support: usize) -> Self {
+        BreakpointIterator {
+            inner: inner.peekable(),
+            min_support,
+        }
+    }
+}
+
+impl<I> Iterator for BreakpointIterator<I>
+where
+    I: Iterator<Item = std::result::Result<CollatedEvent, CigarError>>,
+{
+    type Item = std::result::Result<BreakpointCandidate, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (elem, count) = match self.inner.next()? {
+                Ok(event) => event,
+                Err(e) => return Some(Err(e)),
+            };
+            if !matches!(elem.op, CigarOp::SoftClip | CigarOp::HardClip) {
+                continue;
+            }
+            let mut candidate = BreakpointCandidate {
+                chrom_id: elem.chrom_id,
+                position: elem.reference_position,
+                left: 0,
+                right: 0,
+            };
+            let mut add = |elem: &AugmentedCigarElement, count: usize| {
+                if elem.is_leading_clip() {
+                    candidate.left += count;
+                } else {
+                    candidate.right += count;
+                }
+            };
+            add(&elem, count);
+            while let Some(Ok((next, _))) = self.inner.peek() {
+                if (next.chrom_id, next.reference_position)
+                    != (elem.chrom_id, elem.reference_position)
+                {
+                    break;
+                }
+                if let Some(Ok((next, count))) = self.inner.next()
+                    && matches!(next.op, CigarOp::SoftClip | CigarOp::HardClip)
+                {
+                    add(&next, count);
+                }
+            }
+            if candidate.support() >= self.min_support.max(1) {
+                return Some(Ok(candidate));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collated::CollatedAugmentedCigarIterator;
+
+    fn candidates(cigars: &[(&str, u32, u64)], min_support: usize) -> Vec<BreakpointCandidate> {
+        let records: Vec<_> = cigars
+            .iter()
+            .map(|(cigar, chrom_id, position)| {
+                std::io::Result::Ok((cigar.to_string(), *chrom_id, *position))
+            })
+            .collect();
+        BreakpointIterator::new(
+            CollatedAugmentedCigarIterator::new(records.into_iter()),
+            min_support,
+        )
+        .collect::<Result<_, _>>()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_breakpoints_by_chromosome() {
+        let found = candidates(
+            &[
+                ("5S5M", 0, 10),
+                ("5S5M", 0, 10),
+                ("5S5M", 1, 10),
+                ("10M", 1, 10),
+                ("2M3H", 1, 18),
+            ],
+            1,
+        );
+        assert_eq!(
+            found,
+            vec![
+                BreakpointCandidate {
+                    chrom_id: 0,
+                    position: 10,
+                    left: 2,
+                    right: 0
+                },
+                BreakpointCandidate {
+                    chrom_id: 1,
+                    position: 10,
+                    left: 1,
+                    right: 0
+                },
+                BreakpointCandidate {
+                    chrom_id: 1,
+                    position: 20,
+                    left: 0,
+                    right: 1
+                },
+            ]
+        );
+        assert_eq!(found[0].dominant_side(), Some(ClipSide::Left));
+    }
+
+    #[test]
+    fn test_breakpoint_threshold_and_ties() {
+        let found = candidates(&[("5M5S", 0, 5), ("5S5M", 0, 10), ("5M5S", 0, 50)], 2);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].support(), 2);
+        assert_eq!(found[0].dominant_side(), None);
+    }
+}
@@ -0,0 +1,244 @@
+//! Incremental construction of CIGARs.
+//!
+//! The [`CigarBuilder`] accumulates operations one at a time, merging consecutive
+//! operations of the same type and rejecting zero length operations. The [`Coalesce`]
+//! adapter does the same lazily for a stream of elements, such as the output of a chain
+//! of transformations.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::CigarOp;
+//! use cigar_utils::builder::CigarBuilder;
+//!
+//! let mut builder = CigarBuilder::new();
+//! builder.push(CigarOp::SoftClip, 2).unwrap();
+//! builder.push(CigarOp::Match, 5).unwrap();
+//! builder.push(CigarOp::Match, 3).unwrap();
+//! builder.push(CigarOp::Insertion, 1).unwrap();
+//!
+//! let cigar = builder.build_validated().unwrap();
+//! assert_eq!(cigar.to_string(), "2S8M1I");
+//! ```
+
+use crate::error::CigarError;
+use crate::validate::{ValidationOptions, Violation, validate};
+use crate::{Cigar, CigarElement, CigarOp};
+
+/// A builder for CIGARs which merges adjacent identical operations.
+#[derive(Debug, Clone, Default)]
+pub struct CigarBuilder {
+    elements: Vec<CigarElement>,
+}
+
+impl CigarBuilder {
+    /// Create a new, empty builder.
+    pub fn new() -> Self {
+        CigarBuilder {
+            elements: Vec::new(),
+        }
+    }
+
+    /// Append an operation, merging it with the previous element if the operations are the same.
+    pub fn push(&mut self, op: CigarOp, length: u32) -> std::result::Result<&mut Self, CigarError> {
+        if length == 0 {
+            return Err(CigarError::ZeroLength(op));
+        }
+        match self.elements.last_mut() {
+            Some(last) if last.op == op => last.length += length,
+            _ => self.elements.push(CigarElement::new(length, op)),
+        }
+        Ok(self)
+    }
+
+    /// Append an element, merging it with the previous element if the operations are the same.
+    pub fn push_element(
+        &mut self,
+        elem: CigarElement,
+    ) -> std::result::Result<&mut Self, CigarError> {
+        self.push(elem.op, elem.length)
+    }
+
+    /// The number of (merged) elements accumulated so far.
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    /// Whether no elements have been accumulated.
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// Finish building, returning the CIGAR without further checks.
+    pub fn build(self) -> Cigar {
+        Cigar::from(self.elements)
+    }
+
+    /// Finish building, checking the placement of clipping operations against the SAM specification.
+    ///
+    /// Hard clips may only be the first and/or last element, and soft clips may only
+    /// have hard clips between them and the ends of the CIGAR.
+    pub fn build_validated(self) -> std::result::Result<Cigar, CigarError> {
+        let cigar = self.build();
+        for violation in validate(&cigar, &ValidationOptions::default()) {
+            match violation {
+                Violation::MisplacedHardClip(index) => {
+                    return Err(CigarError::MisplacedClip(CigarOp::HardClip, index));
+                }
+                Violation::MisplacedSoftClip(index) => {
+                    return Err(CigarError::MisplacedClip(CigarOp::SoftClip, index));
+                }
+                _ => {}
+            }
+        }
+        Ok(cigar)
+    }
+}
+
+/// An iterator adapter merging adjacent elements with the same operation, and dropping
+/// zero length elements, as they are produced.
+///
+/// Errors are passed through in order, after any merged element preceding them.
+///
+/// ```rust
+/// use cigar_utils::{CigarElement, CigarIterator};
+/// use cigar_utils::builder::Coalesce;
+///
+/// let elements: Vec<CigarElement> = Coalesce::new(CigarIterator::new("2M0I3M1D1D"))
+///     .collect::<Result<_, _>>()
+///     .unwrap();
+/// assert_eq!(CigarElement::cigar_string(elements), "5M2D");
+/// ```
+pub struct Coalesce<I, E> {
+    inner: I,
+    pending: Option<CigarElement>,
+    error: Option<E>,
+}
+
+impl<I, E> Coalesce<I, E>
+where
+    I: Iterator<Item = std::result::Result<CigarElement, E>>,
+{
+    /// Create a new adapter merging the elements of `inner`.
+    pub fn new(inner: I) -> Self {
+        Coalesce {
+            inner,
+            pending: None,
+            error: None,
+        }
+    }
+}
+
+impl<I, E> Iterator for Coalesce<I, E>
+where
+    I: Iterator<Item = std::result::Result<CigarElement, E>>,
+{
+    type Item = std::result::Result<CigarElement, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+        loop {
+            match self.inner.next() {
+                Some(Ok(elem)) if elem.length == 0 => {}
+                Some(Ok(elem)) => match self.pending.as_mut() {
+                    Some(pending) if pending.op == elem.op => pending.length += elem.length,
+                    _ => {
+                        if let Some(pending) = self.pending.replace(elem) {
+                            return Some(Ok(pending));
+                        }
+                    }
+                },
+                Some(Err(e)) => match self.pending.take() {
+                    Some(pending) => {
+                        self.error = Some(e);
+                        return Some(Ok(pending));
+                    }
+                    None => return Some(Err(e)),
+                },
+                None => return self.pending.take().map(Ok),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_merges_adjacent() {
+        let mut builder = CigarBuilder::new();
+        builder.push(CigarOp::Match, 3).unwrap();
+        builder.push(CigarOp::Match, 4).unwrap();
+        builder.push(CigarOp::Deletion, 1).unwrap();
+        builder.push(CigarOp::Match, 2).unwrap();
+        let cigar = builder.build();
+        assert_eq!(cigar.to_string(), "7M1D2M");
+    }
+
+    #[test]
+    fn test_builder_rejects_zero_length() {
+        let mut builder = CigarBuilder::new();
+        assert!(matches!(
+            builder.push(CigarOp::Insertion, 0),
+            Err(CigarError::ZeroLength(CigarOp::Insertion))
+        ));
+        assert!(builder.is_empty());
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let elements = vec![
+            Ok(CigarElement::new(2, CigarOp::Match)),
+            Ok(CigarElement::new(3, CigarOp::Match)),
+            Err(CigarError::InvalidOpCode(9)),
+            Ok(CigarElement::new(1, CigarOp::Match)),
+            Ok(CigarElement::new(0, CigarOp::Deletion)),
+            Ok(CigarElement::new(1, CigarOp::Match)),
+            Ok(CigarElement::new(4, CigarOp::Insertion)),
+        ];
+        let coalesced: Vec<_> = Coalesce::new(elements.into_iter()).collect();
+        assert!(matches!(coalesced[0], Ok(ref e) if *e == CigarElement::new(5, CigarOp::Match)));
+        assert!(matches!(coalesced[1], Err(CigarError::InvalidOpCode(9))));
+        assert!(matches!(coalesced[2], Ok(ref e) if *e == CigarElement::new(2, CigarOp::Match)));
+        assert!(
+            matches!(coalesced[3], Ok(ref e) if *e == CigarElement::new(4, CigarOp::Insertion))
+        );
+        assert_eq!(coalesced.len(), 4);
+    }
+
+    #[test]
+    fn test_builder_validated_clips() {
+        let mut builder = CigarBuilder::new();
+        builder.push(CigarOp::HardClip, 2).unwrap();
+        builder.push(CigarOp::SoftClip, 3).unwrap();
+        builder.push(CigarOp::Match, 10).unwrap();
+        builder.push(CigarOp::SoftClip, 1).unwrap();
+        builder.push(CigarOp::HardClip, 4).unwrap();
+        let cigar = builder.build_validated().unwrap();
+        assert_eq!(cigar.to_string(), "2H3S10M1S4H");
+    }
+
+    #[test]
+    fn test_builder_validated_misplaced_clips() {
+        let mut builder = CigarBuilder::new();
+        builder.push(CigarOp::Match, 5).unwrap();
+        builder.push(CigarOp::HardClip, 2).unwrap();
+        builder.push(CigarOp::Match, 5).unwrap();
+        assert!(matches!(
+            builder.build_validated(),
+            Err(CigarError::MisplacedClip(CigarOp::HardClip, 1))
+        ));
+
+        let mut builder = CigarBuilder::new();
+        builder.push(CigarOp::Match, 5).unwrap();
+        builder.push(CigarOp::SoftClip, 2).unwrap();
+        builder.push(CigarOp::Match, 5).unwrap();
+        assert!(matches!(
+            builder.build_validated(),
+            Err(CigarError::MisplacedClip(CigarOp::SoftClip, 1))
+        ));
+    }
+}
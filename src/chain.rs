@@ -0,0 +1,472 @@
+//! UCSC chain format.
+//!
+//! Chains, as used by `liftOver`, describe an alignment as a header followed by a list
+//! of ungapped blocks, each with the gaps in the reference (`dt`) and query (`dq`)
+//! before the next block. These functions convert between CIGAR alignments and chain
+//! blocks, and read and write chain text.
+//!
+//! Chain query coordinates on the reverse strand are positions on the reverse
+//! complement of the query, which is how SAM lays out reverse strand reads, so the
+//! CIGAR of a reverse strand chain is not reversed.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::{Cigar, Strand};
+//! use cigar_utils::chain::Chain;
+//!
+//! let cigar: Cigar = "2H5M2I3M4D6M1H".parse().unwrap();
+//! let chain = Chain::from_alignment(&cigar, "chr1", 1000, 100, "read1", Strand::Reverse);
+//! assert_eq!(
+//!     chain.to_string(),
+//!     "chain 0 chr1 1000 + 100 118 read1 19 - 2 18 0\n5\t0\t2\n3\t4\t0\n6\n"
+//! );
+//!
+//! let parsed: Chain = chain.to_string().parse().unwrap();
+//! assert_eq!(parsed, chain);
+//! let (cigar, position, strand) = parsed.to_alignment().unwrap();
+//! assert_eq!(cigar.to_string(), "2H5M2I3M4D6M1H");
+//! assert_eq!((position, strand), (100, Strand::Reverse));
+//! ```
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::error::{CigarError, element_length, parse_number};
+use crate::{Cigar, CigarElement, CigarOp, Strand};
+
+/// An ungapped block of a chain, and the gaps following it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainBlock {
+    /// The number of bases in the block.
+    pub size: u64,
+    /// The number of reference (target) bases in the gap after the block.
+    pub dt: u64,
+    /// The number of query bases in the gap after the block.
+    pub dq: u64,
+}
+
+/// Convert the aligned part of a CIGAR into chain blocks.
+///
+/// Deletions and skips become reference gaps, and insertions query gaps. Clips and
+/// padding are ignored. The last block has no gaps.
+pub fn chain_blocks(cigar: &Cigar) -> Vec<ChainBlock> {
+    let mut blocks: Vec<ChainBlock> = Vec::new();
+    let (mut dt, mut dq) = (0, 0);
+    for elem in cigar.iter() {
+        let length = elem.length as u64;
+        match elem.op {
+            CigarOp::Match | CigarOp::Equal | CigarOp::Diff => match blocks.last_mut() {
+                Some(last) if dt == 0 && dq == 0 => last.size += length,
+                Some(last) => {
+                    (last.dt, last.dq) = (dt, dq);
+                    (dt, dq) = (0, 0);
+                    blocks.push(ChainBlock {
+                        size: length,
+                        dt: 0,
+                        dq: 0,
+                    });
+                }
+                None => blocks.push(ChainBlock {
+                    size: length,
+                    dt: 0,
+                    dq: 0,
+                }),
+            },
+            // Gaps before the first block are not part of the chain.
+            CigarOp::Deletion | CigarOp::Skip if !blocks.is_empty() => dt += length,
+            CigarOp::Insertion if !blocks.is_empty() => dq += length,
+            _ => {}
+        }
+    }
+    blocks
+}
+
+/// Convert chain blocks into CIGAR elements.
+///
+/// Query gaps become insertions and reference gaps deletions; where a gap has both, the
+/// insertion comes first. Fails if a block or gap is too long for a CIGAR element.
+pub fn cigar_from_chain_blocks(blocks: &[ChainBlock]) -> std::result::Result<Cigar, CigarError> {
+    let mut elements = Vec::new();
+    for block in blocks {
+        for (length, op) in [
+            (block.size, CigarOp::Match),
+            (block.dq, CigarOp::Insertion),
+            (block.dt, CigarOp::Deletion),
+        ] {
+            if length > 0 {
+                elements.push(CigarElement::new(element_length(FORMAT, length)?, op));
+            }
+        }
+    }
+    Ok(Cigar::from(elements))
+}
+
+/// A chain: an alignment of a query to a reference (target) sequence.
+///
+/// Intervals are zero-based and half open, as in chain text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chain {
+    /// The alignment score.
+    pub score: i64,
+    /// The name of the reference sequence.
+    pub reference_name: String,
+    /// The length of the reference sequence.
+    pub reference_size: u64,
+    /// The start of the alignment on the reference.
+    pub reference_start: u64,
+    /// The end of the alignment on the reference.
+    pub reference_end: u64,
+    /// The name of the query sequence.
+    pub query_name: String,
+    /// The length of the query sequence.
+    pub query_size: u64,
+    /// The strand of the query.
+    pub query_strand: Strand,
+    /// The start of the alignment on the query, on its strand.
+    pub query_start: u64,
+    /// The end of the alignment on the query, on its strand.
+    pub query_end: u64,
+    /// The chain ID.
+    pub id: u64,
+    /// The blocks of the alignment.
+    pub blocks: Vec<ChainBlock>,
+}
+
+impl Chain {
+    /// Build a chain from an alignment starting at `reference_position`.
+    ///
+    /// The query size is the read length including hard clips. The chain starts at the
+    /// first aligned base and ends after the last, so its intervals exclude the clipped
+    /// bases, and any insertions or deletions outside them. The score and ID are zero.
+    pub fn from_alignment(
+        cigar: &Cigar,
+        reference_name: &str,
+        reference_size: u64,
+        reference_position: u64,
+        query_name: &str,
+        strand: Strand,
+    ) -> Chain {
+        // The reference and query positions of the start of the first aligned element and
+        // the end of the last. Query positions include hard clipped bases.
+        let (mut reference, mut query) = (reference_position, 0);
+        let mut span = None;
+        for elem in cigar.iter() {
+            let length = elem.length as u64;
+            if matches!(elem.op, CigarOp::Match | CigarOp::Equal | CigarOp::Diff) {
+                let start = span.map_or((reference, query), |(start, _)| start);
+                span = Some((start, (reference + length, query + length)));
+            }
+            if elem.op.consumes_reference() {
+                reference += length;
+            }
+            if elem.op.consumes_query() || elem.op == CigarOp::HardClip {
+                query += length;
+            }
+        }
+        let empty = (reference_position, cigar.leading_clip() as u64);
+        let ((reference_start, query_start), (reference_end, query_end)) =
+            span.unwrap_or((empty, empty));
+        Chain {
+            score: 0,
+            reference_name: reference_name.to_string(),
+            reference_size,
+            reference_start,
+            reference_end,
+            query_name: query_name.to_string(),
+            query_size: query,
+            query_strand: strand,
+            query_start,
+            query_end,
+            id: 0,
+            blocks: chain_blocks(cigar),
+        }
+    }
+
+    /// The alignment of the chain: its CIGAR, reference position and strand.
+    ///
+    /// The query bases outside the chain become hard clips. Fails if a block, gap or
+    /// clip is too long for a CIGAR element.
+    pub fn to_alignment(&self) -> std::result::Result<(Cigar, u64, Strand), CigarError> {
+        let mut elements = Vec::new();
+        if self.query_start > 0 {
+            elements.push(CigarElement::new(
+                element_length(FORMAT, self.query_start)?,
+                CigarOp::HardClip,
+            ));
+        }
+        elements.extend(cigar_from_chain_blocks(&self.blocks)?);
+        if self.query_size > self.query_end {
+            elements.push(CigarElement::new(
+                element_length(FORMAT, self.query_size - self.query_end)?,
+                CigarOp::HardClip,
+            ));
+        }
+        Ok((
+            Cigar::from(elements),
+            self.reference_start,
+            self.query_strand,
+        ))
+    }
+}
+
+fn strand_char(strand: Strand) -> char {
+    match strand {
+        Strand::Forward => '+',
+        Strand::Reverse => '-',
+    }
+}
+
+impl Display for Chain {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "chain {} {} {} + {} {} {} {} {} {} {} {}",
+            self.score,
+            self.reference_name,
+            self.reference_size,
+            self.reference_start,
+            self.reference_end,
+            self.query_name,
+            self.query_size,
+            strand_char(self.query_strand),
+            self.query_start,
+            self.query_end,
+            self.id
+        )?;
+        for (i, block) in self.blocks.iter().enumerate() {
+            if i + 1 == self.blocks.len() {
+                writeln!(f, "{}", block.size)?;
+            } else {
+                writeln!(f, "{}\t{}\t{}", block.size, block.dt, block.dq)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The format name given in [`CigarError::InvalidFormat`] errors.
+const FORMAT: &str = "chain";
+
+fn strand(field: &str) -> std::result::Result<Strand, CigarError> {
+    match field {
+        "+" => Ok(Strand::Forward),
+        "-" => Ok(Strand::Reverse),
+        _ => Err(CigarError::invalid_format(
+            FORMAT,
+            format!("'{}' is not a strand", field),
+        )),
+    }
+}
+
+impl FromStr for Chain {
+    type Err = CigarError;
+
+    /// Parse a single chain: a header line and its block lines.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().filter(|line| !line.trim().is_empty());
+        let header = lines
+            .next()
+            .ok_or_else(|| CigarError::invalid_format(FORMAT, "empty chain"))?;
+        let fields: Vec<&str> = header.split_whitespace().collect();
+        if fields.len() != 13 || fields[0] != "chain" {
+            return Err(CigarError::invalid_format(
+                FORMAT,
+                format!("bad header line '{}'", header),
+            ));
+        }
+        if strand(fields[4])? != Strand::Forward {
+            return Err(CigarError::invalid_format(
+                FORMAT,
+                "reference strand must be '+'",
+            ));
+        }
+        let mut chain = Chain {
+            score: parse_number(FORMAT, fields[1])?,
+            reference_name: fields[2].to_string(),
+            reference_size: parse_number(FORMAT, fields[3])?,
+            reference_start: parse_number(FORMAT, fields[5])?,
+            reference_end: parse_number(FORMAT, fields[6])?,
+            query_name: fields[7].to_string(),
+            query_size: parse_number(FORMAT, fields[8])?,
+            query_strand: strand(fields[9])?,
+            query_start: parse_number(FORMAT, fields[10])?,
+            query_end: parse_number(FORMAT, fields[11])?,
+            id: parse_number(FORMAT, fields[12])?,
+            blocks: Vec::new(),
+        };
+        if chain.reference_start > chain.reference_end {
+            return Err(CigarError::invalid_format(
+                FORMAT,
+                format!(
+                    "reference interval {}..{} ends before it starts",
+                    chain.reference_start, chain.reference_end
+                ),
+            ));
+        }
+        if chain.query_start > chain.query_end {
+            return Err(CigarError::invalid_format(
+                FORMAT,
+                format!(
+                    "query interval {}..{} ends before it starts",
+                    chain.query_start, chain.query_end
+                ),
+            ));
+        }
+        for line in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let block = match fields[..] {
+                [size] => ChainBlock {
+                    size: parse_number(FORMAT, size)?,
+                    dt: 0,
+                    dq: 0,
+                },
+                [size, dt, dq] => ChainBlock {
+                    size: parse_number(FORMAT, size)?,
+                    dt: parse_number(FORMAT, dt)?,
+                    dq: parse_number(FORMAT, dq)?,
+                },
+                _ => {
+                    return Err(CigarError::invalid_format(
+                        FORMAT,
+                        format!("bad block line '{}'", line),
+                    ));
+                }
+            };
+            chain.blocks.push(block);
+        }
+        let (t, q) = chain
+            .blocks
+            .iter()
+            .try_fold((0u64, 0u64), |(t, q), b| {
+                let t = t.checked_add(b.size)?.checked_add(b.dt)?;
+                let q = q.checked_add(b.size)?.checked_add(b.dq)?;
+                Some((t, q))
+            })
+            .ok_or_else(|| CigarError::invalid_format(FORMAT, "block lengths overflow"))?;
+        let reference_length = chain.reference_end - chain.reference_start;
+        if t != reference_length {
+            return Err(CigarError::LengthMismatch(
+                reference_length as usize,
+                t as usize,
+            ));
+        }
+        let query_length = chain.query_end - chain.query_start;
+        if q != query_length {
+            return Err(CigarError::LengthMismatch(
+                query_length as usize,
+                q as usize,
+            ));
+        }
+        Ok(chain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_blocks() {
+        let cigar: Cigar = "3S2=1X4M1I2D5N3M2S".parse().unwrap();
+        assert_eq!(
+            chain_blocks(&cigar),
+            vec![
+                ChainBlock {
+                    size: 7,
+                    dt: 7,
+                    dq: 1
+                },
+                ChainBlock {
+                    size: 3,
+                    dt: 0,
+                    dq: 0
+                },
+            ]
+        );
+        assert_eq!(
+            cigar_from_chain_blocks(&chain_blocks(&cigar))
+                .unwrap()
+                .to_string(),
+            "7M1I7D3M"
+        );
+        let long = ChainBlock {
+            size: 1 << 32,
+            dt: 0,
+            dq: 0,
+        };
+        assert!(matches!(
+            cigar_from_chain_blocks(&[long]),
+            Err(CigarError::InvalidFormat("chain", _))
+        ));
+        assert!(chain_blocks(&"4S".parse().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_parse_chain() {
+        let text = "chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528 1\n\
+                    9\t1\t0\n\
+                    10\t0\t2\n\
+                    215\n";
+        let chain: Chain = text.parse().unwrap();
+        assert_eq!(chain.score, 4900);
+        assert_eq!(chain.query_strand, Strand::Reverse);
+        assert_eq!(chain.blocks.len(), 3);
+        assert_eq!(chain.to_string(), text);
+        let (cigar, position, _) = chain.to_alignment().unwrap();
+        assert_eq!(position, 25985403);
+        assert_eq!(cigar.reference_length(), 235);
+        assert_eq!(cigar.query_length_with_hard_clips(), 151006098);
+    }
+
+    #[test]
+    fn test_parse_chain_errors() {
+        assert!(matches!(
+            "chain 1 chr1 100 + 0 10 q 10 + 0 10".parse::<Chain>(),
+            Err(CigarError::InvalidFormat("chain", _))
+        ));
+        assert!(matches!(
+            "chain 1 chr1 100 + 0 10 q 10 * 0 10 1\n10\n".parse::<Chain>(),
+            Err(CigarError::InvalidFormat("chain", _))
+        ));
+        assert!(matches!(
+            "chain 1 chr1 100 + 0 10 q 10 + 0 10 1\n9\n".parse::<Chain>(),
+            Err(CigarError::LengthMismatch(10, 9))
+        ));
+        for text in [
+            "chain 1 chr1 100 + 50 40 q 100 + 0 10 1\n10\n",
+            "chain 1 chr1 100 + 0 10 q 100 + 10 0 1\n10\n",
+        ] {
+            assert!(matches!(
+                text.parse::<Chain>(),
+                Err(CigarError::InvalidFormat("chain", _))
+            ));
+        }
+        let overflowing = format!(
+            "chain 1 chr1 100 + 0 10 q 100 + 0 10 1\n{}\t0\t0\n2\n",
+            u64::MAX
+        );
+        assert!(overflowing.parse::<Chain>().is_err());
+    }
+
+    #[test]
+    fn test_chain_outer_gaps() {
+        for (cigar, reference, query) in [
+            ("2I8M", (100, 108), (2, 10)),
+            ("5M2D", (100, 105), (0, 5)),
+            ("1H3S2D4M1I3M2I1S", (102, 109), (4, 12)),
+            ("2N5M3N2H", (102, 107), (0, 5)),
+        ] {
+            let cigar: Cigar = cigar.parse().unwrap();
+            let chain = Chain::from_alignment(&cigar, "chr1", 1000, 100, "read1", Strand::Forward);
+            assert_eq!((chain.reference_start, chain.reference_end), reference);
+            assert_eq!((chain.query_start, chain.query_end), query);
+            assert_eq!(
+                chain.query_size,
+                cigar.query_length_with_hard_clips() as u64
+            );
+            let parsed: Chain = chain.to_string().parse().unwrap();
+            assert_eq!(parsed, chain);
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Chromosome names, IDs and lengths.
+//!
+//! Records and events identify chromosomes by numeric ID. A [`ChromMap`] maps between the
+//! IDs and the chromosome names, and holds the lengths of the chromosomes where known. It
+//! is read from a FASTA index (`.fai`) or SAM header, or built from a list of names, and
+//! shared by the parts of the crate which need names or lengths: the
+//! [writers](crate::writers) and [sinks](crate::sink), which write names in place of IDs,
+//! the [SAM source](crate::sam::SamSource), which numbers chromosomes as it reads, and
+//! the [collator](crate::collated::CollatedAugmentedCigarIterator::check_reference_lengths),
+//! which checks alignments against chromosome lengths.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::chrom_map::ChromMap;
+//!
+//! let header = "@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr2\tLN:500\n";
+//! let chroms = ChromMap::from_sam_header(header.as_bytes()).unwrap();
+//! assert_eq!(chroms.chrom_id("chr2"), Some(1));
+//! assert_eq!(chroms.name(0), Some("chr1"));
+//! assert_eq!(chroms.length(1), Some(500));
+//!
+//! // Writers take a map, or just the names.
+//! let names = ChromMap::from(vec!["chrX".to_string()]);
+//! assert_eq!(names.length(0), None);
+//! ```
+
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use crate::Cigar;
+use crate::error::{CigarError, parse_number};
+use crate::validate::check_reference_length;
+
+/// A map between chromosome names and IDs, with the chromosome lengths where known.
+/// IDs are assigned in order from zero.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChromMap {
+    names: Vec<String>,
+    lengths: Vec<Option<u64>>,
+    ids: HashMap<String, u32>,
+}
+
+/// The format name given in [`CigarError::InvalidFormat`] errors.
+const FORMAT: &str = "sequence dictionary";
+
+impl ChromMap {
+    /// Create an empty map.
+    pub fn new() -> Self {
+        ChromMap::default()
+    }
+
+    /// Add a chromosome of known length, giving it the next ID, which is returned.
+    ///
+    /// Gives [`CigarError::InvalidFormat`] if the name is already in the map.
+    pub fn push(&mut self, name: &str, length: u64) -> std::result::Result<u32, CigarError> {
+        if self.ids.contains_key(name) {
+            return Err(CigarError::invalid_format(
+                FORMAT,
+                format!("sequence '{}' appears more than once", name),
+            ));
+        }
+        Ok(self.add(name, Some(length)))
+    }
+
+    /// The ID of a chromosome, adding it with unknown length if it is not in the map.
+    pub fn insert(&mut self, name: &str) -> u32 {
+        match self.chrom_id(name) {
+            Some(id) => id,
+            None => self.add(name, None),
+        }
+    }
+
+    fn add(&mut self, name: &str, length: Option<u64>) -> u32 {
+        let id = self.names.len() as u32;
+        self.names.push(name.to_string());
+        self.lengths.push(length);
+        self.ids.entry(name.to_string()).or_insert(id);
+        id
+    }
+
+    /// Read a FASTA index (`.fai`), numbering the sequences in the order of its lines.
+    /// A sequence name given more than once is an error.
+    pub fn from_fai<R: BufRead>(reader: R) -> std::result::Result<Self, CigarError> {
+        let mut chroms = ChromMap::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut fields = line.split('\t');
+            let name = fields.next().unwrap_or_default();
+            let length = fields.next().ok_or_else(|| {
+                CigarError::invalid_format(FORMAT, format!("no length for sequence '{}'", name))
+            })?;
+            chroms.push(name, parse_number(FORMAT, length)?)?;
+        }
+        Ok(chroms)
+    }
+
+    /// Read the `@SQ` lines of a SAM header (or a Picard `.dict` file), numbering the
+    /// sequences in the order of the lines. Other lines are ignored, and a sequence name
+    /// given more than once is an error.
+    pub fn from_sam_header<R: BufRead>(reader: R) -> std::result::Result<Self, CigarError> {
+        let mut chroms = ChromMap::new();
+        for line in reader.lines() {
+            let line = line?;
+            let Some(header) = line.strip_prefix("@SQ\t") else {
+                continue;
+            };
+            let tag = |prefix: &str| {
+                header
+                    .trim_end()
+                    .split('\t')
+                    .find_map(|tag| tag.strip_prefix(prefix))
+            };
+            let name = tag("SN:")
+                .ok_or_else(|| CigarError::invalid_format(FORMAT, "@SQ line without SN tag"))?;
+            let length = tag("LN:").ok_or_else(|| {
+                CigarError::invalid_format(FORMAT, format!("no LN tag for sequence '{}'", name))
+            })?;
+            chroms.push(name, parse_number(FORMAT, length)?)?;
+        }
+        Ok(chroms)
+    }
+
+    /// The number of chromosomes.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Whether the map has no chromosomes.
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// The chromosome names, indexed by ID.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The name of a chromosome.
+    pub fn name(&self, chrom_id: u32) -> Option<&str> {
+        self.names.get(chrom_id as usize).map(|n| n.as_str())
+    }
+
+    /// The ID of a chromosome name.
+    pub fn chrom_id(&self, name: &str) -> Option<u32> {
+        self.ids.get(name).copied()
+    }
+
+    /// The length of a chromosome, if it is in the map and its length is known.
+    pub fn length(&self, chrom_id: u32) -> Option<u64> {
+        self.lengths.get(chrom_id as usize).copied().flatten()
+    }
+
+    /// Check that an alignment ends within its chromosome, as for
+    /// [`check_reference_length`], giving [`CigarError::UnknownChromosome`] if the
+    /// chromosome is not in the map or its length is unknown.
+    pub fn check(
+        &self,
+        cigar: &Cigar,
+        chrom_id: u32,
+        position: u64,
+    ) -> std::result::Result<(), CigarError> {
+        let length = self
+            .length(chrom_id)
+            .ok_or(CigarError::UnknownChromosome(chrom_id))?;
+        check_reference_length(cigar, chrom_id, position, length)
+    }
+}
+
+/// A map of the given names, indexed by ID, with unknown lengths. A name given more
+/// than once keeps the ID of its first occurrence.
+impl From<Vec<String>> for ChromMap {
+    fn from(names: Vec<String>) -> Self {
+        let mut chroms = ChromMap::new();
+        for name in &names {
+            chroms.add(name, None);
+        }
+        chroms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chrom_map() {
+        let cigar: Cigar = "5S10M2D3M4S".parse().unwrap();
+        let fai = "chr1\t1000\t6\t60\t61\nchr2\t500\t1030\t60\t61\n";
+        let chroms = ChromMap::from_fai(fai.as_bytes()).unwrap();
+        assert_eq!(chroms.chrom_id("chr2"), Some(1));
+        assert!(chroms.check(&cigar, 1, 490).is_err());
+        assert!(matches!(
+            chroms.check(&cigar, 2, 0),
+            Err(CigarError::UnknownChromosome(2))
+        ));
+        let header = "@HD\tVN:1.6\n@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr2\tLN:500\n";
+        assert_eq!(
+            ChromMap::from_sam_header(header.as_bytes()).unwrap(),
+            chroms
+        );
+        assert!(matches!(
+            ChromMap::from_fai("chr1\tlong\n".as_bytes()),
+            Err(CigarError::InvalidFormat("sequence dictionary", _))
+        ));
+        assert!(matches!(
+            ChromMap::from_fai("chr1\t1000\nchr1\t500\n".as_bytes()),
+            Err(CigarError::InvalidFormat("sequence dictionary", _))
+        ));
+        let repeated = "@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr1\tLN:1000\n";
+        assert!(ChromMap::from_sam_header(repeated.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_chrom_map_names() {
+        let mut chroms = ChromMap::from(vec!["chr1".to_string(), "chr2".to_string()]);
+        assert_eq!(chroms.insert("chr2"), 1);
+        assert_eq!(chroms.insert("chrM"), 2);
+        assert_eq!(chroms.push("chrY", 100).unwrap(), 3);
+        assert!(chroms.push("chr1", 100).is_err());
+        assert_eq!(chroms.name(2), Some("chrM"));
+        assert_eq!((chroms.length(2), chroms.length(3)), (None, Some(100)));
+        assert!(matches!(
+            chroms.check(&"10M".parse().unwrap(), 0, 0),
+            Err(CigarError::UnknownChromosome(0))
+        ));
+    }
+}
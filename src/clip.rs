@@ -0,0 +1,274 @@
+//! Soft and hard clip conversion.
+//!
+//! Supplementary alignments are often hard clipped while the primary alignment is soft
+//! clipped. The functions in this module convert between the two representations,
+//! keeping the CIGAR and the read sequence consistent, and extract the soft clipped
+//! bases at either end of a read along with the reference position each clip abuts.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::Cigar;
+//! use cigar_utils::clip::{hard_to_soft_clips, soft_to_hard_clips};
+//!
+//! let cigar: Cigar = "2S4M1S".parse().unwrap();
+//! let clipped = soft_to_hard_clips(&cigar, b"TTACGTA").unwrap();
+//! assert_eq!(clipped.cigar.to_string(), "2H4M1H");
+//! assert_eq!(clipped.sequence, b"ACGT");
+//! assert_eq!(clipped.leading, b"TT");
+//! assert_eq!(clipped.trailing, b"A");
+//!
+//! let restored = hard_to_soft_clips(&clipped.cigar, b"TTACGTA").unwrap();
+//! assert_eq!(restored, cigar);
+//! ```
+
+use crate::builder::CigarBuilder;
+use crate::error::CigarError;
+use crate::{Cigar, CigarElement, CigarOp};
+
+/// The result of converting soft clips to hard clips.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HardClipped {
+    /// The CIGAR with terminal soft clips replaced by hard clips.
+    pub cigar: Cigar,
+    /// The read sequence with the clipped bases removed.
+    pub sequence: Vec<u8>,
+    /// The bases removed from the start of the read.
+    pub leading: Vec<u8>,
+    /// The bases removed from the end of the read.
+    pub trailing: Vec<u8>,
+}
+
+/// Convert terminal soft clips to hard clips, removing the clipped bases from the sequence.
+pub fn soft_to_hard_clips<S: AsRef<[u8]> + ?Sized>(
+    cigar: &Cigar,
+    seq: &S,
+) -> std::result::Result<HardClipped, CigarError> {
+    let seq = seq.as_ref();
+    let elements = cigar.elements();
+    let query_length = query_length(elements);
+    if seq.len() != query_length {
+        return Err(CigarError::LengthMismatch(query_length, seq.len()));
+    }
+
+    let (first, last) = aligned_span(elements);
+    let leading_length = soft_clipped(&elements[..first]);
+    let trailing_length = soft_clipped(&elements[last..]);
+
+    let mut builder = CigarBuilder::new();
+    for (i, elem) in elements.iter().enumerate() {
+        let terminal = i < first || i >= last;
+        if terminal && elem.op == CigarOp::SoftClip {
+            builder.push(CigarOp::HardClip, elem.length)?;
+        } else {
+            builder.push_element(elem.clone())?;
+        }
+    }
+
+    Ok(HardClipped {
+        cigar: builder.build(),
+        sequence: seq[leading_length..seq.len() - trailing_length].to_vec(),
+        leading: seq[..leading_length].to_vec(),
+        trailing: seq[seq.len() - trailing_length..].to_vec(),
+    })
+}
+
+/// Convert hard clips to soft clips, given the full (unclipped) read sequence.
+///
+/// The sequence is used to check that the resulting CIGAR is consistent with the read.
+pub fn hard_to_soft_clips<S: AsRef<[u8]> + ?Sized>(
+    cigar: &Cigar,
+    full_seq: &S,
+) -> std::result::Result<Cigar, CigarError> {
+    let full_seq = full_seq.as_ref();
+    let elements = cigar.elements();
+    let hard_clipped: usize = elements
+        .iter()
+        .filter(|e| e.op == CigarOp::HardClip)
+        .map(|e| e.length as usize)
+        .sum();
+    let expected = query_length(elements) + hard_clipped;
+    if full_seq.len() != expected {
+        return Err(CigarError::LengthMismatch(expected, full_seq.len()));
+    }
+
+    let mut builder = CigarBuilder::new();
+    for elem in elements {
+        match elem.op {
+            CigarOp::HardClip => builder.push(CigarOp::SoftClip, elem.length)?,
+            _ => builder.push_element(elem.clone())?,
+        };
+    }
+    Ok(builder.build())
+}
+
+/// The soft clipped bases at one end of a read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoftClip<'a> {
+    /// The clipped bases.
+    pub sequence: &'a [u8],
+    /// The base qualities of the clipped bases, if the read has them.
+    pub qualities: Option<&'a [u8]>,
+    /// The reference position of the clip boundary: the alignment start for a leading
+    /// clip, and the position following the alignment end for a trailing clip.
+    pub boundary: u64,
+}
+
+/// Check the sequence and qualities against the CIGAR, and find the soft clipped
+/// lengths at each end.
+fn clip_lengths(
+    elements: &[CigarElement],
+    seq: &[u8],
+    qual: Option<&[u8]>,
+) -> std::result::Result<(usize, usize), CigarError> {
+    let query_length = query_length(elements);
+    if seq.len() != query_length {
+        return Err(CigarError::LengthMismatch(query_length, seq.len()));
+    }
+    if let Some(qual) = qual
+        && qual.len() != query_length
+    {
+        return Err(CigarError::LengthMismatch(query_length, qual.len()));
+    }
+    let (first, last) = aligned_span(elements);
+    Ok((
+        soft_clipped(&elements[..first]),
+        soft_clipped(&elements[last..]),
+    ))
+}
+
+/// The soft clipped bases at the start of a read aligned at `reference_position`, if any.
+///
+/// # Example
+///
+/// ```rust
+/// use cigar_utils::Cigar;
+/// use cigar_utils::clip::{leading_soft_clip, trailing_soft_clip};
+///
+/// let cigar: Cigar = "1H2S4M1D2M3S".parse().unwrap();
+/// let (seq, qual) = (b"TTACGTACGGG", b"ABCDEFGHIJK");
+/// let leading = leading_soft_clip(&cigar, 100, seq, Some(qual)).unwrap().unwrap();
+/// assert_eq!((leading.sequence, leading.qualities), (&b"TT"[..], Some(&b"AB"[..])));
+/// assert_eq!(leading.boundary, 100);
+/// let trailing = trailing_soft_clip(&cigar, 100, seq, None).unwrap().unwrap();
+/// assert_eq!((trailing.sequence, trailing.boundary), (&b"GGG"[..], 107));
+/// ```
+pub fn leading_soft_clip<'a>(
+    cigar: &Cigar,
+    reference_position: u64,
+    seq: &'a [u8],
+    qual: Option<&'a [u8]>,
+) -> std::result::Result<Option<SoftClip<'a>>, CigarError> {
+    let (leading, _) = clip_lengths(cigar.elements(), seq, qual)?;
+    Ok((leading > 0).then(|| SoftClip {
+        sequence: &seq[..leading],
+        qualities: qual.map(|q| &q[..leading]),
+        boundary: reference_position,
+    }))
+}
+
+/// The soft clipped bases at the end of a read aligned at `reference_position`, if any.
+pub fn trailing_soft_clip<'a>(
+    cigar: &Cigar,
+    reference_position: u64,
+    seq: &'a [u8],
+    qual: Option<&'a [u8]>,
+) -> std::result::Result<Option<SoftClip<'a>>, CigarError> {
+    let (_, trailing) = clip_lengths(cigar.elements(), seq, qual)?;
+    let reference_length: u64 = cigar
+        .iter()
+        .filter(|e| e.op.consumes_reference())
+        .map(|e| e.length as u64)
+        .sum();
+    let start = seq.len() - trailing;
+    Ok((trailing > 0).then(|| SoftClip {
+        sequence: &seq[start..],
+        qualities: qual.map(|q| &q[start..]),
+        boundary: reference_position + reference_length,
+    }))
+}
+
+fn query_length(elements: &[CigarElement]) -> usize {
+    elements
+        .iter()
+        .filter(|e| e.op.consumes_query())
+        .map(|e| e.length as usize)
+        .sum()
+}
+
+fn is_clip(op: CigarOp) -> bool {
+    matches!(op, CigarOp::SoftClip | CigarOp::HardClip)
+}
+
+/// The index of the first non-clip element and one past the last non-clip element.
+fn aligned_span(elements: &[CigarElement]) -> (usize, usize) {
+    match elements.iter().position(|e| !is_clip(e.op)) {
+        Some(first) => {
+            let last = elements.iter().rposition(|e| !is_clip(e.op)).unwrap() + 1;
+            (first, last)
+        }
+        None => (elements.len(), elements.len()),
+    }
+}
+
+fn soft_clipped(elements: &[CigarElement]) -> usize {
+    elements
+        .iter()
+        .filter(|e| e.op == CigarOp::SoftClip)
+        .map(|e| e.length as usize)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_soft_to_hard_merges_existing_hard_clips() {
+        let cigar: Cigar = "3H2S4M1I2M".parse().unwrap();
+        let clipped = soft_to_hard_clips(&cigar, b"GGACGTAAC").unwrap();
+        assert_eq!(clipped.cigar.to_string(), "5H4M1I2M");
+        assert_eq!(clipped.sequence, b"ACGTAAC");
+        assert_eq!(clipped.leading, b"GG");
+        assert!(clipped.trailing.is_empty());
+    }
+
+    #[test]
+    fn test_soft_to_hard_length_mismatch() {
+        let cigar: Cigar = "2S4M".parse().unwrap();
+        assert!(matches!(
+            soft_to_hard_clips(&cigar, b"ACGT"),
+            Err(CigarError::LengthMismatch(6, 4))
+        ));
+    }
+
+    #[test]
+    fn test_hard_to_soft() {
+        let cigar: Cigar = "3H4M2S1H".parse().unwrap();
+        let restored = hard_to_soft_clips(&cigar, b"GGGACGTAAC").unwrap();
+        assert_eq!(restored.to_string(), "3S4M3S");
+    }
+
+    #[test]
+    fn test_hard_to_soft_length_mismatch() {
+        let cigar: Cigar = "3H4M".parse().unwrap();
+        assert!(matches!(
+            hard_to_soft_clips(&cigar, b"ACGT"),
+            Err(CigarError::LengthMismatch(7, 4))
+        ));
+    }
+
+    #[test]
+    fn test_soft_clip_extraction() {
+        let cigar: Cigar = "5M2H".parse().unwrap();
+        assert_eq!(leading_soft_clip(&cigar, 0, b"ACGTA", None).unwrap(), None);
+        assert_eq!(trailing_soft_clip(&cigar, 0, b"ACGTA", None).unwrap(), None);
+        assert!(matches!(
+            leading_soft_clip(&cigar, 0, b"ACGTA", Some(b"ABC")),
+            Err(CigarError::LengthMismatch(5, 3))
+        ));
+        let cigar: Cigar = "3S".parse().unwrap();
+        let clip = leading_soft_clip(&cigar, 9, b"ACG", None).unwrap().unwrap();
+        assert_eq!((clip.sequence, clip.boundary), (&b"ACG"[..], 9));
+    }
+}
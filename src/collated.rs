@@ -27,97 +27,1366 @@
 //! ```
 //!
 //! This will print each collated event in order of reference position, with the count of how many times each event occurs at that position.
+//!
+//! Sources which already hold parsed CIGARs (for example, decoded from BAM) can yield
+//! `(Vec<CigarElement>, u32, u64)` or `(&[CigarElement], u32, u64)` records instead,
+//! avoiding re-parsing a CIGAR string for every record.
 
-use std::{cmp::Reverse, collections::BinaryHeap, iter::Peekable};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, VecDeque},
+    iter::{FusedIterator, Peekable},
+    path::PathBuf,
+};
 
-use crate::augmented_cigar::{AugmentedCigarElement, AugmentedCigarIterator};
+use crate::augmented_cigar::{AugmentedCigarElement, AugmentedCigarIterator, EventFilter};
+use crate::chrom_map::ChromMap;
 use crate::error::CigarError;
+use crate::queue::EventQueue;
+use crate::record::{CigarRecord, CigarRef, RecordFilter, record_label, region_order};
+use crate::sequenced::SequencedCollatedIterator;
+use crate::spill::SpillRun;
+use crate::summary::PositionSummaryIterator;
+use crate::validate::check_alignment_end;
+use crate::variants::ReferenceSequences;
+use crate::{CigarElement, CigarIterator, CigarOp};
 
 /// A collated iterator over augmented CIGAR elements.
+///
+/// The source yields records implementing [`CigarRecord`], such as `(String, u32, u64)`
+/// tuples of CIGAR string, chromosome ID and reference position.
+///
+/// Elements with the same chromosome, reference position, operation and length are
+/// counted as one event, except that clips at the start of reads are kept apart from
+/// clips at their end.
 pub struct CollatedAugmentedCigarIterator<
-    Source: Iterator<Item = std::result::Result<(String, u32, u32), E>>,
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
     E: std::error::Error + Send + Sync + 'static,
 > {
     source: Peekable<Source>,
-    queue: BinaryHeap<Reverse<AugmentedCigarElement>>,
+    queue: EventQueue,
+    check_sorted: bool,
+    slack: u64,
+    furthest: Option<(u32, u64)>,
+    region: Option<(u32, u64, u64)>,
+    depth_cap: Option<DepthCap>,
+    pending: Vec<AugmentedCigarElement>,
+    spill: Option<(usize, PathBuf)>,
+    spilled: Vec<SpillRun>,
+    cigar_cache: Option<CigarCache>,
+    mates: Option<MateOverlaps>,
+    record_filter: Option<RecordFilter>,
+    event_filter: EventFilter,
+    quality_weights: bool,
+    inserted_bases: Option<InsertedBases>,
+    consumed: u64,
+    emitted: u64,
+    progress: Option<ProgressHook>,
+    reference_lengths: Option<ChromMap>,
 }
 
 impl<
-    Source: Iterator<Item = std::result::Result<(String, u32, u32), E>>,
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
     E: std::error::Error + Send + Sync + 'static,
-> CollatedAugmentedCigarIterator<Source, E>
+> CollatedAugmentedCigarIterator<Source, R, E>
 {
     /// Create a new collated augmented CIGAR iterator.
     pub fn new(source: Source) -> Self {
         let source = source.peekable();
-        let queue = BinaryHeap::new();
-        CollatedAugmentedCigarIterator { source, queue }
+        let queue = EventQueue::new();
+        CollatedAugmentedCigarIterator {
+            source,
+            queue,
+            check_sorted: false,
+            slack: 0,
+            furthest: None,
+            region: None,
+            depth_cap: None,
+            pending: Vec::new(),
+            spill: None,
+            spilled: Vec::new(),
+            cigar_cache: None,
+            mates: None,
+            record_filter: None,
+            event_filter: EventFilter::all(),
+            quality_weights: false,
+            inserted_bases: None,
+            consumed: 0,
+            emitted: 0,
+            progress: None,
+            reference_lengths: None,
+        }
+    }
+
+    /// Check that the source is sorted, returning [`CigarError::UnsortedInput`] if it is not.
+    ///
+    /// Without checking, unsorted input silently produces incorrect collation.
+    pub fn checked(mut self) -> Self {
+        self.check_sorted = true;
+        self
+    }
+
+    /// Allow records to arrive up to `slack` positions out of order, buffering them until
+    /// they can be collated correctly. This implies [`checked`](Self::checked): records
+    /// further out of order than the slack allows produce [`CigarError::UnsortedInput`].
+    pub fn with_slack(mut self, slack: u64) -> Self {
+        self.check_sorted = true;
+        self.slack = slack;
+        self
+    }
+
+    /// Only collate events within the half open interval `start..end` of chromosome
+    /// `chrom_id`.
+    ///
+    /// Records on earlier chromosomes are skipped without being parsed, and reading stops
+    /// once the source has moved past the region, so the source must be sorted. Elements
+    /// which straddle the region boundaries are clipped to the region.
+    pub fn restrict(mut self, chrom_id: u32, start: u64, end: u64) -> Self {
+        self.region = Some((chrom_id, start, end));
+        self
+    }
+
+    /// Check that each record ends within its chromosome, using the lengths in
+    /// `chroms`, returning [`CigarError::BeyondChromosomeEnd`] for any which runs off
+    /// the end, or [`CigarError::UnknownChromosome`] for any on a chromosome whose length
+    /// it does not have. Collation carries on after the failing record, which contributes
+    /// no events.
+    pub fn check_reference_lengths(mut self, chroms: &ChromMap) -> Self {
+        self.reference_lengths = Some(chroms.clone());
+        self
+    }
+
+    /// Cap the number of records covering any position at `max_depth`, in the manner of
+    /// `samtools mpileup -d`.
+    ///
+    /// Records are taken in the order they arrive, and any record starting where the
+    /// depth is already at the cap is dropped, so memory use is bounded for very deep data.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.depth_cap = Some(DepthCap::new(max_depth, None));
+        self
+    }
+
+    /// Cap the number of records covering any position at `max_depth`, dropping records at
+    /// random rather than in arrival order.
+    ///
+    /// Each record is kept with a probability that falls as the depth at its start
+    /// approaches the cap, so the kept records are spread through the input rather than
+    /// being the first to arrive. The same `seed` always drops the same records.
+    pub fn max_depth_seeded(mut self, max_depth: usize, seed: u64) -> Self {
+        self.depth_cap = Some(DepthCap::new(max_depth, Some(seed)));
+        self
+    }
+
+    /// Bound the number of events held in memory, spilling the furthest events to sorted
+    /// runs in temporary files once more than `max_events` are queued.
+    ///
+    /// This keeps memory bounded when reads spanning long skips (such as megabase introns)
+    /// leave many events queued far ahead of the collation front. The files are created
+    /// in the system temporary directory and removed when no longer needed.
+    pub fn spill_to_disk(self, max_events: usize) -> Self {
+        self.spill_to_disk_in(max_events, std::env::temp_dir())
+    }
+
+    /// Bound the number of events held in memory as for
+    /// [`spill_to_disk`](Self::spill_to_disk), creating the files in `dir`.
+    pub fn spill_to_disk_in<P: Into<PathBuf>>(mut self, max_events: usize, dir: P) -> Self {
+        self.spill = Some((max_events.max(1), dir.into()));
+        self
+    }
+
+    /// Keep the parsed elements of up to `capacity` distinct CIGAR strings, reusing them
+    /// for records with the same CIGAR rather than parsing it again.
+    ///
+    /// This pays off where the same CIGARs recur many times, as in amplicon data. Once
+    /// the cache is full, CIGARs which have not been reused since they were last passed
+    /// over are dropped to make room. Only records with CIGAR text are cached, and CIGARs
+    /// which fail to parse are not. A capacity of zero turns the cache off.
+    pub fn cache_cigars(mut self, capacity: usize) -> Self {
+        self.cigar_cache = (capacity > 0).then(|| CigarCache::new(capacity));
+        self
+    }
+
+    /// Count the bases where the two mates of a read pair overlap only once, so that both
+    /// reads of one DNA fragment do not count as independent evidence.
+    ///
+    /// Primary records with the same [read name](CigarRecord::read_name) are taken to be
+    /// mates; secondary and supplementary records (flags `0x100` and `0x800`) are never
+    /// paired, and records which do not report their flags are taken to be primary. Where the later mate overlaps the reference span of the earlier one, its elements
+    /// are clipped to begin where the earlier mate ends, as by `bam clipOverlap`; elements
+    /// beyond the overlap are unaffected. Names are only held until the collation passes
+    /// the end of the earlier mate, and records without names are collated as usual.
+    pub fn merge_mates(mut self) -> Self {
+        self.mates = Some(MateOverlaps::default());
+        self
+    }
+
+    /// Skip records which fail `filter`, as a [`SamSource`](crate::sam::SamSource) does,
+    /// for sources which do not filter their records themselves.
+    ///
+    /// Records which do not report their flags (see [`CigarRecord::flags`]) are not
+    /// excluded by them. A region in the filter restricts the collation to it, as
+    /// [`restrict`](Self::restrict) does.
+    pub fn record_filter(mut self, filter: RecordFilter) -> Self {
+        if let Some(region) = filter.region {
+            self.region = Some(region);
+        }
+        self.record_filter = Some(filter);
+        self
+    }
+
+    /// Only collate events whose operations pass `filter`, dropping the rest before they
+    /// are queued.
+    ///
+    /// Depth caps and mate overlaps still take account of the whole record, but
+    /// [summaries](Self::summarized) only see the events passed, so their depths are
+    /// only complete if matches and deletions are kept.
+    pub fn event_filter(mut self, filter: EventFilter) -> Self {
+        self.event_filter = filter;
+        self
+    }
+
+    /// Weight each occurrence of an event by the probability that its record is correctly
+    /// mapped, `1 - 10^(-MAPQ/10)`, so that low-MAPQ evidence counts for less. Records
+    /// with no mapping quality have a weight of 1.
+    pub fn mapq_weighted(self) -> WeightedCollatedIterator<Source, R, E> {
+        WeightedCollatedIterator {
+            inner: self,
+            by_mapq: true,
+            by_quality: false,
+        }
     }
+
+    /// Weight each occurrence of an event by the probability that its bases were called
+    /// correctly, `1 - 10^(-Q/10)`, using the base qualities of the records (see
+    /// [`CigarRecord::qualities`]).
+    ///
+    /// Q is the mean quality of the bases of the event; deletions and skips take the
+    /// quality of the bases either side of them. Events without base qualities have a
+    /// weight of 1.
+    pub fn quality_weighted(mut self) -> WeightedCollatedIterator<Source, R, E> {
+        self.quality_weights = true;
+        WeightedCollatedIterator {
+            inner: self,
+            by_mapq: false,
+            by_quality: true,
+        }
+    }
+
+    /// Break the count of each event down by sample, using the sample IDs of the records
+    /// (see [`CigarRecord::sample`]).
+    ///
+    /// The counts are indexed by sample ID, and have at least `samples` entries.
+    pub fn per_sample(self, samples: usize) -> PerSampleCollatedIterator<Source, R, E> {
+        PerSampleCollatedIterator {
+            inner: self,
+            samples,
+        }
+    }
+
+    /// Count events with different bases apart, so that two different 2bp insertions at a
+    /// position are two events. See [`SequencedCollatedIterator`].
+    ///
+    /// Inserted bases come from the [sequences](CigarRecord::sequence) of the records,
+    /// and deleted bases from `reference`.
+    pub fn sequenced<'a, Ref: ReferenceSequences + ?Sized>(
+        mut self,
+        reference: &'a Ref,
+    ) -> SequencedCollatedIterator<'a, Source, R, E, Ref> {
+        self.inserted_bases = Some(InsertedBases::default());
+        SequencedCollatedIterator::new(self, reference)
+    }
+
+    /// Report the progress of the collation to `observer` after every `interval` records
+    /// consumed from the source, and once more when the collation is complete.
+    ///
+    /// This lets long collations drive progress bars, and shows the number of queued
+    /// events growing if the input is not sorted or has reads with very long skips.
+    pub fn on_progress<F>(mut self, interval: u64, observer: F) -> Self
+    where
+        F: FnMut(&CollationProgress) + Send + 'static,
+    {
+        self.progress = Some(ProgressHook {
+            interval: interval.max(1),
+            observer: Box::new(observer),
+            finished: false,
+        });
+        self
+    }
+
+    /// Summarize the collated events position by position, with the depth of coverage at
+    /// each position. See [`PositionSummary`](crate::summary::PositionSummary).
+    pub fn summarized(self) -> PositionSummaryIterator<Self> {
+        PositionSummaryIterator::new(self)
+    }
+
+    /// Take a snapshot of the state of the collation, from which it can be
+    /// [resumed](Self::resume) after an interruption.
+    ///
+    /// The snapshot holds the events read but not yet collated, so any spilled to disk
+    /// are read back into memory first. With the `serde` feature the snapshot can be
+    /// serialized, and so written out periodically during a long collation.
+    pub fn checkpoint(&mut self) -> std::result::Result<CollationCheckpoint, CigarError> {
+        for run in self.spilled.iter_mut() {
+            while let Some(event) = run.take()? {
+                self.queue.push(event);
+            }
+        }
+        self.spilled.clear();
+        let mut queued = self.queue.drain();
+        queued.sort();
+        self.queue.extend(queued.iter().cloned());
+        let (depth_ends, rng) = match &self.depth_cap {
+            Some(cap) => (cap.ends.iter().map(|Reverse(e)| *e).collect(), cap.rng),
+            None => (Vec::new(), None),
+        };
+        let mates = match &self.mates {
+            Some(mates) => mates
+                .open
+                .iter()
+                .map(|(name, &(chrom_id, end))| (name.clone(), chrom_id, end))
+                .collect(),
+            None => Vec::new(),
+        };
+        Ok(CollationCheckpoint {
+            records_consumed: self.consumed,
+            furthest: self.furthest,
+            queued,
+            depth_ends,
+            rng,
+            mates,
+        })
+    }
+
+    /// Resume a collation from a checkpoint.
+    ///
+    /// The iterator must be configured as the one checkpointed was, and its source must
+    /// continue just after the last record the checkpointed collation consumed: a source
+    /// read again from the start should skip
+    /// [`records_consumed`](CollationCheckpoint::records_consumed) records. The events
+    /// then follow on from the last event yielded before the checkpoint.
+    pub fn resume(mut self, checkpoint: CollationCheckpoint) -> Self {
+        self.consumed = checkpoint.records_consumed;
+        self.furthest = checkpoint.furthest;
+        self.queue.extend(checkpoint.queued);
+        if let Some(cap) = self.depth_cap.as_mut() {
+            cap.ends = checkpoint.depth_ends.into_iter().map(Reverse).collect();
+            if cap.rng.is_some() {
+                cap.rng = checkpoint.rng;
+            }
+        }
+        if let Some(mates) = self.mates.as_mut() {
+            for (name, chrom_id, end) in checkpoint.mates {
+                mates.open.insert(name.clone(), (chrom_id, end));
+                mates.expiry.push(Reverse((chrom_id, end, name)));
+            }
+        }
+        self
+    }
+}
+
+/// A snapshot of the state of a collation, taken by
+/// [`checkpoint`](CollatedAugmentedCigarIterator::checkpoint).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CollationCheckpoint {
+    records_consumed: u64,
+    furthest: Option<(u32, u64)>,
+    queued: Vec<(AugmentedCigarElement, usize)>,
+    depth_ends: Vec<(u32, u64)>,
+    rng: Option<u64>,
+    mates: Vec<(String, u32, u64)>,
+}
+
+impl CollationCheckpoint {
+    /// The number of records consumed from the source, including those skipped.
+    pub fn records_consumed(&self) -> u64 {
+        self.records_consumed
+    }
+
+    /// The chromosome ID and position of the furthest record consumed, if any.
+    pub fn last_position(&self) -> Option<(u32, u64)> {
+        self.furthest
+    }
+
+    /// The number of events read but not yet collated.
+    pub fn pending_events(&self) -> usize {
+        self.queued.len()
+    }
+}
+
+/// The progress of a collation, reported to the observer given to
+/// [`on_progress`](CollatedAugmentedCigarIterator::on_progress).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CollationProgress {
+    /// The number of records consumed from the source, including those skipped.
+    pub records_consumed: u64,
+    /// The number of collated events yielded.
+    pub events_emitted: u64,
+    /// The chromosome ID and position of the furthest record consumed, if any.
+    pub position: Option<(u32, u64)>,
+    /// The number of events read but not yet collated, including any spilled to disk.
+    pub queued_events: usize,
+    /// Whether the collation is complete.
+    pub finished: bool,
+}
+
+/// An observer of the progress of a collation.
+struct ProgressHook {
+    interval: u64,
+    observer: Box<dyn FnMut(&CollationProgress) + Send>,
+    finished: bool,
+}
+
+/// The state of a depth cap: the ends of the records currently covering the collation front.
+struct DepthCap {
+    max_depth: usize,
+    rng: Option<u64>,
+    ends: BinaryHeap<Reverse<(u32, u64)>>,
+}
+
+impl DepthCap {
+    fn new(max_depth: usize, seed: Option<u64>) -> Self {
+        DepthCap {
+            max_depth,
+            rng: seed,
+            ends: BinaryHeap::new(),
+        }
+    }
+
+    /// Decide whether to keep a record starting at `start` whose alignment ends at `end`.
+    fn admit(&mut self, start: (u32, u64), end: u64) -> bool {
+        while let Some(Reverse(e)) = self.ends.peek() {
+            if *e <= start {
+                self.ends.pop();
+            } else {
+                break;
+            }
+        }
+        let depth = self.ends.len();
+        if depth >= self.max_depth {
+            return false;
+        }
+        if let Some(state) = self.rng.as_mut() {
+            let r = splitmix64(state) % self.max_depth as u64;
+            if r < depth as u64 {
+                return false;
+            }
+        }
+        self.ends.push(Reverse((start.0, end.max(start.1 + 1))));
+        true
+    }
+}
+
+/// A cache of parsed CIGARs, keyed by their text.
+///
+/// CIGARs are evicted in the order they were added, except that one which has been used
+/// since it was last considered gets a second chance and goes to the back of the queue.
+struct CigarCache {
+    capacity: usize,
+    entries: HashMap<String, (Vec<CigarElement>, bool)>,
+    order: VecDeque<String>,
+}
+
+impl CigarCache {
+    fn new(capacity: usize) -> Self {
+        CigarCache {
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// The parsed elements of `cigar`, parsing and caching them if need be, or `None` if
+    /// the CIGAR does not parse.
+    fn elements(&mut self, cigar: &str) -> Option<&[CigarElement]> {
+        if !self.entries.contains_key(cigar) {
+            let elements = CigarIterator::new(cigar)
+                .collect::<Result<Vec<_>, _>>()
+                .ok()?;
+            while self.entries.len() >= self.capacity {
+                let Some(oldest) = self.order.pop_front() else {
+                    break;
+                };
+                match self.entries.get_mut(&oldest) {
+                    Some((_, used)) if *used => {
+                        *used = false;
+                        self.order.push_back(oldest);
+                    }
+                    _ => {
+                        self.entries.remove(&oldest);
+                    }
+                }
+            }
+            self.order.push_back(cigar.to_string());
+            self.entries.insert(cigar.to_string(), (elements, false));
+            return self
+                .entries
+                .get(cigar)
+                .map(|(elements, _)| elements.as_slice());
+        }
+        self.entries.get_mut(cigar).map(|(elements, used)| {
+            *used = true;
+            elements.as_slice()
+        })
+    }
+}
+
+/// The SAM flags of secondary and supplementary records, which are not paired as mates.
+const NON_PRIMARY_FLAGS: u16 = 0x100 | 0x800;
+
+/// The reference spans of reads whose mates may yet overlap them, keyed by read name.
+#[derive(Default)]
+struct MateOverlaps {
+    open: HashMap<String, (u32, u64)>,
+    expiry: BinaryHeap<Reverse<(u32, u64, String)>>,
+}
+
+impl MateOverlaps {
+    /// Take the reference position where the elements of the read `name` starting at
+    /// `start` and ending at `end` must begin to avoid overlapping its mate, if they do.
+    fn overlap_end(&mut self, name: &str, start: (u32, u64), end: u64) -> Option<u64> {
+        while let Some(Reverse((chrom_id, mate_end, _))) = self.expiry.peek() {
+            if (*chrom_id, *mate_end) > start {
+                break;
+            }
+            if let Some(Reverse((_, _, expired))) = self.expiry.pop()
+                && self.open.get(&expired).is_some_and(|&span| span <= start)
+            {
+                self.open.remove(&expired);
+            }
+        }
+        match self.open.remove(name) {
+            Some((chrom_id, mate_end)) if chrom_id == start.0 && mate_end > start.1 => {
+                Some(mate_end)
+            }
+            Some(_) => None,
+            None => {
+                if end > start.1 {
+                    self.open.insert(name.to_string(), (start.0, end));
+                    self.expiry.push(Reverse((start.0, end, name.to_string())));
+                }
+                None
+            }
+        }
+    }
+}
+
+/// The inserted bases of queued insertions, held while sequenced collation is on.
+///
+/// The bases of an insertion are put in a slot, and the slot is queued as the tag of the
+/// insertion in place of its [packed tag](pack_tag). A record without bases fills its
+/// slots with `None`.
+#[derive(Default)]
+struct InsertedBases {
+    slots: Vec<Option<Vec<u8>>>,
+    free: Vec<usize>,
+}
+
+impl InsertedBases {
+    /// Hold the bases of an insertion, returning its slot.
+    fn insert(&mut self, bases: Option<Vec<u8>>) -> usize {
+        match self.free.pop() {
+            Some(slot) => {
+                self.slots[slot] = bases;
+                slot
+            }
+            None => {
+                self.slots.push(bases);
+                self.slots.len() - 1
+            }
+        }
+    }
+
+    /// Take the bases held in a slot, freeing it.
+    fn take(&mut self, slot: usize) -> Option<Vec<u8>> {
+        self.free.push(slot);
+        self.slots[slot].take()
+    }
+}
+
+/// Pack the sample ID and mapping quality of a record, and the base quality of an event,
+/// into the tag queued with the event, with 255 standing for an unknown quality as in SAM.
+fn pack_tag(sample: usize, mapq: Option<u8>, quality: Option<u8>) -> usize {
+    (sample << 16) | (quality.unwrap_or(255) as usize) << 8 | mapq.unwrap_or(255) as usize
+}
+
+/// The sample ID of a queued tag.
+fn tag_sample(tag: usize) -> usize {
+    tag >> 16
+}
+
+/// The probability that a Phred quality is correct, or 1 if the quality is unknown.
+fn phred_weight(quality: u8) -> f64 {
+    match quality {
+        255 => 1.0,
+        q => 1.0 - 10f64.powf(-(q as f64) / 10.0),
+    }
+}
+
+/// The mean base quality of an event, from the qualities of the bases in SEQ.
+fn event_quality(elem: &AugmentedCigarElement, qualities: &[u8]) -> Option<u8> {
+    let start = elem.read_position as usize;
+    let bases = match elem.op {
+        CigarOp::Deletion | CigarOp::Skip => qualities.get(start.checked_sub(1)?..=start)?,
+        op if op.consumes_query() => qualities.get(start..start + elem.length as usize)?,
+        _ => return None,
+    };
+    if bases.is_empty() {
+        return None;
+    }
+    let mean = bases.iter().map(|&q| q as usize).sum::<usize>() as f64 / bases.len() as f64;
+    Some(mean.round().min(254.0) as u8)
+}
+
+/// A small, fast pseudo-random generator, so that seeded downsampling is reproducible.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
 }
 
 impl<
-    Source: Iterator<Item = std::result::Result<(String, u32, u32), E>>,
+    S: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
     E: std::error::Error + Send + Sync + 'static,
-> Iterator for CollatedAugmentedCigarIterator<Source, E>
+> CollatedAugmentedCigarIterator<MergedSources<S>, R, E>
+{
+    /// Create a new collated augmented CIGAR iterator over several independently sorted
+    /// sources (e.g. one per sample or BAM shard), merging them as they are read.
+    pub fn merged<I: IntoIterator<Item = S>>(sources: I) -> Self {
+        CollatedAugmentedCigarIterator::new(MergedSources::new(sources))
+    }
+}
+
+/// A k-way merge of several sorted sources of records into a single sorted source.
+///
+/// Each step scans the head of every source, so this is intended for modest numbers of
+/// sources. Ties are broken in favour of the earlier source, and errors are passed
+/// through as soon as they are seen.
+pub struct MergedSources<S: Iterator> {
+    sources: Vec<Peekable<S>>,
+}
+
+impl<S: Iterator> MergedSources<S> {
+    /// Create a new merge of the given sources.
+    pub fn new<I: IntoIterator<Item = S>>(sources: I) -> Self {
+        MergedSources {
+            sources: sources.into_iter().map(|s| s.peekable()).collect(),
+        }
+    }
+}
+
+impl<S, R, E> Iterator for MergedSources<S>
+where
+    S: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
 {
-    type Item = std::result::Result<(AugmentedCigarElement, usize), CigarError>;
+    type Item = std::result::Result<R, E>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        let mut best: Option<(usize, (u32, u64))> = None;
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            match source.peek() {
+                None => {}
+                Some(Err(_)) => return source.next(),
+                Some(Ok(record)) => {
+                    let key = (record.chrom_id(), record.reference_position());
+                    if best.is_none_or(|(_, best_key)| key < best_key) {
+                        best = Some((i, key));
+                    }
+                }
+            }
+        }
+        let (i, _) = best?;
+        self.sources[i].next()
+    }
+}
+
+/// Whether two elements are occurrences of the same event, and so collate together.
+pub(crate) fn same_event(a: &AugmentedCigarElement, b: &AugmentedCigarElement) -> bool {
+    a.chrom_id == b.chrom_id
+        && a.reference_position == b.reference_position
+        && a.op == b.op
+        && a.length == b.length
+        && a.is_leading_clip() == b.is_leading_clip()
+}
+
+/// Merge several collated streams, each sorted as the collator emits events (for
+/// example, collated separately per shard and read back), into a single collated
+/// stream, summing the counts of the same event across streams.
+///
+/// This is the reduce step of distributed collation: merging the collated streams of
+/// the shards of a source gives the same events and counts as collating the whole
+/// source, provided no record is in more than one shard.
+///
+/// ```rust
+/// use cigar_utils::collated::{CollatedAugmentedCigarIterator, merge_collated};
+///
+/// let shard = |records: Vec<(&str, u64)>| {
+///     let records: Vec<_> = records
+///         .into_iter()
+///         .map(|(cigar, position)| std::io::Result::Ok((cigar.to_string(), 0, position)))
+///         .collect();
+///     CollatedAugmentedCigarIterator::new(records.into_iter())
+/// };
+/// let merged: Vec<_> = merge_collated([shard(vec![("2M1D2M", 10)]), shard(vec![("3M1D1M", 9)])])
+///     .collect::<Result<_, _>>()
+///     .unwrap();
+/// let deletion = merged.iter().find(|(elem, _)| elem.length == 1).unwrap();
+/// assert_eq!((deletion.0.reference_position, deletion.1), (12, 2));
+/// ```
+pub fn merge_collated<I>(streams: I) -> MergedCollated<<I::Item as IntoIterator>::IntoIter>
+where
+    I: IntoIterator,
+    I::Item: IntoIterator<Item = std::result::Result<CollatedEvent, CigarError>>,
+{
+    MergedCollated {
+        streams: streams
+            .into_iter()
+            .map(|s| s.into_iter().peekable())
+            .collect(),
+        last: Vec::new(),
+    }
+}
+
+/// A merge of collated streams, created by [`merge_collated`].
+///
+/// Each step scans the head of every stream, so this is intended for modest numbers of
+/// streams. Errors are passed through as soon as they are seen, and an event out of
+/// order in its stream gives [`CigarError::UnsortedInput`].
+pub struct MergedCollated<S: Iterator> {
+    streams: Vec<Peekable<S>>,
+    last: Vec<Option<(u32, u64)>>,
+}
+
+impl<S> MergedCollated<S>
+where
+    S: Iterator<Item = std::result::Result<CollatedEvent, CigarError>>,
+{
+    /// Take the head of stream `i`, checking that it follows the last event taken.
+    fn take(&mut self, i: usize) -> std::result::Result<CollatedEvent, CigarError> {
+        let (elem, count) = self.streams[i].next().expect("stream has a head")?;
+        let current = (elem.chrom_id, elem.reference_position);
+        self.last.resize(self.streams.len(), None);
+        if let Some(previous) = self.last[i]
+            && current < previous
+        {
+            return Err(CigarError::UnsortedInput { previous, current });
+        }
+        self.last[i] = Some(current);
+        Ok((elem, count))
+    }
+}
+
+impl<S> Iterator for MergedCollated<S>
+where
+    S: Iterator<Item = std::result::Result<CollatedEvent, CigarError>>,
+{
+    type Item = std::result::Result<CollatedEvent, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut best: Option<(usize, &AugmentedCigarElement)> = None;
+        for (i, stream) in self.streams.iter_mut().enumerate() {
+            match stream.peek() {
+                Some(Err(_)) => return stream.next(),
+                Some(Ok((elem, _))) if best.is_none_or(|(_, best_elem)| elem < best_elem) => {
+                    best = Some((i, elem));
+                }
+                _ => {}
+            }
+        }
+        let (i, _) = best?;
+        let (elem, mut count) = match self.take(i) {
+            Ok(event) => event,
+            Err(e) => return Some(Err(e)),
+        };
+        for j in 0..self.streams.len() {
+            while let Some(Ok((next, _))) = self.streams[j].peek()
+                && same_event(next, &elem)
+            {
+                match self.take(j) {
+                    Ok((_, n)) => count += n,
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+        }
+        Some(Ok((elem, count)))
+    }
+}
+
+impl<
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+> CollatedAugmentedCigarIterator<Source, R, E>
+{
+    /// Take the next record from the source, counting it as consumed.
+    fn advance(&mut self) -> Option<std::result::Result<R, E>> {
+        self.consumed += 1;
+        if self
+            .progress
+            .as_ref()
+            .is_some_and(|p| self.consumed.is_multiple_of(p.interval))
+        {
+            self.report(false);
+        }
+        self.source.next()
+    }
+
+    /// Report the progress of the collation to the observer, if there is one.
+    fn report(&mut self, finished: bool) {
+        let queued_events =
+            self.queue.len() + self.spilled.iter().map(|run| run.len()).sum::<usize>();
+        let progress = CollationProgress {
+            records_consumed: self.consumed,
+            events_emitted: self.emitted,
+            position: self.furthest,
+            queued_events,
+            finished,
+        };
+        if let Some(hook) = self.progress.as_mut() {
+            hook.finished |= finished;
+            (hook.observer)(&progress);
+        }
+    }
+
+    /// Read records from the source until the next event in the queue is complete, and
+    /// bring back any spilled events at the front of the collation.
+    pub(crate) fn fill(&mut self) -> Option<CigarError> {
         while let Some(item) = self.source.peek() {
             let item = match item {
                 Ok(ord) => ord,
                 Err(_) => {
-                    let e = self.source.next().unwrap().unwrap_err();
-                    return Some(Err(CigarError::External(Box::new(e))));
+                    if let Some(Err(e)) = self.advance() {
+                        return Some(CigarError::External(Box::new(e)));
+                    }
+                    unreachable!("peeked an error");
                 }
             };
-            let (cigar_str, chrom_id, reference_position) = item;
-            let mut augmented_iter =
-                AugmentedCigarIterator::from((cigar_str as &str, *chrom_id, *reference_position))
-                    .peekable();
-            if let Some(Ok(elem)) = augmented_iter.peek() {
-                if let Some(Reverse(existing)) = self.queue.peek() {
-                    if elem.chrom_id > existing.chrom_id
-                        || (elem.chrom_id == existing.chrom_id
-                            && elem.reference_position > existing.reference_position)
-                    {
-                        break;
+            if item.cigar().is_unavailable() {
+                // Records without a CIGAR (e.g. unmapped reads) contribute no events.
+                self.advance();
+                continue;
+            }
+            if self
+                .record_filter
+                .is_some_and(|filter| !filter.accepts(item))
+            {
+                self.advance();
+                continue;
+            }
+            if let Some(region) = self.region {
+                match region_order(region, item.chrom_id(), item.reference_position()) {
+                    Ordering::Less => {
+                        self.advance();
+                        continue;
                     }
+                    Ordering::Greater => break,
+                    Ordering::Equal => {}
                 }
             }
+            let (sample, mapq) = (item.sample(), item.mapq());
+            let qualities = if self.quality_weights {
+                item.qualities()
+            } else {
+                None
+            };
+            let cached = match (self.cigar_cache.as_mut(), item.cigar()) {
+                (Some(cache), CigarRef::Text(text)) => cache.elements(text),
+                _ => None,
+            };
+            let augmented = match cached {
+                Some(elements) => AugmentedCigarIterator::from((
+                    elements,
+                    item.chrom_id(),
+                    item.reference_position(),
+                )),
+                None => item.augmented(),
+            };
+            let mut augmented_iter = augmented.peekable();
+            if let Some(Ok(elem)) = augmented_iter.peek()
+                && let Some((existing, _)) = self.queue.peek()
+                && (elem.chrom_id > existing.chrom_id
+                    || (elem.chrom_id == existing.chrom_id
+                        && elem.reference_position > existing.reference_position + self.slack))
+            {
+                break;
+            }
+            let current = (item.chrom_id(), item.reference_position());
+            if let Some(previous) = self.furthest {
+                let out_of_order = current.0 < previous.0
+                    || (current.0 == previous.0 && current.1 + self.slack < previous.1);
+                if self.check_sorted && out_of_order {
+                    self.advance();
+                    return Some(CigarError::UnsortedInput { previous, current });
+                }
+            }
+            self.furthest = self.furthest.max(Some(current));
+            self.pending.clear();
             for elem in augmented_iter {
                 match elem {
-                    Ok(e) => self.queue.push(Reverse(e)),
-                    Err(e) => return Some(Err(e)),
+                    Ok(e) => self.pending.push(e),
+                    Err(e) => {
+                        let record = record_label(item);
+                        // Move past the record, so that collation can carry on after it.
+                        self.advance();
+                        return Some(e.with_record(&record));
+                    }
                 }
             }
-            self.source.next();
-        }
-        if let Some(Reverse(elem)) = self.queue.pop() {
-            let mut count = 1;
-            while let Some(Reverse(next)) = self.queue.peek() {
-                if next.chrom_id == elem.chrom_id
-                    && next.reference_position == elem.reference_position
-                    && next.op == elem.op
-                    && next.length == elem.length
+            if let Some(chroms) = &self.reference_lengths {
+                let end = self.pending.iter().map(|e| e.reference_end()).max();
+                let checked = match chroms.length(current.0) {
+                    Some(length) => {
+                        check_alignment_end(current.0, end.unwrap_or(current.1), length)
+                    }
+                    None => Err(CigarError::UnknownChromosome(current.0)),
+                };
+                if let Err(e) = checked {
+                    let record = record_label(item);
+                    self.advance();
+                    return Some(e.with_record(&record));
+                }
+            }
+            let read = item.sequence();
+            if self.inserted_bases.is_some()
+                && let Some(read) = read
+                && let Some(e) = self.pending.iter().find_map(|e| {
+                    let end = e.read_position as usize + e.length as usize;
+                    (e.op == CigarOp::Insertion && end > read.len())
+                        .then(|| CigarError::SequenceTooShort(end, read.len()))
+                })
+            {
+                let record = record_label(item);
+                self.advance();
+                return Some(e.with_record(&record));
+            }
+            let inserted_bases = &mut self.inserted_bases;
+            let mut tag = |e: &AugmentedCigarElement| match inserted_bases.as_mut() {
+                Some(bases) if e.op == CigarOp::Insertion => {
+                    let start = e.read_position as usize;
+                    bases.insert(read.map(|r| r[start..start + e.length as usize].to_vec()))
+                }
+                _ => {
+                    let quality = qualities.and_then(|q| event_quality(e, q));
+                    pack_tag(sample, mapq, quality)
+                }
+            };
+            if let Some(cap) = self.depth_cap.as_mut() {
+                let end = self
+                    .pending
+                    .iter()
+                    .map(|e| e.reference_end())
+                    .max()
+                    .unwrap_or(current.1);
+                if !cap.admit(current, end) {
+                    self.pending.clear();
+                }
+            }
+            if let Some(mates) = self.mates.as_mut()
+                && !self.pending.is_empty()
+                && item
+                    .flags()
+                    .is_none_or(|flags| flags & NON_PRIMARY_FLAGS == 0)
+                && let Some(name) = item.read_name()
+            {
+                let end = self.pending.iter().map(|e| e.reference_end()).max();
+                if let Some(overlap_end) =
+                    mates.overlap_end(name, current, end.unwrap_or(current.1))
                 {
-                    self.queue.pop();
-                    count += 1;
-                } else {
-                    break;
+                    self.pending = self
+                        .pending
+                        .iter()
+                        .filter_map(|e| e.clip_reference(overlap_end, u64::MAX))
+                        .collect();
+                }
+            }
+            let filter = self.event_filter;
+            for elem in self.pending.drain(..).filter(|e| filter.accepts(e.op)) {
+                match self.region {
+                    Some((_, start, end)) => {
+                        if let Some(e) = elem.clip_reference(start, end) {
+                            let tag = tag(&e);
+                            self.queue.push((e, tag));
+                        }
+                    }
+                    None => {
+                        let tag = tag(&elem);
+                        self.queue.push((elem, tag));
+                    }
+                }
+            }
+            self.advance();
+            if let Some((max_events, _)) = &self.spill
+                && self.queue.len() > *max_events
+                && let Err(e) = self.spill()
+            {
+                return Some(CigarError::External(Box::new(e)));
+            }
+        }
+        self.unspill()
+            .err()
+            .map(|e| CigarError::External(Box::new(e)))
+    }
+
+    /// Move the furthest half of the queued events to a sorted run on disk.
+    fn spill(&mut self) -> std::io::Result<()> {
+        let dir = match &self.spill {
+            Some((_, dir)) => dir.clone(),
+            None => return Ok(()),
+        };
+        let mut events = self.queue.drain();
+        events.sort();
+        let far = events.split_off(events.len() / 2);
+        self.queue.extend(events);
+        self.spilled.push(SpillRun::create(&dir, &far)?);
+        Ok(())
+    }
+
+    /// Move the events at the front of the collation back from disk into the queue.
+    fn unspill(&mut self) -> std::io::Result<()> {
+        let key = |e: &AugmentedCigarElement| (e.chrom_id, e.reference_position);
+        let queued = self.queue.peek().map(|(e, _)| key(e));
+        let spilled = self
+            .spilled
+            .iter()
+            .filter_map(|run| run.head().map(|(e, _)| key(e)))
+            .min();
+        let front = match (queued, spilled) {
+            (_, None) => return Ok(()),
+            (Some(q), Some(s)) => q.min(s),
+            (None, Some(s)) => s,
+        };
+        for run in self.spilled.iter_mut() {
+            while run.head().is_some_and(|(e, _)| key(e) <= front) {
+                if let Some(event) = run.take()? {
+                    self.queue.push(event);
+                }
+            }
+        }
+        self.spilled.retain(|run| run.head().is_some());
+        Ok(())
+    }
+
+    /// Pop the next event from the queue, calling `member` with the tag of each
+    /// occurrence.
+    pub(crate) fn pop_group(&mut self, mut member: impl FnMut(usize)) -> Option<CollatedEvent> {
+        let Some((elem, tag)) = self.queue.pop() else {
+            if self.progress.as_ref().is_some_and(|p| !p.finished) {
+                self.report(true);
+            }
+            return None;
+        };
+        self.emitted += 1;
+        member(tag);
+        let mut count = 1;
+        while let Some((next, tag)) = self.queue.peek() {
+            if same_event(next, &elem) {
+                member(*tag);
+                self.queue.pop();
+                count += 1;
+            } else {
+                break;
+            }
+        }
+        Some((elem, count))
+    }
+
+    /// The inserted bases of a queued insertion, given the tag it was popped with, in
+    /// sequenced collation.
+    pub(crate) fn take_inserted_bases(&mut self, tag: usize) -> Option<Vec<u8>> {
+        self.inserted_bases.as_mut()?.take(tag)
+    }
+}
+
+impl<
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+> Iterator for CollatedAugmentedCigarIterator<Source, R, E>
+{
+    type Item = std::result::Result<CollatedEvent, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.fill() {
+            return Some(Err(e));
+        }
+        self.pop_group(|_| {}).map(Ok)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Queued events are grouped, so there is at least one more event but possibly no
+        // more than one per queued event, until further records or spilled runs are read.
+        let lower = usize::from(!self.queue.is_empty() || !self.spilled.is_empty());
+        let exhausted = self.source.size_hint().1 == Some(0) && self.spilled.is_empty();
+        (lower, exhausted.then_some(self.queue.len()))
+    }
+}
+
+impl<
+    Source: FusedIterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+> FusedIterator for CollatedAugmentedCigarIterator<Source, R, E>
+{
+}
+
+/// A collated iterator which breaks the count of each event down by sample, created by
+/// [`CollatedAugmentedCigarIterator::per_sample`].
+pub struct PerSampleCollatedIterator<
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+> {
+    inner: CollatedAugmentedCigarIterator<Source, R, E>,
+    samples: usize,
+}
+
+impl<
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+> Iterator for PerSampleCollatedIterator<Source, R, E>
+{
+    type Item = std::result::Result<SampleCollatedEvent, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.inner.fill() {
+            return Some(Err(e));
+        }
+        let mut counts = vec![0; self.samples];
+        let (elem, _) = self.inner.pop_group(|tag| {
+            let sample = tag_sample(tag);
+            if sample >= counts.len() {
+                counts.resize(sample + 1, 0);
+            }
+            counts[sample] += 1;
+        })?;
+        Some(Ok((elem, counts)))
+    }
+}
+
+/// A collated iterator which weights each occurrence of an event by the mapping quality
+/// of its record or its base qualities, created by
+/// [`CollatedAugmentedCigarIterator::mapq_weighted`] or
+/// [`CollatedAugmentedCigarIterator::quality_weighted`].
+pub struct WeightedCollatedIterator<
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+> {
+    inner: CollatedAugmentedCigarIterator<Source, R, E>,
+    by_mapq: bool,
+    by_quality: bool,
+}
+
+impl<
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+> WeightedCollatedIterator<Source, R, E>
+{
+    /// Also weight by mapping quality, multiplying the weights.
+    pub fn by_mapq(mut self) -> Self {
+        self.by_mapq = true;
+        self
+    }
+
+    /// Also weight by base quality, multiplying the weights.
+    pub fn by_quality(mut self) -> Self {
+        self.inner.quality_weights = true;
+        self.by_quality = true;
+        self
+    }
+
+    fn weight(&self, tag: usize) -> f64 {
+        let mut weight = 1.0;
+        if self.by_mapq {
+            weight *= phred_weight((tag & 0xff) as u8);
+        }
+        if self.by_quality {
+            weight *= phred_weight((tag >> 8 & 0xff) as u8);
+        }
+        weight
+    }
+}
+
+impl<
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+> Iterator for WeightedCollatedIterator<Source, R, E>
+{
+    type Item = std::result::Result<WeightedCollatedEvent, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.inner.fill() {
+            return Some(Err(e));
+        }
+        let mut tags = Vec::new();
+        let (elem, count) = self.inner.pop_group(|tag| tags.push(tag))?;
+        let weight = tags.iter().map(|&tag| self.weight(tag)).sum();
+        Some(Ok((elem, count, weight)))
+    }
+}
+
+/// A collated event: an augmented CIGAR element and the number of times it was seen.
+pub type CollatedEvent = (AugmentedCigarElement, usize);
+
+/// A collated event with the number of times it was seen in each sample, indexed by sample ID.
+pub type SampleCollatedEvent = (AugmentedCigarElement, Vec<usize>);
+
+/// A collated event with the number of times it was seen and the sum of the weights of
+/// its occurrences.
+pub type WeightedCollatedEvent = (AugmentedCigarElement, usize, f64);
+
+/// A filter over a stream of collated events.
+///
+/// Created with the methods of [`CollatedFilterExt`]; further conditions can be chained
+/// onto an existing filter.
+pub struct CollatedFilter<I> {
+    inner: I,
+    min_count: usize,
+    ops: Option<Vec<CigarOp>>,
+    exclude_clips: bool,
+}
+
+impl<I> CollatedFilter<I> {
+    fn new(inner: I) -> Self {
+        CollatedFilter {
+            inner,
+            min_count: 0,
+            ops: None,
+            exclude_clips: false,
+        }
+    }
+
+    /// Only pass events seen at least `n` times.
+    pub fn min_count(mut self, n: usize) -> Self {
+        self.min_count = n;
+        self
+    }
+
+    /// Only pass events with one of the given operations.
+    pub fn ops<V: IntoIterator<Item = CigarOp>>(mut self, ops: V) -> Self {
+        self.ops = Some(ops.into_iter().collect());
+        self
+    }
+
+    /// Drop soft and hard clip events.
+    pub fn exclude_clips(mut self) -> Self {
+        self.exclude_clips = true;
+        self
+    }
+
+    fn accepts(&self, elem: &AugmentedCigarElement, count: usize) -> bool {
+        if count < self.min_count {
+            return false;
+        }
+        if self.exclude_clips && matches!(elem.op, CigarOp::SoftClip | CigarOp::HardClip) {
+            return false;
+        }
+        match &self.ops {
+            Some(ops) => ops.contains(&elem.op),
+            None => true,
+        }
+    }
+}
+
+impl<I> Iterator for CollatedFilter<I>
+where
+    I: Iterator<Item = std::result::Result<CollatedEvent, CigarError>>,
+{
+    type Item = std::result::Result<CollatedEvent, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.inner.next()? {
+                Ok((elem, count)) => {
+                    if self.accepts(&elem, count) {
+                        return Some(Ok((elem, count)));
+                    }
                 }
+                Err(e) => return Some(Err(e)),
             }
-            Some(Ok((elem, count)))
-        } else {
-            None
         }
     }
 }
 
+/// Filtering adapters for streams of collated events.
+///
+/// ```rust
+/// use cigar_utils::CigarOp;
+/// use cigar_utils::collated::{CollatedAugmentedCigarIterator, CollatedFilterExt};
+///
+/// let cigars = vec![
+///     std::io::Result::Ok(("2M1I2M".to_string(), 1, 100)),
+///     std::io::Result::Ok(("2M1I2M".to_string(), 1, 100)),
+///     std::io::Result::Ok(("1S1M1D2M".to_string(), 1, 101)),
+/// ];
+/// let indels: Vec<_> = CollatedAugmentedCigarIterator::new(cigars.into_iter())
+///     .ops([CigarOp::Insertion, CigarOp::Deletion])
+///     .min_count(2)
+///     .collect::<Result<_, _>>()
+///     .unwrap();
+/// assert_eq!(indels.len(), 1);
+/// assert_eq!(indels[0].0.op, CigarOp::Insertion);
+/// ```
+pub trait CollatedFilterExt: Sized {
+    /// Only pass events seen at least `n` times.
+    fn min_count(self, n: usize) -> CollatedFilter<Self> {
+        CollatedFilter::new(self).min_count(n)
+    }
+
+    /// Only pass events with one of the given operations.
+    fn ops<V: IntoIterator<Item = CigarOp>>(self, ops: V) -> CollatedFilter<Self> {
+        CollatedFilter::new(self).ops(ops)
+    }
+
+    /// Drop soft and hard clip events.
+    fn exclude_clips(self) -> CollatedFilter<Self> {
+        CollatedFilter::new(self).exclude_clips()
+    }
+}
+
+impl<I> CollatedFilterExt for I where
+    I: Iterator<Item = std::result::Result<CollatedEvent, CigarError>>
+{
+}
+
+/// A `(CIGAR, chromosome ID, position)` record, for tests.
+#[cfg(test)]
+pub(crate) type TestRecord<'a> = (&'a str, u32, u64);
+
+/// A source of records, for tests.
+#[cfg(test)]
+pub(crate) fn records(
+    cigars: Vec<TestRecord<'_>>,
+) -> impl Iterator<Item = std::io::Result<TestRecord<'_>>> {
+    cigars.into_iter().map(std::io::Result::Ok)
+}
+
+/// A collation of records, for tests.
+#[cfg(test)]
+pub(crate) fn collated(
+    cigars: Vec<TestRecord<'_>>,
+) -> CollatedAugmentedCigarIterator<
+    impl Iterator<Item = std::io::Result<TestRecord<'_>>>,
+    TestRecord<'_>,
+    std::io::Error,
+> {
+    CollatedAugmentedCigarIterator::new(records(cigars))
+}
+
 #[cfg(test)]
 mod tests {
 
-    use crate::CigarOp;
+    use crate::CigarElement;
+    use crate::sam::SamRecord;
 
     use super::*;
 
@@ -146,18 +1415,34 @@ mod tests {
         assert_eq!(results[3].0.reference_position, 103); // 2M from second
     }
 
+    #[test]
+    fn test_collated_augmented_cigar_iterator_size_hint() {
+        let cigars = vec![
+            std::io::Result::Ok(("2M1I".to_string(), 1, 100)),
+            std::io::Result::Ok(("1D2M".to_string(), 1, 102)),
+        ];
+        let mut collated = CollatedAugmentedCigarIterator::new(cigars.into_iter());
+        assert_eq!(collated.size_hint(), (0, None));
+        collated.next();
+        collated.next();
+        assert_eq!(collated.size_hint(), (1, Some(2)));
+        assert_eq!(collated.by_ref().count(), 2);
+        assert_eq!(collated.size_hint(), (0, Some(0)));
+        assert!(collated.next().is_none());
+    }
+
     #[test]
     fn test_collated_augmented_cigar_iterator_error() {
         let cigars = vec![
             std::io::Result::Ok(("2M1Z".to_string(), 1, 100)), // Invalid op 'Z'
             std::io::Result::Ok(("1M".to_string(), 1, 101)),
         ];
-        let mut collated = CollatedAugmentedCigarIterator::new(cigars.into_iter());
+        let collated = CollatedAugmentedCigarIterator::new(cigars.into_iter());
         let mut saw_error = false;
-        while let Some(res) = collated.next() {
+        for res in collated {
             match res {
                 Ok(_) => {}
-                Err(CigarError::InvalidCharacter('Z')) => {
+                Err(e) if matches!(e.root(), CigarError::InvalidCharacter('Z', _)) => {
                     saw_error = true;
                     break;
                 }
@@ -166,6 +1451,410 @@ mod tests {
         }
         assert!(saw_error);
     }
+    #[test]
+    fn test_collated_augmented_cigar_iterator_skips_unavailable() {
+        let cigars = vec![
+            std::io::Result::Ok(("1M".to_string(), 1, 100)),
+            std::io::Result::Ok(("*".to_string(), 1, 100)),
+            std::io::Result::Ok(("1M".to_string(), 1, 100)),
+        ];
+        let results: Vec<_> = CollatedAugmentedCigarIterator::new(cigars.into_iter())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].1, 2);
+    }
+
+    #[test]
+    fn test_collated_augmented_cigar_iterator_borrowed_records() {
+        let cigars = vec![
+            std::io::Result::Ok(("2M", 1, 100)),
+            std::io::Result::Ok(("1M", 1, 101)),
+        ];
+        let results: Vec<_> = CollatedAugmentedCigarIterator::new(cigars.into_iter())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0.reference_position, 100);
+        assert_eq!(results[1].0.reference_position, 101);
+    }
+
+    #[test]
+    fn test_collated_augmented_cigar_iterator_parsed_elements() {
+        let first = vec![
+            CigarElement::new(2, CigarOp::Match),
+            CigarElement::new(1, CigarOp::Insertion),
+        ];
+        let second = vec![
+            CigarElement::new(1, CigarOp::Deletion),
+            CigarElement::new(2, CigarOp::Match),
+        ];
+        let owned = vec![
+            std::io::Result::Ok((first.clone(), 1, 100)),
+            std::io::Result::Ok((second.clone(), 1, 102)),
+        ];
+        let borrowed = vec![
+            std::io::Result::Ok((first.as_slice(), 1, 100)),
+            std::io::Result::Ok((second.as_slice(), 1, 102)),
+        ];
+        let strings = vec![
+            std::io::Result::Ok(("2M1I".to_string(), 1, 100)),
+            std::io::Result::Ok(("1D2M".to_string(), 1, 102)),
+        ];
+        let expected: Vec<_> = CollatedAugmentedCigarIterator::new(strings.into_iter())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let owned: Vec<_> = CollatedAugmentedCigarIterator::new(owned.into_iter())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let borrowed: Vec<_> = CollatedAugmentedCigarIterator::new(borrowed.into_iter())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(owned, expected);
+        assert_eq!(borrowed, expected);
+    }
+
+    #[test]
+    fn test_collated_augmented_cigar_iterator_checked_unsorted() {
+        let cigars = vec![
+            std::io::Result::Ok(("1M".to_string(), 1, 100)),
+            std::io::Result::Ok(("1M".to_string(), 2, 50)),
+            std::io::Result::Ok(("1M".to_string(), 1, 200)),
+        ];
+        let results: Vec<_> = CollatedAugmentedCigarIterator::new(cigars.into_iter())
+            .checked()
+            .collect();
+        assert!(results.iter().any(|r| matches!(
+            r,
+            Err(CigarError::UnsortedInput {
+                previous: (2, 50),
+                current: (1, 200)
+            })
+        )));
+    }
+
+    #[test]
+    fn test_collated_augmented_cigar_iterator_checked_sorted() {
+        let cigars = vec![
+            std::io::Result::Ok(("1M".to_string(), 1, 100)),
+            std::io::Result::Ok(("1M".to_string(), 1, 100)),
+            std::io::Result::Ok(("1M".to_string(), 2, 50)),
+        ];
+        let results: Vec<_> = CollatedAugmentedCigarIterator::new(cigars.into_iter())
+            .checked()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn test_collated_augmented_cigar_iterator_slack() {
+        let cigars = vec![
+            std::io::Result::Ok(("1M".to_string(), 1, 100)),
+            std::io::Result::Ok(("1M".to_string(), 1, 105)),
+            std::io::Result::Ok(("1M".to_string(), 1, 100)),
+            std::io::Result::Ok(("1M".to_string(), 1, 102)),
+            std::io::Result::Ok(("1M".to_string(), 1, 120)),
+        ];
+        let results: Vec<_> = CollatedAugmentedCigarIterator::new(cigars.into_iter())
+            .with_slack(5)
+            .map(|r| r.map(|(e, count)| (e.reference_position, count)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(results, vec![(100, 2), (102, 1), (105, 1), (120, 1)]);
+    }
+
+    #[test]
+    fn test_collated_augmented_cigar_iterator_slack_exceeded() {
+        let cigars = vec![
+            std::io::Result::Ok(("1M".to_string(), 1, 100)),
+            std::io::Result::Ok(("1M".to_string(), 1, 110)),
+            std::io::Result::Ok(("1M".to_string(), 1, 102)),
+        ];
+        let results: Vec<_> = CollatedAugmentedCigarIterator::new(cigars.into_iter())
+            .with_slack(5)
+            .collect();
+        assert!(results.iter().any(|r| matches!(
+            r,
+            Err(CigarError::UnsortedInput {
+                previous: (1, 110),
+                current: (1, 102)
+            })
+        )));
+    }
+
+    #[test]
+    fn test_collated_augmented_cigar_iterator_merged() {
+        let first = vec![
+            std::io::Result::Ok(("1M".to_string(), 1, 100)),
+            std::io::Result::Ok(("1M".to_string(), 1, 102)),
+            std::io::Result::Ok(("1M".to_string(), 2, 10)),
+        ];
+        let second = vec![
+            std::io::Result::Ok(("1M".to_string(), 1, 100)),
+            std::io::Result::Ok(("1M".to_string(), 1, 101)),
+            std::io::Result::Ok(("1M".to_string(), 2, 5)),
+        ];
+        let results: Vec<_> =
+            CollatedAugmentedCigarIterator::merged(vec![first.into_iter(), second.into_iter()])
+                .checked()
+                .map(|r| r.map(|(e, count)| (e.chrom_id, e.reference_position, count)))
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(
+            results,
+            vec![(1, 100, 2), (1, 101, 1), (1, 102, 1), (2, 5, 1), (2, 10, 1)]
+        );
+    }
+
+    #[test]
+    fn test_collated_filter_exclude_clips() {
+        let cigars = vec![
+            std::io::Result::Ok(("2S3M".to_string(), 1, 100)),
+            std::io::Result::Ok(("3M2H".to_string(), 1, 100)),
+        ];
+        let results: Vec<_> = CollatedAugmentedCigarIterator::new(cigars.into_iter())
+            .exclude_clips()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0.op, CigarOp::Match);
+        assert_eq!(results[0].1, 2);
+    }
+
+    #[test]
+    fn test_collated_filter_passes_errors() {
+        let cigars = vec![std::io::Result::Ok(("2M1Z".to_string(), 1, 100))];
+        let mut filtered = CollatedAugmentedCigarIterator::new(cigars.into_iter()).min_count(10);
+        let error = filtered.next().unwrap().unwrap_err();
+        assert!(matches!(error.root(), CigarError::InvalidCharacter('Z', _)));
+        assert_eq!(
+            error.to_string(),
+            "Invalid character in CIGAR string: Z (at byte 3, element 1) (record 1:100, CIGAR 2M1Z)"
+        );
+    }
+
+    #[test]
+    fn test_collated_augmented_cigar_iterator_restrict() {
+        let cigars = vec![
+            std::io::Result::Ok(("10M".to_string(), 1, 100)),
+            std::io::Result::Ok(("10M".to_string(), 2, 100)),
+            std::io::Result::Ok(("2M3D5M".to_string(), 2, 103)),
+            std::io::Result::Ok(("5M".to_string(), 2, 110)),
+            std::io::Result::Ok(("5M".to_string(), 3, 0)),
+        ];
+        let results: Vec<_> = CollatedAugmentedCigarIterator::new(cigars.into_iter())
+            .restrict(2, 105, 110)
+            .map(|r| r.map(|(e, n)| (e.reference_position, e.op, e.length, e.read_position, n)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            results,
+            vec![
+                (105, CigarOp::Match, 5, 5, 1),
+                (105, CigarOp::Deletion, 3, 2, 1),
+                (108, CigarOp::Match, 2, 2, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collated_augmented_cigar_iterator_max_depth() {
+        let mut cigars: Vec<_> = (0..5)
+            .map(|_| std::io::Result::Ok(("10M".to_string(), 1, 100)))
+            .collect();
+        cigars.push(std::io::Result::Ok(("5M".to_string(), 1, 105)));
+        cigars.push(std::io::Result::Ok(("5M".to_string(), 1, 110)));
+        let results: Vec<_> = CollatedAugmentedCigarIterator::new(cigars.into_iter())
+            .max_depth(2)
+            .map(|r| r.map(|(e, n)| (e.reference_position, n)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(results, vec![(100, 2), (110, 1)]);
+    }
+
+    #[test]
+    fn test_collated_augmented_cigar_iterator_max_depth_seeded() {
+        let run = |seed| {
+            let cigars: Vec<_> = (0..100)
+                .map(|i| std::io::Result::Ok(("10M".to_string(), 1, 100 + i / 10)))
+                .collect();
+            CollatedAugmentedCigarIterator::new(cigars.into_iter())
+                .max_depth_seeded(5, seed)
+                .map(|r| r.map(|(e, n)| (e.reference_position, n)))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+        let results = run(42);
+        assert_eq!(results, run(42));
+        let total: usize = results.iter().map(|(_, n)| n).sum();
+        assert!((5..=50).contains(&total));
+        assert!(results.iter().all(|(_, n)| *n <= 5));
+    }
+
+    #[test]
+    fn test_collated_per_sample() {
+        use crate::record::Sampled;
+        let tumour = vec![
+            std::io::Result::Ok(Sampled::new(("2M1I2M", 1, 100), 0)),
+            std::io::Result::Ok(Sampled::new(("2M1I2M", 1, 100), 0)),
+        ];
+        let normal = vec![
+            std::io::Result::Ok(Sampled::new(("2M1I2M", 1, 100), 1)),
+            std::io::Result::Ok(Sampled::new(("5M", 1, 100), 1)),
+        ];
+        let results: Vec<_> =
+            CollatedAugmentedCigarIterator::merged([tumour.into_iter(), normal.into_iter()])
+                .per_sample(3)
+                .map(|r| r.map(|(e, counts)| (e.reference_position, e.op, counts)))
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(
+            results,
+            vec![
+                (100, CigarOp::Match, vec![2, 1, 0]),
+                (100, CigarOp::Match, vec![0, 1, 0]),
+                (102, CigarOp::Match, vec![2, 1, 0]),
+                (102, CigarOp::Insertion, vec![2, 1, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collated_spill_to_disk() {
+        let cigars: Vec<_> = (0..50)
+            .map(|i| (format!("5M{}N5M", 1000 - i * 10), 1, 100 + i * 5))
+            .collect();
+        let expected: Vec<_> = CollatedAugmentedCigarIterator::new(
+            cigars.clone().into_iter().map(std::io::Result::Ok),
+        )
+        .collect::<Result<_, _>>()
+        .unwrap();
+        let spilled: Vec<_> =
+            CollatedAugmentedCigarIterator::new(cigars.into_iter().map(std::io::Result::Ok))
+                .spill_to_disk(8)
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(spilled, expected);
+    }
+
+    #[test]
+    fn test_cigar_cache() {
+        let mut cache = CigarCache::new(2);
+        assert_eq!(cache.elements("3S7M").unwrap().len(), 2);
+        assert!(cache.elements("3Q").is_none());
+        cache.elements("5M");
+        // 3S7M has been used again, so 5M goes to make room for 4M.
+        cache.elements("3S7M");
+        cache.elements("4M");
+        assert_eq!(cache.entries.len(), 2);
+        assert!(cache.entries.contains_key("3S7M"));
+        assert!(cache.entries.contains_key("4M"));
+    }
+
+    #[test]
+    fn test_collated_cache_cigars() {
+        let cigars: Vec<_> = (0..40)
+            .map(|i| {
+                (
+                    ["3S7M", "5M1I4M", "2M1D8M"][i % 3].to_string(),
+                    1,
+                    100 + i as u64 / 2,
+                )
+            })
+            .collect();
+        let expected: Vec<_> = CollatedAugmentedCigarIterator::new(
+            cigars.clone().into_iter().map(std::io::Result::Ok),
+        )
+        .collect::<Result<_, _>>()
+        .unwrap();
+        let cached: Vec<_> =
+            CollatedAugmentedCigarIterator::new(cigars.iter().cloned().map(std::io::Result::Ok))
+                .cache_cigars(2)
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(cached, expected);
+        let uncached: Vec<_> =
+            CollatedAugmentedCigarIterator::new(cigars.iter().cloned().map(std::io::Result::Ok))
+                .cache_cigars(0)
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(uncached, expected);
+
+        let cigars = vec![
+            std::io::Result::Ok(("5M".to_string(), 1, 100)),
+            std::io::Result::Ok(("5Q".to_string(), 1, 101)),
+        ];
+        let mut collated = CollatedAugmentedCigarIterator::new(cigars.into_iter()).cache_cigars(4);
+        assert!(collated.any(|event| matches!(
+            event.as_ref().map_err(|e| e.root()),
+            Err(CigarError::InvalidCharacter('Q', _))
+        )));
+    }
+
+    #[test]
+    fn test_collated_merge_mates() {
+        let records = || {
+            [
+                "r1\t99\tchr1\t101\t60\t2S8M\t=\t106\t0\t*\t*",
+                "r2\t99\tchr1\t103\t60\t4M\t=\t150\t0\t*\t*",
+                "r1\t147\tchr1\t106\t60\t3M1I6M\t=\t101\t0\t*\t*",
+                "r3\t99\tchr1\t107\t60\t4M\t=\t107\t0\t*\t*",
+                "r2\t147\tchr1\t150\t60\t4M\t=\t103\t0\t*\t*",
+            ]
+            .map(|line| std::io::Result::Ok(line.parse::<SamRecord>().unwrap()))
+            .into_iter()
+        };
+        let events = |collated: CollatedAugmentedCigarIterator<_, _, _>| {
+            collated
+                .map(|event| {
+                    let (elem, count) = event.unwrap();
+                    (elem.reference_position, elem.length, elem.op, count)
+                })
+                .collect::<Vec<_>>()
+        };
+        let plain = events(CollatedAugmentedCigarIterator::new(records()));
+        assert!(plain.contains(&(105, 3, CigarOp::Match, 1)));
+        assert!(plain.contains(&(108, 1, CigarOp::Insertion, 1)));
+
+        let merged = events(CollatedAugmentedCigarIterator::new(records()).merge_mates());
+        assert_eq!(
+            merged,
+            vec![
+                (100, 8, CigarOp::Match, 1),
+                (100, 2, CigarOp::SoftClip, 1),
+                (102, 4, CigarOp::Match, 1),
+                (106, 4, CigarOp::Match, 1),
+                (108, 6, CigarOp::Match, 1),
+                (108, 1, CigarOp::Insertion, 1),
+                (149, 4, CigarOp::Match, 1),
+            ]
+        );
+
+        // A supplementary alignment of r1 is neither clipped nor taken as its mate.
+        let records = [
+            "r1\t99\tchr1\t101\t60\t8M\t=\t106\t0\t*\t*",
+            "r1\t2147\tchr1\t104\t60\t4M\t=\t106\t0\t*\t*",
+            "r1\t147\tchr1\t106\t60\t6M\t=\t101\t0\t*\t*",
+        ]
+        .map(|line| std::io::Result::Ok(line.parse::<SamRecord>().unwrap()));
+        let merged: Vec<_> = CollatedAugmentedCigarIterator::new(records.into_iter())
+            .merge_mates()
+            .map(|event| {
+                let (elem, count) = event.unwrap();
+                (elem.reference_position, elem.length, elem.op, count)
+            })
+            .collect();
+        assert_eq!(
+            merged,
+            vec![
+                (100, 8, CigarOp::Match, 1),
+                (103, 4, CigarOp::Match, 1),
+                (108, 3, CigarOp::Match, 1),
+            ]
+        );
+    }
+
     #[test]
     fn test_collated_augmented_cigar_iterator_chrom_id_collation() {
         let cigars = vec![
@@ -223,4 +1912,297 @@ mod tests {
         assert_eq!(results[0].0.reference_position, 100);
         assert_eq!(results[0].1, 3);
     }
+
+    #[test]
+    fn test_collated_record_flags_and_mapq() {
+        let records = || {
+            [
+                "r1\t0\tchr1\t101\t60\t4M\t*\t0\t0\t*\t*",
+                "r2\t1024\tchr1\t101\t60\t4M\t*\t0\t0\t*\t*",
+                "r3\t0\tchr1\t101\t10\t4M\t*\t0\t0\t*\t*",
+                "r4\t0\tchr1\t101\t255\t4M\t*\t0\t0\t*\t*",
+            ]
+            .map(|line| std::io::Result::Ok(line.parse::<SamRecord>().unwrap()))
+            .into_iter()
+        };
+        let counts = |collated: CollatedAugmentedCigarIterator<_, _, _>| {
+            collated.map(|event| event.unwrap().1).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            counts(CollatedAugmentedCigarIterator::new(records())),
+            vec![4]
+        );
+        assert_eq!(
+            counts(
+                CollatedAugmentedCigarIterator::new(records())
+                    .record_filter(RecordFilter::default())
+            ),
+            vec![3]
+        );
+        let by_mapq = RecordFilter {
+            min_mapq: 20,
+            exclude_flags: 0,
+            region: None,
+        };
+        assert_eq!(
+            counts(CollatedAugmentedCigarIterator::new(records()).record_filter(by_mapq)),
+            vec![2]
+        );
+        let in_region = RecordFilter {
+            region: Some((0, 102, 103)),
+            ..by_mapq
+        };
+        let clipped: Vec<_> = CollatedAugmentedCigarIterator::new(records())
+            .record_filter(in_region)
+            .map(|event| {
+                let (elem, count) = event.unwrap();
+                (elem.reference_position, elem.length, count)
+            })
+            .collect();
+        assert_eq!(clipped, vec![(102, 1, 2)]);
+
+        let weighted: Vec<_> = CollatedAugmentedCigarIterator::new(records())
+            .record_filter(RecordFilter::default())
+            .mapq_weighted()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(weighted.len(), 1);
+        assert_eq!(weighted[0].1, 3);
+        assert!((weighted[0].2 - (1.0 - 1e-6 + 0.9 + 1.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_collated_event_filter() {
+        let cigars = vec![
+            std::io::Result::Ok(("2S3M1I2M".to_string(), 1, 100)),
+            std::io::Result::Ok(("3M2D2M".to_string(), 1, 101)),
+        ];
+        let events: Vec<_> = CollatedAugmentedCigarIterator::new(cigars.into_iter())
+            .event_filter(EventFilter::indels())
+            .map(|event| {
+                let (elem, count) = event.unwrap();
+                (elem.op, elem.reference_position, count)
+            })
+            .collect();
+        assert_eq!(
+            events,
+            vec![(CigarOp::Insertion, 103, 1), (CigarOp::Deletion, 104, 1)]
+        );
+    }
+
+    #[test]
+    fn test_collated_quality_weighted() {
+        let records = || {
+            [
+                "r1\t0\tchr1\t101\t60\t2H2M1I1M1D1M\t*\t0\t0\tACGTA\t5+!55",
+                "r2\t0\tchr1\t101\t60\t4M\t*\t0\t0\tACGT\t*",
+            ]
+            .map(|line| std::io::Result::Ok(line.parse::<SamRecord>().unwrap()))
+            .into_iter()
+        };
+        let weights = |events: Vec<WeightedCollatedEvent>| {
+            events
+                .into_iter()
+                .map(|(elem, count, weight)| (elem.op, elem.length, count, weight))
+                .collect::<Vec<_>>()
+        };
+        let events = weights(
+            CollatedAugmentedCigarIterator::new(records())
+                .quality_weighted()
+                .collect::<Result<_, _>>()
+                .unwrap(),
+        );
+        let weight = |op: CigarOp, length: u32| {
+            events
+                .iter()
+                .find(|e| e.0 == op && e.1 == length)
+                .map(|e| e.3)
+                .unwrap()
+        };
+        assert!((weight(CigarOp::Match, 2) - (1.0 - 10f64.powf(-1.5))).abs() < 1e-9);
+        assert_eq!(weight(CigarOp::Insertion, 1), 0.0);
+        assert!((weight(CigarOp::Deletion, 1) - 0.99).abs() < 1e-9);
+        assert_eq!(weight(CigarOp::Match, 4), 1.0);
+        assert_eq!(weight(CigarOp::HardClip, 2), 1.0);
+
+        let both = weights(
+            CollatedAugmentedCigarIterator::new(records())
+                .quality_weighted()
+                .by_mapq()
+                .collect::<Result<_, _>>()
+                .unwrap(),
+        );
+        let four = both
+            .iter()
+            .find(|e| e.0 == CigarOp::Match && e.1 == 4)
+            .unwrap();
+        assert_eq!(four.2, 1);
+        assert!((four.3 - (1.0 - 1e-6)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_collated_checkpoint_resume() {
+        let records = || {
+            vec![
+                ("5M", 1, 100),
+                ("2M1I3M", 1, 101),
+                ("4M2D2M", 1, 102),
+                ("6M", 1, 102),
+                ("3M", 1, 110),
+                ("1S4M", 2, 5),
+            ]
+            .into_iter()
+            .map(|(cigar, chrom_id, position)| {
+                std::io::Result::Ok((cigar.to_string(), chrom_id, position))
+            })
+        };
+        let whole: Vec<_> = CollatedAugmentedCigarIterator::new(records())
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let mut first = CollatedAugmentedCigarIterator::new(records()).spill_to_disk(2);
+        let mut events: Vec<_> = first.by_ref().take(3).collect::<Result<_, _>>().unwrap();
+        let checkpoint = first.checkpoint().unwrap();
+        assert!(checkpoint.pending_events() > 0);
+        assert_eq!(checkpoint.last_position(), Some((1, 102)));
+        drop(first);
+
+        let skipped = records().skip(checkpoint.records_consumed() as usize);
+        let resumed = CollatedAugmentedCigarIterator::new(skipped).resume(checkpoint);
+        events.extend(resumed.map(|e| e.unwrap()));
+        assert_eq!(events, whole);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_collated_checkpoint_serde() {
+        let records = vec![
+            std::io::Result::Ok(("3M1I2M".to_string(), 0, 10)),
+            std::io::Result::Ok(("4M".to_string(), 0, 11)),
+        ];
+        let mut collated = CollatedAugmentedCigarIterator::new(records.into_iter());
+        collated.next().unwrap().unwrap();
+        let checkpoint = collated.checkpoint().unwrap();
+        let json = serde_json::to_string(&checkpoint).unwrap();
+        let restored: CollationCheckpoint = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, checkpoint);
+    }
+
+    #[test]
+    fn test_collated_progress() {
+        use std::sync::{Arc, Mutex};
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&reports);
+        let records = vec![
+            std::io::Result::Ok(("5M".to_string(), 1, 100)),
+            std::io::Result::Ok(("*".to_string(), 1, 100)),
+            std::io::Result::Ok(("2M1I2M".to_string(), 1, 101)),
+            std::io::Result::Ok(("4M".to_string(), 2, 7)),
+        ];
+        let events = CollatedAugmentedCigarIterator::new(records.into_iter())
+            .on_progress(2, move |p| seen.lock().unwrap().push(*p))
+            .count();
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[0].records_consumed, 2);
+        assert_eq!(reports[0].position, Some((1, 100)));
+        assert_eq!(reports[0].queued_events, 1);
+        assert_eq!(reports[1].records_consumed, 4);
+        assert!(!reports[1].finished);
+        let last = reports[2];
+        assert!(last.finished);
+        assert_eq!(last.events_emitted, events as u64);
+        assert_eq!((last.queued_events, last.position), (0, Some((2, 7))));
+    }
+
+    #[test]
+    fn test_collated_check_reference_lengths() {
+        let mut chroms = ChromMap::new();
+        chroms.push("chr1", 110).unwrap();
+        let records = vec![
+            std::io::Result::Ok(("5M".to_string(), 0, 100)),
+            std::io::Result::Ok(("8M2S".to_string(), 0, 105)),
+            std::io::Result::Ok(("3M".to_string(), 0, 107)),
+            std::io::Result::Ok(("3M".to_string(), 1, 0)),
+        ];
+        let results: Vec<_> = CollatedAugmentedCigarIterator::new(records.into_iter())
+            .check_reference_lengths(&chroms)
+            .collect();
+        let errors: Vec<_> = results
+            .iter()
+            .filter_map(|r| r.as_ref().err())
+            .map(|e| e.root())
+            .collect();
+        assert!(matches!(
+            errors[..],
+            [
+                CigarError::BeyondChromosomeEnd { overhang: 3, .. },
+                CigarError::UnknownChromosome(1)
+            ]
+        ));
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 2);
+    }
+
+    #[test]
+    fn test_merge_collated_shards() {
+        let records = [
+            ("5S2M1I2M", 1, 100),
+            ("2M1I2M", 1, 100),
+            ("3M1D2M", 1, 101),
+            ("3M1D2M", 1, 101),
+            ("4M2S", 1, 102),
+            ("2M", 2, 5),
+        ];
+        let collate = |shard: Vec<(&str, u32, u64)>| {
+            let records: Vec<_> = shard
+                .into_iter()
+                .map(|(cigar, chrom_id, position)| {
+                    std::io::Result::Ok((cigar.to_string(), chrom_id, position))
+                })
+                .collect();
+            CollatedAugmentedCigarIterator::new(records.into_iter())
+        };
+        let whole: Vec<CollatedEvent> =
+            collate(records.to_vec()).collect::<Result<_, _>>().unwrap();
+        let shards = [
+            vec![records[0], records[2], records[5]],
+            vec![records[1], records[3]],
+            vec![records[4]],
+        ];
+        let merged: Vec<CollatedEvent> = merge_collated(shards.into_iter().map(collate))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(merged, whole);
+    }
+
+    #[test]
+    fn test_merge_collated_errors() {
+        let elem = |position| AugmentedCigarElement {
+            length: 2,
+            op: CigarOp::Match,
+            read_position: 0,
+            chrom_id: 0,
+            reference_position: position,
+            leading_clip: false,
+        };
+        let unsorted = vec![Ok((elem(10), 1)), Ok((elem(5), 1))];
+        let results: Vec<_> = merge_collated([unsorted, vec![Ok((elem(10), 2))]]).collect();
+        assert!(matches!(results[0], Ok((ref e, 3)) if e.reference_position == 10));
+        assert!(matches!(
+            results[1],
+            Err(CigarError::UnsortedInput {
+                previous: (0, 10),
+                current: (0, 5)
+            })
+        ));
+        let failing = vec![Err(CigarError::InvalidOpCode(9))];
+        let mut merged = merge_collated([vec![Ok((elem(1), 1))], failing]);
+        assert!(matches!(
+            merged.next(),
+            Some(Err(CigarError::InvalidOpCode(9)))
+        ));
+        assert!(matches!(merged.next(), Some(Ok((_, 1)))));
+        assert!(merged.next().is_none());
+    }
 }
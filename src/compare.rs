@@ -0,0 +1,467 @@
+//! Comparison of alignments of the same read.
+//!
+//! Two aligners, or one aligner before and after realignment, may place a read
+//! differently. [`compare`] measures the disagreement between two CIGARs of the same
+//! read by the number of read bases placed at different reference positions, and
+//! summarizes how the bases given to each operation differ. [`equivalent`] checks
+//! whether two CIGARs differ only in how they are written, and [`diff`] locates and
+//! labels the regions of the read where two alignments disagree.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::{Cigar, CigarOp};
+//! use cigar_utils::compare::{compare, equivalent};
+//!
+//! // The same 10 base read, with a deletion placed one base apart.
+//! let first: Cigar = "4M1D6M".parse().unwrap();
+//! let second: Cigar = "5M1D5M".parse().unwrap();
+//! let comparison = compare(&first, 100, &second, 100).unwrap();
+//! assert_eq!(comparison.read_bases, 10);
+//! assert_eq!(comparison.differently_placed, 1);
+//! assert!(comparison.op_differences().is_empty());
+//!
+//! // The same read soft clipped rather than aligned at its start.
+//! let clipped: Cigar = "2S8M".parse().unwrap();
+//! let comparison = compare(&"10M".parse().unwrap(), 100, &clipped, 102).unwrap();
+//! assert_eq!(comparison.differently_placed, 2);
+//! assert_eq!(
+//!     comparison.op_differences(),
+//!     vec![(CigarOp::Match, 10, 8), (CigarOp::SoftClip, 0, 2)]
+//! );
+//!
+//! assert!(equivalent(&"3=1X2M0I".parse().unwrap(), &"6M".parse().unwrap()));
+//! ```
+
+use std::ops::Range;
+
+use crate::error::CigarError;
+use crate::{Cigar, CigarElement, CigarOp};
+
+/// The reference position each base of the read is aligned to, if any.
+///
+/// Inserted and soft clipped bases are not aligned; hard clipped bases are not in the read.
+fn placements(cigar: &Cigar, reference_position: u64) -> Vec<Option<u64>> {
+    let mut placements = Vec::with_capacity(cigar.query_length());
+    let mut position = reference_position;
+    for elem in cigar.iter() {
+        let length = elem.length as u64;
+        match elem.op {
+            CigarOp::Match | CigarOp::Equal | CigarOp::Diff => {
+                placements.extend((position..position + length).map(Some));
+            }
+            CigarOp::Insertion | CigarOp::SoftClip => {
+                placements.extend((0..length).map(|_| None));
+            }
+            _ => {}
+        }
+        if elem.op.consumes_reference() {
+            position += length;
+        }
+    }
+    placements
+}
+
+/// The disagreement between two alignments of the same read.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CigarComparison {
+    /// The number of read bases compared.
+    pub read_bases: u64,
+    /// The number of read bases aligned to a different reference position by each
+    /// alignment, or aligned by only one of them.
+    pub differently_placed: u64,
+    first: [u64; 9],
+    second: [u64; 9],
+}
+
+impl CigarComparison {
+    /// The number of read bases placed the same way by both alignments.
+    pub fn same_placement(&self) -> u64 {
+        self.read_bases - self.differently_placed
+    }
+
+    /// The fraction of read bases placed the same way by both alignments, or `None` if
+    /// no bases were compared.
+    pub fn agreement(&self) -> Option<f64> {
+        (self.read_bases > 0).then(|| self.same_placement() as f64 / self.read_bases as f64)
+    }
+
+    /// The number of bases given to `op` by the first and second alignments.
+    pub fn bases(&self, op: CigarOp) -> (u64, u64) {
+        let code = u8::from(op) as usize;
+        (self.first[code], self.second[code])
+    }
+
+    /// The operations given a different number of bases by the two alignments, with the
+    /// bases of each, in operation code order.
+    pub fn op_differences(&self) -> Vec<(CigarOp, u64, u64)> {
+        (0..9u8)
+            .filter_map(|code| {
+                let op = CigarOp::try_from(code).ok()?;
+                let (first, second) = self.bases(op);
+                (first != second).then_some((op, first, second))
+            })
+            .collect()
+    }
+
+    /// Add the counts of another comparison to this one, as when summarizing many reads.
+    pub fn merge(&mut self, other: &CigarComparison) {
+        self.read_bases += other.read_bases;
+        self.differently_placed += other.differently_placed;
+        for code in 0..9 {
+            self.first[code] += other.first[code];
+            self.second[code] += other.second[code];
+        }
+    }
+}
+
+/// Compare two alignments of the same read, starting at `first_position` and
+/// `second_position` respectively.
+///
+/// Both CIGARs must describe the same read sequence (SEQ), so their query lengths must
+/// agree; hard clips are only counted in the per-operation summary.
+pub fn compare(
+    first: &Cigar,
+    first_position: u64,
+    second: &Cigar,
+    second_position: u64,
+) -> std::result::Result<CigarComparison, CigarError> {
+    if first.query_length() != second.query_length() {
+        return Err(CigarError::LengthMismatch(
+            first.query_length(),
+            second.query_length(),
+        ));
+    }
+    let a = placements(first, first_position);
+    let b = placements(second, second_position);
+    let mut comparison = CigarComparison {
+        read_bases: a.len() as u64,
+        differently_placed: a.iter().zip(b.iter()).filter(|(x, y)| x != y).count() as u64,
+        ..Default::default()
+    };
+    for elem in first.iter() {
+        comparison.first[u8::from(elem.op) as usize] += elem.length as u64;
+    }
+    for elem in second.iter() {
+        comparison.second[u8::from(elem.op) as usize] += elem.length as u64;
+    }
+    Ok(comparison)
+}
+
+/// The elements of a CIGAR with sequence matches and mismatches as alignment matches,
+/// zero length elements dropped and adjacent identical operations merged.
+fn canonical(cigar: &Cigar) -> Vec<CigarElement> {
+    let mut elements: Vec<CigarElement> = Vec::with_capacity(cigar.len());
+    for elem in cigar.iter().filter(|e| e.length > 0) {
+        let op = match elem.op {
+            CigarOp::Equal | CigarOp::Diff => CigarOp::Match,
+            op => op,
+        };
+        match elements.last_mut() {
+            Some(last) if last.op == op => last.length += elem.length,
+            _ => elements.push(CigarElement::new(elem.length, op)),
+        }
+    }
+    elements
+}
+
+/// Whether two CIGARs describe the same alignment, differing only in how it is written.
+///
+/// Alignment matches (`M`) are equivalent to any mixture of sequence matches and
+/// mismatches (`=`/`X`), zero length elements are ignored, and adjacent elements of the
+/// same operation are treated as one.
+pub fn equivalent(a: &Cigar, b: &Cigar) -> bool {
+    canonical(a) == canonical(b)
+}
+
+/// What changed between two alignments of a region of a read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DifferenceKind {
+    /// The second alignment soft clips more of the region.
+    ClipExtended,
+    /// The second alignment soft clips less of the region.
+    ClipShortened,
+    /// The second alignment has an intron the first does not.
+    IntronAdded,
+    /// The first alignment has an intron the second does not.
+    IntronRemoved,
+    /// Both alignments have introns in the region, of different lengths.
+    IntronChanged,
+    /// Both alignments have introns of the same length in the region, placed differently.
+    IntronMoved,
+    /// Both alignments have the same insertions and deletions in the region, placed differently.
+    IndelMoved,
+    /// The alignments have different insertions or deletions in the region.
+    IndelChanged,
+    /// The aligned bases of the region are placed differently, with no change in clips
+    /// or gaps, as when the read is aligned to a different position.
+    Realigned,
+}
+
+/// A region of a read where two alignments disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CigarDifference {
+    /// What changed in the region.
+    pub kind: DifferenceKind,
+    /// The read bases of the region (as positions in SEQ).
+    pub read: Range<usize>,
+    /// The reference interval the first alignment gives the region.
+    pub first_reference: Range<u64>,
+    /// The reference interval the second alignment gives the region.
+    pub second_reference: Range<u64>,
+}
+
+/// How an alignment places one read base, and the gap in the reference before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BaseAlignment {
+    op: CigarOp,
+    placement: Option<u64>,
+    deleted: u64,
+    skipped: u64,
+}
+
+/// The alignment of each read base, and the reference position before the gap preceding
+/// each base (with the position after the last base at the end).
+fn base_alignments(cigar: &Cigar, reference_position: u64) -> (Vec<BaseAlignment>, Vec<u64>) {
+    let mut bases = Vec::with_capacity(cigar.query_length());
+    let mut cursors = Vec::with_capacity(cigar.query_length() + 1);
+    let mut position = reference_position;
+    let mut cursor = reference_position;
+    let (mut deleted, mut skipped) = (0, 0);
+    for elem in cigar.iter() {
+        let length = elem.length as u64;
+        let (op, aligned) = match elem.op {
+            CigarOp::Match | CigarOp::Equal | CigarOp::Diff => (CigarOp::Match, true),
+            CigarOp::Insertion | CigarOp::SoftClip => (elem.op, false),
+            CigarOp::Deletion => {
+                deleted += length;
+                position += length;
+                continue;
+            }
+            CigarOp::Skip => {
+                skipped += length;
+                position += length;
+                continue;
+            }
+            CigarOp::HardClip | CigarOp::Padding => continue,
+        };
+        for _ in 0..length {
+            cursors.push(cursor);
+            bases.push(BaseAlignment {
+                op,
+                placement: aligned.then_some(position),
+                deleted,
+                skipped,
+            });
+            (deleted, skipped) = (0, 0);
+            if aligned {
+                position += 1;
+            }
+            cursor = position;
+        }
+    }
+    cursors.push(cursor);
+    (bases, cursors)
+}
+
+fn classify(first: &[BaseAlignment], second: &[BaseAlignment]) -> DifferenceKind {
+    let count = |bases: &[BaseAlignment], op: CigarOp| bases.iter().filter(|b| b.op == op).count();
+    let gaps = |bases: &[BaseAlignment]| {
+        bases
+            .iter()
+            .fold((0, 0), |(d, n), b| (d + b.deleted, n + b.skipped))
+    };
+    let (clipped, reclipped) = (
+        count(first, CigarOp::SoftClip),
+        count(second, CigarOp::SoftClip),
+    );
+    let indels = (count(first, CigarOp::Insertion), gaps(first).0);
+    let reindels = (count(second, CigarOp::Insertion), gaps(second).0);
+    let (skipped, reskipped) = (gaps(first).1, gaps(second).1);
+    if reclipped > clipped {
+        DifferenceKind::ClipExtended
+    } else if reclipped < clipped {
+        DifferenceKind::ClipShortened
+    } else if skipped != reskipped {
+        match (skipped, reskipped) {
+            (0, _) => DifferenceKind::IntronAdded,
+            (_, 0) => DifferenceKind::IntronRemoved,
+            _ => DifferenceKind::IntronChanged,
+        }
+    } else if indels != reindels {
+        DifferenceKind::IndelChanged
+    } else if indels != (0, 0) {
+        DifferenceKind::IndelMoved
+    } else if skipped > 0 {
+        DifferenceKind::IntronMoved
+    } else {
+        DifferenceKind::Realigned
+    }
+}
+
+/// Find the regions of a read where two of its alignments, starting at `first_position`
+/// and `second_position` respectively, disagree.
+///
+/// A region is a maximal run of read bases which the alignments place differently
+/// (aligned to different reference positions, or aligned by only one), or which follow
+/// different gaps in the reference. Each region is labeled by what changed, in the order
+/// of precedence of the [`DifferenceKind`] variants. As for [`compare`], the CIGARs must
+/// have the same query length, and hard clips are ignored.
+///
+/// # Example
+///
+/// ```rust
+/// use cigar_utils::Cigar;
+/// use cigar_utils::compare::{DifferenceKind, diff};
+///
+/// let first: Cigar = "2S4M1D6M".parse().unwrap();
+/// let second: Cigar = "3S4M1D5M".parse().unwrap();
+/// let differences = diff(&first, 100, &second, 101).unwrap();
+/// assert_eq!(differences.len(), 2);
+/// assert_eq!(differences[0].kind, DifferenceKind::ClipExtended);
+/// assert_eq!((differences[0].read.clone(), differences[0].first_reference.clone()), (2..3, 100..101));
+/// assert_eq!(differences[1].kind, DifferenceKind::IndelMoved);
+/// assert_eq!(differences[1].read, 6..8);
+/// ```
+pub fn diff(
+    first: &Cigar,
+    first_position: u64,
+    second: &Cigar,
+    second_position: u64,
+) -> std::result::Result<Vec<CigarDifference>, CigarError> {
+    if first.query_length() != second.query_length() {
+        return Err(CigarError::LengthMismatch(
+            first.query_length(),
+            second.query_length(),
+        ));
+    }
+    let (a, a_cursors) = base_alignments(first, first_position);
+    let (b, b_cursors) = base_alignments(second, second_position);
+    let mut differences = Vec::new();
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] == b[i] {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < a.len() && a[i] != b[i] {
+            i += 1;
+        }
+        differences.push(CigarDifference {
+            kind: classify(&a[start..i], &b[start..i]),
+            read: start..i,
+            first_reference: a_cursors[start]..a_cursors[i],
+            second_reference: b_cursors[start]..b_cursors[i],
+        });
+    }
+    Ok(differences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cigar(s: &str) -> Cigar {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_identical() {
+        let comparison = compare(&cigar("3H5M1I4M"), 10, &cigar("3H5M1I4M"), 10).unwrap();
+        assert_eq!(comparison.differently_placed, 0);
+        assert_eq!(comparison.agreement(), Some(1.0));
+        assert_eq!(comparison.bases(CigarOp::HardClip), (3, 3));
+    }
+
+    #[test]
+    fn test_shifted_and_inserted() {
+        // The whole read shifted by one base.
+        let comparison = compare(&cigar("6M"), 10, &cigar("6M"), 11).unwrap();
+        assert_eq!(comparison.differently_placed, 6);
+        // The read realigned with an insertion, moving the bases after it.
+        let comparison = compare(&cigar("6M"), 10, &cigar("2M1I3M"), 10).unwrap();
+        assert_eq!(comparison.differently_placed, 4);
+        assert_eq!(
+            comparison.op_differences(),
+            vec![(CigarOp::Match, 6, 5), (CigarOp::Insertion, 0, 1)]
+        );
+    }
+
+    #[test]
+    fn test_length_mismatch_and_merge() {
+        assert!(matches!(
+            compare(&cigar("5M"), 0, &cigar("4M"), 0),
+            Err(CigarError::LengthMismatch(5, 4))
+        ));
+        let mut total = compare(&cigar("4M"), 0, &cigar("4M"), 1).unwrap();
+        total.merge(&compare(&cigar("4M"), 0, &cigar("4M"), 0).unwrap());
+        assert_eq!((total.read_bases, total.differently_placed), (8, 4));
+        assert_eq!(total.agreement(), Some(0.5));
+        assert_eq!(CigarComparison::default().agreement(), None);
+    }
+
+    fn kinds(first: &str, second: &str) -> Vec<DifferenceKind> {
+        diff(&cigar(first), 0, &cigar(second), 0)
+            .unwrap()
+            .into_iter()
+            .map(|d| d.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_kinds() {
+        assert!(kinds("3S5M1I2M", "3S5M1I2M").is_empty());
+        assert!(kinds("2=1X3=", "6M").is_empty());
+        assert_eq!(kinds("10M", "5M100N5M"), vec![DifferenceKind::IntronAdded]);
+        assert_eq!(
+            kinds("5M90N5M", "5M100N5M"),
+            vec![DifferenceKind::IntronChanged]
+        );
+        assert_eq!(
+            kinds("5M100N5M", "5M100D5M"),
+            vec![DifferenceKind::IntronRemoved]
+        );
+        assert_eq!(
+            kinds("4M100N6M", "6M100N4M"),
+            vec![DifferenceKind::IntronMoved]
+        );
+        assert_eq!(
+            kinds("3M2I5M", "3M2I3M2S"),
+            vec![DifferenceKind::ClipExtended]
+        );
+        assert_eq!(kinds("2M1I4M", "4M1I2M"), vec![DifferenceKind::IndelMoved]);
+        assert_eq!(
+            kinds("4M1D3M", "4M2D3M"),
+            vec![DifferenceKind::IndelChanged]
+        );
+    }
+
+    #[test]
+    fn test_diff_intervals() {
+        let differences = diff(&cigar("4M1D6M"), 10, &cigar("4M2D6M"), 10).unwrap();
+        assert_eq!(
+            differences,
+            vec![CigarDifference {
+                kind: DifferenceKind::IndelChanged,
+                read: 4..10,
+                first_reference: 14..21,
+                second_reference: 14..22,
+            }]
+        );
+        let differences = diff(&cigar("6M"), 10, &cigar("6M"), 20).unwrap();
+        assert_eq!(differences[0].kind, DifferenceKind::Realigned);
+        assert_eq!(differences[0].second_reference, 20..26);
+        assert!(diff(&cigar("6M"), 0, &cigar("5M"), 0).is_err());
+    }
+
+    #[test]
+    fn test_equivalent() {
+        assert!(equivalent(&cigar("2S2M3M1I4=1X"), &cigar("2S5M1I5M")));
+        assert!(equivalent(&cigar("3M0D2M"), &cigar("5M")));
+        assert!(equivalent(&Cigar::new(), &cigar("0M")));
+        assert!(!equivalent(&cigar("5M"), &cigar("2S3M")));
+        assert!(!equivalent(&cigar("2M1I1D2M"), &cigar("2M1D1I2M")));
+        assert!(!equivalent(&cigar("5M"), &cigar("5M1H")));
+    }
+}
@@ -0,0 +1,285 @@
+//! Composition of CIGAR alignments.
+//!
+//! Given an alignment of a read to a contig, and an alignment of that contig to a
+//! reference, composition produces the alignment of the read to the reference. This
+//! is what is needed to lift reads through an assembly, or to polish an assembly
+//! against a reference.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::Cigar;
+//! use cigar_utils::compose::compose;
+//!
+//! // The read has an insertion relative to the contig,
+//! // and the contig has a deletion relative to the reference.
+//! let read_to_contig: Cigar = "3M1I3M".parse().unwrap();
+//! let contig_to_reference: Cigar = "3M2D3M".parse().unwrap();
+//!
+//! let (cigar, position) = compose(&read_to_contig, 0, &contig_to_reference, 100).unwrap();
+//! assert_eq!(cigar.to_string(), "3M1I2D3M");
+//! assert_eq!(position, 100);
+//! ```
+
+use crate::builder::CigarBuilder;
+use crate::error::CigarError;
+use crate::{Cigar, CigarElement, CigarOp};
+
+/// Compose a read-to-contig alignment with a contig-to-reference alignment.
+///
+/// The read alignment starts at `contig_position` on the contig, and the contig
+/// alignment starts at `reference_position` on the reference. Contig coordinates are
+/// positions in the contig sequence including any bases clipped in the contig alignment.
+///
+/// Read bases that fall on contig bases absent from the reference become insertions
+/// (or soft clips, at the ends of the alignment), and reference bases absent from the
+/// contig become deletions (or skips). The result is trimmed so that it starts and ends
+/// with aligned bases. Returns the composed CIGAR and its reference position.
+pub fn compose(
+    read_to_contig: &Cigar,
+    contig_position: u64,
+    contig_to_reference: &Cigar,
+    reference_position: u64,
+) -> std::result::Result<(Cigar, u64), CigarError> {
+    let contig_length: u64 = contig_to_reference
+        .iter()
+        .filter(|e| consumes_contig(e.op))
+        .map(|e| e.length as u64)
+        .sum();
+    let contig_end = contig_position
+        + read_to_contig
+            .iter()
+            .filter(|e| e.op.consumes_reference())
+            .map(|e| e.length as u64)
+            .sum::<u64>();
+    if contig_end > contig_length {
+        return Err(CigarError::LengthMismatch(
+            contig_length as usize,
+            contig_end as usize,
+        ));
+    }
+
+    let mut a = Cursor::new(read_to_contig.elements());
+    let mut b = Cursor::new(contig_to_reference.elements());
+    let mut reference_position = reference_position;
+
+    // Move the contig alignment forward to the start of the read alignment.
+    let mut skip = contig_position;
+    while let Some(op) = b.op() {
+        if skip == 0 && consumes_contig(op) {
+            break;
+        }
+        let n = if consumes_contig(op) {
+            b.remaining.min(skip.min(u32::MAX as u64) as u32)
+        } else {
+            b.remaining
+        };
+        if consumes_contig(op) {
+            skip -= n as u64;
+        }
+        if op.consumes_reference() {
+            reference_position += n as u64;
+        }
+        b.advance(n);
+    }
+
+    let mut leading_hard = 0;
+    let mut trailing_hard = 0;
+    let mut composed: Vec<CigarElement> = Vec::new();
+    while let Some(a_op) = a.op() {
+        if a_op == CigarOp::HardClip {
+            if composed.is_empty() {
+                leading_hard += a.remaining;
+            } else {
+                trailing_hard += a.remaining;
+            }
+            a.advance(a.remaining);
+            continue;
+        }
+        if !a_op.consumes_reference() {
+            if a_op.consumes_query() {
+                let op = match a_op {
+                    CigarOp::SoftClip => CigarOp::SoftClip,
+                    _ => CigarOp::Insertion,
+                };
+                composed.push(CigarElement::new(a.remaining, op));
+            }
+            a.advance(a.remaining);
+            continue;
+        }
+        let b_op = match b.op() {
+            Some(op) => op,
+            None => break,
+        };
+        if !consumes_contig(b_op) {
+            if b_op.consumes_reference() {
+                composed.push(CigarElement::new(b.remaining, b_op));
+            }
+            b.advance(b.remaining);
+            continue;
+        }
+        let n = a.remaining.min(b.remaining);
+        if let Some(op) = combine(a_op, b_op) {
+            composed.push(CigarElement::new(n, op));
+        }
+        a.advance(n);
+        b.advance(n);
+    }
+
+    // Trim so the alignment starts and ends with aligned bases.
+    let first = composed.iter().position(|e| is_aligned(e.op));
+    let last = composed.iter().rposition(|e| is_aligned(e.op));
+    let (first, last) = match (first, last) {
+        (Some(first), Some(last)) => (first, last + 1),
+        _ => (composed.len(), composed.len()),
+    };
+    let mut builder = CigarBuilder::new();
+    if leading_hard > 0 {
+        builder.push(CigarOp::HardClip, leading_hard)?;
+    }
+    for elem in &composed[..first] {
+        if elem.op.consumes_query() {
+            builder.push(CigarOp::SoftClip, elem.length)?;
+        }
+        if elem.op.consumes_reference() {
+            reference_position += elem.length as u64;
+        }
+    }
+    for elem in &composed[first..last] {
+        builder.push_element(elem.clone())?;
+    }
+    for elem in &composed[last..] {
+        if elem.op.consumes_query() {
+            builder.push(CigarOp::SoftClip, elem.length)?;
+        }
+    }
+    if trailing_hard > 0 {
+        builder.push(CigarOp::HardClip, trailing_hard)?;
+    }
+    Ok((builder.build(), reference_position))
+}
+
+/// Combine a read-to-contig operation with a contig-to-reference operation over the same contig bases.
+fn combine(a: CigarOp, b: CigarOp) -> Option<CigarOp> {
+    match (is_aligned(a), is_aligned(b)) {
+        (true, true) => Some(match (a, b) {
+            (CigarOp::Equal, CigarOp::Equal) => CigarOp::Equal,
+            (CigarOp::Equal, CigarOp::Diff) | (CigarOp::Diff, CigarOp::Equal) => CigarOp::Diff,
+            _ => CigarOp::Match,
+        }),
+        (true, false) => match b {
+            CigarOp::Insertion => Some(CigarOp::Insertion),
+            _ => Some(CigarOp::SoftClip),
+        },
+        (false, true) => Some(a),
+        (false, false) => None,
+    }
+}
+
+fn is_aligned(op: CigarOp) -> bool {
+    matches!(op, CigarOp::Match | CigarOp::Equal | CigarOp::Diff)
+}
+
+/// Whether an operation of the contig-to-reference alignment covers contig bases.
+fn consumes_contig(op: CigarOp) -> bool {
+    op.consumes_query() || op == CigarOp::HardClip
+}
+
+struct Cursor<'a> {
+    elements: &'a [CigarElement],
+    index: usize,
+    remaining: u32,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(elements: &'a [CigarElement]) -> Self {
+        let mut cursor = Cursor {
+            elements,
+            index: 0,
+            remaining: elements.first().map(|e| e.length).unwrap_or(0),
+        };
+        cursor.skip_empty();
+        cursor
+    }
+
+    fn op(&self) -> Option<CigarOp> {
+        self.elements.get(self.index).map(|e| e.op)
+    }
+
+    fn advance(&mut self, n: u32) {
+        self.remaining -= n;
+        self.skip_empty();
+    }
+
+    fn skip_empty(&mut self) {
+        while self.remaining == 0 && self.index < self.elements.len() {
+            self.index += 1;
+            self.remaining = self.elements.get(self.index).map(|e| e.length).unwrap_or(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn composed(a: &str, a_pos: u64, b: &str, b_pos: u64) -> (String, u64) {
+        let a: Cigar = a.parse().unwrap();
+        let b: Cigar = b.parse().unwrap();
+        let (cigar, pos) = compose(&a, a_pos, &b, b_pos).unwrap();
+        (cigar.to_string(), pos)
+    }
+
+    #[test]
+    fn test_compose_identity() {
+        assert_eq!(composed("10M", 0, "10M", 100), ("10M".to_string(), 100));
+    }
+
+    #[test]
+    fn test_compose_offsets() {
+        assert_eq!(composed("5M", 3, "2S10M", 100), ("5M".to_string(), 101));
+    }
+
+    #[test]
+    fn test_compose_contig_indels() {
+        assert_eq!(
+            composed("6M", 0, "3M2D3M", 100),
+            ("3M2D3M".to_string(), 100)
+        );
+        assert_eq!(
+            composed("6M", 0, "3M1I2M", 100),
+            ("3M1I2M".to_string(), 100)
+        );
+    }
+
+    #[test]
+    fn test_compose_cancelling_indels() {
+        assert_eq!(
+            composed("3M1D2M", 0, "3M1I2M", 100),
+            ("5M".to_string(), 100)
+        );
+    }
+
+    #[test]
+    fn test_compose_clipped_contig() {
+        assert_eq!(composed("2H4M", 0, "2S6M", 50), ("2H2S2M".to_string(), 50));
+    }
+
+    #[test]
+    fn test_compose_eqx() {
+        assert_eq!(
+            composed("2=1X2=", 0, "1X4=", 10),
+            ("1X1=1X2=".to_string(), 10)
+        );
+    }
+
+    #[test]
+    fn test_compose_beyond_contig() {
+        let a: Cigar = "10M".parse().unwrap();
+        let b: Cigar = "8M".parse().unwrap();
+        assert!(matches!(
+            compose(&a, 0, &b, 0),
+            Err(CigarError::LengthMismatch(8, 10))
+        ));
+    }
+}
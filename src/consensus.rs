@@ -0,0 +1,268 @@
+//! Consensus calling over collated events.
+//!
+//! The consensus at a reference position is the event carried by the majority of the
+//! records covering it. [`ConsensusIterator`] consumes per-position
+//! [summaries](crate::summary) and reports the positions where an insertion, deletion
+//! or mismatch is the consensus, and [`consensus_cigar`] turns those variants into a
+//! consensus alignment against the reference.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::CigarOp;
+//! use cigar_utils::collated::CollatedAugmentedCigarIterator;
+//! use cigar_utils::consensus::{ConsensusIterator, ConsensusOptions, consensus_cigar};
+//!
+//! let cigars = vec![
+//!     std::io::Result::Ok(("3M2D3M".to_string(), 1, 100)),
+//!     std::io::Result::Ok(("3M2D3M".to_string(), 1, 100)),
+//!     std::io::Result::Ok(("8M".to_string(), 1, 100)),
+//! ];
+//! let summaries = CollatedAugmentedCigarIterator::new(cigars.into_iter()).summarized();
+//! let variants: Vec<_> = ConsensusIterator::new(summaries, ConsensusOptions::default())
+//!     .collect::<Result<_, _>>()
+//!     .unwrap();
+//! assert_eq!(variants.len(), 1);
+//! assert_eq!((variants[0].position, variants[0].op), (103, CigarOp::Deletion));
+//!
+//! let cigar = consensus_cigar(&variants, 100, 108).unwrap();
+//! assert_eq!(cigar.to_string(), "3M2D3M");
+//! ```
+
+use crate::builder::CigarBuilder;
+use crate::error::CigarError;
+use crate::summary::{EventCount, PositionSummary};
+use crate::{Cigar, CigarOp};
+
+/// How to resolve a variant supported by exactly as many records as the reference.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TieBreak {
+    /// Call the reference.
+    #[default]
+    PreferReference,
+    /// Call the variant.
+    PreferVariant,
+}
+
+/// Options controlling when a variant is called as the consensus.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConsensusOptions {
+    /// The minimum depth of coverage at which to call a variant.
+    pub min_depth: usize,
+    /// The minimum fraction of covering records which must carry the variant.
+    pub min_fraction: f64,
+    /// How to resolve a variant carried by exactly half the covering records.
+    pub tie_break: TieBreak,
+}
+
+impl Default for ConsensusOptions {
+    fn default() -> Self {
+        ConsensusOptions {
+            min_depth: 1,
+            min_fraction: 0.5,
+            tie_break: TieBreak::PreferReference,
+        }
+    }
+}
+
+/// A variant which is the consensus at a reference position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConsensusVariant {
+    /// The chromosome ID of the variant.
+    pub chrom_id: u32,
+    /// The reference position of the variant.
+    pub position: u64,
+    /// The operation of the variant: an insertion, deletion or mismatch.
+    pub op: CigarOp,
+    /// The length of the variant.
+    pub length: u32,
+    /// The number of records carrying the variant.
+    pub count: usize,
+    /// The depth of coverage at the position.
+    pub depth: usize,
+}
+
+impl ConsensusVariant {
+    /// The fraction of covering records carrying the variant.
+    pub fn fraction(&self) -> f64 {
+        self.count as f64 / self.depth as f64
+    }
+}
+
+/// Choose the consensus variant at a position, if any.
+///
+/// The candidate is the variant event with the highest count; ties between variant
+/// events go to the first in collation order.
+pub fn call(summary: &PositionSummary, options: &ConsensusOptions) -> Option<ConsensusVariant> {
+    if summary.depth == 0 || summary.depth < options.min_depth {
+        return None;
+    }
+    let mut best: Option<&EventCount> = None;
+    for event in summary.events.iter().filter(|e| is_variant(e.op)) {
+        if best.is_none_or(|b| event.count > b.count) {
+            best = Some(event);
+        }
+    }
+    let best = best?;
+    let fraction = best.count as f64 / summary.depth as f64;
+    if fraction < options.min_fraction {
+        return None;
+    }
+    if 2 * best.count == summary.depth && options.tie_break == TieBreak::PreferReference {
+        return None;
+    }
+    Some(ConsensusVariant {
+        chrom_id: summary.chrom_id,
+        position: summary.position,
+        op: best.op,
+        length: best.length,
+        count: best.count,
+        depth: summary.depth,
+    })
+}
+
+fn is_variant(op: CigarOp) -> bool {
+    matches!(op, CigarOp::Insertion | CigarOp::Deletion | CigarOp::Diff)
+}
+
+/// An iterator over the consensus variants of a stream of per-position summaries.
+pub struct ConsensusIterator<I> {
+    inner: I,
+    options: ConsensusOptions,
+}
+
+impl<I> ConsensusIterator<I> {
+    /// Create a new consensus iterator.
+    pub fn new(inner: I, options: ConsensusOptions) -> Self {
+        ConsensusIterator { inner, options }
+    }
+}
+
+impl<I> Iterator for ConsensusIterator<I>
+where
+    I: Iterator<Item = std::result::Result<PositionSummary, CigarError>>,
+{
+    type Item = std::result::Result<ConsensusVariant, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.inner.next()? {
+                Ok(summary) => {
+                    if let Some(variant) = call(&summary, &self.options) {
+                        return Some(Ok(variant));
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Build the consensus CIGAR of the reference interval `start..end` from its consensus
+/// variants, which must be on a single chromosome and sorted by position.
+///
+/// Mismatches become `X` and the rest of the interval `M`. Variants outside the interval,
+/// or starting inside an earlier consensus deletion, are ignored.
+pub fn consensus_cigar(
+    variants: &[ConsensusVariant],
+    start: u64,
+    end: u64,
+) -> std::result::Result<Cigar, CigarError> {
+    let mut builder = CigarBuilder::new();
+    let mut position = start;
+    for variant in variants {
+        if variant.position < position || variant.position >= end {
+            continue;
+        }
+        if variant.position > position {
+            builder.push(CigarOp::Match, (variant.position - position) as u32)?;
+        }
+        position = variant.position;
+        match variant.op {
+            CigarOp::Insertion => {
+                builder.push(CigarOp::Insertion, variant.length)?;
+            }
+            op => {
+                let length = variant.length.min((end - position) as u32);
+                builder.push(op, length)?;
+                position += length as u64;
+            }
+        }
+    }
+    if end > position {
+        builder.push(CigarOp::Match, (end - position) as u32)?;
+    }
+    Ok(builder.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collated::collated;
+
+    fn variants(cigars: Vec<(&str, u32, u64)>, options: ConsensusOptions) -> Vec<ConsensusVariant> {
+        let summaries = collated(cigars).summarized();
+        ConsensusIterator::new(summaries, options)
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_consensus_majority() {
+        let result = variants(
+            vec![("2M1I2M", 1, 100), ("2M1I2M", 1, 100), ("4M", 1, 100)],
+            ConsensusOptions::default(),
+        );
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].op, CigarOp::Insertion);
+        assert_eq!((result[0].count, result[0].depth), (2, 3));
+    }
+
+    #[test]
+    fn test_consensus_tie_break() {
+        let cigars = vec![("2M1X2M", 1, 100), ("5M", 1, 100)];
+        assert!(variants(cigars.clone(), ConsensusOptions::default()).is_empty());
+        let options = ConsensusOptions {
+            tie_break: TieBreak::PreferVariant,
+            ..Default::default()
+        };
+        let result = variants(cigars, options);
+        assert_eq!((result[0].position, result[0].op), (102, CigarOp::Diff));
+    }
+
+    #[test]
+    fn test_consensus_thresholds() {
+        let cigars = vec![("2M1D2M", 1, 100), ("2M1D2M", 1, 100), ("5M", 1, 100)];
+        let options = ConsensusOptions {
+            min_fraction: 0.75,
+            ..Default::default()
+        };
+        assert!(variants(cigars.clone(), options).is_empty());
+        let options = ConsensusOptions {
+            min_depth: 4,
+            ..Default::default()
+        };
+        assert!(variants(cigars, options).is_empty());
+    }
+
+    #[test]
+    fn test_consensus_cigar() {
+        let variant = |position, op, length| ConsensusVariant {
+            chrom_id: 1,
+            position,
+            op,
+            length,
+            count: 1,
+            depth: 1,
+        };
+        let variants = vec![
+            variant(102, CigarOp::Insertion, 2),
+            variant(104, CigarOp::Deletion, 3),
+            variant(105, CigarOp::Diff, 1),
+            variant(108, CigarOp::Diff, 1),
+            variant(109, CigarOp::Deletion, 5),
+        ];
+        let cigar = consensus_cigar(&variants, 100, 110).unwrap();
+        assert_eq!(cigar.to_string(), "2M2I2M3D1M1X1D");
+    }
+}
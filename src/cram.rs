@@ -0,0 +1,351 @@
+//! Conversion to and from CRAM read features.
+//!
+//! CRAM does not store a CIGAR or the bases of a mapped read. Instead each read has a
+//! length and a series of read features, each at a position in the read, describing
+//! how it differs from the reference: substituted bases, inserted and soft clipped bases,
+//! and deleted, skipped, hard clipped and padded lengths. Bases between the features
+//! match the reference. [`to_read_features`] derives the features of an alignment, and
+//! [`from_read_features`] rebuilds the CIGAR and the read from them.
+//!
+//! Feature positions count from 1, as in CRAM. Aligned stretches are decoded as `M`, so
+//! a CIGAR using `=` and `X` comes back with `M` in their place.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::Cigar;
+//! use cigar_utils::cram::{ReadFeature, from_read_features, to_read_features};
+//!
+//! let reference = b"ACGTACGT";
+//! let cigar: Cigar = "1S3M1D3M".parse().unwrap();
+//! let features = to_read_features(0, &cigar, b"TACTACG", reference).unwrap();
+//! assert_eq!(
+//!     features,
+//!     vec![
+//!         ReadFeature::SoftClip { position: 1, bases: b"T".to_vec() },
+//!         ReadFeature::Substitution { position: 4, base: b'T' },
+//!         ReadFeature::Deletion { position: 5, length: 1 },
+//!     ]
+//! );
+//! let (decoded, read) = from_read_features(0, &features, 7, reference).unwrap();
+//! assert_eq!(decoded, cigar);
+//! assert_eq!(read, b"TACTACG");
+//! ```
+
+use crate::builder::CigarBuilder;
+use crate::error::CigarError;
+use crate::{Cigar, CigarOp};
+
+/// A CRAM read feature. Positions are 1-based positions in the read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadFeature {
+    /// An aligned base differing from the reference (`X`).
+    Substitution {
+        /// The position of the base.
+        position: u32,
+        /// The read base.
+        base: u8,
+    },
+    /// Inserted bases (`I`, or `i` for a single base).
+    Insertion {
+        /// The position of the first inserted base.
+        position: u32,
+        /// The inserted bases.
+        bases: Vec<u8>,
+    },
+    /// Soft clipped bases (`S`).
+    SoftClip {
+        /// The position of the first clipped base.
+        position: u32,
+        /// The clipped bases.
+        bases: Vec<u8>,
+    },
+    /// Deleted reference bases (`D`), before the read position.
+    Deletion {
+        /// The position of the read base following the deletion.
+        position: u32,
+        /// The number of reference bases deleted.
+        length: u32,
+    },
+    /// Skipped reference bases (`N`), before the read position.
+    ReferenceSkip {
+        /// The position of the read base following the skip.
+        position: u32,
+        /// The number of reference bases skipped.
+        length: u32,
+    },
+    /// Hard clipped bases (`H`), before the read position.
+    HardClip {
+        /// The position of the read base following the clip.
+        position: u32,
+        /// The number of bases clipped.
+        length: u32,
+    },
+    /// Padding (`P`), before the read position.
+    Padding {
+        /// The position of the read base following the padding.
+        position: u32,
+        /// The length of the padding.
+        length: u32,
+    },
+}
+
+impl ReadFeature {
+    /// The position of the feature in the read.
+    pub fn position(&self) -> u32 {
+        match self {
+            ReadFeature::Substitution { position, .. }
+            | ReadFeature::Insertion { position, .. }
+            | ReadFeature::SoftClip { position, .. }
+            | ReadFeature::Deletion { position, .. }
+            | ReadFeature::ReferenceSkip { position, .. }
+            | ReadFeature::HardClip { position, .. }
+            | ReadFeature::Padding { position, .. } => *position,
+        }
+    }
+
+    /// The CRAM feature code.
+    pub fn code(&self) -> char {
+        match self {
+            ReadFeature::Substitution { .. } => 'X',
+            ReadFeature::Insertion { bases, .. } if bases.len() == 1 => 'i',
+            ReadFeature::Insertion { .. } => 'I',
+            ReadFeature::SoftClip { .. } => 'S',
+            ReadFeature::Deletion { .. } => 'D',
+            ReadFeature::ReferenceSkip { .. } => 'N',
+            ReadFeature::HardClip { .. } => 'H',
+            ReadFeature::Padding { .. } => 'P',
+        }
+    }
+}
+
+/// The read features of `seq` aligned by `cigar` at `reference_position` in `reference`.
+///
+/// Aligned bases are compared to the reference case insensitively, and each which
+/// differs becomes a substitution, whatever its operation.
+pub fn to_read_features(
+    reference_position: usize,
+    cigar: &Cigar,
+    seq: &[u8],
+    reference: &[u8],
+) -> std::result::Result<Vec<ReadFeature>, CigarError> {
+    let mut features = Vec::new();
+    let mut read_position = 0;
+    let mut position = reference_position;
+    for elem in cigar.iter() {
+        let length = elem.length as usize;
+        let next = read_position as u32 + 1;
+        if elem.op.consumes_query() && read_position + length > seq.len() {
+            return Err(CigarError::SequenceTooShort(
+                read_position + length,
+                seq.len(),
+            ));
+        }
+        match elem.op {
+            CigarOp::Match | CigarOp::Equal | CigarOp::Diff => {
+                let end = position + length;
+                if end > reference.len() {
+                    return Err(CigarError::ReferenceOutOfBounds(
+                        position as u64,
+                        end as u64,
+                    ));
+                }
+                let read = &seq[read_position..read_position + length];
+                for (i, (base, reference_base)) in
+                    read.iter().zip(&reference[position..end]).enumerate()
+                {
+                    if !base.eq_ignore_ascii_case(reference_base) {
+                        features.push(ReadFeature::Substitution {
+                            position: next + i as u32,
+                            base: *base,
+                        });
+                    }
+                }
+                position = end;
+            }
+            CigarOp::Insertion => features.push(ReadFeature::Insertion {
+                position: next,
+                bases: seq[read_position..read_position + length].to_vec(),
+            }),
+            CigarOp::SoftClip => features.push(ReadFeature::SoftClip {
+                position: next,
+                bases: seq[read_position..read_position + length].to_vec(),
+            }),
+            CigarOp::Deletion => {
+                features.push(ReadFeature::Deletion {
+                    position: next,
+                    length: elem.length,
+                });
+                position += length;
+            }
+            CigarOp::Skip => {
+                features.push(ReadFeature::ReferenceSkip {
+                    position: next,
+                    length: elem.length,
+                });
+                position += length;
+            }
+            CigarOp::HardClip => features.push(ReadFeature::HardClip {
+                position: next,
+                length: elem.length,
+            }),
+            CigarOp::Padding => features.push(ReadFeature::Padding {
+                position: next,
+                length: elem.length,
+            }),
+        }
+        if elem.op.consumes_query() {
+            read_position += length;
+        }
+    }
+    if read_position != seq.len() {
+        return Err(CigarError::LengthMismatch(read_position, seq.len()));
+    }
+    Ok(features)
+}
+
+/// The format name given in [`CigarError::InvalidFormat`] errors.
+const FORMAT: &str = "CRAM";
+
+/// Rebuild the CIGAR and the bases of a read of `read_length` bases from its read
+/// features, with the alignment starting at `reference_position` in `reference`.
+///
+/// The features must be in order of position, and must not overlap.
+pub fn from_read_features(
+    reference_position: usize,
+    features: &[ReadFeature],
+    read_length: usize,
+    reference: &[u8],
+) -> std::result::Result<(Cigar, Vec<u8>), CigarError> {
+    let mut builder = CigarBuilder::new();
+    let mut read = Vec::with_capacity(read_length);
+    let mut position = reference_position;
+    let matched = |read: &mut Vec<u8>,
+                   position: &mut usize,
+                   builder: &mut CigarBuilder,
+                   length: usize|
+     -> std::result::Result<(), CigarError> {
+        if length == 0 {
+            return Ok(());
+        }
+        let end = *position + length;
+        if end > reference.len() {
+            return Err(CigarError::ReferenceOutOfBounds(
+                *position as u64,
+                end as u64,
+            ));
+        }
+        read.extend_from_slice(&reference[*position..end]);
+        builder.push(CigarOp::Match, length as u32)?;
+        *position = end;
+        Ok(())
+    };
+    for feature in features {
+        let start = feature.position() as usize;
+        if start <= read.len() {
+            return Err(CigarError::invalid_format(
+                FORMAT,
+                format!(
+                    "'{}' feature at read position {} overlaps the previous feature",
+                    feature.code(),
+                    start
+                ),
+            ));
+        }
+        let length = start - 1 - read.len();
+        matched(&mut read, &mut position, &mut builder, length)?;
+        match feature {
+            ReadFeature::Substitution { base, .. } => {
+                matched(&mut read, &mut position, &mut builder, 1)?;
+                *read.last_mut().expect("a base was just added") = *base;
+            }
+            ReadFeature::Insertion { bases, .. } => {
+                builder.push(CigarOp::Insertion, bases.len() as u32)?;
+                read.extend_from_slice(bases);
+            }
+            ReadFeature::SoftClip { bases, .. } => {
+                builder.push(CigarOp::SoftClip, bases.len() as u32)?;
+                read.extend_from_slice(bases);
+            }
+            ReadFeature::Deletion { length, .. } => {
+                builder.push(CigarOp::Deletion, *length)?;
+                position += *length as usize;
+            }
+            ReadFeature::ReferenceSkip { length, .. } => {
+                builder.push(CigarOp::Skip, *length)?;
+                position += *length as usize;
+            }
+            ReadFeature::HardClip { length, .. } => {
+                builder.push(CigarOp::HardClip, *length)?;
+            }
+            ReadFeature::Padding { length, .. } => {
+                builder.push(CigarOp::Padding, *length)?;
+            }
+        }
+        if read.len() > read_length {
+            return Err(CigarError::LengthMismatch(read_length, read.len()));
+        }
+    }
+    let remaining = read_length - read.len();
+    matched(&mut read, &mut position, &mut builder, remaining)?;
+    Ok((builder.build(), read))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_feature_round_trip() {
+        let reference = b"TTACGTACGTACGTAC";
+        let seq = b"GGACTGTATACAAAA";
+        let cigar: Cigar = "3H2S3M1I2M2D3M4S".parse().unwrap();
+        let features = to_read_features(2, &cigar, seq, reference).unwrap();
+        let codes: String = features.iter().map(|f| f.code()).collect();
+        assert_eq!(codes, "HSXiDS");
+        assert_eq!(
+            features[2],
+            ReadFeature::Substitution {
+                position: 5,
+                base: b'T'
+            }
+        );
+        let (decoded, read) = from_read_features(2, &features, seq.len(), reference).unwrap();
+        assert_eq!(decoded, cigar);
+        assert_eq!(read, seq);
+
+        let spliced: Cigar = "2=100N2X1P1I".parse().unwrap();
+        let reference = vec![b'A'; 110];
+        let features = to_read_features(0, &spliced, b"AACCG", &reference).unwrap();
+        let (decoded, read) = from_read_features(0, &features, 5, &reference).unwrap();
+        assert_eq!(decoded.to_string(), "2M100N2M1P1I");
+        assert_eq!(read, b"AACCG");
+    }
+
+    #[test]
+    fn test_invalid_read_features() {
+        let cigar: Cigar = "4M".parse().unwrap();
+        assert!(matches!(
+            to_read_features(0, &cigar, b"ACG", b"ACGT"),
+            Err(CigarError::SequenceTooShort(4, 3))
+        ));
+        let overlapping = [
+            ReadFeature::Insertion {
+                position: 2,
+                bases: b"AA".to_vec(),
+            },
+            ReadFeature::Substitution {
+                position: 3,
+                base: b'G',
+            },
+        ];
+        assert!(matches!(
+            from_read_features(0, &overlapping, 4, b"ACGT"),
+            Err(CigarError::InvalidFormat("CRAM", _))
+        ));
+        assert!(matches!(
+            from_read_features(0, &overlapping[..1], 2, b"ACGT"),
+            Err(CigarError::LengthMismatch(2, 3))
+        ));
+    }
+}
@@ -0,0 +1,472 @@
+//! MUMmer delta format.
+//!
+//! `nucmer` and `promer` write alignments as delta files: a `>` header naming the
+//! reference and query sequences, then for each alignment a line of one-based, inclusive
+//! coordinates and error counts, followed by the delta-encoded gaps and a terminating
+//! zero. A positive delta `n` means `n - 1` aligned bases and then a base in the
+//! reference missing from the query (a deletion); a negative delta means the same with a
+//! base in the query missing from the reference (an insertion).
+//!
+//! Reverse strand alignments have query start greater than query end, and their deltas
+//! run along the reverse complement of the query, which is how SAM lays out reverse
+//! strand reads.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::{Cigar, Strand};
+//! use cigar_utils::delta::{DeltaAlignment, cigar_from_deltas, deltas};
+//!
+//! let cigar: Cigar = "5M1I3M2D4M".parse().unwrap();
+//! assert_eq!(deltas(&cigar), vec![-6, 4, 1]);
+//! assert_eq!(cigar_from_deltas(&[-6, 4, 1], 14).unwrap().to_string(), "5M1I3M2D4M");
+//!
+//! let cigar: Cigar = "3S5M1I3M2D4M".parse().unwrap();
+//! let alignment = DeltaAlignment::from_alignment(&cigar, "chr1", 1000, 99, "read1", Strand::Forward);
+//! assert_eq!(
+//!     alignment.to_string(),
+//!     ">chr1 read1 1000 16\n100 113 4 16 3 3 0\n-6\n4\n1\n0\n"
+//! );
+//!
+//! let parsed: DeltaAlignment = alignment.to_string().parse().unwrap();
+//! let (cigar, position, strand) = parsed.to_alignment().unwrap();
+//! assert_eq!(cigar.to_string(), "3H5M1I3M2D4M");
+//! assert_eq!((position, strand), (99, Strand::Forward));
+//! ```
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::error::{CigarError, parse_number};
+use crate::{Cigar, CigarElement, CigarOp, Strand};
+
+/// The deltas of the aligned part of a CIGAR, without the terminating zero.
+///
+/// Each deleted or skipped reference base gives a positive delta and each inserted base
+/// a negative one. Clips and padding are ignored.
+pub fn deltas(cigar: &Cigar) -> Vec<i64> {
+    let mut deltas = Vec::new();
+    let mut distance = 1;
+    for elem in cigar.iter() {
+        match elem.op {
+            CigarOp::Match | CigarOp::Equal | CigarOp::Diff => distance += elem.length as i64,
+            CigarOp::Deletion | CigarOp::Skip => {
+                for _ in 0..elem.length {
+                    deltas.push(distance);
+                    distance = 1;
+                }
+            }
+            CigarOp::Insertion => {
+                for _ in 0..elem.length {
+                    deltas.push(-distance);
+                    distance = 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    deltas
+}
+
+/// Convert deltas into CIGAR elements for an alignment spanning `reference_length`
+/// reference bases.
+///
+/// A zero delta ends the list. The aligned bases after the last gap fill out the
+/// reference span.
+pub fn cigar_from_deltas(
+    deltas: &[i64],
+    reference_length: u64,
+) -> std::result::Result<Cigar, CigarError> {
+    let mut elements: Vec<CigarElement> = Vec::new();
+    let mut push = |length: u64, op: CigarOp| {
+        if length == 0 {
+            return;
+        }
+        match elements.last_mut() {
+            Some(last) if last.op == op => last.length += length as u32,
+            _ => elements.push(CigarElement::new(length as u32, op)),
+        }
+    };
+    let mut consumed = 0;
+    for &delta in deltas.iter().take_while(|&&delta| delta != 0) {
+        let matched = delta.unsigned_abs() - 1;
+        push(matched, CigarOp::Match);
+        consumed += matched;
+        if delta > 0 {
+            push(1, CigarOp::Deletion);
+            consumed += 1;
+        } else {
+            push(1, CigarOp::Insertion);
+        }
+    }
+    if consumed > reference_length {
+        return Err(CigarError::LengthMismatch(
+            reference_length as usize,
+            consumed as usize,
+        ));
+    }
+    push(reference_length - consumed, CigarOp::Match);
+    Ok(Cigar::from(elements))
+}
+
+/// An alignment from a delta file, with the header of its sequence pair.
+///
+/// Coordinates are one-based and inclusive, as in delta files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaAlignment {
+    /// The name of the reference sequence.
+    pub reference_name: String,
+    /// The name of the query sequence.
+    pub query_name: String,
+    /// The length of the reference sequence.
+    pub reference_size: u64,
+    /// The length of the query sequence.
+    pub query_size: u64,
+    /// The first aligned reference base.
+    pub reference_start: u64,
+    /// The last aligned reference base.
+    pub reference_end: u64,
+    /// The first aligned query base; greater than `query_end` on the reverse strand.
+    pub query_start: u64,
+    /// The last aligned query base.
+    pub query_end: u64,
+    /// The number of errors (mismatches and gaps).
+    pub errors: u64,
+    /// The number of similarity errors (non-positive protein scores for `promer`).
+    pub similarity_errors: u64,
+    /// The number of stop codons (`promer` only).
+    pub stop_codons: u64,
+    /// The deltas, without the terminating zero.
+    pub deltas: Vec<i64>,
+}
+
+impl DeltaAlignment {
+    /// Build a delta alignment from an alignment starting at the zero-based
+    /// `reference_position`.
+    ///
+    /// The query size is the read length including hard clips. Without the sequences,
+    /// errors count only gapped and mismatched (`X`) bases.
+    pub fn from_alignment(
+        cigar: &Cigar,
+        reference_name: &str,
+        reference_size: u64,
+        reference_position: u64,
+        query_name: &str,
+        strand: Strand,
+    ) -> DeltaAlignment {
+        let query_size = cigar.query_length_with_hard_clips() as u64;
+        let (leading, trailing) = (cigar.leading_clip() as u64, cigar.trailing_clip() as u64);
+        let (query_start, query_end) = match strand {
+            Strand::Forward => (leading + 1, query_size - trailing),
+            Strand::Reverse => (query_size - leading, trailing + 1),
+        };
+        let errors = cigar
+            .iter()
+            .filter(|e| {
+                matches!(
+                    e.op,
+                    CigarOp::Diff | CigarOp::Insertion | CigarOp::Deletion | CigarOp::Skip
+                )
+            })
+            .map(|e| e.length as u64)
+            .sum();
+        DeltaAlignment {
+            reference_name: reference_name.to_string(),
+            query_name: query_name.to_string(),
+            reference_size,
+            query_size,
+            reference_start: reference_position + 1,
+            reference_end: cigar.alignment_end(reference_position),
+            query_start,
+            query_end,
+            errors,
+            similarity_errors: errors,
+            stop_codons: 0,
+            deltas: deltas(cigar),
+        }
+    }
+
+    /// The strand of the query.
+    pub fn strand(&self) -> Strand {
+        if self.query_start > self.query_end {
+            Strand::Reverse
+        } else {
+            Strand::Forward
+        }
+    }
+
+    /// Check that the coordinates are one-based positions within the sequences, with the
+    /// reference interval in order.
+    fn check_coordinates(&self) -> std::result::Result<(), CigarError> {
+        let within =
+            |start: u64, end: u64, size: u64| start >= 1 && end >= 1 && start.max(end) <= size;
+        if self.reference_start > self.reference_end
+            || !within(
+                self.reference_start,
+                self.reference_end,
+                self.reference_size,
+            )
+            || !within(self.query_start, self.query_end, self.query_size)
+        {
+            return Err(self.outside_sequences());
+        }
+        Ok(())
+    }
+
+    fn outside_sequences(&self) -> CigarError {
+        CigarError::invalid_format(
+            FORMAT,
+            format!(
+                "alignment {} {} {} {} is not within sequences of lengths {} and {}",
+                self.reference_start,
+                self.reference_end,
+                self.query_start,
+                self.query_end,
+                self.reference_size,
+                self.query_size
+            ),
+        )
+    }
+
+    /// The alignment: its CIGAR, zero-based reference position and strand.
+    ///
+    /// The query bases outside the alignment become hard clips. Fails if the coordinates
+    /// are not one-based positions within the sequences.
+    pub fn to_alignment(&self) -> std::result::Result<(Cigar, u64, Strand), CigarError> {
+        let strand = self.strand();
+        let (leading, trailing) = match strand {
+            Strand::Forward => (
+                self.query_start.checked_sub(1),
+                self.query_size.checked_sub(self.query_end),
+            ),
+            Strand::Reverse => (
+                self.query_size.checked_sub(self.query_start),
+                self.query_end.checked_sub(1),
+            ),
+        };
+        let reference_length = self
+            .reference_end
+            .checked_sub(self.reference_start)
+            .and_then(|length| length.checked_add(1));
+        let position = self.reference_start.checked_sub(1);
+        let (Some(leading), Some(trailing), Some(reference_length), Some(position)) =
+            (leading, trailing, reference_length, position)
+        else {
+            return Err(self.outside_sequences());
+        };
+        let clip = |length: u64| {
+            u32::try_from(length)
+                .map(|length| CigarElement::new(length, CigarOp::HardClip))
+                .map_err(|_| {
+                    CigarError::invalid_format(
+                        FORMAT,
+                        format!("{} bases are too many to clip", length),
+                    )
+                })
+        };
+        let mut elements = Vec::new();
+        if leading > 0 {
+            elements.push(clip(leading)?);
+        }
+        elements.extend(cigar_from_deltas(&self.deltas, reference_length)?);
+        if trailing > 0 {
+            elements.push(clip(trailing)?);
+        }
+        Ok((Cigar::from(elements), position, strand))
+    }
+}
+
+impl Display for DeltaAlignment {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            ">{} {} {} {}",
+            self.reference_name, self.query_name, self.reference_size, self.query_size
+        )?;
+        writeln!(
+            f,
+            "{} {} {} {} {} {} {}",
+            self.reference_start,
+            self.reference_end,
+            self.query_start,
+            self.query_end,
+            self.errors,
+            self.similarity_errors,
+            self.stop_codons
+        )?;
+        for delta in &self.deltas {
+            writeln!(f, "{}", delta)?;
+        }
+        writeln!(f, "0")
+    }
+}
+
+/// The format name given in [`CigarError::InvalidFormat`] errors.
+const FORMAT: &str = "delta";
+
+/// Parse the alignments of a delta file.
+///
+/// The two preamble lines (the sequence file names and the program) are skipped if
+/// present. Each alignment takes the names and sizes of the `>` header before it, and
+/// its coordinates must be one-based positions within those sizes.
+pub fn parse_delta(text: &str) -> std::result::Result<Vec<DeltaAlignment>, CigarError> {
+    let mut alignments = Vec::new();
+    let mut header: Option<(String, String, u64, u64)> = None;
+    let mut current: Option<DeltaAlignment> = None;
+    let lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+    for line in lines.skip_while(|line| !line.starts_with('>')) {
+        if let Some(rest) = line.strip_prefix('>') {
+            if current.is_some() {
+                return Err(CigarError::invalid_format(
+                    FORMAT,
+                    "alignment without terminating zero",
+                ));
+            }
+            let fields: Vec<&str> = rest.split_whitespace().collect();
+            if fields.len() != 4 {
+                return Err(CigarError::invalid_format(
+                    FORMAT,
+                    format!("bad header line '{}'", line),
+                ));
+            }
+            header = Some((
+                fields[0].to_string(),
+                fields[1].to_string(),
+                parse_number(FORMAT, fields[2])?,
+                parse_number(FORMAT, fields[3])?,
+            ));
+            continue;
+        }
+        if let Some(alignment) = current.as_mut() {
+            match parse_number::<i64>(FORMAT, line)? {
+                0 => alignments.extend(current.take()),
+                delta => alignment.deltas.push(delta),
+            }
+            continue;
+        }
+        let Some((reference_name, query_name, reference_size, query_size)) = header.clone() else {
+            return Err(CigarError::invalid_format(
+                FORMAT,
+                "alignment before header",
+            ));
+        };
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 7 {
+            return Err(CigarError::invalid_format(
+                FORMAT,
+                format!("bad alignment line '{}'", line),
+            ));
+        }
+        let alignment = DeltaAlignment {
+            reference_name,
+            query_name,
+            reference_size,
+            query_size,
+            reference_start: parse_number(FORMAT, fields[0])?,
+            reference_end: parse_number(FORMAT, fields[1])?,
+            query_start: parse_number(FORMAT, fields[2])?,
+            query_end: parse_number(FORMAT, fields[3])?,
+            errors: parse_number(FORMAT, fields[4])?,
+            similarity_errors: parse_number(FORMAT, fields[5])?,
+            stop_codons: parse_number(FORMAT, fields[6])?,
+            deltas: Vec::new(),
+        };
+        alignment.check_coordinates()?;
+        current = Some(alignment);
+    }
+    if current.is_some() {
+        return Err(CigarError::invalid_format(
+            FORMAT,
+            "alignment without terminating zero",
+        ));
+    }
+    Ok(alignments)
+}
+
+impl FromStr for DeltaAlignment {
+    type Err = CigarError;
+
+    /// Parse a single alignment: a header line, an alignment line and its deltas.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut alignments = parse_delta(s)?;
+        if alignments.len() != 1 {
+            return Err(CigarError::invalid_format(
+                FORMAT,
+                format!("expected one alignment, found {}", alignments.len()),
+            ));
+        }
+        Ok(alignments.remove(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deltas() {
+        let cigar: Cigar = "2S3M2I4M1D1N2M".parse().unwrap();
+        assert_eq!(deltas(&cigar), vec![-4, -1, 5, 1]);
+        assert_eq!(
+            cigar_from_deltas(&deltas(&cigar), 11).unwrap().to_string(),
+            "3M2I4M2D2M"
+        );
+        assert!(matches!(
+            cigar_from_deltas(&[5, 0, 3], 3),
+            Err(CigarError::LengthMismatch(3, 5))
+        ));
+        assert_eq!(cigar_from_deltas(&[], 4).unwrap().to_string(), "4M");
+    }
+
+    #[test]
+    fn test_parse_delta() {
+        let text = "/data/ref.fa /data/qry.fa\nNUCMER\n\
+                    >chr1 contig7 5000 300\n\
+                    1 10 20 11 0 0 0\n0\n\
+                    101 150 1 50 2 2 0\n-10\n3\n0\n\
+                    >chr2 contig7 4000 300\n\
+                    11 20 1 10 0 0 0\n0\n";
+        let alignments = parse_delta(text).unwrap();
+        assert_eq!(alignments.len(), 3);
+        assert_eq!(alignments[0].strand(), Strand::Reverse);
+        let (cigar, position, strand) = alignments[0].to_alignment().unwrap();
+        assert_eq!(cigar.to_string(), "280H10M10H");
+        assert_eq!((position, strand), (0, Strand::Reverse));
+        let (cigar, position, _) = alignments[1].to_alignment().unwrap();
+        assert_eq!(cigar.to_string(), "9M1I2M1D38M250H");
+        assert_eq!(position, 100);
+        assert_eq!(alignments[2].reference_name, "chr2");
+    }
+
+    #[test]
+    fn test_parse_delta_errors() {
+        assert!(matches!(
+            parse_delta(">chr1 q 100 10\n1 10 1 10 0 0 0\n"),
+            Err(CigarError::InvalidFormat("delta", _))
+        ));
+        assert!(matches!(
+            parse_delta(">chr1 q 100 10\n1 10 1 10 0 0\n0\n"),
+            Err(CigarError::InvalidFormat("delta", _))
+        ));
+        assert!(matches!(
+            ">chr1 q 100 10\n1 10 1 10 0 0 0\nx\n0\n".parse::<DeltaAlignment>(),
+            Err(CigarError::InvalidFormat("delta", _))
+        ));
+        for coordinates in [
+            "1 10 0 10",
+            "0 10 1 10",
+            "10 1 1 10",
+            "1 101 1 10",
+            "1 10 11 1",
+        ] {
+            let text = format!(">r q 100 10\n{} 0 0 0\n0\n", coordinates);
+            assert!(matches!(
+                parse_delta(&text),
+                Err(CigarError::InvalidFormat("delta", _))
+            ));
+        }
+        let mut alignment: DeltaAlignment = ">r q 100 10\n1 10 1 10 0 0 0\n0\n".parse().unwrap();
+        alignment.query_start = 0;
+        assert!(alignment.to_alignment().is_err());
+    }
+}
@@ -0,0 +1,487 @@
+//! Coverage depth.
+//!
+//! This module computes the depth of coverage along the reference from the same sorted
+//! source of records used by [`collation`](crate::collated), so that counts of events can
+//! be turned into frequencies. Depth is reported as runs of positions with the same
+//! depth, or position by position.
+//!
+//! Bases covered by alignment matches and deletions count towards the depth. Skipped
+//! regions (introns) do not, unless [`include_skips`](DepthIterator::include_skips) is used.
+//!
+//! A [`DepthTrack`] holds the runs of a whole genome in memory, run-length encoded, for
+//! looking up the depth at any position. It can be built from a source of records, or
+//! from the [gap-filled summaries](crate::summary::PositionSummaryIterator::with_gaps) of
+//! a collation as they are consumed, so that depth comes from the same pass.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::depth::{DepthIterator, DepthRun};
+//!
+//! let records = vec![
+//!     std::io::Result::Ok(("4M".to_string(), 1, 100)),
+//!     std::io::Result::Ok(("2M2N2M".to_string(), 1, 102)),
+//! ];
+//! let runs: Vec<_> = DepthIterator::new(records.into_iter())
+//!     .collect::<Result<_, _>>()
+//!     .unwrap();
+//! assert_eq!(
+//!     runs,
+//!     vec![
+//!         DepthRun { chrom_id: 1, start: 100, end: 102, depth: 1 },
+//!         DepthRun { chrom_id: 1, start: 102, end: 104, depth: 2 },
+//!         DepthRun { chrom_id: 1, start: 106, end: 108, depth: 1 },
+//!     ]
+//! );
+//! ```
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+    iter::Peekable,
+};
+
+use crate::CigarOp;
+use crate::error::CigarError;
+use crate::record::CigarRecord;
+use crate::summary::CoverageRecord;
+
+/// A run of reference positions `start..end` on a chromosome, all with the same depth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepthRun {
+    /// The chromosome ID of the run.
+    pub chrom_id: u32,
+    /// The first reference position of the run.
+    pub start: u64,
+    /// The reference position just past the end of the run.
+    pub end: u64,
+    /// The number of records covering each position of the run.
+    pub depth: u32,
+}
+
+/// The depth of coverage at a single reference position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PositionDepth {
+    /// The chromosome ID of the position.
+    pub chrom_id: u32,
+    /// The reference position.
+    pub position: u64,
+    /// The number of records covering the position.
+    pub depth: u32,
+}
+
+/// An iterator over runs of equal, non-zero depth, computed from a sorted source of records.
+///
+/// Positions with no coverage are not reported.
+pub struct DepthIterator<
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+> {
+    source: Peekable<Source>,
+    changes: BinaryHeap<Reverse<(u32, u64, i64)>>,
+    include_skips: bool,
+    depth: i64,
+    start: u64,
+}
+
+impl<
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+> DepthIterator<Source, R, E>
+{
+    /// Create a new depth iterator.
+    pub fn new(source: Source) -> Self {
+        DepthIterator {
+            source: source.peekable(),
+            changes: BinaryHeap::new(),
+            include_skips: false,
+            depth: 0,
+            start: 0,
+        }
+    }
+
+    /// Count skipped regions (`N`) towards the depth.
+    pub fn include_skips(mut self) -> Self {
+        self.include_skips = true;
+        self
+    }
+
+    /// Report the depth position by position rather than in runs.
+    pub fn per_position(self) -> PerPositionDepthIterator<Self> {
+        PerPositionDepthIterator {
+            inner: self,
+            current: None,
+        }
+    }
+}
+
+impl<
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+> Iterator for DepthIterator<Source, R, E>
+{
+    type Item = std::result::Result<DepthRun, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            while let Some(item) = self.source.peek() {
+                let item = match item {
+                    Ok(record) => record,
+                    Err(_) => {
+                        if let Some(Err(e)) = self.source.next() {
+                            return Some(Err(CigarError::External(Box::new(e))));
+                        }
+                        unreachable!("peeked an error");
+                    }
+                };
+                if item.cigar().is_unavailable() {
+                    self.source.next();
+                    continue;
+                }
+                let current = (item.chrom_id(), item.reference_position());
+                if let Some(Reverse((chrom_id, position, _))) = self.changes.peek()
+                    && current > (*chrom_id, *position)
+                {
+                    break;
+                }
+                let include_skips = self.include_skips;
+                let changes: std::result::Result<Vec<_>, CigarError> = item
+                    .augmented()
+                    .filter(|elem| !matches!(elem, Ok(e) if !covers(e.op, include_skips)))
+                    .collect();
+                let changes = match changes {
+                    Ok(changes) => changes,
+                    Err(e) => {
+                        self.source.next();
+                        return Some(Err(e));
+                    }
+                };
+                self.changes.extend(changes.into_iter().flat_map(|e| {
+                    [
+                        Reverse((e.chrom_id, e.reference_position, 1)),
+                        Reverse((e.chrom_id, e.reference_end(), -1)),
+                    ]
+                }));
+                self.source.next();
+            }
+
+            let Reverse((chrom_id, position, mut delta)) = self.changes.pop()?;
+            while let Some(Reverse((c, p, d))) = self.changes.peek() {
+                if (*c, *p) != (chrom_id, position) {
+                    break;
+                }
+                delta += d;
+                self.changes.pop();
+            }
+            if delta == 0 {
+                continue;
+            }
+            let run = DepthRun {
+                chrom_id,
+                start: self.start,
+                end: position,
+                depth: self.depth as u32,
+            };
+            self.depth += delta;
+            self.start = position;
+            if run.depth > 0 && run.start < run.end {
+                return Some(Ok(run));
+            }
+        }
+    }
+}
+
+fn covers(op: CigarOp, include_skips: bool) -> bool {
+    op.consumes_reference() && (include_skips || op != CigarOp::Skip)
+}
+
+/// An iterator over the depth at each covered position, created by
+/// [`DepthIterator::per_position`].
+pub struct PerPositionDepthIterator<I> {
+    inner: I,
+    current: Option<DepthRun>,
+}
+
+impl<I> Iterator for PerPositionDepthIterator<I>
+where
+    I: Iterator<Item = std::result::Result<DepthRun, CigarError>>,
+{
+    type Item = std::result::Result<PositionDepth, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(run) = self.current.as_mut()
+                && run.start < run.end
+            {
+                let position = PositionDepth {
+                    chrom_id: run.chrom_id,
+                    position: run.start,
+                    depth: run.depth,
+                };
+                run.start += 1;
+                return Some(Ok(position));
+            }
+            match self.inner.next()? {
+                Ok(run) => self.current = Some(run),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// The depth of coverage along a whole genome, stored as runs of equal depth.
+///
+/// Each chromosome holds the start of every run, with a run of depth zero for each
+/// uncovered interval between runs, so memory grows with the number of depth changes
+/// rather than the length of the genome. Looking up the depth at a position takes
+/// logarithmic time. Runs must be added in coordinate order; adjacent runs of equal
+/// depth are merged.
+///
+/// ```rust
+/// use cigar_utils::depth::DepthTrack;
+///
+/// let records = vec![
+///     std::io::Result::Ok(("4M".to_string(), 1, 100)),
+///     std::io::Result::Ok(("2M2N2M".to_string(), 1, 102)),
+///     std::io::Result::Ok(("3M".to_string(), 2, 10)),
+/// ];
+/// let track = DepthTrack::from_source(records.into_iter()).unwrap();
+/// assert_eq!(track.depth(1, 103), 2);
+/// assert_eq!(track.depth(1, 104), 0);
+/// assert_eq!(track.depth(2, 12), 1);
+/// assert_eq!(track.runs().count(), 4);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DepthTrack {
+    chroms: BTreeMap<u32, Vec<(u64, u32)>>,
+}
+
+impl DepthTrack {
+    /// Create an empty track.
+    pub fn new() -> Self {
+        DepthTrack::default()
+    }
+
+    /// Build a track from the depth runs of a sorted source of records.
+    pub fn from_source<Source, R, E>(source: Source) -> std::result::Result<Self, CigarError>
+    where
+        Source: Iterator<Item = std::result::Result<R, E>>,
+        R: CigarRecord,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        DepthTrack::from_runs(DepthIterator::new(source))
+    }
+
+    /// Build a track from a stream of runs in coordinate order.
+    pub fn from_runs<I>(runs: I) -> std::result::Result<Self, CigarError>
+    where
+        I: IntoIterator<Item = std::result::Result<DepthRun, CigarError>>,
+    {
+        let mut track = DepthTrack::new();
+        for run in runs {
+            track.push(run?)?;
+        }
+        Ok(track)
+    }
+
+    /// Add a run, which must not start before the end of the last run added.
+    ///
+    /// Gives [`CigarError::UnsortedInput`] for a run out of order. Empty runs and runs
+    /// of depth zero are ignored.
+    pub fn push(&mut self, run: DepthRun) -> std::result::Result<(), CigarError> {
+        if run.start >= run.end || run.depth == 0 {
+            return Ok(());
+        }
+        if let Some((&chrom_id, starts)) = self.chroms.last_key_value() {
+            let end = starts.last().map_or(0, |&(end, _)| end);
+            if (run.chrom_id, run.start) < (chrom_id, end) {
+                return Err(CigarError::UnsortedInput {
+                    previous: (chrom_id, end),
+                    current: (run.chrom_id, run.start),
+                });
+            }
+        }
+        let starts = self.chroms.entry(run.chrom_id).or_default();
+        // The last entry ends the last run, and starts an uncovered interval.
+        let n = starts.len();
+        if n > 0 && starts[n - 1].0 == run.start {
+            if n > 1 && starts[n - 2].1 == run.depth {
+                starts.pop();
+            } else {
+                starts[n - 1].1 = run.depth;
+            }
+        } else {
+            starts.push((run.start, run.depth));
+        }
+        starts.push((run.end, 0));
+        Ok(())
+    }
+
+    /// Add the coverage of a record of a [gap-filled summary
+    /// stream](crate::summary::PositionSummaryIterator::with_gaps), which are in order.
+    pub fn add_coverage(&mut self, record: &CoverageRecord) -> std::result::Result<(), CigarError> {
+        let run = match record {
+            CoverageRecord::Events(summary) => DepthRun {
+                chrom_id: summary.chrom_id,
+                start: summary.position,
+                end: summary.position + 1,
+                depth: summary.depth as u32,
+            },
+            CoverageRecord::Gap(gap) => DepthRun {
+                chrom_id: gap.chrom_id,
+                start: gap.start,
+                end: gap.end,
+                depth: gap.depth as u32,
+            },
+        };
+        self.push(run)
+    }
+
+    /// The depth at a position, zero if it is not covered.
+    pub fn depth(&self, chrom_id: u32, position: u64) -> u32 {
+        let Some(starts) = self.chroms.get(&chrom_id) else {
+            return 0;
+        };
+        match starts.partition_point(|&(start, _)| start <= position) {
+            0 => 0,
+            i => starts[i - 1].1,
+        }
+    }
+
+    /// The runs of non-zero depth, in coordinate order.
+    pub fn runs(&self) -> impl Iterator<Item = DepthRun> + '_ {
+        self.chroms.iter().flat_map(|(&chrom_id, starts)| {
+            starts
+                .windows(2)
+                .filter(|w| w[0].1 > 0)
+                .map(move |w| DepthRun {
+                    chrom_id,
+                    start: w[0].0,
+                    end: w[1].0,
+                    depth: w[0].1,
+                })
+        })
+    }
+
+    /// The number of runs of non-zero depth.
+    pub fn len(&self) -> usize {
+        self.chroms
+            .values()
+            .map(|starts| starts.iter().filter(|&&(_, depth)| depth > 0).count())
+            .sum()
+    }
+
+    /// Whether the track has no coverage.
+    pub fn is_empty(&self) -> bool {
+        self.chroms.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collated::{collated, records};
+
+    fn runs(cigars: Vec<(&str, u32, u64)>) -> Vec<(u32, u64, u64, u32)> {
+        DepthIterator::new(records(cigars))
+            .map(|r| r.map(|r| (r.chrom_id, r.start, r.end, r.depth)))
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_depth_overlapping() {
+        assert_eq!(
+            runs(vec![("5M", 1, 100), ("5M", 1, 102), ("2S3M", 1, 105)]),
+            vec![(1, 100, 102, 1), (1, 102, 107, 2), (1, 107, 108, 1)]
+        );
+    }
+
+    #[test]
+    fn test_depth_adjacent_records_merge() {
+        assert_eq!(
+            runs(vec![("5M", 1, 100), ("5M", 1, 105)]),
+            vec![(1, 100, 110, 1)]
+        );
+    }
+
+    #[test]
+    fn test_depth_deletions_and_insertions() {
+        assert_eq!(runs(vec![("2M2I2D2M", 1, 100)]), vec![(1, 100, 106, 1)]);
+    }
+
+    #[test]
+    fn test_depth_chromosomes_and_gaps() {
+        assert_eq!(
+            runs(vec![
+                ("2M", 1, 100),
+                ("2M", 1, 200),
+                ("2M", 2, 50),
+                ("*", 2, 50)
+            ]),
+            vec![(1, 100, 102, 1), (1, 200, 202, 1), (2, 50, 52, 1)]
+        );
+    }
+
+    #[test]
+    fn test_depth_skips_and_per_position() {
+        let records = vec![std::io::Result::Ok(("1M2N1M", 1, 10))];
+        let depths: Vec<_> = DepthIterator::new(records.into_iter())
+            .include_skips()
+            .per_position()
+            .map(|r| r.map(|p| (p.position, p.depth)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(depths, vec![(10, 1), (11, 1), (12, 1), (13, 1)]);
+    }
+
+    #[test]
+    fn test_depth_track() {
+        let cigars = vec![("5M", 1, 100), ("5M", 1, 102), ("5M", 1, 120), ("2M", 3, 5)];
+        let track = DepthTrack::from_source(records(cigars.clone())).unwrap();
+        let expected: Vec<_> = DepthIterator::new(records(cigars))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(track.runs().collect::<Vec<_>>(), expected);
+        assert_eq!(track.len(), 5);
+        let depths: Vec<_> = [99, 100, 102, 106, 107, 110, 124, 125]
+            .iter()
+            .map(|&p| track.depth(1, p))
+            .collect();
+        assert_eq!(depths, vec![0, 1, 2, 1, 0, 0, 1, 0]);
+        assert_eq!((track.depth(2, 5), track.depth(3, 6)), (0, 1));
+
+        let mut track = DepthTrack::new();
+        let run = |chrom_id, start, end, depth| DepthRun {
+            chrom_id,
+            start,
+            end,
+            depth,
+        };
+        track.push(run(0, 10, 20, 3)).unwrap();
+        track.push(run(0, 20, 25, 3)).unwrap();
+        assert_eq!(track.runs().collect::<Vec<_>>(), vec![run(0, 10, 25, 3)]);
+        assert!(matches!(
+            track.push(run(0, 24, 30, 1)),
+            Err(CigarError::UnsortedInput {
+                previous: (0, 25),
+                current: (0, 24)
+            })
+        ));
+    }
+
+    #[test]
+    fn test_depth_track_from_summaries() {
+        let cigars = vec![("2M1D2M", 1, 100), ("6M", 1, 101), ("3M", 1, 110)];
+        let mut track = DepthTrack::new();
+        let coverage = collated(cigars.clone()).summarized().with_gaps();
+        for record in coverage {
+            track.add_coverage(&record.unwrap()).unwrap();
+        }
+        let expected = DepthTrack::from_source(records(cigars)).unwrap();
+        assert_eq!(track, expected);
+    }
+}
@@ -0,0 +1,196 @@
+//! Formatting CIGARs for different readers.
+//!
+//! The [`Display`](std::fmt::Display) implementation of [`Cigar`] writes its elements
+//! exactly as they are. Other readers want something different: a SAM file needs `*` for
+//! an empty CIGAR, a consumer which does not understand sequence match (`=`) and mismatch
+//! (`X`) operations needs them as alignment matches (`M`), and a person reading a log or
+//! debug report finds long CIGARs easier to follow with the elements separated.
+//! [`DisplayOptions`] describes the output wanted, and [`DisplayOptions::format`] writes
+//! a CIGAR with them.
+//!
+//! Separating elements or collapsing `=` and `X` into `M` gives output which does not
+//! parse back to the same elements. In strict mode, formatting fails with
+//! [`CigarError::NotRoundTrip`] rather than produce such output, so a CIGAR written with
+//! strict options is guaranteed to parse (with [`parse_optional_cigar`]) to the elements
+//! it was written from.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::Cigar;
+//! use cigar_utils::display::DisplayOptions;
+//!
+//! let cigar: Cigar = "5S10=1X4=2I8=".parse().unwrap();
+//! let readable = DisplayOptions::new().separator(" ");
+//! assert_eq!(readable.format(&cigar).unwrap(), "5S 10= 1X 4= 2I 8=");
+//!
+//! let legacy = DisplayOptions::new().collapse_matches();
+//! assert_eq!(legacy.format(&cigar).unwrap(), "5S15M2I8M");
+//!
+//! // Strict options refuse to write output which does not parse back unchanged.
+//! assert!(legacy.strict().format(&cigar).is_err());
+//! assert_eq!(DisplayOptions::sam().format(&Cigar::default()).unwrap(), "*");
+//! ```
+
+use crate::builder::Coalesce;
+use crate::error::CigarError;
+use crate::{Cigar, CigarElement, CigarOp, UNAVAILABLE_CIGAR, parse_optional_cigar};
+
+/// How sequence match (`=`) and mismatch (`X`) elements are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchStyle {
+    /// Write `=`, `X` and `M` elements as they are.
+    #[default]
+    Preserve,
+    /// Write `=` and `X` elements as alignment matches (`M`), merging adjacent ones.
+    Collapse,
+}
+
+/// Options for formatting a [`Cigar`].
+///
+/// The default options write the elements as they are, as the `Display` implementation
+/// of [`Cigar`] does.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DisplayOptions {
+    /// How sequence match and mismatch elements are written.
+    pub matches: MatchStyle,
+    /// A string written between elements.
+    pub separator: Option<String>,
+    /// Whether an empty CIGAR is written as the unavailable placeholder `*`.
+    pub unavailable: bool,
+    /// Whether to fail rather than write output which does not parse back to the same
+    /// elements.
+    pub strict: bool,
+}
+
+impl DisplayOptions {
+    /// Create the default options.
+    pub fn new() -> Self {
+        DisplayOptions::default()
+    }
+
+    /// Options for SAM records: elements as they are, `*` for an empty CIGAR, strictly.
+    pub fn sam() -> Self {
+        DisplayOptions::new().unavailable().strict()
+    }
+
+    /// Write `=` and `X` elements as alignment matches (`M`), merging adjacent elements
+    /// with the same operation and dropping zero length ones.
+    pub fn collapse_matches(mut self) -> Self {
+        self.matches = MatchStyle::Collapse;
+        self
+    }
+
+    /// Write `separator` between elements.
+    pub fn separator(mut self, separator: &str) -> Self {
+        self.separator = Some(separator.to_string());
+        self
+    }
+
+    /// Write an empty CIGAR as the unavailable placeholder `*`.
+    pub fn unavailable(mut self) -> Self {
+        self.unavailable = true;
+        self
+    }
+
+    /// Fail with [`CigarError::NotRoundTrip`] rather than write output which does not
+    /// parse back to the same elements.
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Format a CIGAR with the options.
+    ///
+    /// In strict mode, the output is parsed with [`parse_optional_cigar`], taking `*` as
+    /// an empty CIGAR, and compared with the elements of `cigar`.
+    pub fn format(&self, cigar: &Cigar) -> std::result::Result<String, CigarError> {
+        let elements: Vec<CigarElement> = match self.matches {
+            MatchStyle::Preserve => cigar.elements().to_vec(),
+            MatchStyle::Collapse => Coalesce::new(cigar.iter().map(|elem| {
+                let op = match elem.op {
+                    CigarOp::Equal | CigarOp::Diff => CigarOp::Match,
+                    op => op,
+                };
+                Ok::<_, CigarError>(CigarElement::new(elem.length, op))
+            }))
+            .collect::<std::result::Result<_, _>>()?,
+        };
+        let output = if elements.is_empty() && self.unavailable {
+            UNAVAILABLE_CIGAR.to_string()
+        } else {
+            let separator = self.separator.as_deref().unwrap_or("");
+            elements
+                .iter()
+                .map(|elem| elem.to_string())
+                .collect::<Vec<_>>()
+                .join(separator)
+        };
+        if self.strict {
+            let round_trips = match parse_optional_cigar(&output) {
+                Ok(Some(parsed)) => parsed.elements() == cigar.elements(),
+                Ok(None) => cigar.is_empty(),
+                Err(_) => false,
+            };
+            if !round_trips {
+                return Err(CigarError::NotRoundTrip(output));
+            }
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let cigar: Cigar = "3S4=1X2M1D0I5=".parse().unwrap();
+        assert_eq!(
+            DisplayOptions::new().format(&cigar).unwrap(),
+            cigar.to_string()
+        );
+        assert_eq!(
+            DisplayOptions::new()
+                .collapse_matches()
+                .separator(", ")
+                .format(&cigar)
+                .unwrap(),
+            "3S, 7M, 1D, 5M"
+        );
+        assert_eq!(DisplayOptions::new().format(&Cigar::default()).unwrap(), "");
+        assert_eq!(
+            DisplayOptions::new()
+                .unavailable()
+                .format(&Cigar::default())
+                .unwrap(),
+            "*"
+        );
+    }
+
+    #[test]
+    fn test_format_strict() {
+        let options = DisplayOptions::sam();
+        let cigar: Cigar = "3S4=1X2M1D0I5=".parse().unwrap();
+        assert_eq!(options.format(&cigar).unwrap(), "3S4=1X2M1D0I5=");
+        // Collapsing is fine where it changes nothing.
+        let collapsing = options.clone().collapse_matches();
+        assert_eq!(collapsing.format(&"2S8M".parse().unwrap()).unwrap(), "2S8M");
+        assert!(matches!(
+            collapsing.format(&cigar),
+            Err(CigarError::NotRoundTrip(output)) if output == "3S7M1D5M"
+        ));
+        // Merging adjacent elements changes the element sequence too.
+        assert!(collapsing.format(&"4M2M".parse().unwrap()).is_err());
+        let separated = options.separator(" ");
+        assert_eq!(separated.format(&"10M".parse().unwrap()).unwrap(), "10M");
+        assert!(separated.format(&cigar).is_err());
+        assert!(
+            DisplayOptions::new()
+                .strict()
+                .format(&Cigar::default())
+                .is_ok()
+        );
+    }
+}
@@ -2,27 +2,299 @@
 
 use std::error::Error;
 use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::CigarOp;
+
+/// The location of a parse error within a CIGAR string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParsePosition {
+    /// The byte offset of the error within the CIGAR string.
+    pub offset: usize,
+    /// The index of the element being parsed when the error occurred.
+    pub element: usize,
+}
+
+impl ParsePosition {
+    /// The text of the CIGAR string within `radius` bytes either side of the error.
+    pub fn snippet<'a>(&self, cigar: &'a str, radius: usize) -> &'a str {
+        let mut start = self.offset.saturating_sub(radius).min(cigar.len());
+        let mut end = (self.offset + radius + 1).min(cigar.len());
+        while !cigar.is_char_boundary(start) {
+            start -= 1;
+        }
+        while !cigar.is_char_boundary(end) {
+            end += 1;
+        }
+        &cigar[start..end]
+    }
+}
+
+impl Display for ParsePosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "byte {}, element {}", self.offset, self.element)
+    }
+}
+
+/// Where an error occurred: the CIGAR string and the record being processed, as far as
+/// they are known.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// The CIGAR string being processed.
+    pub cigar: Option<String>,
+    /// An identifier of the record being processed, such as its read name.
+    pub record: Option<String>,
+}
+
+/// The number of characters of a CIGAR string shown in error messages.
+const CIGAR_DISPLAY_LIMIT: usize = 60;
+
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut separator = "";
+        if let Some(record) = &self.record {
+            write!(f, "record {}", record)?;
+            separator = ", ";
+        }
+        if let Some(cigar) = &self.cigar {
+            match cigar.char_indices().nth(CIGAR_DISPLAY_LIMIT) {
+                Some((end, _)) => write!(f, "{}CIGAR {}...", separator, &cigar[..end])?,
+                None => write!(f, "{}CIGAR {}", separator, cigar)?,
+            }
+        }
+        Ok(())
+    }
+}
 
 /// Errors that can occur while parsing CIGAR strings.
+///
+/// Errors raised while processing a particular CIGAR or record may be wrapped in
+/// [`CigarError::Context`]; [`CigarError::root`] gives the underlying error.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum CigarError {
     /// An error indicating an invalid character in the CIGAR string.
-    InvalidCharacter(char),
+    InvalidCharacter(char, ParsePosition),
     /// An error indicating a missing count in a CIGAR element.
-    MissingCount(char),
+    MissingCount(char, ParsePosition),
     /// An error indicating a missing operation in a CIGAR element.
-    MissingOperation(u32),
+    MissingOperation(u32, ParsePosition),
+    /// An error indicating a CIGAR element length too large for 32 bits.
+    LengthOverflow(ParsePosition),
+    /// An error indicating a CIGAR element with a length of zero.
+    ZeroLength(CigarOp),
+    /// An error indicating a clipping operation at a position the SAM specification does not allow.
+    MisplacedClip(CigarOp, usize),
+    /// An error indicating that a sequence length differs from the length implied by the CIGAR (expected, actual).
+    LengthMismatch(usize, usize),
+    /// An error indicating a read range (start, end) that is not within the read.
+    InvalidRange(usize, usize),
+    /// An error indicating an invalid BAM operation code.
+    InvalidOpCode(u8),
+    /// An error indicating a CIGAR element too long for the 28 bits of its length in the BAM encoding.
+    ElementTooLong(u32),
+    /// An error indicating that collation input was not sorted by (chromosome ID, position).
+    UnsortedInput {
+        /// The (chromosome ID, position) of the previous record.
+        previous: (u32, u64),
+        /// The (chromosome ID, position) of the out of order record.
+        current: (u32, u64),
+    },
+    /// An error indicating a reference interval (start, end) beyond the end of the reference sequence.
+    ReferenceOutOfBounds(u64, u64),
+    /// An error indicating that a read sequence is shorter than the CIGAR requires (required, actual).
+    SequenceTooShort(usize, usize),
+    /// An error indicating a chromosome ID with no reference sequence.
+    UnknownChromosome(u32),
+    /// An error indicating an alignment which runs off the end of its chromosome.
+    BeyondChromosomeEnd {
+        /// The chromosome ID of the alignment.
+        chrom_id: u32,
+        /// The reference position just past the end of the alignment.
+        end: u64,
+        /// The length of the chromosome.
+        length: u64,
+        /// The number of aligned reference bases beyond the end of the chromosome.
+        overhang: u64,
+    },
+    /// An error indicating an alignment match (M) element, at the given element index, where sequence matches and mismatches must be distinguished.
+    UnresolvedMatch(usize),
+    /// An error indicating a malformed record of another alignment format (format name, description).
+    InvalidFormat(&'static str, String),
+    /// An error indicating that a CIGAR formatted for output would not parse back to the same elements.
+    NotRoundTrip(String),
     /// An external error.
     External(Box<dyn Error + Send + Sync + 'static>),
+    /// An error annotated with the CIGAR string and record it occurred in.
+    Context(ErrorContext, Box<CigarError>),
+}
+
+/// Parse a numeric field of a record in `format`, giving
+/// [`CigarError::InvalidFormat`] if it is not a number of the type wanted.
+pub(crate) fn parse_number<T: FromStr>(
+    format: &'static str,
+    field: &str,
+) -> std::result::Result<T, CigarError> {
+    field
+        .parse()
+        .map_err(|_| CigarError::invalid_format(format, format!("'{}' is not a number", field)))
+}
+
+/// The length of a CIGAR element of `length` bases from a record in `format`, giving
+/// [`CigarError::InvalidFormat`] if it does not fit in a CIGAR element.
+pub(crate) fn element_length(
+    format: &'static str,
+    length: u64,
+) -> std::result::Result<u32, CigarError> {
+    u32::try_from(length).map_err(|_| {
+        CigarError::invalid_format(
+            format,
+            format!("{} bases are too many for a CIGAR element", length),
+        )
+    })
+}
+
+impl CigarError {
+    /// An [`InvalidFormat`](CigarError::InvalidFormat) error for a malformed record in
+    /// `format`.
+    pub fn invalid_format(format: &'static str, description: impl Into<String>) -> Self {
+        CigarError::InvalidFormat(format, description.into())
+    }
+
+    /// The underlying error, without any context.
+    pub fn root(&self) -> &CigarError {
+        match self {
+            CigarError::Context(_, inner) => inner.root(),
+            e => e,
+        }
+    }
+
+    /// The context attached to the error, if any.
+    pub fn error_context(&self) -> Option<&ErrorContext> {
+        match self {
+            CigarError::Context(context, _) => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Attach context to the error, keeping any context already attached closer to
+    /// where the error occurred.
+    fn attach(self, update: impl FnOnce(&mut ErrorContext)) -> CigarError {
+        match self {
+            CigarError::Context(mut context, inner) => {
+                let mut outer = ErrorContext::default();
+                update(&mut outer);
+                context.cigar = context.cigar.or(outer.cigar);
+                context.record = context.record.or(outer.record);
+                CigarError::Context(context, inner)
+            }
+            e => {
+                let mut context = ErrorContext::default();
+                update(&mut context);
+                CigarError::Context(context, Box::new(e))
+            }
+        }
+    }
+
+    /// Attach the CIGAR string the error occurred in.
+    pub fn with_cigar(self, cigar: &str) -> CigarError {
+        self.attach(|c| c.cigar = Some(cigar.to_string()))
+    }
+
+    /// Attach an identifier of the record the error occurred in, such as its read name.
+    pub fn with_record(self, record: &str) -> CigarError {
+        self.attach(|c| c.record = Some(record.to_string()))
+    }
+}
+
+impl From<std::io::Error> for CigarError {
+    fn from(e: std::io::Error) -> Self {
+        CigarError::External(Box::new(e))
+    }
 }
 
 impl Display for CigarError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            CigarError::InvalidCharacter(c) => write!(f, "Invalid character in CIGAR string: {}", c),
-            CigarError::MissingCount(c) => write!(f, "Missing count in CIGAR element (found '{}')", c),
-            CigarError::MissingOperation(length) => write!(f, "Missing operation in CIGAR element (length was {})", length),
+            CigarError::InvalidCharacter(c, pos) => {
+                write!(f, "Invalid character in CIGAR string: {} (at {})", c, pos)
+            }
+            CigarError::MissingCount(c, pos) => write!(
+                f,
+                "Missing count in CIGAR element (found '{}' at {})",
+                c, pos
+            ),
+            CigarError::MissingOperation(length, pos) => write!(
+                f,
+                "Missing operation in CIGAR element (length was {}, at {})",
+                length, pos
+            ),
+            CigarError::LengthOverflow(pos) => {
+                write!(f, "CIGAR element length too large (at {})", pos)
+            }
+            CigarError::ZeroLength(op) => write!(f, "Zero length CIGAR element ({})", op),
+            CigarError::MisplacedClip(op, index) => {
+                write!(f, "Misplaced clip operation '{}' at element {}", op, index)
+            }
+            CigarError::LengthMismatch(expected, actual) => write!(
+                f,
+                "Sequence length {} does not match CIGAR length {}",
+                actual, expected
+            ),
+            CigarError::InvalidRange(start, end) => {
+                write!(f, "Invalid read range {}..{}", start, end)
+            }
+            CigarError::InvalidOpCode(code) => {
+                write!(f, "Invalid BAM CIGAR operation code: {}", code)
+            }
+            CigarError::ElementTooLong(length) => write!(
+                f,
+                "CIGAR element length {} does not fit in the BAM encoding",
+                length
+            ),
+            CigarError::UnsortedInput { previous, current } => write!(
+                f,
+                "Unsorted input: record at {}:{} follows {}:{}",
+                current.0, current.1, previous.0, previous.1
+            ),
+            CigarError::ReferenceOutOfBounds(start, end) => write!(
+                f,
+                "Reference interval {}..{} is beyond the end of the reference",
+                start, end
+            ),
+            CigarError::SequenceTooShort(required, actual) => write!(
+                f,
+                "Sequence length {} is shorter than the {} bases required by the CIGAR",
+                actual, required
+            ),
+            CigarError::UnknownChromosome(chrom_id) => {
+                write!(f, "No reference sequence for chromosome ID {}", chrom_id)
+            }
+            CigarError::BeyondChromosomeEnd {
+                chrom_id,
+                end,
+                length,
+                overhang,
+            } => write!(
+                f,
+                "Alignment ending at {} runs {} bases beyond the end of chromosome ID {} (length {})",
+                end, overhang, chrom_id, length
+            ),
+            CigarError::UnresolvedMatch(index) => write!(
+                f,
+                "Alignment match at element {} does not distinguish sequence matches from mismatches",
+                index
+            ),
+            CigarError::InvalidFormat(format, description) => {
+                write!(f, "Invalid {} record: {}", format, description)
+            }
+            CigarError::NotRoundTrip(output) => write!(
+                f,
+                "Formatted CIGAR '{}' does not parse back to the same elements",
+                output
+            ),
             CigarError::External(_) => write!(f, "External error"),
+            CigarError::Context(context, inner) => write!(f, "{} ({})", inner, context),
         }
     }
 }
@@ -31,7 +303,40 @@ impl Error for CigarError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             CigarError::External(e) => Some(&**e),
+            CigarError::Context(_, inner) => Some(&**inner),
             _ => None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_context() {
+        let error = CigarError::ZeroLength(CigarOp::Match)
+            .with_cigar("0M")
+            .with_record("read1")
+            .with_cigar("ignored");
+        assert!(matches!(
+            error.root(),
+            CigarError::ZeroLength(CigarOp::Match)
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Zero length CIGAR element (M) (record read1, CIGAR 0M)"
+        );
+        assert!(error.source().is_some());
+
+        let long = "1M".repeat(40);
+        let error = CigarError::InvalidOpCode(9).with_cigar(&long);
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Invalid BAM CIGAR operation code: 9 (CIGAR {}...)",
+                &long[..60]
+            )
+        );
+    }
+}
@@ -8,7 +8,7 @@
 //! ```rust
 //! use cigar_utils::{CigarOp, CigarElement};
 //! use cigar_utils::expand::expand_cigar_operations;
-//! 
+//!
 //! let reference = b"ACGT";
 //! let reference = b"ACGT";
 //! let seq = b"AGGT";
@@ -21,92 +21,742 @@
 //! assert_eq!(cigar_string, "1=1X2=");
 //! ```
 
-use crate::{CigarElement, CigarIterator, CigarOp, error::CigarError};
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use crate::{CigarElement, CigarIterator, CigarOp, Strand, error::CigarError};
+
+/// How bases that are `N` in the reference or the read are classified.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownBases {
+    /// Compare `N` like any other base.
+    #[default]
+    Compare,
+    /// Treat `N` as a sequence match.
+    Match,
+    /// Treat `N` as a sequence mismatch.
+    Mismatch,
+    /// Leave `N` unclassified, as an alignment match (`M`).
+    Unknown,
+}
+
+/// Options controlling how bases are compared during expansion.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExpandOptions {
+    /// Compare bases ignoring case, so soft-masked (lower case) reference bases match.
+    pub case_insensitive: bool,
+    /// Count a base as matching an IUPAC ambiguity code which includes it.
+    pub iupac: bool,
+    /// How to classify `N` bases.
+    pub unknown_bases: UnknownBases,
+}
+
+impl ExpandOptions {
+    /// Classify a read base against a reference base as `=`, `X`, or `M` if unknown.
+    pub fn classify(&self, read: u8, reference: u8) -> CigarOp {
+        let (read, reference) = if self.case_insensitive {
+            (read.to_ascii_uppercase(), reference.to_ascii_uppercase())
+        } else {
+            (read, reference)
+        };
+        if self.unknown_bases != UnknownBases::Compare && (read == b'N' || reference == b'N') {
+            return match self.unknown_bases {
+                UnknownBases::Match => CigarOp::Equal,
+                UnknownBases::Mismatch => CigarOp::Diff,
+                _ => CigarOp::Match,
+            };
+        }
+        let equal = if self.iupac {
+            match (iupac_bases(read), iupac_bases(reference)) {
+                (Some(r), Some(f)) => r & f != 0,
+                _ => read == reference,
+            }
+        } else {
+            read == reference
+        };
+        if equal { CigarOp::Equal } else { CigarOp::Diff }
+    }
+}
+
+/// The set of bases an IUPAC code stands for, as a bit mask of A, C, G and T.
+fn iupac_bases(code: u8) -> Option<u8> {
+    const A: u8 = 1;
+    const C: u8 = 2;
+    const G: u8 = 4;
+    const T: u8 = 8;
+    match code {
+        b'A' => Some(A),
+        b'C' => Some(C),
+        b'G' => Some(G),
+        b'T' | b'U' => Some(T),
+        b'R' => Some(A | G),
+        b'Y' => Some(C | T),
+        b'S' => Some(C | G),
+        b'W' => Some(A | T),
+        b'K' => Some(G | T),
+        b'M' => Some(A | C),
+        b'B' => Some(C | G | T),
+        b'D' => Some(A | G | T),
+        b'H' => Some(A | C | T),
+        b'V' => Some(A | C | G),
+        b'N' => Some(A | C | G | T),
+        _ => None,
+    }
+}
 
 /// Expand a CIGAR string, using the reference and the sequence to split
 /// match elements into sequence match and sequence mismatch elements.
+///
+/// This collects an [`ExpandedCigarIterator`].
 pub fn expand_cigar_operations<R: AsRef<[u8]>, S: AsRef<[u8]>>(
     reference_position: usize,
     cigar: &str,
     reference: &R,
     seq: &S,
 ) -> std::result::Result<Vec<CigarElement>, CigarError> {
-    let mut expanded = Vec::new();
-    let mut reference_position = reference_position;
-    let mut read_sequence_position = 0;
+    ExpandedCigarIterator::new(reference_position, cigar, reference.as_ref(), seq.as_ref())
+        .collect()
+}
 
-    for elem in CigarIterator::new(cigar) {
-        let elem = elem?;
-        match elem.op {
-            CigarOp::Match => {
-                // Split the match element into sequence match and mismatch elements
-                let seq_slice = &seq.as_ref()[read_sequence_position..read_sequence_position + elem.length as usize];
-                let ref_slice = &reference.as_ref()[reference_position..reference_position + elem.length as usize];
-                let mut match_length = 0;
-                let mut mismatch_length = 0;
-                for (s, r) in seq_slice.iter().zip(ref_slice.iter()) {
-                    if s == r {
-                        if mismatch_length > 0 {
-                            expanded.push(CigarElement::new(mismatch_length, CigarOp::Diff));
-                            mismatch_length = 0;
-                        }
-                        match_length += 1;
+/// Expand a CIGAR string as for [`expand_cigar_operations`], where `reference` is the
+/// window of the chromosome starting at `window_start`.
+pub fn expand_cigar_operations_in_window<R: AsRef<[u8]>, S: AsRef<[u8]>>(
+    reference_position: usize,
+    cigar: &str,
+    reference: &R,
+    window_start: usize,
+    seq: &S,
+) -> std::result::Result<Vec<CigarElement>, CigarError> {
+    ExpandedCigarIterator::new(reference_position, cigar, reference.as_ref(), seq.as_ref())
+        .with_window_start(window_start)
+        .collect()
+}
+
+/// Expand a CIGAR string as for [`expand_cigar_operations`], comparing bases according to `options`.
+pub fn expand_cigar_operations_with_options<R: AsRef<[u8]>, S: AsRef<[u8]>>(
+    reference_position: usize,
+    cigar: &str,
+    reference: &R,
+    seq: &S,
+    options: &ExpandOptions,
+) -> std::result::Result<Vec<CigarElement>, CigarError> {
+    ExpandedCigarIterator::new(reference_position, cigar, reference.as_ref(), seq.as_ref())
+        .with_options(*options)
+        .collect()
+}
+
+/// The expanded CIGARs of a batch of reads aligned to one reference window, held in a
+/// single buffer.
+///
+/// Filling a batch again reuses its buffers, so expanding a pileup a batch at a time
+/// allocates little once the buffers have grown. With the `rayon` feature, the reads of
+/// a batch are expanded in parallel.
+#[derive(Debug, Default)]
+pub struct ExpandedBatch {
+    elements: Vec<CigarElement>,
+    ranges: Vec<std::result::Result<Range<usize>, CigarError>>,
+    options: ExpandOptions,
+    /// The buffers each task expands its chunk of reads into, kept for the next fill.
+    #[cfg(feature = "rayon")]
+    chunks: Vec<ExpandedChunk>,
+}
+
+/// The expansions of one chunk of a batch, with ranges relative to the chunk.
+#[cfg(feature = "rayon")]
+type ExpandedChunk = (
+    Vec<CigarElement>,
+    Vec<std::result::Result<Range<usize>, CigarError>>,
+);
+
+/// The number of reads expanded together by one task.
+#[cfg(feature = "rayon")]
+const BATCH_CHUNK: usize = 64;
+
+impl ExpandedBatch {
+    /// Create an empty batch.
+    pub fn new() -> Self {
+        ExpandedBatch::default()
+    }
+
+    /// Compare bases according to `options`.
+    pub fn with_options(mut self, options: ExpandOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Replace the contents of the batch with the expansions of `records`, each a
+    /// reference position, CIGAR and read sequence, against `reference_window`, the
+    /// window of the chromosome starting at `window_start`.
+    ///
+    /// A read which fails to expand, for example by extending beyond the window, gives
+    /// an error in its place without affecting the others.
+    pub fn fill<C, S>(
+        &mut self,
+        reference_window: &[u8],
+        window_start: usize,
+        records: &[(usize, C, S)],
+    ) where
+        C: AsRef<str> + Sync,
+        S: AsRef<[u8]> + Sync,
+    {
+        self.elements.clear();
+        self.ranges.clear();
+        let options = self.options;
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+            let count = records.len().div_ceil(BATCH_CHUNK);
+            if self.chunks.len() < count {
+                self.chunks.resize_with(count, Default::default);
+            }
+            records
+                .par_chunks(BATCH_CHUNK)
+                .zip(self.chunks.par_iter_mut())
+                .for_each(|(chunk, (elements, ranges))| {
+                    elements.clear();
+                    ranges.clear();
+                    expand_records(
+                        chunk,
+                        reference_window,
+                        window_start,
+                        options,
+                        elements,
+                        ranges,
+                    );
+                });
+            for (elements, ranges) in &mut self.chunks[..count] {
+                let offset = self.elements.len();
+                self.elements.append(elements);
+                self.ranges.extend(
+                    ranges
+                        .drain(..)
+                        .map(|r| r.map(|r| r.start + offset..r.end + offset)),
+                );
+            }
+        }
+        #[cfg(not(feature = "rayon"))]
+        expand_records(
+            records,
+            reference_window,
+            window_start,
+            options,
+            &mut self.elements,
+            &mut self.ranges,
+        );
+    }
+
+    /// The number of reads in the batch.
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Whether the batch holds no reads.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The expanded elements of the read at `index`, or the error expanding it.
+    pub fn get(&self, index: usize) -> Option<std::result::Result<&[CigarElement], &CigarError>> {
+        self.ranges
+            .get(index)
+            .map(|range| range.as_ref().map(|r| &self.elements[r.clone()]))
+    }
+
+    /// Iterate over the expansions of the reads, in order.
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = std::result::Result<&[CigarElement], &CigarError>> + '_ {
+        (0..self.len()).filter_map(|i| self.get(i))
+    }
+}
+
+/// Expand records onto the end of `elements`, recording the range of each.
+fn expand_records<C: AsRef<str>, S: AsRef<[u8]>>(
+    records: &[(usize, C, S)],
+    reference_window: &[u8],
+    window_start: usize,
+    options: ExpandOptions,
+    elements: &mut Vec<CigarElement>,
+    ranges: &mut Vec<std::result::Result<Range<usize>, CigarError>>,
+) {
+    for (reference_position, cigar, seq) in records {
+        let start = elements.len();
+        let expanded = ExpandedCigarIterator::new(
+            *reference_position,
+            cigar.as_ref(),
+            reference_window,
+            seq.as_ref(),
+        )
+        .with_window_start(window_start)
+        .with_options(options);
+        let mut error = None;
+        for elem in expanded {
+            match elem {
+                Ok(elem) => elements.push(elem),
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            }
+        }
+        match error {
+            Some(e) => {
+                elements.truncate(start);
+                ranges.push(Err(e));
+            }
+            None => ranges.push(Ok(start..elements.len())),
+        }
+    }
+}
+
+/// Expand many reads aligned to one reference window in a single call, as for
+/// [`expand_cigar_operations_in_window`]. See [`ExpandedBatch::fill`].
+pub fn expand_batch<C, S>(
+    reference_window: &[u8],
+    window_start: usize,
+    records: &[(usize, C, S)],
+) -> ExpandedBatch
+where
+    C: AsRef<str> + Sync,
+    S: AsRef<[u8]> + Sync,
+{
+    let mut batch = ExpandedBatch::new();
+    batch.fill(reference_window, window_start, records);
+    batch
+}
+
+/// An iterator over the elements of an expanded CIGAR string, yielding them lazily
+/// without allocating.
+///
+/// A match element extending beyond the end of the reference or the read sequence
+/// produces [`CigarError::ReferenceOutOfBounds`] or [`CigarError::SequenceTooShort`].
+///
+/// # Example
+///
+/// ```rust
+/// use cigar_utils::CigarElement;
+/// use cigar_utils::expand::ExpandedCigarIterator;
+///
+/// let expanded: Vec<CigarElement> = ExpandedCigarIterator::new(0, "4M", b"ACGT", b"AGGT")
+///     .collect::<Result<_, _>>()
+///     .unwrap();
+/// assert_eq!(CigarElement::cigar_string(expanded), "1=1X2=");
+/// ```
+#[derive(Clone)]
+pub struct ExpandedCigarIterator<'a> {
+    cigar: CigarIterator<'a>,
+    reference: &'a [u8],
+    seq: &'a [u8],
+    reference_position: usize,
+    read_sequence_position: usize,
+    // Bases remaining in the match element being split.
+    remaining: usize,
+    options: ExpandOptions,
+    window_start: usize,
+}
+
+impl<'a> ExpandedCigarIterator<'a> {
+    /// Create a new expanded CIGAR iterator for a read aligned at `reference_position`.
+    pub fn new(
+        reference_position: usize,
+        cigar: &'a str,
+        reference: &'a [u8],
+        seq: &'a [u8],
+    ) -> Self {
+        ExpandedCigarIterator {
+            cigar: CigarIterator::new(cigar),
+            reference,
+            seq,
+            reference_position,
+            read_sequence_position: 0,
+            remaining: 0,
+            options: ExpandOptions::default(),
+            window_start: 0,
+        }
+    }
+
+    /// Treat the reference as a window of the chromosome starting at `window_start`,
+    /// rather than the whole chromosome. Reference positions remain chromosome coordinates.
+    pub fn with_window_start(mut self, window_start: usize) -> Self {
+        self.window_start = window_start;
+        self
+    }
+
+    /// Compare bases according to `options`.
+    pub fn with_options(mut self, options: ExpandOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// An iterator over the variants observed in the read, rather than the expanded elements.
+    pub fn observations(self) -> VariantObservationIterator<'a> {
+        VariantObservationIterator {
+            inner: self,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// The reference bases `start..start + length`, in chromosome coordinates.
+    fn reference_bases(
+        &self,
+        start: usize,
+        length: usize,
+    ) -> std::result::Result<&'a [u8], CigarError> {
+        let end = start + length;
+        if start < self.window_start || end > self.window_start + self.reference.len() {
+            return Err(CigarError::ReferenceOutOfBounds(start as u64, end as u64));
+        }
+        Ok(&self.reference[start - self.window_start..end - self.window_start])
+    }
+
+    /// The read bases `start..start + length`.
+    fn read_bases(&self, start: usize, length: usize) -> std::result::Result<&'a [u8], CigarError> {
+        let end = start + length;
+        if end > self.seq.len() {
+            return Err(CigarError::SequenceTooShort(end, self.seq.len()));
+        }
+        Ok(&self.seq[start..end])
+    }
+
+    /// Take the next run of identically classified bases from the match element being split.
+    fn next_run(&mut self) -> CigarElement {
+        let seq_slice =
+            &self.seq[self.read_sequence_position..self.read_sequence_position + self.remaining];
+        let offset = self.reference_position - self.window_start;
+        let ref_slice = &self.reference[offset..offset + self.remaining];
+        let op = self.options.classify(seq_slice[0], ref_slice[0]);
+        let length = seq_slice
+            .iter()
+            .zip(ref_slice.iter())
+            .take_while(|(s, r)| self.options.classify(**s, **r) == op)
+            .count();
+        self.read_sequence_position += length;
+        self.reference_position += length;
+        self.remaining -= length;
+        CigarElement::new(length as u32, op)
+    }
+}
+
+impl<'a> Iterator for ExpandedCigarIterator<'a> {
+    type Item = std::result::Result<CigarElement, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining == 0 {
+            let elem = match self.cigar.next()? {
+                Ok(elem) => elem,
+                Err(e) => return Some(Err(e.with_cigar(self.cigar.as_str()))),
+            };
+            match elem.op {
+                CigarOp::Match => {
+                    // Split the match element into sequence match and mismatch elements
+                    let length = elem.length as usize;
+                    let reference_end = self.reference_position + length;
+                    let read_end = self.read_sequence_position + length;
+                    let error = if self.reference_position < self.window_start
+                        || reference_end > self.window_start + self.reference.len()
+                    {
+                        Some(CigarError::ReferenceOutOfBounds(
+                            self.reference_position as u64,
+                            reference_end as u64,
+                        ))
+                    } else if read_end > self.seq.len() {
+                        Some(CigarError::SequenceTooShort(read_end, self.seq.len()))
                     } else {
-                        if match_length > 0 {
-                            expanded.push(CigarElement::new(match_length, CigarOp::Equal));
-                            match_length = 0;
-                        }
-                        mismatch_length += 1;
+                        None
+                    };
+                    if let Some(e) = error {
+                        // Skip the element, so iteration can carry on past it.
+                        self.reference_position = reference_end;
+                        self.read_sequence_position = read_end;
+                        return Some(Err(e.with_cigar(self.cigar.as_str())));
                     }
+                    self.remaining = length;
+                    continue;
+                }
+                CigarOp::Insertion | CigarOp::SoftClip => {
+                    self.read_sequence_position += elem.length as usize;
+                }
+                CigarOp::Deletion | CigarOp::Skip => {
+                    self.reference_position += elem.length as usize;
                 }
-                if match_length > 0 {
-                    expanded.push(CigarElement::new(match_length, CigarOp::Equal));
+                CigarOp::HardClip | CigarOp::Padding => {
+                    // Neither consumes read bases in the sequence or reference bases.
                 }
-                if mismatch_length > 0 {
-                    expanded.push(CigarElement::new(mismatch_length, CigarOp::Diff));
+                CigarOp::Equal | CigarOp::Diff => {
+                    self.read_sequence_position += elem.length as usize;
+                    self.reference_position += elem.length as usize;
+                }
+            }
+            return Some(Ok(elem));
+        }
+        Some(Ok(self.next_run()))
+    }
+}
+
+/// A variant observed in a single read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VariantObservation {
+    /// A mismatched base.
+    Mismatch {
+        /// The reference position of the base.
+        reference_position: usize,
+        /// The read position of the base.
+        read_position: usize,
+        /// The reference base.
+        reference_base: u8,
+        /// The read base.
+        read_base: u8,
+    },
+    /// Bases inserted before a reference position.
+    Insertion {
+        /// The reference position the bases are inserted before.
+        reference_position: usize,
+        /// The read position of the first inserted base.
+        read_position: usize,
+        /// The inserted bases.
+        sequence: Vec<u8>,
+    },
+    /// Reference bases deleted from the read.
+    Deletion {
+        /// The reference position of the first deleted base.
+        reference_position: usize,
+        /// The read position following the deletion.
+        read_position: usize,
+        /// The deleted reference bases.
+        sequence: Vec<u8>,
+    },
+}
+
+/// Find the mismatches, insertions and deletions of a read against the reference.
+///
+/// # Example
+///
+/// ```rust
+/// use cigar_utils::expand::{VariantObservation, variant_observations};
+///
+/// let observations = variant_observations(0, "2M1I2M1D1M", &b"ACGTAC", &b"AGTGTC").unwrap();
+/// assert_eq!(
+///     observations,
+///     vec![
+///         VariantObservation::Mismatch { reference_position: 1, read_position: 1, reference_base: b'C', read_base: b'G' },
+///         VariantObservation::Insertion { reference_position: 2, read_position: 2, sequence: b"T".to_vec() },
+///         VariantObservation::Deletion { reference_position: 4, read_position: 5, sequence: b"A".to_vec() },
+///     ]
+/// );
+/// ```
+pub fn variant_observations<R: AsRef<[u8]>, S: AsRef<[u8]>>(
+    reference_position: usize,
+    cigar: &str,
+    reference: &R,
+    seq: &S,
+) -> std::result::Result<Vec<VariantObservation>, CigarError> {
+    ExpandedCigarIterator::new(reference_position, cigar, reference.as_ref(), seq.as_ref())
+        .observations()
+        .collect()
+}
+
+/// An iterator over the variants observed in a read, created by
+/// [`ExpandedCigarIterator::observations`].
+pub struct VariantObservationIterator<'a> {
+    inner: ExpandedCigarIterator<'a>,
+    pending: VecDeque<VariantObservation>,
+}
+
+impl<'a> VariantObservationIterator<'a> {
+    fn observe(
+        &mut self,
+        elem: CigarElement,
+        reference_position: usize,
+        read_position: usize,
+    ) -> std::result::Result<(), CigarError> {
+        let length = elem.length as usize;
+        match elem.op {
+            CigarOp::Diff => {
+                let reference_bases = self.inner.reference_bases(reference_position, length)?;
+                let read_bases = self.inner.read_bases(read_position, length)?;
+                for (i, (r, s)) in reference_bases.iter().zip(read_bases).enumerate() {
+                    self.pending.push_back(VariantObservation::Mismatch {
+                        reference_position: reference_position + i,
+                        read_position: read_position + i,
+                        reference_base: *r,
+                        read_base: *s,
+                    });
                 }
-                read_sequence_position += elem.length as usize;
-                reference_position += elem.length as usize;
             }
             CigarOp::Insertion => {
-                read_sequence_position += elem.length as usize;
-                expanded.push(elem);
-            },
+                let sequence = self.inner.read_bases(read_position, length)?.to_vec();
+                self.pending.push_back(VariantObservation::Insertion {
+                    reference_position,
+                    read_position,
+                    sequence,
+                });
+            }
             CigarOp::Deletion => {
-                reference_position += elem.length as usize;
-                expanded.push(elem);
-            },
-            CigarOp::Skip => {
-                reference_position += elem.length as usize;
-                expanded.push(elem);
-            },
-            CigarOp::SoftClip => {
-                read_sequence_position += elem.length as usize;
-                expanded.push(elem);
-            },
-            CigarOp::HardClip => {
-                // We want the read sequence position only.
-                // read_sequence_position += elem.length as usize;
-                expanded.push(elem);
-            },
-            CigarOp::Padding => {
-                reference_position += elem.length as usize;
-                expanded.push(elem);
-            },
-            CigarOp::Equal => {
-                read_sequence_position += elem.length as usize;
-                reference_position += elem.length as usize;
-                expanded.push(elem);
-            },
-            CigarOp::Diff => {
-                read_sequence_position += elem.length as usize;
-                reference_position += elem.length as usize;
-                expanded.push(elem);
-            },
+                let sequence = self
+                    .inner
+                    .reference_bases(reference_position, length)?
+                    .to_vec();
+                self.pending.push_back(VariantObservation::Deletion {
+                    reference_position,
+                    read_position,
+                    sequence,
+                });
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for VariantObservationIterator<'a> {
+    type Item = std::result::Result<VariantObservation, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let reference_position = self.inner.reference_position;
+            let read_position = self.inner.read_sequence_position;
+            let elem = match self.inner.next()? {
+                Ok(elem) => elem,
+                Err(e) => return Some(Err(e)),
+            };
+            if let Err(e) = self.observe(elem, reference_position, read_position) {
+                return Some(Err(e));
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+/// The index of a base in the order A, C, G, T, ignoring case.
+fn base_index(base: u8) -> Option<usize> {
+    match base.to_ascii_uppercase() {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' => Some(3),
+        _ => None,
+    }
+}
+
+/// Counts of aligned base pairs and of inserted and deleted bases, for building error
+/// models of sequencing platforms.
+///
+/// Bases are indexed in the order A, C, G and T, ignoring case; other bases are not
+/// counted. For reads on the reverse strand, bases are complemented so the counts are
+/// in the orientation the read was sequenced in.
+///
+/// # Example
+///
+/// ```rust
+/// use cigar_utils::Strand;
+/// use cigar_utils::expand::{ExpandedCigarIterator, SubstitutionSpectrum};
+///
+/// let mut spectrum = SubstitutionSpectrum::default();
+/// spectrum.add(ExpandedCigarIterator::new(0, "2M1I2M1D1M", b"ACGTAC", b"AGTGTC"), Strand::Forward).unwrap();
+/// assert_eq!(spectrum.count(b'C', b'G'), 1);
+/// assert_eq!(spectrum.count(b'A', b'A'), 1);
+/// assert_eq!(spectrum.inserted, [0, 0, 0, 1]);
+/// assert_eq!(spectrum.deleted, [1, 0, 0, 0]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubstitutionSpectrum {
+    /// The number of aligned base pairs, indexed by reference base then read base.
+    pub substitutions: [[u64; 4]; 4],
+    /// The number of inserted bases, by base.
+    pub inserted: [u64; 4],
+    /// The number of deleted reference bases, by base.
+    pub deleted: [u64; 4],
+}
+
+impl SubstitutionSpectrum {
+    /// The number of times `read` was aligned to `reference`, or zero if either is not
+    /// one of A, C, G or T.
+    pub fn count(&self, reference: u8, read: u8) -> u64 {
+        match (base_index(reference), base_index(read)) {
+            (Some(r), Some(s)) => self.substitutions[r][s],
+            _ => 0,
+        }
+    }
+
+    /// Accumulate the bases of a read, expanded by `iter`, aligned on `strand`.
+    pub fn add(
+        &mut self,
+        mut iter: ExpandedCigarIterator<'_>,
+        strand: Strand,
+    ) -> std::result::Result<(), CigarError> {
+        // The complement of a base is the reverse of its index.
+        let orient = |i: usize| if strand == Strand::Reverse { 3 - i } else { i };
+        loop {
+            let reference_position = iter.reference_position;
+            let read_position = iter.read_sequence_position;
+            let elem = match iter.next() {
+                Some(elem) => elem?,
+                None => return Ok(()),
+            };
+            let length = elem.length as usize;
+            match elem.op {
+                CigarOp::Equal | CigarOp::Diff | CigarOp::Match => {
+                    let reference_bases = iter.reference_bases(reference_position, length)?;
+                    let read_bases = iter.read_bases(read_position, length)?;
+                    for (r, s) in reference_bases.iter().zip(read_bases) {
+                        if let (Some(r), Some(s)) = (base_index(*r), base_index(*s)) {
+                            self.substitutions[orient(r)][orient(s)] += 1;
+                        }
+                    }
+                }
+                CigarOp::Insertion => {
+                    for s in iter
+                        .read_bases(read_position, length)?
+                        .iter()
+                        .filter_map(|b| base_index(*b))
+                    {
+                        self.inserted[orient(s)] += 1;
+                    }
+                }
+                CigarOp::Deletion => {
+                    for r in iter
+                        .reference_bases(reference_position, length)?
+                        .iter()
+                        .filter_map(|b| base_index(*b))
+                    {
+                        self.deleted[orient(r)] += 1;
+                    }
+                }
+                _ => {}
+            }
         }
     }
 
-    Ok(expanded)
+    /// Add the counts of another spectrum to this one.
+    pub fn merge(&mut self, other: &SubstitutionSpectrum) {
+        for (row, other_row) in self.substitutions.iter_mut().zip(&other.substitutions) {
+            for (count, other_count) in row.iter_mut().zip(other_row) {
+                *count += other_count;
+            }
+        }
+        for (count, other_count) in self.inserted.iter_mut().zip(&other.inserted) {
+            *count += other_count;
+        }
+        for (count, other_count) in self.deleted.iter_mut().zip(&other.deleted) {
+            *count += other_count;
+        }
+    }
+}
+
+/// Compute the substitution spectrum of a single read aligned on `strand`.
+pub fn substitution_spectrum<R: AsRef<[u8]>, S: AsRef<[u8]>>(
+    reference_position: usize,
+    cigar: &str,
+    reference: &R,
+    seq: &S,
+    strand: Strand,
+) -> std::result::Result<SubstitutionSpectrum, CigarError> {
+    let mut spectrum = SubstitutionSpectrum::default();
+    spectrum.add(
+        ExpandedCigarIterator::new(reference_position, cigar, reference.as_ref(), seq.as_ref()),
+        strand,
+    )?;
+    Ok(spectrum)
 }
 
 #[cfg(test)]
@@ -205,6 +855,117 @@ mod tests {
         assert_eq!(result[1].length, 4);
     }
 
+    #[test]
+    fn test_expand_cigar_with_padding() {
+        // Padding is silent deletion from the padded reference: it consumes neither.
+        let result = expand_cigar_operations(0, "2M1P2M", b"ACGT", b"ACGT").unwrap();
+        assert_eq!(CigarElement::cigar_string(result), "2=1P2=");
+    }
+
+    #[test]
+    fn test_expanded_cigar_iterator() {
+        let reference = b"ACGTACGT";
+        let seq = b"TTCGACGA";
+        let result: Vec<_> = ExpandedCigarIterator::new(1, "2S3M1D3M", reference, seq)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(CigarElement::cigar_string(result), "2S2=1X1D2=1X");
+    }
+
+    #[test]
+    fn test_expand_cigar_out_of_bounds() {
+        let reference = b"ACGT";
+        let seq = b"ACGTACGT";
+        assert!(matches!(
+            expand_cigar_operations(2, "1M1D3M", &reference, &seq)
+                .as_ref()
+                .map_err(|e| e.root()),
+            Err(CigarError::ReferenceOutOfBounds(4, 7))
+        ));
+        assert!(matches!(
+            expand_cigar_operations(0, "2S3M", &reference, &b"ACG")
+                .as_ref()
+                .map_err(|e| e.root()),
+            Err(CigarError::SequenceTooShort(5, 3))
+        ));
+    }
+
+    #[test]
+    fn test_expand_cigar_with_options() {
+        let reference = b"acgtNRAC";
+        let seq = b"ACGTAGTC";
+        let expand = |options: ExpandOptions| {
+            let result =
+                expand_cigar_operations_with_options(0, "8M", &reference, &seq, &options).unwrap();
+            CigarElement::cigar_string(result)
+        };
+        assert_eq!(expand(ExpandOptions::default()), "7X1=");
+        let options = ExpandOptions {
+            case_insensitive: true,
+            ..Default::default()
+        };
+        assert_eq!(expand(options), "4=3X1=");
+        let options = ExpandOptions {
+            case_insensitive: true,
+            iupac: true,
+            unknown_bases: UnknownBases::Unknown,
+        };
+        assert_eq!(expand(options), "4=1M1=1X1=");
+        let options = ExpandOptions {
+            case_insensitive: true,
+            unknown_bases: UnknownBases::Mismatch,
+            ..Default::default()
+        };
+        assert_eq!(expand(options), "4=3X1=");
+    }
+
+    #[test]
+    fn test_expand_cigar_in_window() {
+        // The window covers chromosome positions 1000..1008.
+        let window = b"ACGTACGT";
+        let seq = b"GTTGT";
+        let result =
+            expand_cigar_operations_in_window(1002, "3M1D2M", &window, 1000, &seq).unwrap();
+        assert_eq!(CigarElement::cigar_string(result), "2=1X1D2=");
+        assert!(matches!(
+            expand_cigar_operations_in_window(998, "4M", &window, 1000, &seq)
+                .as_ref()
+                .map_err(|e| e.root()),
+            Err(CigarError::ReferenceOutOfBounds(998, 1002))
+        ));
+        assert!(matches!(
+            expand_cigar_operations_in_window(1006, "4M", &window, 1000, &seq)
+                .as_ref()
+                .map_err(|e| e.root()),
+            Err(CigarError::ReferenceOutOfBounds(1006, 1010))
+        ));
+    }
+
+    #[test]
+    fn test_variant_observations_existing_eqx() {
+        let observations = variant_observations(1, "1S2=1X1D1=", &b"ACGTAC", &b"TCGAC").unwrap();
+        assert_eq!(
+            observations,
+            vec![
+                VariantObservation::Mismatch {
+                    reference_position: 3,
+                    read_position: 3,
+                    reference_base: b'T',
+                    read_base: b'A'
+                },
+                VariantObservation::Deletion {
+                    reference_position: 4,
+                    read_position: 4,
+                    sequence: b"A".to_vec()
+                },
+            ]
+        );
+        assert!(matches!(
+            variant_observations(0, "4M2I", &b"ACGT", &b"ACGTA"),
+            Err(CigarError::SequenceTooShort(6, 5))
+        ));
+    }
+
     #[test]
     fn test_expand_cigar_with_right_hardclip() {
         let reference = b"ACGT";
@@ -217,4 +978,69 @@ mod tests {
         assert_eq!(result[1].op, CigarOp::HardClip);
         assert_eq!(result[1].length, 1);
     }
+
+    #[test]
+    fn test_substitution_spectrum_reverse_strand() {
+        let spectrum =
+            substitution_spectrum(0, "2M1I1M2D", &b"ACGTA", &b"AGTN", Strand::Reverse).unwrap();
+        // Complemented: A>A is T>T, C>G is G>C; the N is not counted.
+        assert_eq!(spectrum.count(b'T', b't'), 1);
+        assert_eq!(spectrum.count(b'G', b'C'), 1);
+        assert_eq!(spectrum.substitutions.iter().flatten().sum::<u64>(), 2);
+        assert_eq!(spectrum.inserted, [1, 0, 0, 0]);
+        assert_eq!(spectrum.deleted, [1, 0, 0, 1]);
+
+        let mut merged = spectrum.clone();
+        merged.merge(&spectrum);
+        assert_eq!(merged.count(b'G', b'C'), 2);
+        assert_eq!(merged.deleted, [2, 0, 0, 2]);
+    }
+
+    #[test]
+    fn test_expand_batch() {
+        let window = b"ACGTACGTAC";
+        let records = vec![
+            (100, "4M", b"ACTT".to_vec()),
+            (102, "2M1I2M", b"GTTAC".to_vec()),
+            (108, "4M", b"ACGT".to_vec()),
+            (101, "1S3M", b"GCGT".to_vec()),
+        ];
+        let mut batch = expand_batch(window, 100, &records);
+        assert_eq!(batch.len(), 4);
+        let cigars: Vec<_> = batch
+            .iter()
+            .map(|r| {
+                r.map(|e| CigarElement::cigar_string(e.to_vec()))
+                    .map_err(|e| e.to_string())
+            })
+            .collect();
+        assert_eq!(cigars[0], Ok("2=1X1=".to_string()));
+        assert_eq!(cigars[1], Ok("2=1I2=".to_string()));
+        assert!(matches!(batch.get(2), Some(Err(CigarError::Context(..)))));
+        assert_eq!(cigars[3], Ok("1S3=".to_string()));
+        for (i, (position, cigar, seq)) in records.iter().enumerate() {
+            if let Some(Ok(elements)) = batch.get(i) {
+                let expected =
+                    expand_cigar_operations_in_window(*position, cigar, window, 100, seq).unwrap();
+                assert_eq!(elements, &expected[..]);
+            }
+        }
+
+        // Filling the batch again replaces its contents.
+        batch.fill(window, 100, &records[1..2]);
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch.get(0).unwrap().unwrap().len(), 3);
+        assert!(expand_batch::<&str, &[u8]>(window, 100, &[]).is_empty());
+
+        // Batches larger than one task's chunk keep the reads in order.
+        let many: Vec<_> = records.iter().cycle().take(150).cloned().collect();
+        batch.fill(window, 100, &many);
+        assert_eq!(batch.len(), 150);
+        assert_eq!(
+            batch.get(145).unwrap().unwrap(),
+            batch.get(1).unwrap().unwrap()
+        );
+        batch.fill(window, 100, &records[..1]);
+        assert_eq!(batch.iter().count(), 1);
+    }
 }
@@ -0,0 +1,566 @@
+//! C interface.
+//!
+//! With the `ffi` feature enabled, this module exposes CIGAR parsing, expansion,
+//! coordinate mapping and alignment statistics as `extern "C"` functions, so C and C++
+//! tools (htslib plugins, for example) can use them. All types crossing the boundary
+//! are `#[repr(C)]` or opaque, so a header can be generated with `cbindgen`.
+//!
+//! A static or dynamic library for linking is built with, for example,
+//! `cargo rustc --release --features ffi --crate-type staticlib`.
+//!
+//! Functions return a [`CuStatus`] and write their results through out pointers. A
+//! parsed CIGAR is an opaque [`CuCigar`] owned by the caller and released with
+//! [`cu_cigar_free`].
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::ffi::CString;
+//! use std::ptr;
+//! use cigar_utils::ffi::*;
+//!
+//! let text = CString::new("2S5M1I3M").unwrap();
+//! let mut cigar = ptr::null_mut();
+//! unsafe {
+//!     assert_eq!(cu_cigar_parse(text.as_ptr(), &mut cigar), CuStatus::Ok);
+//!     assert_eq!(cu_cigar_len(cigar), 4);
+//!     assert_eq!(cu_cigar_reference_length(cigar), 8);
+//!     let mut position = 0;
+//!     assert_eq!(cu_read_to_reference(cigar, 100, 8, &mut position), CuStatus::Ok);
+//!     assert_eq!(position, 105);
+//!     cu_cigar_free(cigar);
+//! }
+//! ```
+
+use std::ffi::{CStr, c_char};
+use std::ptr;
+
+use crate::Cigar;
+use crate::error::CigarError;
+use crate::expand::expand_cigar_operations;
+use crate::stats::{AlignmentStats, stats, stats_with_sequences};
+
+/// The result of a C interface function.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CuStatus {
+    /// The function succeeded.
+    Ok = 0,
+    /// A required pointer argument was null.
+    NullPointer = 1,
+    /// A string argument was not valid UTF-8.
+    InvalidUtf8 = 2,
+    /// A CIGAR string could not be parsed.
+    InvalidCigar = 3,
+    /// The read sequence is shorter than the CIGAR requires.
+    SequenceTooShort = 4,
+    /// The alignment extends beyond the end of the reference sequence.
+    ReferenceOutOfBounds = 5,
+    /// An alignment match (`M`) element was found where `=`/`X` are required.
+    UnresolvedMatch = 6,
+    /// A position or index is outside the alignment, or has no counterpart.
+    OutOfRange = 7,
+    /// An output buffer is too small.
+    BufferTooSmall = 8,
+    /// Any other error.
+    Error = 9,
+}
+
+impl From<CigarError> for CuStatus {
+    fn from(e: CigarError) -> Self {
+        match e {
+            CigarError::InvalidCharacter(..)
+            | CigarError::MissingCount(..)
+            | CigarError::MissingOperation(..)
+            | CigarError::LengthOverflow(..)
+            | CigarError::ZeroLength(..)
+            | CigarError::InvalidOpCode(..)
+            | CigarError::ElementTooLong(..) => CuStatus::InvalidCigar,
+            CigarError::SequenceTooShort(..) => CuStatus::SequenceTooShort,
+            CigarError::ReferenceOutOfBounds(..) | CigarError::BeyondChromosomeEnd { .. } => {
+                CuStatus::ReferenceOutOfBounds
+            }
+            CigarError::UnresolvedMatch(..) => CuStatus::UnresolvedMatch,
+            CigarError::Context(_, inner) => CuStatus::from(*inner),
+            _ => CuStatus::Error,
+        }
+    }
+}
+
+/// An opaque parsed CIGAR.
+pub struct CuCigar(Cigar);
+
+/// A CIGAR element: a length and the operation's SAM character.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CuCigarElement {
+    /// The length of the element.
+    pub length: u32,
+    /// The operation character, such as `b'M'`.
+    pub op: u8,
+}
+
+/// Alignment statistics, as computed by [`stats`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CuAlignmentStats {
+    /// The number of matching bases.
+    pub matches: u64,
+    /// The number of mismatching bases.
+    pub mismatches: u64,
+    /// The number of insertions.
+    pub insertions: u64,
+    /// The number of inserted bases.
+    pub inserted_bases: u64,
+    /// The number of deletions.
+    pub deletions: u64,
+    /// The number of deleted bases.
+    pub deleted_bases: u64,
+    /// The BLAST identity, or NaN if there are no alignment columns.
+    pub blast_identity: f64,
+    /// The gap-compressed identity, or NaN if there are no alignment columns.
+    pub gap_compressed_identity: f64,
+}
+
+impl From<AlignmentStats> for CuAlignmentStats {
+    fn from(stats: AlignmentStats) -> Self {
+        CuAlignmentStats {
+            matches: stats.matches as u64,
+            mismatches: stats.mismatches as u64,
+            insertions: stats.insertions as u64,
+            inserted_bases: stats.inserted_bases as u64,
+            deletions: stats.deletions as u64,
+            deleted_bases: stats.deleted_bases as u64,
+            blast_identity: stats.blast_identity().unwrap_or(f64::NAN),
+            gap_compressed_identity: stats.gap_compressed_identity().unwrap_or(f64::NAN),
+        }
+    }
+}
+
+/// Run the body of an interface function, converting its error into a status.
+fn run<F: FnOnce() -> std::result::Result<(), CuStatus>>(body: F) -> CuStatus {
+    match body() {
+        Ok(()) => CuStatus::Ok,
+        Err(status) => status,
+    }
+}
+
+/// # Safety
+///
+/// `text` must be null or a valid nul terminated string.
+unsafe fn str_arg<'a>(text: *const c_char) -> std::result::Result<&'a str, CuStatus> {
+    if text.is_null() {
+        return Err(CuStatus::NullPointer);
+    }
+    unsafe { CStr::from_ptr(text) }
+        .to_str()
+        .map_err(|_| CuStatus::InvalidUtf8)
+}
+
+/// # Safety
+///
+/// `bytes` must be null with `length` zero, or valid for reads of `length` bytes.
+unsafe fn bytes_arg<'a>(
+    bytes: *const u8,
+    length: usize,
+) -> std::result::Result<&'a [u8], CuStatus> {
+    match (bytes.is_null(), length) {
+        (true, 0) => Ok(&[]),
+        (true, _) => Err(CuStatus::NullPointer),
+        (false, _) => Ok(unsafe { std::slice::from_raw_parts(bytes, length) }),
+    }
+}
+
+/// # Safety
+///
+/// `cigar` must be null or a pointer returned by this interface and not yet freed.
+unsafe fn cigar_arg<'a>(cigar: *const CuCigar) -> std::result::Result<&'a Cigar, CuStatus> {
+    unsafe { cigar.as_ref() }
+        .map(|cigar| &cigar.0)
+        .ok_or(CuStatus::NullPointer)
+}
+
+/// # Safety
+///
+/// `out` must be null or valid for writes.
+unsafe fn write_out<T>(out: *mut T, value: T) -> std::result::Result<(), CuStatus> {
+    if out.is_null() {
+        return Err(CuStatus::NullPointer);
+    }
+    unsafe { out.write(value) };
+    Ok(())
+}
+
+/// Parse a CIGAR string, writing a new [`CuCigar`] to `out`.
+///
+/// # Safety
+///
+/// `text` must be a valid nul terminated string and `out` valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cu_cigar_parse(text: *const c_char, out: *mut *mut CuCigar) -> CuStatus {
+    run(|| {
+        if out.is_null() {
+            return Err(CuStatus::NullPointer);
+        }
+        let cigar: Cigar = unsafe { str_arg(text) }?.parse()?;
+        unsafe { write_out(out, Box::into_raw(Box::new(CuCigar(cigar)))) }
+    })
+}
+
+/// Free a [`CuCigar`]. Null pointers are ignored.
+///
+/// # Safety
+///
+/// `cigar` must be null or a pointer returned by this interface and not yet freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cu_cigar_free(cigar: *mut CuCigar) {
+    if !cigar.is_null() {
+        drop(unsafe { Box::from_raw(cigar) });
+    }
+}
+
+/// The number of elements of a CIGAR, or zero for a null pointer.
+///
+/// # Safety
+///
+/// `cigar` must be null or a pointer returned by this interface and not yet freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cu_cigar_len(cigar: *const CuCigar) -> usize {
+    unsafe { cigar_arg(cigar) }.map_or(0, |cigar| cigar.len())
+}
+
+/// Write the element at `index` of a CIGAR to `out`.
+///
+/// # Safety
+///
+/// `cigar` must be a pointer returned by this interface and not yet freed, and `out`
+/// valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cu_cigar_element(
+    cigar: *const CuCigar,
+    index: usize,
+    out: *mut CuCigarElement,
+) -> CuStatus {
+    run(|| {
+        let elem = unsafe { cigar_arg(cigar) }?
+            .elements()
+            .get(index)
+            .ok_or(CuStatus::OutOfRange)?;
+        let elem = CuCigarElement {
+            length: elem.length,
+            op: elem.op.to_ascii(),
+        };
+        unsafe { write_out(out, elem) }
+    })
+}
+
+/// The number of reference bases a CIGAR consumes, or zero for a null pointer.
+///
+/// # Safety
+///
+/// `cigar` must be null or a pointer returned by this interface and not yet freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cu_cigar_reference_length(cigar: *const CuCigar) -> u64 {
+    unsafe { cigar_arg(cigar) }.map_or(0, |cigar| cigar.reference_length())
+}
+
+/// The number of read bases a CIGAR consumes, excluding hard clips, or zero for a null
+/// pointer.
+///
+/// # Safety
+///
+/// `cigar` must be null or a pointer returned by this interface and not yet freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cu_cigar_query_length(cigar: *const CuCigar) -> u64 {
+    unsafe { cigar_arg(cigar) }.map_or(0, |cigar| cigar.query_length() as u64)
+}
+
+/// Write a CIGAR as a nul terminated string into `buffer` of `capacity` bytes.
+///
+/// The length of the string, without the terminator, is written to `length` if it is
+/// not null, including when the buffer is too small.
+///
+/// # Safety
+///
+/// `cigar` must be a pointer returned by this interface and not yet freed, `buffer`
+/// valid for writes of `capacity` bytes, and `length` null or valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cu_cigar_to_string(
+    cigar: *const CuCigar,
+    buffer: *mut c_char,
+    capacity: usize,
+    length: *mut usize,
+) -> CuStatus {
+    run(|| {
+        let text = unsafe { cigar_arg(cigar) }?.to_string();
+        if !length.is_null() {
+            unsafe { length.write(text.len()) };
+        }
+        if buffer.is_null() {
+            return Err(CuStatus::NullPointer);
+        }
+        if text.len() >= capacity {
+            return Err(CuStatus::BufferTooSmall);
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(text.as_ptr(), buffer.cast::<u8>(), text.len());
+            buffer.add(text.len()).write(0);
+        }
+        Ok(())
+    })
+}
+
+/// Expand the alignment match (`M`) elements of a read aligned at `reference_position`
+/// into sequence match and mismatch elements, writing a new [`CuCigar`] to `out`.
+///
+/// # Safety
+///
+/// `cigar` must be a valid nul terminated string, `reference` and `seq` valid for reads
+/// of their lengths, and `out` valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cu_expand(
+    reference_position: usize,
+    cigar: *const c_char,
+    reference: *const u8,
+    reference_length: usize,
+    seq: *const u8,
+    seq_length: usize,
+    out: *mut *mut CuCigar,
+) -> CuStatus {
+    run(|| {
+        if out.is_null() {
+            return Err(CuStatus::NullPointer);
+        }
+        let cigar = unsafe { str_arg(cigar) }?;
+        let reference = unsafe { bytes_arg(reference, reference_length) }?;
+        let seq = unsafe { bytes_arg(seq, seq_length) }?;
+        let elements = expand_cigar_operations(reference_position, cigar, &reference, &seq)?;
+        let expanded = Box::new(CuCigar(Cigar::from(elements)));
+        unsafe { write_out(out, Box::into_raw(expanded)) }
+    })
+}
+
+/// Write the reference position aligned to `read_position` of a read aligned at
+/// `reference_position` to `out`.
+///
+/// Read positions count soft clipped but not hard clipped bases. Clipped and inserted
+/// bases have no reference position, giving [`CuStatus::OutOfRange`].
+///
+/// # Safety
+///
+/// `cigar` must be a pointer returned by this interface and not yet freed, and `out`
+/// valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cu_read_to_reference(
+    cigar: *const CuCigar,
+    reference_position: u64,
+    read_position: u64,
+    out: *mut u64,
+) -> CuStatus {
+    run(|| {
+        let cigar = unsafe { cigar_arg(cigar) }?;
+        let position = cigar
+            .read_to_reference(reference_position, read_position as usize)
+            .ok_or(CuStatus::OutOfRange)?;
+        unsafe { write_out(out, position) }
+    })
+}
+
+/// Write the read position aligned to the reference position `position` of a read
+/// aligned at `reference_position` to `out`.
+///
+/// Deleted and skipped bases have no read position, giving [`CuStatus::OutOfRange`].
+///
+/// # Safety
+///
+/// `cigar` must be a pointer returned by this interface and not yet freed, and `out`
+/// valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cu_reference_to_read(
+    cigar: *const CuCigar,
+    reference_position: u64,
+    position: u64,
+    out: *mut u64,
+) -> CuStatus {
+    run(|| {
+        let cigar = unsafe { cigar_arg(cigar) }?;
+        let read_position = cigar
+            .reference_to_read(reference_position, position)
+            .ok_or(CuStatus::OutOfRange)?;
+        unsafe { write_out(out, read_position as u64) }
+    })
+}
+
+/// Compute the statistics of a CIGAR using sequence match (`=`) and mismatch (`X`)
+/// operations, writing them to `out`.
+///
+/// # Safety
+///
+/// `cigar` must be a pointer returned by this interface and not yet freed, and `out`
+/// valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cu_stats(cigar: *const CuCigar, out: *mut CuAlignmentStats) -> CuStatus {
+    run(|| {
+        let stats = stats(unsafe { cigar_arg(cigar) }?)?;
+        unsafe { write_out(out, stats.into()) }
+    })
+}
+
+/// Compute the statistics of a read aligned at `reference_position`, comparing the
+/// bases of alignment match (`M`) elements, writing them to `out`.
+///
+/// # Safety
+///
+/// `cigar` must be a valid nul terminated string, `reference` and `seq` valid for reads
+/// of their lengths, and `out` valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cu_stats_with_sequences(
+    reference_position: usize,
+    cigar: *const c_char,
+    reference: *const u8,
+    reference_length: usize,
+    seq: *const u8,
+    seq_length: usize,
+    out: *mut CuAlignmentStats,
+) -> CuStatus {
+    run(|| {
+        let cigar = unsafe { str_arg(cigar) }?;
+        let reference = unsafe { bytes_arg(reference, reference_length) }?;
+        let seq = unsafe { bytes_arg(seq, seq_length) }?;
+        let stats = stats_with_sequences(reference_position, cigar, &reference, &seq)?;
+        unsafe { write_out(out, stats.into()) }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    fn parse(text: &str) -> *mut CuCigar {
+        let text = CString::new(text).unwrap();
+        let mut cigar = ptr::null_mut();
+        assert_eq!(
+            unsafe { cu_cigar_parse(text.as_ptr(), &mut cigar) },
+            CuStatus::Ok
+        );
+        cigar
+    }
+
+    #[test]
+    fn test_parse_and_format() {
+        let cigar = parse("3S4M2D1I");
+        let mut elem = CuCigarElement { length: 0, op: 0 };
+        unsafe {
+            assert_eq!(cu_cigar_element(cigar, 2, &mut elem), CuStatus::Ok);
+            assert_eq!(
+                elem,
+                CuCigarElement {
+                    length: 2,
+                    op: b'D'
+                }
+            );
+            assert_eq!(cu_cigar_element(cigar, 4, &mut elem), CuStatus::OutOfRange);
+            assert_eq!(cu_cigar_query_length(cigar), 8);
+
+            let mut buffer = [0 as c_char; 16];
+            let mut length = 0;
+            let status = cu_cigar_to_string(cigar, buffer.as_mut_ptr(), 4, &mut length);
+            assert_eq!((status, length), (CuStatus::BufferTooSmall, 8));
+            let status = cu_cigar_to_string(cigar, buffer.as_mut_ptr(), 16, &mut length);
+            assert_eq!(status, CuStatus::Ok);
+            assert_eq!(
+                CStr::from_ptr(buffer.as_ptr()).to_str().unwrap(),
+                "3S4M2D1I"
+            );
+            cu_cigar_free(cigar);
+        }
+
+        let text = CString::new("4Q").unwrap();
+        let mut cigar = ptr::null_mut();
+        assert_eq!(
+            unsafe { cu_cigar_parse(text.as_ptr(), &mut cigar) },
+            CuStatus::InvalidCigar
+        );
+        assert!(cigar.is_null());
+        let text = CString::new("99999999999M").unwrap();
+        assert_eq!(
+            unsafe { cu_cigar_parse(text.as_ptr(), &mut cigar) },
+            CuStatus::InvalidCigar
+        );
+        assert_eq!(
+            unsafe { cu_cigar_parse(ptr::null(), &mut cigar) },
+            CuStatus::NullPointer
+        );
+    }
+
+    #[test]
+    fn test_coordinate_mapping() {
+        let cigar = parse("2S3M2I2M3D2M");
+        let mut position = 0;
+        unsafe {
+            assert_eq!(
+                cu_read_to_reference(cigar, 10, 2, &mut position),
+                CuStatus::Ok
+            );
+            assert_eq!(position, 10);
+            assert_eq!(
+                cu_read_to_reference(cigar, 10, 8, &mut position),
+                CuStatus::Ok
+            );
+            assert_eq!(position, 14);
+            assert_eq!(
+                cu_read_to_reference(cigar, 10, 5, &mut position),
+                CuStatus::OutOfRange
+            );
+            assert_eq!(
+                cu_reference_to_read(cigar, 10, 18, &mut position),
+                CuStatus::Ok
+            );
+            assert_eq!(position, 9);
+            assert_eq!(
+                cu_reference_to_read(cigar, 10, 16, &mut position),
+                CuStatus::OutOfRange
+            );
+            cu_cigar_free(cigar);
+        }
+    }
+
+    #[test]
+    fn test_expand_and_stats() {
+        let text = CString::new("4M1I").unwrap();
+        let (reference, seq) = (b"AACGTA", b"ACTTG");
+        let mut expanded = ptr::null_mut();
+        let mut stats = CuAlignmentStats::default();
+        unsafe {
+            let status = cu_expand(
+                1,
+                text.as_ptr(),
+                reference.as_ptr(),
+                6,
+                seq.as_ptr(),
+                5,
+                &mut expanded,
+            );
+            assert_eq!(status, CuStatus::Ok);
+            assert_eq!(cu_cigar_len(expanded), 4);
+            assert_eq!(cu_stats(expanded, &mut stats), CuStatus::Ok);
+            assert_eq!(
+                (stats.matches, stats.mismatches, stats.inserted_bases),
+                (3, 1, 1)
+            );
+            cu_cigar_free(expanded);
+
+            let status = cu_stats_with_sequences(
+                1,
+                text.as_ptr(),
+                reference.as_ptr(),
+                6,
+                seq.as_ptr(),
+                3,
+                &mut stats,
+            );
+            assert_eq!(status, CuStatus::SequenceTooShort);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Alignment footprints for duplicate detection.
+//!
+//! Duplicate marking tools group reads by the footprint of their alignment: the
+//! chromosome, the unclipped start (the alignment start extended back through any leading
+//! clips), the orientation and the shape of the CIGAR. A [`Footprint`] holds these, and
+//! reduces them to a 64 or 128-bit digest for use as a grouping key.
+//!
+//! The digests are FNV-1a hashes of a fixed byte encoding of the footprint, so they are
+//! stable between runs, platforms and versions of this crate, and may be stored.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::{Cigar, Strand};
+//! use cigar_utils::footprint::Footprint;
+//!
+//! let cigar: Cigar = "3S47M".parse().unwrap();
+//! let a = Footprint::new(0, 1003, Strand::Forward, &cigar);
+//! let b = Footprint::new(0, 1003, Strand::Forward, &cigar);
+//! let c = Footprint::new(0, 1003, Strand::Reverse, &cigar);
+//! assert_eq!(a.unclipped_start, 1000);
+//! assert_eq!(a.digest(), b.digest());
+//! assert_ne!(a.digest(), c.digest());
+//! ```
+
+use crate::error::CigarError;
+use crate::record::{CigarRecord, CigarRef};
+use crate::{Cigar, CigarIterator, Strand};
+
+/// The footprint of an alignment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Footprint {
+    /// The chromosome ID of the alignment.
+    pub chrom_id: u32,
+    /// The start of the alignment less the bases clipped from the start of the read,
+    /// which is negative where the clipped bases run off the start of the reference.
+    pub unclipped_start: i64,
+    /// The strand of the alignment.
+    pub strand: Strand,
+    /// The CIGAR of the alignment.
+    pub cigar: Cigar,
+}
+
+const FNV64_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV64_PRIME: u64 = 0x0000_0100_0000_01b3;
+const FNV128_OFFSET: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+const FNV128_PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+
+impl Footprint {
+    /// The footprint of an alignment starting at `position`.
+    pub fn new(chrom_id: u32, position: u64, strand: Strand, cigar: &Cigar) -> Self {
+        Footprint {
+            chrom_id,
+            unclipped_start: cigar.unclipped_start(position),
+            strand,
+            cigar: cigar.clone(),
+        }
+    }
+
+    /// The footprint of a record, or `None` if its CIGAR is unavailable. Records of
+    /// unknown strand are taken to be on the forward strand.
+    pub fn from_record<R: CigarRecord>(
+        record: &R,
+    ) -> std::result::Result<Option<Self>, CigarError> {
+        let cigar = match record.cigar() {
+            c if c.is_unavailable() => return Ok(None),
+            CigarRef::Text(text) => CigarIterator::new(text)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| e.with_cigar(text))?
+                .into(),
+            CigarRef::Elements(elements) => Cigar::from(elements.to_vec()),
+        };
+        let strand = record.strand().unwrap_or(Strand::Forward);
+        Ok(Some(Footprint::new(
+            record.chrom_id(),
+            record.reference_position(),
+            strand,
+            &cigar,
+        )))
+    }
+
+    /// The bytes the digests are computed over.
+    fn bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        let strand = match self.strand {
+            Strand::Forward => 0u8,
+            Strand::Reverse => 1u8,
+        };
+        self.chrom_id
+            .to_le_bytes()
+            .into_iter()
+            .chain(self.unclipped_start.to_le_bytes())
+            .chain([strand])
+            .chain(self.cigar.iter().flat_map(|e| {
+                let length = e.length.to_le_bytes();
+                [length[0], length[1], length[2], length[3], e.op.to_ascii()]
+            }))
+    }
+
+    /// A stable 64-bit digest of the footprint.
+    pub fn digest(&self) -> u64 {
+        self.bytes().fold(FNV64_OFFSET, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(FNV64_PRIME)
+        })
+    }
+
+    /// A stable 128-bit digest of the footprint, for when collisions of the 64-bit digest
+    /// matter.
+    pub fn digest128(&self) -> u128 {
+        self.bytes().fold(FNV128_OFFSET, |hash, byte| {
+            (hash ^ byte as u128).wrapping_mul(FNV128_PRIME)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sam::SamRecord;
+
+    #[test]
+    fn test_footprint_digests() {
+        let cigar: Cigar = "2H3S10M".parse().unwrap();
+        let footprint = Footprint::new(1, 105, Strand::Forward, &cigar);
+        assert_eq!(footprint.unclipped_start, 100);
+        assert_eq!(footprint.digest(), 0x8872_0a77_aac0_7d47);
+        assert_eq!(
+            footprint.digest128(),
+            0x315b_2a93_03de_4e32_790e_b2b3_34ed_53c7
+        );
+
+        // The same unclipped start with a different CIGAR shape is a different footprint.
+        let other = Footprint::new(1, 100, Strand::Forward, &"15M".parse().unwrap());
+        assert_eq!(other.unclipped_start, 100);
+        assert_ne!(other.digest(), footprint.digest());
+        // Clips running off the start of the reference still give distinct footprints.
+        let cigar: Cigar = "5S3M".parse().unwrap();
+        let at_1 = Footprint::new(0, 1, Strand::Reverse, &cigar);
+        let at_2 = Footprint::new(0, 2, Strand::Reverse, &cigar);
+        assert_eq!((at_1.unclipped_start, at_2.unclipped_start), (-4, -3));
+        assert_ne!(at_1.digest(), at_2.digest());
+    }
+
+    #[test]
+    fn test_footprint_from_record() {
+        let record: SamRecord = "r1\t16\tchr1\t101\t60\t2S8M\t*\t0\t0\tACGTACGTAC\t*"
+            .parse()
+            .unwrap();
+        let footprint = Footprint::from_record(&record).unwrap().unwrap();
+        assert_eq!(footprint.strand, Strand::Reverse);
+        assert_eq!(footprint.unclipped_start, 98);
+        let unmapped: SamRecord = "r2\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\t*".parse().unwrap();
+        assert_eq!(Footprint::from_record(&unmapped).unwrap(), None);
+    }
+}
@@ -0,0 +1,269 @@
+//! GAF graph alignments.
+//!
+//! GAF, the format of GraphAligner and minigraph, records alignments of reads to paths
+//! through a sequence graph. The path is either a walk of oriented segments such as
+//! `>s1<s2>s3` or the name of a linear sequence, and the alignment's CIGAR, in the
+//! `cg:Z` tag, runs along the path in its own orientation. Treating each path as a
+//! chromosome, GAF records can be collated like linear alignments.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::Strand;
+//! use cigar_utils::gaf::GafRecord;
+//! use cigar_utils::record::CigarRecord;
+//!
+//! let line = "read1\t20\t2\t18\t+\t>s1<s2\t30\t5\t20\t14\t16\t60\tcg:Z:8=1X1I6=";
+//! let record: GafRecord = line.parse().unwrap();
+//! assert_eq!(record.cigar.to_string(), "2S8=1X1I6=2S");
+//! assert_eq!(record.mapq(), Some(60));
+//!
+//! let elements: Vec<_> = record.augmented().collect::<Result<_, _>>().unwrap();
+//! assert_eq!(elements[1].reference_position, 5);
+//! assert_eq!(elements[4].reference_position, 14);
+//! assert_eq!(elements[4].read_position, 12);
+//! ```
+
+use std::str::FromStr;
+
+use crate::error::{CigarError, element_length, parse_number};
+use crate::record::{CigarRecord, CigarRef};
+use crate::{Cigar, CigarElement, CigarOp, Strand};
+
+/// A step of a GAF path: a graph segment and the orientation it is walked in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathStep {
+    /// The segment name.
+    pub segment: String,
+    /// The orientation of the segment along the path.
+    pub orientation: Strand,
+}
+
+/// A GAF alignment record.
+///
+/// Intervals are zero-based and half open, as in GAF.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GafRecord {
+    /// The name of the read.
+    pub query_name: String,
+    /// The length of the read.
+    pub query_length: u64,
+    /// The start of the alignment on the read.
+    pub query_start: u64,
+    /// The end of the alignment on the read.
+    pub query_end: u64,
+    /// The strand of the read relative to the path.
+    pub strand: Strand,
+    /// The path: a walk of oriented segments, or a sequence name.
+    pub path: String,
+    /// The length of the path.
+    pub path_length: u64,
+    /// The start of the alignment on the path.
+    pub path_start: u64,
+    /// The end of the alignment on the path.
+    pub path_end: u64,
+    /// The number of matching bases.
+    pub matches: u64,
+    /// The number of bases in the alignment, including gaps.
+    pub block_length: u64,
+    /// The mapping quality; 255 if missing.
+    pub mapq: u8,
+    /// The `cg:Z` CIGAR, with the unaligned ends of the read soft clipped, or empty if the
+    /// record has no `cg` tag.
+    pub cigar: Cigar,
+    /// The ID used as the chromosome of the record in collation, assigned by the caller
+    /// (for example from a map of path names); zero when parsed.
+    pub path_id: u32,
+}
+
+/// The format name given in [`CigarError::InvalidFormat`] errors.
+const FORMAT: &str = "GAF";
+
+impl GafRecord {
+    /// The steps of the path, or `None` if the path is a sequence name.
+    pub fn steps(&self) -> Option<Vec<PathStep>> {
+        if !self.path.starts_with(['>', '<']) {
+            return None;
+        }
+        let mut steps = Vec::new();
+        let mut rest = self.path.as_str();
+        while let Some(orientation) = rest.chars().next() {
+            let orientation = match orientation {
+                '>' => Strand::Forward,
+                _ => Strand::Reverse,
+            };
+            rest = &rest[1..];
+            let end = rest.find(['>', '<']).unwrap_or(rest.len());
+            steps.push(PathStep {
+                segment: rest[..end].to_string(),
+                orientation,
+            });
+            rest = &rest[end..];
+        }
+        Some(steps)
+    }
+}
+
+impl FromStr for GafRecord {
+    type Err = CigarError;
+
+    /// Parse a tab separated GAF line.
+    ///
+    /// The CIGAR is checked against the path interval and the aligned part of the read.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.trim_end_matches(['\r', '\n']).split('\t').collect();
+        if fields.len() < 12 {
+            return Err(CigarError::invalid_format(
+                FORMAT,
+                format!("expected at least 12 fields, found {}", fields.len()),
+            ));
+        }
+        let strand = match fields[4] {
+            "+" => Strand::Forward,
+            "-" => Strand::Reverse,
+            field => {
+                return Err(CigarError::invalid_format(
+                    FORMAT,
+                    format!("'{}' is not a strand", field),
+                ));
+            }
+        };
+        let mut record = GafRecord {
+            query_name: fields[0].to_string(),
+            query_length: parse_number(FORMAT, fields[1])?,
+            query_start: parse_number(FORMAT, fields[2])?,
+            query_end: parse_number(FORMAT, fields[3])?,
+            strand,
+            path: fields[5].to_string(),
+            path_length: parse_number(FORMAT, fields[6])?,
+            path_start: parse_number(FORMAT, fields[7])?,
+            path_end: parse_number(FORMAT, fields[8])?,
+            matches: parse_number(FORMAT, fields[9])?,
+            block_length: parse_number(FORMAT, fields[10])?,
+            mapq: parse_number(FORMAT, fields[11])?,
+            cigar: Cigar::new(),
+            path_id: 0,
+        };
+        if record.query_start > record.query_end || record.query_end > record.query_length {
+            return Err(CigarError::invalid_format(
+                FORMAT,
+                format!(
+                    "bad read interval {}-{}",
+                    record.query_start, record.query_end
+                ),
+            ));
+        }
+        let Some(cg) = fields[12..]
+            .iter()
+            .find_map(|tag| tag.strip_prefix("cg:Z:"))
+        else {
+            return Ok(record);
+        };
+        let aligned: Cigar = cg.parse()?;
+        if aligned.reference_length() != record.path_end.saturating_sub(record.path_start) {
+            return Err(CigarError::LengthMismatch(
+                record.path_end.saturating_sub(record.path_start) as usize,
+                aligned.reference_length() as usize,
+            ));
+        }
+        if aligned.query_length() as u64 != record.query_end - record.query_start {
+            return Err(CigarError::LengthMismatch(
+                (record.query_end - record.query_start) as usize,
+                aligned.query_length(),
+            ));
+        }
+        // The CIGAR runs along the path, so on the reverse strand the read is reversed.
+        let (leading, trailing) = match strand {
+            Strand::Forward => (record.query_start, record.query_length - record.query_end),
+            Strand::Reverse => (record.query_length - record.query_end, record.query_start),
+        };
+        let mut elements = Vec::new();
+        if leading > 0 {
+            elements.push(CigarElement::new(
+                element_length(FORMAT, leading)?,
+                CigarOp::SoftClip,
+            ));
+        }
+        elements.extend(aligned);
+        if trailing > 0 {
+            elements.push(CigarElement::new(
+                element_length(FORMAT, trailing)?,
+                CigarOp::SoftClip,
+            ));
+        }
+        record.cigar = Cigar::from(elements);
+        Ok(record)
+    }
+}
+
+impl CigarRecord for GafRecord {
+    fn cigar(&self) -> CigarRef<'_> {
+        CigarRef::Elements(self.cigar.elements())
+    }
+
+    fn chrom_id(&self) -> u32 {
+        self.path_id
+    }
+
+    fn reference_position(&self) -> u64 {
+        self.path_start
+    }
+
+    fn strand(&self) -> Option<Strand> {
+        Some(self.strand)
+    }
+
+    fn mapq(&self) -> Option<u8> {
+        (self.mapq != 255).then_some(self.mapq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reverse_strand() {
+        let line = "read2\t15\t1\t11\t-\tchr3\t1000\t100\t111\t9\t11\t255\tNM:i:2\tcg:Z:4M1D6M";
+        let mut record: GafRecord = line.parse().unwrap();
+        assert_eq!(record.cigar.to_string(), "4S4M1D6M1S");
+        assert_eq!(record.mapq(), None);
+        assert_eq!(record.steps(), None);
+        record.path_id = 3;
+        let first = record.augmented().next().unwrap().unwrap();
+        assert_eq!((first.chrom_id, first.reference_position), (3, 100));
+    }
+
+    #[test]
+    fn test_path_steps() {
+        let line = "r\t10\t0\t10\t+\t>s1<s22>s3\t40\t0\t10\t10\t10\t30";
+        let record: GafRecord = line.parse().unwrap();
+        assert!(record.cigar().is_unavailable());
+        let steps = record.steps().unwrap();
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[1].segment, "s22");
+        assert_eq!(steps[1].orientation, Strand::Reverse);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(
+            "r\t10\t0\t10\t+\ts1".parse::<GafRecord>(),
+            Err(CigarError::InvalidFormat("GAF", _))
+        ));
+        assert!(matches!(
+            "r\t10\t0\t10\t*\ts1\t40\t0\t10\t10\t10\t30".parse::<GafRecord>(),
+            Err(CigarError::InvalidFormat("GAF", _))
+        ));
+        assert!(matches!(
+            "r\t10\t0\t10\t+\ts1\t40\t0\t10\t10\t10\t30\tcg:Z:9M".parse::<GafRecord>(),
+            Err(CigarError::LengthMismatch(10, 9))
+        ));
+        // Clips too long for a CIGAR element are not truncated.
+        assert!(matches!(
+            "r\t5000000020\t5000000000\t5000000010\t+\ts1\t40\t0\t10\t10\t10\t30\tcg:Z:10M"
+                .parse::<GafRecord>(),
+            Err(CigarError::InvalidFormat("GAF", _))
+        ));
+    }
+}
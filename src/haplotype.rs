@@ -0,0 +1,449 @@
+//! Haplotype blocks of nearby events.
+//!
+//! Collation counts each event independently, so two deletions a few bases apart seen
+//! in ten reads each might be one complex variant carried by ten reads, or two variants
+//! carried by different reads. A [`HaplotypeBlockIterator`] tags the events of each
+//! record with the identity of its read, groups events lying within a configurable
+//! distance of each other into [`HaplotypeBlock`]s, and counts the reads spanning each
+//! block by the combination of its events they carry.
+//!
+//! Given the reference sequences, insertions and deletions are left-aligned in each read
+//! first, so that the same indel placed differently by the aligner counts as one event.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::haplotype::HaplotypeBlockIterator;
+//!
+//! let records = vec![
+//!     std::io::Result::Ok(("5M2D3M1D5M".to_string(), 0, 100)),
+//!     std::io::Result::Ok(("5M2D3M1D5M".to_string(), 0, 100)),
+//!     std::io::Result::Ok(("5M2D9M".to_string(), 0, 100)),
+//!     std::io::Result::Ok(("16M".to_string(), 0, 100)),
+//! ];
+//! let blocks: Vec<_> = HaplotypeBlockIterator::new(records.into_iter(), 5)
+//!     .collect::<Result<_, _>>()
+//!     .unwrap();
+//! assert_eq!(blocks.len(), 1);
+//! let block = &blocks[0];
+//! assert_eq!((block.start, block.end), (105, 111));
+//! assert_eq!(block.counts, vec![3, 2]);
+//! assert_eq!(block.joint_count(), 2);
+//! assert_eq!(block.count(&[0]), 1);
+//! assert_eq!(block.count(&[]), 1);
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+use std::marker::PhantomData;
+
+use crate::CigarElement;
+use crate::augmented_cigar::{
+    AugmentedCigarElement, AugmentedCigarIterator, EventFilter, TaggedAugmentedCigarElement,
+    TaggedAugmentedCigarIterator,
+};
+use crate::collated::same_event;
+use crate::error::CigarError;
+use crate::normalize::left_align_indels;
+use crate::record::{CigarRecord, CigarRef, record_label};
+use crate::variants::ReferenceSequences;
+
+/// A group of nearby events, with the reads carrying each combination of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HaplotypeBlock {
+    /// The chromosome ID of the block.
+    pub chrom_id: u32,
+    /// The reference position of the first event.
+    pub start: u64,
+    /// The reference position of the end of the last event to end.
+    pub end: u64,
+    /// The distinct events of the block, in collation order. The read position of each
+    /// is that of the first read carrying it.
+    pub events: Vec<AugmentedCigarElement>,
+    /// The number of reads carrying each event.
+    pub counts: Vec<usize>,
+    /// The number of reads aligned across the whole block carrying each combination of
+    /// events, given as indices into `events`. Reads carrying none of the events count
+    /// towards the empty combination.
+    pub haplotypes: BTreeMap<Vec<usize>, usize>,
+}
+
+impl HaplotypeBlock {
+    /// The number of reads aligned across the whole block.
+    pub fn depth(&self) -> usize {
+        self.haplotypes.values().sum()
+    }
+
+    /// The number of reads across the block carrying exactly the given events.
+    pub fn count(&self, events: &[usize]) -> usize {
+        self.haplotypes.get(events).copied().unwrap_or(0)
+    }
+
+    /// The number of reads across the block carrying every event.
+    pub fn joint_count(&self) -> usize {
+        self.count(&(0..self.events.len()).collect::<Vec<_>>())
+    }
+
+    /// Whether the events occur together, as one complex variant: there are several,
+    /// some read across the block carries all of them, and every read across it carries
+    /// either all or none of them.
+    pub fn is_complex(&self) -> bool {
+        self.events.len() > 1
+            && self.joint_count() > 0
+            && self
+                .haplotypes
+                .keys()
+                .all(|events| events.is_empty() || events.len() == self.events.len())
+    }
+}
+
+/// An iterator over the [haplotype blocks](HaplotypeBlock) of a coordinate-sorted
+/// source of records.
+///
+/// Events are grouped by single linkage: an event starting within `distance` of the end
+/// of an event already in a block joins it. Blocks of a single event are produced too,
+/// so every event is in exactly one block. By default only insertions and deletions are
+/// grouped. Blocks are produced in order of their start as soon as the source has moved
+/// far enough past them that no later record can add to them. Records without a CIGAR
+/// are skipped.
+pub struct HaplotypeBlockIterator<'a, Source, R, E, Ref: ?Sized> {
+    source: Source,
+    reference: Option<&'a Ref>,
+    distance: u64,
+    filter: EventFilter,
+    chrom_id: u32,
+    last: Option<(u32, u64)>,
+    next_tag: u64,
+    /// The pending reads, with their tags and the reference intervals they cover.
+    reads: VecDeque<(u64, u64, u64)>,
+    /// The events of the pending reads not yet in a block.
+    events: Vec<TaggedAugmentedCigarElement<u64>>,
+    ready: VecDeque<HaplotypeBlock>,
+    done: bool,
+    _record: PhantomData<(R, E)>,
+}
+
+impl<Source, R, E> HaplotypeBlockIterator<'static, Source, R, E, Vec<Vec<u8>>>
+where
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    /// Create a new iterator grouping events within `distance` of each other, as the
+    /// records place them.
+    pub fn new(source: Source, distance: u64) -> Self {
+        HaplotypeBlockIterator::with_reference(source, distance, None)
+    }
+}
+
+impl<'a, Source, R, E, Ref> HaplotypeBlockIterator<'a, Source, R, E, Ref>
+where
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+    Ref: ReferenceSequences + ?Sized,
+{
+    /// Create a new iterator grouping events within `distance` of each other, after
+    /// left-aligning the indels of each record with bases against the reference.
+    pub fn normalized(source: Source, distance: u64, reference: &'a Ref) -> Self {
+        HaplotypeBlockIterator::with_reference(source, distance, Some(reference))
+    }
+
+    fn with_reference(source: Source, distance: u64, reference: Option<&'a Ref>) -> Self {
+        HaplotypeBlockIterator {
+            source,
+            reference,
+            distance,
+            filter: EventFilter::indels(),
+            chrom_id: 0,
+            last: None,
+            next_tag: 0,
+            reads: VecDeque::new(),
+            events: Vec::new(),
+            ready: VecDeque::new(),
+            done: false,
+            _record: PhantomData,
+        }
+    }
+
+    /// Group only events whose operations pass `filter`, in place of insertions and
+    /// deletions.
+    pub fn event_filter(mut self, filter: EventFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// The events of a record tagged with `tag`, and the end of its alignment.
+    fn record_events(
+        &self,
+        record: &R,
+        tag: u64,
+    ) -> std::result::Result<(Vec<TaggedAugmentedCigarElement<u64>>, u64), CigarError> {
+        let (chrom_id, position) = (record.chrom_id(), record.reference_position());
+        let normalized = match (self.reference, record.sequence()) {
+            (Some(reference), Some(seq)) => {
+                let sequence = reference
+                    .sequence(chrom_id)
+                    .ok_or(CigarError::UnknownChromosome(chrom_id))?;
+                let cigar = match record.cigar() {
+                    CigarRef::Text(text) => text.to_string(),
+                    CigarRef::Elements(elements) => {
+                        CigarElement::cigar_string(elements.iter().cloned())
+                    }
+                };
+                Some(left_align_indels(
+                    position as usize,
+                    &cigar,
+                    &sequence,
+                    &seq,
+                )?)
+            }
+            _ => None,
+        };
+        let augmented = match &normalized {
+            Some(elements) => {
+                AugmentedCigarIterator::from((elements.as_slice(), chrom_id, position))
+            }
+            None => record.augmented(),
+        };
+        let mut events = Vec::new();
+        let mut end = position;
+        for elem in TaggedAugmentedCigarIterator::new(augmented, tag) {
+            let elem = elem?;
+            end = end.max(elem.element.reference_end());
+            if self.filter.accepts(elem.element.op) {
+                events.push(elem);
+            }
+        }
+        Ok((events, end))
+    }
+
+    /// Make blocks of the pending events which no record starting at `frontier` or
+    /// later can add to, or of every pending event if there is no frontier.
+    fn finish_blocks(&mut self, frontier: Option<u64>) {
+        self.events.sort_by(|a, b| {
+            let key = |e: &TaggedAugmentedCigarElement<u64>| {
+                (
+                    e.element.reference_position,
+                    e.element.op,
+                    e.element.length,
+                    e.element.is_leading_clip(),
+                    e.tag,
+                )
+            };
+            key(a).cmp(&key(b))
+        });
+        // The distinct events, with the tags of the reads carrying them.
+        let mut distinct: Vec<(AugmentedCigarElement, Vec<u64>)> = Vec::new();
+        for event in &self.events {
+            match distinct.last_mut() {
+                Some((elem, tags)) if same_event(elem, &event.element) => tags.push(event.tag),
+                _ => distinct.push((event.element.clone(), vec![event.tag])),
+            }
+        }
+
+        let mut kept_from = None;
+        let mut group: Vec<(AugmentedCigarElement, Vec<u64>)> = Vec::new();
+        let mut group_end = 0;
+        for (elem, tags) in distinct {
+            if !group.is_empty() && elem.reference_position > group_end + self.distance {
+                if frontier.is_some_and(|f| group_end + self.distance >= f) {
+                    kept_from = Some(group[0].0.reference_position);
+                    break;
+                }
+                self.ready
+                    .push_back(self.block(std::mem::take(&mut group), group_end));
+            }
+            group_end = if group.is_empty() {
+                elem.reference_end()
+            } else {
+                group_end.max(elem.reference_end())
+            };
+            group.push((elem, tags));
+        }
+        if kept_from.is_none() && !group.is_empty() {
+            if frontier.is_some_and(|f| group_end + self.distance >= f) {
+                kept_from = Some(group[0].0.reference_position);
+            } else {
+                self.ready.push_back(self.block(group, group_end));
+            }
+        }
+
+        match kept_from {
+            Some(start) => self
+                .events
+                .retain(|e| e.element.reference_position >= start),
+            None => self.events.clear(),
+        }
+        // Reads ending before any block still to come cannot be across it.
+        let horizon = match (kept_from, frontier) {
+            (Some(start), Some(frontier)) => Some(start.min(frontier)),
+            (None, frontier) => frontier,
+            (start, None) => start,
+        };
+        match horizon {
+            Some(horizon) => self.reads.retain(|&(_, _, end)| end >= horizon),
+            None => self.reads.clear(),
+        }
+    }
+
+    fn block(&self, group: Vec<(AugmentedCigarElement, Vec<u64>)>, end: u64) -> HaplotypeBlock {
+        let start = group[0].0.reference_position;
+        let mut haplotypes = BTreeMap::new();
+        for &(tag, read_start, read_end) in &self.reads {
+            if read_start <= start && end <= read_end {
+                let carried: Vec<usize> = group
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (_, tags))| tags.binary_search(&tag).is_ok())
+                    .map(|(i, _)| i)
+                    .collect();
+                *haplotypes.entry(carried).or_insert(0) += 1;
+            }
+        }
+        HaplotypeBlock {
+            chrom_id: self.chrom_id,
+            start,
+            end,
+            counts: group.iter().map(|(_, tags)| tags.len()).collect(),
+            events: group.into_iter().map(|(elem, _)| elem).collect(),
+            haplotypes,
+        }
+    }
+}
+
+impl<Source, R, E, Ref> Iterator for HaplotypeBlockIterator<'_, Source, R, E, Ref>
+where
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+    Ref: ReferenceSequences + ?Sized,
+{
+    type Item = std::result::Result<HaplotypeBlock, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(block) = self.ready.pop_front() {
+                return Some(Ok(block));
+            }
+            if self.done {
+                return None;
+            }
+            let record = match self.source.next() {
+                None => {
+                    self.finish_blocks(None);
+                    self.done = true;
+                    continue;
+                }
+                Some(Err(e)) => return Some(Err(CigarError::External(Box::new(e)))),
+                Some(Ok(record)) => record,
+            };
+            if record.cigar().is_unavailable() {
+                continue;
+            }
+            let current = (record.chrom_id(), record.reference_position());
+            if let Some(previous) = self.last.filter(|&p| current < p) {
+                return Some(Err(CigarError::UnsortedInput { previous, current }));
+            }
+            if current.0 != self.chrom_id {
+                self.finish_blocks(None);
+            } else if self.last.is_some_and(|p| current.1 > p.1) {
+                self.finish_blocks(Some(current.1));
+            }
+            self.chrom_id = current.0;
+            self.last = Some(current);
+            let tag = self.next_tag;
+            self.next_tag += 1;
+            match self.record_events(&record, tag) {
+                Ok((events, end)) => {
+                    self.events.extend(events);
+                    self.reads.push_back((tag, current.1, end));
+                }
+                Err(e) => {
+                    return Some(Err(e.with_record(&record_label(&record))));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CigarOp;
+    use crate::sam::SamRecord;
+
+    fn records(
+        records: Vec<(&str, u32, u64)>,
+    ) -> impl Iterator<Item = std::io::Result<(String, u32, u64)>> {
+        records
+            .into_iter()
+            .map(|(cigar, chrom_id, position)| Ok((cigar.to_string(), chrom_id, position)))
+    }
+
+    #[test]
+    fn test_haplotype_blocks() {
+        let source = records(vec![
+            ("4M1I4M3D4M", 0, 10),
+            ("4M1I4M3D4M", 0, 10),
+            ("8M3D4M", 0, 10),
+            ("2M2I4M3D4M", 0, 12),
+            ("20M", 0, 20),
+            ("2M2D20M", 0, 40),
+            ("5M1I5M", 1, 0),
+        ]);
+        let blocks: Vec<_> = HaplotypeBlockIterator::new(source, 5)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let spans: Vec<_> = blocks
+            .iter()
+            .map(|b| (b.chrom_id, b.start, b.end))
+            .collect();
+        assert_eq!(spans, vec![(0, 14, 21), (0, 42, 44), (1, 5, 5)]);
+        let first = &blocks[0];
+        let ops: Vec<_> = first.events.iter().map(|e| e.op).collect();
+        assert_eq!(
+            ops,
+            vec![CigarOp::Insertion, CigarOp::Insertion, CigarOp::Deletion]
+        );
+        assert_eq!(first.counts, vec![2, 1, 4]);
+        // The read starting at 20 is not aligned across the block.
+        assert_eq!(first.depth(), 4);
+        assert_eq!(first.count(&[0, 2]), 2);
+        assert_eq!((first.count(&[1, 2]), first.count(&[2])), (1, 1));
+        assert_eq!(first.joint_count(), 0);
+        assert!(!first.is_complex());
+        assert!(blocks[1].events.len() == 1 && !blocks[1].is_complex());
+    }
+
+    #[test]
+    fn test_haplotype_blocks_normalized() {
+        let reference = vec![b"ACGTTTTACGTAGGCATCGA".to_vec()];
+        // The deletion lies at either end of a run of Ts.
+        let source = || {
+            [
+                "r1\t0\tchr1\t1\t60\t6M1D5M2I3M\t*\t0\t0\tACGTTTACGTACCGGC\t*",
+                "r2\t0\tchr1\t1\t60\t3M1D8M2I3M\t*\t0\t0\tACGTTTACGTACCGGC\t*",
+            ]
+            .iter()
+            .map(|line| std::io::Result::Ok(line.parse::<SamRecord>().unwrap()))
+            .collect::<Vec<_>>()
+            .into_iter()
+        };
+        let blocks: Vec<_> = HaplotypeBlockIterator::normalized(source(), 10, &reference)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].counts, vec![2, 2]);
+        assert!(blocks[0].is_complex());
+        let blocks: Vec<_> = HaplotypeBlockIterator::new(source(), 10)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(blocks[0].counts, vec![1, 1, 2]);
+
+        let unsorted = records(vec![("5M", 0, 10), ("5M", 0, 5)]);
+        assert!(matches!(
+            HaplotypeBlockIterator::new(unsorted, 3).last(),
+            Some(Err(CigarError::UnsortedInput { .. }))
+        ));
+    }
+}
@@ -0,0 +1,258 @@
+//! Hotspot intervals with frequent events.
+//!
+//! For targeted review it helps to know where a large fraction of the reads show some
+//! kind of event, such as indels or clips. [`HotspotIterator`] screens
+//! [per-position summaries](crate::summary) for positions where the fraction of
+//! covering reads with a selected event exceeds a threshold, and merges neighbouring
+//! positions into candidate regions, which can be written out with
+//! [`BedWriter`](crate::writers::BedWriter).
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::collated::CollatedAugmentedCigarIterator;
+//! use cigar_utils::hotspots::HotspotIterator;
+//!
+//! let cigars = vec![
+//!     std::io::Result::Ok(("5M3D5M".to_string(), 0, 100)),
+//!     std::io::Result::Ok(("5M3D5M".to_string(), 0, 100)),
+//!     std::io::Result::Ok(("13M".to_string(), 0, 100)),
+//! ];
+//! let summaries = CollatedAugmentedCigarIterator::new(cigars.into_iter()).summarized();
+//! let hotspots: Vec<_> = HotspotIterator::new(summaries, 0.5)
+//!     .collect::<Result<_, _>>()
+//!     .unwrap();
+//! assert_eq!(hotspots.len(), 1);
+//! assert_eq!((hotspots[0].start, hotspots[0].end), (105, 108));
+//! assert_eq!(hotspots[0].events, 2);
+//! ```
+
+use crate::CigarOp;
+use crate::augmented_cigar::EventFilter;
+use crate::error::CigarError;
+use crate::summary::PositionSummary;
+
+/// A reference interval where a high fraction of reads show events.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hotspot {
+    /// The chromosome ID of the interval.
+    pub chrom_id: u32,
+    /// The start of the half open interval.
+    pub start: u64,
+    /// The end of the half open interval.
+    pub end: u64,
+    /// The highest event fraction within the interval.
+    pub max_fraction: f64,
+    /// The number of selected events starting within the interval.
+    pub events: usize,
+}
+
+impl Hotspot {
+    /// The length of the interval.
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// Whether the interval is empty.
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+/// An iterator over hotspot intervals in a stream of per-position summaries.
+///
+/// A position qualifies when the selected events starting there, as a fraction of its
+/// depth, exceed the threshold; it covers the reference bases of the longest such
+/// event, or just the position for insertions and clips. Qualifying positions no more
+/// than `max_gap` apart are merged, and merged intervals shorter than `min_length` are
+/// dropped.
+pub struct HotspotIterator<I> {
+    inner: I,
+    threshold: f64,
+    min_length: u64,
+    max_gap: u64,
+    ops: EventFilter,
+    current: Option<Hotspot>,
+    done: bool,
+}
+
+impl<I> HotspotIterator<I>
+where
+    I: Iterator<Item = std::result::Result<PositionSummary, CigarError>>,
+{
+    /// Create a new hotspot iterator, reporting positions where the fraction of reads
+    /// with a non-reference event (anything but a match, skip or padding) exceeds
+    /// `threshold`.
+    pub fn new(inner: I, threshold: f64) -> Self {
+        HotspotIterator {
+            inner,
+            threshold,
+            min_length: 1,
+            max_gap: 0,
+            ops: EventFilter::all().excluding([
+                CigarOp::Match,
+                CigarOp::Equal,
+                CigarOp::Skip,
+                CigarOp::Padding,
+            ]),
+            current: None,
+            done: false,
+        }
+    }
+
+    /// Only report intervals of at least `min_length` reference bases.
+    pub fn min_length(mut self, min_length: u64) -> Self {
+        self.min_length = min_length;
+        self
+    }
+
+    /// Merge qualifying positions up to `max_gap` bases apart.
+    pub fn max_gap(mut self, max_gap: u64) -> Self {
+        self.max_gap = max_gap;
+        self
+    }
+
+    /// Count the events with the operations passed by `ops`.
+    pub fn ops(mut self, ops: EventFilter) -> Self {
+        self.ops = ops;
+        self
+    }
+
+    /// The interval covered by a summary, with its event fraction and count, if it qualifies.
+    fn qualify(&self, summary: &PositionSummary) -> Option<Hotspot> {
+        let selected = summary.events.iter().filter(|e| self.ops.accepts(e.op));
+        let events = selected.clone().map(|e| e.count).sum::<usize>();
+        if summary.depth == 0 {
+            return None;
+        }
+        let fraction = events as f64 / summary.depth as f64;
+        if events == 0 || fraction <= self.threshold {
+            return None;
+        }
+        let span = selected
+            .filter(|e| e.op.consumes_reference())
+            .map(|e| e.length as u64)
+            .max()
+            .unwrap_or(1);
+        Some(Hotspot {
+            chrom_id: summary.chrom_id,
+            start: summary.position,
+            end: summary.position + span.max(1),
+            max_fraction: fraction,
+            events,
+        })
+    }
+
+    /// Take the current interval if it is long enough.
+    fn finish(&mut self) -> Option<Hotspot> {
+        self.current
+            .take()
+            .filter(|hotspot| hotspot.len() >= self.min_length)
+    }
+}
+
+impl<I> Iterator for HotspotIterator<I>
+where
+    I: Iterator<Item = std::result::Result<PositionSummary, CigarError>>,
+{
+    type Item = std::result::Result<Hotspot, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let summary = match self.inner.next() {
+                Some(Ok(summary)) => summary,
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    self.done = true;
+                    break;
+                }
+            };
+            let Some(hotspot) = self.qualify(&summary) else {
+                continue;
+            };
+            if let Some(current) = self.current.as_mut()
+                && current.chrom_id == hotspot.chrom_id
+                && hotspot.start <= current.end + self.max_gap
+            {
+                current.end = current.end.max(hotspot.end);
+                current.max_fraction = current.max_fraction.max(hotspot.max_fraction);
+                current.events += hotspot.events;
+                continue;
+            }
+            let finished = self.finish();
+            self.current = Some(hotspot);
+            if finished.is_some() {
+                return finished.map(Ok);
+            }
+        }
+        self.finish().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collated::collated;
+
+    fn summaries(
+        cigars: &[(&'static str, u32, u64)],
+    ) -> impl Iterator<Item = Result<PositionSummary, CigarError>> {
+        collated(cigars.to_vec()).summarized()
+    }
+
+    fn intervals(
+        hotspots: HotspotIterator<impl Iterator<Item = Result<PositionSummary, CigarError>>>,
+    ) -> Vec<(u32, u64, u64, usize)> {
+        hotspots
+            .map(|h| {
+                let h = h.unwrap();
+                (h.chrom_id, h.start, h.end, h.events)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_hotspots() {
+        let cigars = [
+            ("2M1I1M1I6M", 0, 100),
+            ("2M1I1M1I6M", 0, 100),
+            ("10M", 0, 100),
+            ("4M1D5M2S", 1, 50),
+        ];
+        assert_eq!(
+            intervals(HotspotIterator::new(summaries(&cigars), 0.3)),
+            vec![(0, 102, 104, 4), (1, 54, 55, 1)]
+        );
+        assert_eq!(
+            intervals(HotspotIterator::new(summaries(&cigars), 0.3).min_length(2)),
+            vec![(0, 102, 104, 4)]
+        );
+        assert_eq!(
+            intervals(HotspotIterator::new(summaries(&cigars), 0.7)),
+            vec![(1, 54, 55, 1)]
+        );
+        assert_eq!(
+            intervals(
+                HotspotIterator::new(summaries(&cigars), 0.3)
+                    .ops(EventFilter::only([CigarOp::Deletion]))
+            ),
+            vec![(1, 54, 55, 1)]
+        );
+    }
+
+    #[test]
+    fn test_hotspots_max_gap() {
+        let cigars = [("2M1D3M1D2M", 0, 10), ("2M1D3M1D2M", 0, 10)];
+        assert_eq!(
+            intervals(HotspotIterator::new(summaries(&cigars), 0.5)),
+            vec![(0, 12, 13, 2), (0, 16, 17, 2)]
+        );
+        let hotspot = HotspotIterator::new(summaries(&cigars), 0.5)
+            .max_gap(3)
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!((hotspot.start, hotspot.end, hotspot.events), (12, 17, 4));
+        assert_eq!(hotspot.max_fraction, 1.0);
+    }
+}
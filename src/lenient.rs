@@ -0,0 +1,317 @@
+//! Lenient parsing of CIGAR strings.
+//!
+//! CIGARs from third-party TSV and CSV exports are often slightly malformed: padded with
+//! whitespace, written with lowercase operations, followed by stray text, or containing
+//! zero length elements. [`parse_lenient`] repairs such problems where the intended CIGAR
+//! is clear, recording a [`ParseWarning`] for each, and only fails when no elements can be
+//! recovered at all.
+//!
+//! The repairs are:
+//!
+//! - whitespace anywhere is ignored;
+//! - lowercase operations are taken as their uppercase equivalents;
+//! - zero length elements are dropped, and the elements either side of them merged if
+//!   they have the same operation;
+//! - everything from the first text which does not form an element onwards is dropped.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::CigarOp;
+//! use cigar_utils::lenient::{ParseWarning, parse_lenient};
+//!
+//! let parsed = parse_lenient(" 10m2I0D5M;").unwrap();
+//! assert_eq!(parsed.cigar.to_string(), "10M2I5M");
+//! assert_eq!(
+//!     parsed.warnings,
+//!     vec![
+//!         ParseWarning::Whitespace { offset: 0 },
+//!         ParseWarning::LowercaseOperation { offset: 3, op: CigarOp::Match },
+//!         ParseWarning::ZeroLength { offset: 6, op: CigarOp::Deletion },
+//!         ParseWarning::TrailingGarbage { offset: 10, text: ";".to_string() },
+//!     ]
+//! );
+//! ```
+
+use std::fmt::Display;
+
+use crate::builder::CigarBuilder;
+use crate::error::{CigarError, ParsePosition};
+use crate::{Cigar, CigarElement, CigarOp, UNAVAILABLE_CIGAR};
+
+/// A problem repaired while parsing a CIGAR leniently. Offsets are byte offsets into the
+/// original string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseWarning {
+    /// A run of whitespace starting at the offset was ignored.
+    Whitespace {
+        /// The offset of the whitespace.
+        offset: usize,
+    },
+    /// A lowercase operation was taken as uppercase.
+    LowercaseOperation {
+        /// The offset of the operation character.
+        offset: usize,
+        /// The operation it was taken as.
+        op: CigarOp,
+    },
+    /// A zero length element was dropped.
+    ZeroLength {
+        /// The offset of the element.
+        offset: usize,
+        /// The operation of the element.
+        op: CigarOp,
+    },
+    /// Text which does not form an element was dropped, along with everything after it.
+    TrailingGarbage {
+        /// The offset of the text.
+        offset: usize,
+        /// The text dropped.
+        text: String,
+    },
+}
+
+impl Display for ParseWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseWarning::Whitespace { offset } => {
+                write!(f, "ignored whitespace at byte {}", offset)
+            }
+            ParseWarning::LowercaseOperation { offset, op } => {
+                write!(
+                    f,
+                    "lowercase operation taken as '{}' at byte {}",
+                    op, offset
+                )
+            }
+            ParseWarning::ZeroLength { offset, op } => {
+                write!(f, "dropped zero length '{}' element at byte {}", op, offset)
+            }
+            ParseWarning::TrailingGarbage { offset, text } => {
+                write!(f, "dropped trailing text '{}' at byte {}", text, offset)
+            }
+        }
+    }
+}
+
+/// The result of parsing a CIGAR leniently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LenientCigar {
+    /// The repaired CIGAR.
+    pub cigar: Cigar,
+    /// The problems repaired, in the order they occur in the string.
+    pub warnings: Vec<ParseWarning>,
+}
+
+impl LenientCigar {
+    /// Whether the string parsed without any repairs.
+    pub fn is_clean(&self) -> bool {
+        self.warnings.is_empty()
+    }
+}
+
+/// A lenient parser over the characters of a CIGAR string.
+struct Parser<'a> {
+    text: &'a str,
+    offset: usize,
+    warnings: Vec<ParseWarning>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.offset..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let start = self.offset;
+        while let Some(c) = self.peek().filter(|c| c.is_whitespace()) {
+            self.offset += c.len_utf8();
+        }
+        if self.offset > start {
+            self.warnings
+                .push(ParseWarning::Whitespace { offset: start });
+        }
+    }
+
+    /// Parse the next element, or give the error describing why the text at the current
+    /// offset is not one.
+    fn element(&mut self, elements: usize) -> std::result::Result<CigarElement, CigarError> {
+        let start = self.offset;
+        let mut length: u32 = 0;
+        while let Some(digit) = self.peek().and_then(|c| c.to_digit(10)) {
+            length = length
+                .checked_mul(10)
+                .and_then(|l| l.checked_add(digit))
+                .ok_or(CigarError::LengthOverflow(ParsePosition {
+                    offset: start,
+                    element: elements,
+                }))?;
+            self.offset += 1;
+        }
+        let digits = self.offset - start;
+        self.skip_whitespace();
+        let position = ParsePosition {
+            offset: self.offset,
+            element: elements,
+        };
+        let Some(c) = self.peek() else {
+            return Err(CigarError::MissingOperation(length, position));
+        };
+        if digits == 0 {
+            return Err(CigarError::MissingCount(c, position));
+        }
+        let op = CigarOp::try_from(c.to_ascii_uppercase())
+            .map_err(|_| CigarError::InvalidCharacter(c, position))?;
+        if c.is_ascii_lowercase() {
+            self.warnings.push(ParseWarning::LowercaseOperation {
+                offset: self.offset,
+                op,
+            });
+        }
+        self.offset += c.len_utf8();
+        Ok(CigarElement::new(length, op))
+    }
+}
+
+/// Parse a CIGAR string, repairing recoverable problems and recording a warning for each.
+///
+/// The unavailable placeholder `*` and empty strings give an empty CIGAR. Fails only when
+/// the string is not empty but no element can be parsed from its start.
+pub fn parse_lenient(text: &str) -> std::result::Result<LenientCigar, CigarError> {
+    let mut parser = Parser {
+        text,
+        offset: 0,
+        warnings: Vec::new(),
+    };
+    if text.trim() == UNAVAILABLE_CIGAR {
+        let mut warnings = Vec::new();
+        if text.starts_with(char::is_whitespace) {
+            warnings.push(ParseWarning::Whitespace { offset: 0 });
+        }
+        if text.ends_with(char::is_whitespace) {
+            warnings.push(ParseWarning::Whitespace {
+                offset: text.trim_end().len(),
+            });
+        }
+        return Ok(LenientCigar {
+            cigar: Cigar::new(),
+            warnings,
+        });
+    }
+    let mut builder = CigarBuilder::new();
+    let mut parsed = 0;
+    loop {
+        parser.skip_whitespace();
+        if parser.offset == text.len() {
+            break;
+        }
+        let start = parser.offset;
+        match parser.element(parsed) {
+            Ok(elem) => {
+                parsed += 1;
+                if elem.length == 0 {
+                    parser.warnings.push(ParseWarning::ZeroLength {
+                        offset: start,
+                        op: elem.op,
+                    });
+                } else {
+                    builder.push_element(elem)?;
+                }
+            }
+            Err(e) if parsed == 0 => return Err(e),
+            Err(_) => {
+                // Whitespace within the dropped text is not reported separately.
+                parser.warnings.retain(
+                    |w| !matches!(w, ParseWarning::Whitespace { offset } if *offset >= start),
+                );
+                parser.warnings.push(ParseWarning::TrailingGarbage {
+                    offset: start,
+                    text: text[start..].to_string(),
+                });
+                break;
+            }
+        }
+    }
+    Ok(LenientCigar {
+        cigar: builder.build(),
+        warnings: parser.warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lenient_clean() {
+        let parsed = parse_lenient("3S10M2D").unwrap();
+        assert!(parsed.is_clean());
+        assert_eq!(parsed.cigar.to_string(), "3S10M2D");
+        assert_eq!(parse_lenient("").unwrap().cigar, Cigar::new());
+        let placeholder = parse_lenient("* ").unwrap();
+        assert!(placeholder.cigar.is_empty());
+        assert_eq!(
+            placeholder.warnings,
+            vec![ParseWarning::Whitespace { offset: 1 }]
+        );
+    }
+
+    #[test]
+    fn test_parse_lenient_repairs() {
+        let parsed = parse_lenient("5 M 3i\t0S2x 7Q 4M").unwrap();
+        assert_eq!(parsed.cigar.to_string(), "5M3I2X");
+        assert_eq!(
+            parsed.warnings,
+            vec![
+                ParseWarning::Whitespace { offset: 1 },
+                ParseWarning::Whitespace { offset: 3 },
+                ParseWarning::LowercaseOperation {
+                    offset: 5,
+                    op: CigarOp::Insertion
+                },
+                ParseWarning::Whitespace { offset: 6 },
+                ParseWarning::ZeroLength {
+                    offset: 7,
+                    op: CigarOp::SoftClip
+                },
+                ParseWarning::LowercaseOperation {
+                    offset: 10,
+                    op: CigarOp::Diff
+                },
+                ParseWarning::Whitespace { offset: 11 },
+                ParseWarning::TrailingGarbage {
+                    offset: 12,
+                    text: "7Q 4M".to_string()
+                },
+            ]
+        );
+        assert_eq!(
+            parsed.warnings[7].to_string(),
+            "dropped trailing text '7Q 4M' at byte 12"
+        );
+        let merged = parse_lenient("5M0I5M").unwrap();
+        assert_eq!(merged.cigar.to_string(), "10M");
+        assert_eq!(
+            merged.warnings,
+            vec![ParseWarning::ZeroLength {
+                offset: 2,
+                op: CigarOp::Insertion
+            }]
+        );
+        assert!(matches!(
+            parse_lenient("M5"),
+            Err(CigarError::MissingCount('M', _))
+        ));
+        assert!(matches!(
+            parse_lenient(" 12"),
+            Err(CigarError::MissingOperation(12, _))
+        ));
+        assert!(matches!(
+            parse_lenient("99999999999M"),
+            Err(CigarError::LengthOverflow(ParsePosition {
+                offset: 0,
+                element: 0
+            }))
+        ));
+    }
+}
@@ -1,24 +1,136 @@
 //! A Rust library for parsing and working with CIGAR strings in bioinformatics.
-//! 
+//!
 //! This crate provides types and utilities for handling CIGAR operations, including parsing, iteration, and error handling.
-//! 
+//!
 //! # Features
 //! - Iterator for parsing CIGAR strings
-//! - Augmented CIGAR operations that contextualize the individual operations to an alignment.
-//! - Collation of multiple augmented CIGAR operations across multiple CIGAR strings.
+//! - Lenient parsing of malformed CIGARs, repairing recoverable problems with warnings.
+//! - Configurable formatting of CIGARs for logs, SAM and reports, with a strict mode guaranteeing the output parses back unchanged.
+//! - An owned `Cigar` type, a builder for constructing CIGARs element by element, and an adapter merging adjacent elements of a stream.
+//! - Expansion of alignment matches into sequence matches and mismatches, singly or in batches against a reference window (in parallel with the `rayon` feature).
+//! - Left-alignment of insertions and deletions against the reference.
+//! - Reconstruction of reads and reference segments from observed differences.
+//! - Conversion between alignments and CRAM read features.
+//! - Homopolymer and short tandem repeat context of indels.
+//! - Gapped alignment strings and BLAST-style pretty-printing of alignments, and CIGARs inferred from gapped rows.
+//! - Alignment identity, error rates, summaries of CIGAR operations, indel length histograms and per-cycle error profiles.
+//! - Affine-gap scoring of alignments, for re-ranking candidates or checking aligner scores.
+//! - Searching CIGARs for patterns of operations, such as structural variant signatures.
+//! - Conversion to and from UCSC chains, MUMmer delta alignments, exonerate VULGAR alignments and PSL blocks.
+//! - Affine-gap global and glocal pairwise alignment producing CIGARs (`align` feature).
+//! - Padded coordinates and multiple alignment views of reads with padded SAM `P` operations.
+//! - Liftover of BED intervals through an alignment between two sequences.
+//! - Conversion between soft and hard clipping, and extraction of soft clipped bases.
+//! - Trimming alignments by read coordinates, of amplicon primers by reference coordinates, and of low quality read ends.
+//! - Extraction of exon blocks and splice junctions, with intron motifs, from spliced alignments.
+//! - Stable digests of alignment footprints for duplicate detection.
+//! - Comparison, equivalence checking and structured diffs of alignments of the same read.
+//! - Composition of alignments (read to contig to reference).
+//! - Stitching of co-linear supplementary alignments, and layouts of chimeric reads across their alignments.
+//! - Validation of CIGARs against the rules of the SAM specification, of alignments against chromosome lengths, and of read lengths, with repair by soft clipping.
+//! - Support for long CIGARs stored in the BAM `CG` tag.
+//! - Compact tracepoint encoding of alignments, as used by DALIGNER.
+//! - Augmented CIGAR operations that contextualize the individual operations to an alignment, optionally split into `=` and `X` against the reference as they are produced.
+//! - Visitors with a callback for each kind of operation along an alignment, as an alternative to matching on operations.
+//! - Collation of multiple augmented CIGAR operations across multiple CIGAR strings, with checkpoints to resume from (serializable with the `serde` feature), and merging of separately collated shards.
+//! - Collation of insertions and deletions by their bases as well as their length.
+//! - Partitioning of collation sources by chromosome, for concurrent or checkpointed collation.
+//! - Collation sources from BAM, CRAM and SAM records read with noodles (`noodles` feature).
+//! - A lightweight collation source reading SAM text directly.
+//! - Parsing of GAF graph alignments, collated along their paths.
+//! - Coverage depth along the reference, and run-length encoded whole-genome depth tracks.
+//! - Per-position summaries of collated events with their frequencies, optionally with the covered gaps between them, and mergeable tables of them.
+//! - Breakdowns of the alleles at a locus, read from a source only as far as the locus.
+//! - Consensus calling over collated events.
+//! - Haplotype blocks of nearby events, with the reads carrying each combination of them.
+//! - Structural variant breakpoint candidates from clips in collated events.
+//! - Hotspot intervals where a high fraction of reads show events, written as BED.
+//! - Conversion of collated events into VCF-style variant records.
+//! - Aggregation of collated events into fixed-size reference windows.
+//! - A map between chromosome names, IDs and lengths, read from FASTA indexes or SAM headers and shared by sources, writers and the collator.
+//! - bedGraph, wiggle, VCF, BED and MAF writers, and TSV and JSON lines writers of augmented and collated elements.
+//! - Pluggable TSV, BED and in-memory sinks for collated events with their depth.
+//! - Arrow record batches and Parquet files of collated events (`arrow` feature).
+//! - Simulation of random alignments from a configurable error model.
+//! - A C interface for parsing, expansion, coordinate mapping and statistics (`ffi` feature).
+//! - Python bindings for CIGARs, expansion and collation (`python` feature).
+//! - Proptest strategies and `Arbitrary` implementations for CIGARs (`testing` feature).
 
 #![deny(missing_docs)]
 
 use std::convert::TryFrom;
 use std::fmt::Display;
+use std::iter::FusedIterator;
+use std::str::FromStr;
 
+#[cfg(feature = "align")]
+pub mod align;
+pub mod alignment;
+#[cfg(feature = "arrow")]
+pub mod arrow;
 pub mod augmented_cigar;
+pub mod breakpoints;
+pub mod builder;
+pub mod chain;
+pub mod chrom_map;
+pub mod clip;
 pub mod collated;
+pub mod compare;
+pub mod compose;
+pub mod consensus;
+pub mod cram;
+pub mod delta;
+pub mod depth;
+pub mod display;
 pub mod error;
 pub mod expand;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod footprint;
+pub mod gaf;
+pub mod haplotype;
+pub mod hotspots;
+pub mod lenient;
+pub mod liftover;
+pub mod locus;
+pub mod long_cigar;
+#[cfg(feature = "noodles")]
+pub mod noodles;
+pub mod normalize;
+pub mod padded;
+pub mod partition;
+pub mod psl;
+#[cfg(feature = "python")]
+pub mod python;
+mod queue;
+pub mod reconstruct;
+pub mod record;
+pub mod repeats;
+pub mod sam;
+pub mod score;
+pub mod search;
+pub mod sequenced;
+pub mod simulate;
+pub mod sink;
+mod spill;
+pub mod splice;
+pub mod stats;
+pub mod summary;
+pub mod supplementary;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod tracepoints;
+pub mod trim;
+pub mod validate;
+pub mod variants;
+pub mod visit;
+pub mod vulgar;
+pub mod window;
+pub mod writers;
 
 /// CIGAR operation types.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CigarOp {
     /// Alignment match (can be a sequence match or mismatch) (M).
     Match,
@@ -40,9 +152,49 @@ pub enum CigarOp {
     Diff,
 }
 
+impl CigarOp {
+    /// Whether the operation consumes bases of the query (read) sequence, per the SAM specification.
+    pub fn consumes_query(&self) -> bool {
+        matches!(
+            self,
+            CigarOp::Match
+                | CigarOp::Insertion
+                | CigarOp::SoftClip
+                | CigarOp::Equal
+                | CigarOp::Diff
+        )
+    }
+
+    /// The operation for an ASCII character code of the SAM text format, such as `b'M'`.
+    ///
+    /// `TryFrom<u8>` converts from the numeric BAM encoding instead.
+    pub fn from_ascii(code: u8) -> Option<CigarOp> {
+        OP_CODES[code as usize]
+    }
+
+    /// The ASCII character code of the operation in the SAM text format.
+    pub fn to_ascii(&self) -> u8 {
+        char::from(*self) as u8
+    }
+
+    /// Whether the operation consumes bases of the reference sequence, per the SAM specification.
+    pub fn consumes_reference(&self) -> bool {
+        matches!(
+            self,
+            CigarOp::Match | CigarOp::Deletion | CigarOp::Skip | CigarOp::Equal | CigarOp::Diff
+        )
+    }
+}
+
 impl Display for CigarOp {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let c = match self {
+        write!(f, "{}", char::from(*self))
+    }
+}
+
+impl From<CigarOp> for char {
+    fn from(op: CigarOp) -> char {
+        match op {
             CigarOp::Match => 'M',
             CigarOp::Insertion => 'I',
             CigarOp::Deletion => 'D',
@@ -52,8 +204,26 @@ impl Display for CigarOp {
             CigarOp::Padding => 'P',
             CigarOp::Equal => '=',
             CigarOp::Diff => 'X',
-        };
-        write!(f, "{}", c)
+        }
+    }
+}
+
+impl TryFrom<char> for CigarOp {
+    type Error = char;
+
+    fn try_from(value: char) -> Result<Self, Self::Error> {
+        match value {
+            'M' => Ok(CigarOp::Match),
+            'I' => Ok(CigarOp::Insertion),
+            'D' => Ok(CigarOp::Deletion),
+            'N' => Ok(CigarOp::Skip),
+            'S' => Ok(CigarOp::SoftClip),
+            'H' => Ok(CigarOp::HardClip),
+            'P' => Ok(CigarOp::Padding),
+            '=' => Ok(CigarOp::Equal),
+            'X' => Ok(CigarOp::Diff),
+            _ => Err(value),
+        }
     }
 }
 
@@ -92,6 +262,41 @@ impl TryFrom<u8> for CigarOp {
     }
 }
 
+/// The placeholder used in SAM records when no CIGAR is available.
+pub const UNAVAILABLE_CIGAR: &str = "*";
+
+/// Parse a CIGAR string which may be the unavailable placeholder `*`, returning `None` if it is.
+pub fn parse_optional_cigar(cigar: &str) -> std::result::Result<Option<Cigar>, error::CigarError> {
+    if cigar == UNAVAILABLE_CIGAR {
+        return Ok(None);
+    }
+    cigar.parse().map(Some)
+}
+
+/// Parse a CIGAR string into a caller-owned buffer, returning the number of elements parsed.
+///
+/// The buffer is cleared first and keeps its capacity, so reusing one buffer across
+/// records avoids allocating for each. On error the buffer holds the elements before it.
+pub fn parse_into(
+    cigar: &str,
+    out: &mut Vec<CigarElement>,
+) -> std::result::Result<usize, error::CigarError> {
+    out.clear();
+    for elem in CigarIterator::new(cigar) {
+        out.push(elem?);
+    }
+    Ok(out.len())
+}
+
+/// The strand of the reference an alignment is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Strand {
+    /// The forward strand.
+    Forward,
+    /// The reverse strand.
+    Reverse,
+}
+
 /// A single CIGAR operation element.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CigarElement {
@@ -119,15 +324,385 @@ impl Display for CigarElement {
     }
 }
 
+/// An owned sequence of CIGAR elements.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cigar {
+    elements: Vec<CigarElement>,
+}
+
+impl Cigar {
+    /// Create a new, empty CIGAR.
+    pub fn new() -> Self {
+        Cigar {
+            elements: Vec::new(),
+        }
+    }
+
+    /// The elements of the CIGAR.
+    pub fn elements(&self) -> &[CigarElement] {
+        &self.elements
+    }
+
+    /// Consume the CIGAR, returning its elements.
+    pub fn into_elements(self) -> Vec<CigarElement> {
+        self.elements
+    }
+
+    /// The number of elements in the CIGAR.
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    /// Whether the CIGAR has no elements.
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// Iterate over the elements of the CIGAR.
+    pub fn iter(&self) -> std::slice::Iter<'_, CigarElement> {
+        self.elements.iter()
+    }
+
+    /// The number of reference bases covered by the alignment.
+    pub fn reference_length(&self) -> u64 {
+        self.elements
+            .iter()
+            .filter(|e| e.op.consumes_reference())
+            .map(|e| e.length as u64)
+            .sum()
+    }
+
+    /// The number of read bases in SEQ: aligned, inserted and soft clipped bases.
+    pub fn query_length(&self) -> usize {
+        self.elements
+            .iter()
+            .filter(|e| e.op.consumes_query())
+            .map(|e| e.length as usize)
+            .sum()
+    }
+
+    /// The length of the original read, including hard clipped bases.
+    pub fn query_length_with_hard_clips(&self) -> usize {
+        self.query_length() + self.clipped(CigarOp::HardClip)
+    }
+
+    /// The reference position following the last aligned base, for an alignment starting at `start`.
+    pub fn alignment_end(&self, start: u64) -> u64 {
+        start + self.reference_length()
+    }
+
+    /// The start of an alignment at `start` extended back through the bases soft or hard
+    /// clipped from the start of the read, as used for duplicate detection. This may be
+    /// before the start of the reference, so it is signed, as in Picard.
+    pub fn unclipped_start(&self, start: u64) -> i64 {
+        start as i64 - self.leading_clip() as i64
+    }
+
+    /// The end of an alignment at `start` extended on through the bases soft or hard
+    /// clipped from the end of the read: the reference position following the last
+    /// clipped base, were the clipped bases aligned.
+    pub fn unclipped_end(&self, start: u64) -> u64 {
+        self.alignment_end(start) + self.trailing_clip() as u64
+    }
+
+    /// The half open reference interval of the aligned part of an alignment at `start`.
+    pub fn clipped_interval(&self, start: u64) -> (u64, u64) {
+        (start, self.alignment_end(start))
+    }
+
+    /// The half open reference interval of an alignment at `start` extended through its
+    /// clipped bases at both ends, from [`unclipped_start`](Self::unclipped_start) to
+    /// [`unclipped_end`](Self::unclipped_end). The start is signed, as it may be before
+    /// the start of the reference.
+    pub fn unclipped_interval(&self, start: u64) -> (i64, u64) {
+        (self.unclipped_start(start), self.unclipped_end(start))
+    }
+
+    /// The reference intervals covered by elements with the given operations, for an
+    /// alignment at `start`, merged where they touch or overlap.
+    ///
+    /// Operations which do not consume the reference cover no positions. With `=` and `X`
+    /// operations this gives a mask of the mismatches of a read, for example; see
+    /// [`AugmentedCigarIterator::covered_intervals`](augmented_cigar::AugmentedCigarIterator::covered_intervals)
+    /// to find them for a CIGAR using `M`.
+    pub fn covered_intervals(&self, start: u64, ops: &[CigarOp]) -> Vec<(u64, u64)> {
+        let mut intervals: Vec<(u64, u64)> = Vec::new();
+        let mut position = start;
+        for elem in self.elements.iter().filter(|e| e.op.consumes_reference()) {
+            let end = position + elem.length as u64;
+            if ops.contains(&elem.op) && end > position {
+                match intervals.last_mut() {
+                    Some(last) if last.1 == position => last.1 = end,
+                    _ => intervals.push((position, end)),
+                }
+            }
+            position = end;
+        }
+        intervals
+    }
+
+    /// The reference position aligned to `read_position`, for an alignment starting at `start`.
+    ///
+    /// Read positions count soft clipped but not hard clipped bases. Clipped and inserted
+    /// bases have no reference position.
+    pub fn read_to_reference(&self, start: u64, read_position: usize) -> Option<u64> {
+        let (mut read, mut reference) = (0, start);
+        for elem in self.elements.iter() {
+            let (query, aligned) = (elem.op.consumes_query(), elem.op.consumes_reference());
+            if query && read_position < read + elem.length as usize {
+                return aligned.then(|| reference + (read_position - read) as u64);
+            }
+            read += if query { elem.length as usize } else { 0 };
+            reference += if aligned { elem.length as u64 } else { 0 };
+        }
+        None
+    }
+
+    /// The read position aligned to the reference position `position`, for an alignment
+    /// starting at `start`.
+    ///
+    /// Read positions count soft clipped but not hard clipped bases. Deleted and skipped
+    /// bases have no read position.
+    pub fn reference_to_read(&self, start: u64, position: u64) -> Option<usize> {
+        let (mut read, mut reference) = (0, start);
+        for elem in self.elements.iter() {
+            let (query, aligned) = (elem.op.consumes_query(), elem.op.consumes_reference());
+            if aligned && reference <= position && position < reference + elem.length as u64 {
+                return query.then(|| read + (position - reference) as usize);
+            }
+            read += if query { elem.length as usize } else { 0 };
+            reference += if aligned { elem.length as u64 } else { 0 };
+        }
+        None
+    }
+
+    /// The number of bases soft or hard clipped from the start of the read.
+    pub fn leading_clip(&self) -> usize {
+        Self::clip_length(self.elements.iter())
+    }
+
+    /// The number of bases soft or hard clipped from the end of the read.
+    pub fn trailing_clip(&self) -> usize {
+        if self
+            .elements
+            .iter()
+            .all(|e| matches!(e.op, CigarOp::SoftClip | CigarOp::HardClip))
+        {
+            // A CIGAR of only clips has all of them leading.
+            return 0;
+        }
+        Self::clip_length(self.elements.iter().rev())
+    }
+
+    fn clip_length<'a>(elements: impl Iterator<Item = &'a CigarElement>) -> usize {
+        elements
+            .take_while(|e| matches!(e.op, CigarOp::SoftClip | CigarOp::HardClip))
+            .map(|e| e.length as usize)
+            .sum()
+    }
+
+    fn clipped(&self, op: CigarOp) -> usize {
+        self.elements
+            .iter()
+            .filter(|e| e.op == op)
+            .map(|e| e.length as usize)
+            .sum()
+    }
+
+    /// The CIGAR with its elements in reverse order, as for the reverse strand of the read.
+    pub fn reversed(&self) -> Cigar {
+        Cigar {
+            elements: self.elements.iter().rev().cloned().collect(),
+        }
+    }
+
+    /// The CIGAR written out with one operation character per base, such as `MMMMIIDD`
+    /// for `4M2I2D`.
+    pub fn to_per_base_string(&self) -> String {
+        let mut s = String::with_capacity(self.elements.iter().map(|e| e.length as usize).sum());
+        for elem in self.elements.iter() {
+            s.extend(std::iter::repeat_n(
+                char::from(elem.op),
+                elem.length as usize,
+            ));
+        }
+        s
+    }
+
+    /// Parse a CIGAR written out with one operation character per base, such as
+    /// `MMMMIIDD`, merging runs of the same operation into elements.
+    pub fn from_per_base_str(s: &str) -> std::result::Result<Cigar, error::CigarError> {
+        let mut elements: Vec<CigarElement> = Vec::new();
+        for (offset, c) in s.char_indices() {
+            let op = CigarOp::try_from(c).map_err(|_| {
+                error::CigarError::InvalidCharacter(
+                    c,
+                    error::ParsePosition {
+                        offset,
+                        element: elements.len(),
+                    },
+                )
+            })?;
+            match elements.last_mut() {
+                Some(last) if last.op == op => last.length += 1,
+                _ => elements.push(CigarElement::new(1, op)),
+            }
+        }
+        Ok(Cigar { elements })
+    }
+}
+
+impl From<Vec<CigarElement>> for Cigar {
+    fn from(elements: Vec<CigarElement>) -> Self {
+        Cigar { elements }
+    }
+}
+
+impl FromStr for Cigar {
+    type Err = error::CigarError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let elements = CigarIterator::new(s).collect::<Result<Vec<_>, _>>()?;
+        Ok(Cigar { elements })
+    }
+}
+
+impl Display for Cigar {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for elem in self.elements.iter() {
+            write!(f, "{}", elem)?;
+        }
+        Ok(())
+    }
+}
+
+impl IntoIterator for Cigar {
+    type Item = CigarElement;
+    type IntoIter = std::vec::IntoIter<CigarElement>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.elements.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Cigar {
+    type Item = &'a CigarElement;
+    type IntoIter = std::slice::Iter<'a, CigarElement>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.elements.iter()
+    }
+}
+
+/// The operation of each ASCII character code of the SAM text format.
+const OP_CODES: [Option<CigarOp>; 256] = {
+    let mut table = [None; 256];
+    table[b'M' as usize] = Some(CigarOp::Match);
+    table[b'I' as usize] = Some(CigarOp::Insertion);
+    table[b'D' as usize] = Some(CigarOp::Deletion);
+    table[b'N' as usize] = Some(CigarOp::Skip);
+    table[b'S' as usize] = Some(CigarOp::SoftClip);
+    table[b'H' as usize] = Some(CigarOp::HardClip);
+    table[b'P' as usize] = Some(CigarOp::Padding);
+    table[b'=' as usize] = Some(CigarOp::Equal);
+    table[b'X' as usize] = Some(CigarOp::Diff);
+    table
+};
+
+/// The number of ASCII digits at the start of `bytes`.
+///
+/// Digits are tested eight bytes at a time: a byte is a digit if its high nibble is 3
+/// and adding 6 to its low nibble does not carry into the high nibble.
+fn digit_run(bytes: &[u8]) -> usize {
+    const HIGH: u64 = 0xF0F0_F0F0_F0F0_F0F0;
+    const LOW: u64 = 0x0F0F_0F0F_0F0F_0F0F;
+    let mut count = 0;
+    while let Some(chunk) = bytes.get(count..count + 8) {
+        let word = u64::from_le_bytes(chunk.try_into().expect("an eight byte chunk"));
+        let non_digits = ((word & HIGH) ^ 0x3030_3030_3030_3030)
+            | ((word & LOW).wrapping_add(0x0606_0606_0606_0606) & HIGH);
+        if non_digits != 0 {
+            return count + (non_digits.trailing_zeros() / 8) as usize;
+        }
+        count += 8;
+    }
+    count
+        + bytes[count..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count()
+}
+
 /// An iterator over CIGAR elements.
+///
+/// Parsing works on bytes: each element's run of digits is found and converted in bulk,
+/// and its operation looked up in a table, so only malformed input is decoded as UTF-8.
+#[derive(Debug, Clone)]
 pub struct CigarIterator<'a> {
-    chars: std::str::Chars<'a>,
+    cigar: &'a str,
+    offset: usize,
+    element: usize,
 }
 
 impl<'a> CigarIterator<'a> {
     /// Create a new CIGAR iterator.
     pub fn new(cigar: &'a str) -> Self {
-        CigarIterator { chars: cigar.chars() }
+        CigarIterator {
+            cigar,
+            offset: 0,
+            element: 0,
+        }
+    }
+
+    /// The CIGAR string being parsed.
+    pub fn as_str(&self) -> &'a str {
+        self.cigar
+    }
+
+    fn next_element(&mut self) -> Option<std::result::Result<CigarElement, error::CigarError>> {
+        let bytes = &self.cigar.as_bytes()[self.offset..];
+        if bytes.is_empty() {
+            return None;
+        }
+        let digits = digit_run(bytes);
+        let Some(length) = bytes[..digits].iter().try_fold(0u32, |length, b| {
+            length.checked_mul(10)?.checked_add((b - b'0') as u32)
+        }) else {
+            let position = error::ParsePosition {
+                offset: self.offset,
+                element: self.element,
+            };
+            self.offset += digits;
+            self.offset += self.cigar[self.offset..]
+                .chars()
+                .next()
+                .map_or(0, char::len_utf8);
+            return Some(Err(error::CigarError::LengthOverflow(position)));
+        };
+        let position = error::ParsePosition {
+            offset: self.offset + digits,
+            element: self.element,
+        };
+        let Some(&code) = bytes.get(digits) else {
+            self.offset += digits;
+            return Some(Err(error::CigarError::MissingOperation(length, position)));
+        };
+        let c = if code.is_ascii() {
+            code as char
+        } else {
+            self.cigar[position.offset..]
+                .chars()
+                .next()
+                .expect("a character at a char boundary")
+        };
+        self.offset += digits + c.len_utf8();
+        if digits == 0 {
+            return Some(Err(error::CigarError::MissingCount(c, position)));
+        }
+        match OP_CODES[code as usize] {
+            Some(op) => Some(Ok(CigarElement::new(length, op))),
+            None => Some(Err(error::CigarError::InvalidCharacter(c, position))),
+        }
     }
 }
 
@@ -135,58 +710,32 @@ impl<'a> Iterator for CigarIterator<'a> {
     type Item = std::result::Result<CigarElement, error::CigarError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut digit_count = 0;
-        let mut length = 0;
-
-        while let Some(c) = self.chars.next() {
-            match c {
-                '0'..='9' => {
-                    length = length * 10 + (c as u32 - '0' as u32);
-                    digit_count += 1;
-                    continue;
-                }
-                _ => {}
-            }
-            if digit_count == 0 {
-                return Some(Err(error::CigarError::MissingCount(c)));
-            }
-
-            match c {
-                'M' => return Some(Ok(CigarElement::new(length, CigarOp::Match))),
-                'I' => return Some(Ok(CigarElement::new(length, CigarOp::Insertion))),
-                'D' => return Some(Ok(CigarElement::new(length, CigarOp::Deletion))),
-                'N' => return Some(Ok(CigarElement::new(length, CigarOp::Skip))),
-                'S' => return Some(Ok(CigarElement::new(length, CigarOp::SoftClip))),
-                'H' => return Some(Ok(CigarElement::new(length, CigarOp::HardClip))),
-                'P' => return Some(Ok(CigarElement::new(length, CigarOp::Padding))),
-                '=' => return Some(Ok(CigarElement::new(length, CigarOp::Equal))),
-                'X' => return Some(Ok(CigarElement::new(length, CigarOp::Diff))),
-                _ => {
-                    return Some(Err(error::CigarError::InvalidCharacter(c)));
-                }
-            }
-        }
-
-        if digit_count > 0 {
-            return Some(Err(error::CigarError::MissingOperation(length)));
+        let result = self.next_element();
+        if result.is_some() {
+            self.element += 1;
         }
+        result
+    }
 
-        None
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Each element, or error, takes at least one character of the remaining input.
+        let remaining = self.cigar.len() - self.offset;
+        (remaining.min(1), Some(remaining))
     }
 }
 
+impl<'a> FusedIterator for CigarIterator<'a> {}
+
 #[cfg(test)]
 mod tests {
-    use crate::error::CigarError;
+    use crate::error::{CigarError, ParsePosition};
 
     use super::*;
 
     #[test]
     fn test_cigar_iterator_basic() {
         let cigar = "10M5I3D";
-        let iter = CigarIterator {
-            chars: cigar.chars(),
-        };
+        let iter = CigarIterator::new(cigar);
         let elems: Vec<_> = iter.collect();
         assert_eq!(elems.len(), 3);
         assert!(matches!(elems[0], Ok(ref e) if e.length == 10 && matches!(e.op, CigarOp::Match)));
@@ -201,9 +750,7 @@ mod tests {
     #[test]
     fn test_cigar_iterator_all_ops() {
         let cigar = "1M2I3D4N5S6H7P8=9X";
-        let iter = CigarIterator {
-            chars: cigar.chars(),
-        };
+        let iter = CigarIterator::new(cigar);
         let expected = [
             (1, CigarOp::Match),
             (2, CigarOp::Insertion),
@@ -231,24 +778,243 @@ mod tests {
     #[test]
     fn test_cigar_iterator_invalid_char() {
         let cigar = "5M2Z";
-        let iter = CigarIterator {
-            chars: cigar.chars(),
-        };
+        let iter = CigarIterator::new(cigar);
         let elems: Vec<_> = iter.collect();
-        assert!(matches!(elems[0], Ok(_)));
-        assert!(matches!(elems[1], Err(CigarError::InvalidCharacter('Z'))));
+        assert!(elems[0].is_ok());
+        assert!(matches!(
+            elems[1],
+            Err(CigarError::InvalidCharacter('Z', _))
+        ));
     }
 
     #[test]
     fn test_cigar_iterator_missing_count() {
         let cigar = "M5I";
-        let iter = CigarIterator {
-            chars: cigar.chars(),
-        };
+        let iter = CigarIterator::new(cigar);
         let elems: Vec<_> = iter.collect();
-        assert!(matches!(elems[0], Err(CigarError::MissingCount('M'))));
+        assert!(matches!(elems[0], Err(CigarError::MissingCount('M', _))));
         assert!(
             matches!(elems[1], Ok(ref e) if e.length == 5 && matches!(e.op, CigarOp::Insertion))
         );
     }
+
+    #[test]
+    fn test_cigar_iterator_error_positions() {
+        let elems: Vec<_> = CigarIterator::new("5M2Z3M4").collect();
+        assert!(matches!(
+            elems[1],
+            Err(CigarError::InvalidCharacter(
+                'Z',
+                error::ParsePosition {
+                    offset: 3,
+                    element: 1
+                }
+            ))
+        ));
+        assert!(matches!(
+            elems[3],
+            Err(CigarError::MissingOperation(
+                4,
+                error::ParsePosition {
+                    offset: 7,
+                    element: 3
+                }
+            ))
+        ));
+        let position = error::ParsePosition {
+            offset: 6,
+            element: 2,
+        };
+        assert_eq!(position.snippet("10M5I2Z3M4D", 2), "I2Z3M");
+    }
+
+    #[test]
+    fn test_cigar_iterator_long_and_multibyte() {
+        let elems: Vec<_> = CigarIterator::new("123456789M1234567890N7=")
+            .map(|e| e.unwrap())
+            .collect();
+        assert_eq!(elems[0], CigarElement::new(123456789, CigarOp::Match));
+        assert_eq!(elems[1], CigarElement::new(1234567890, CigarOp::Skip));
+        assert_eq!(elems[2], CigarElement::new(7, CigarOp::Equal));
+
+        let elems: Vec<_> = CigarIterator::new("3Mé4µ2I").collect();
+        assert!(matches!(
+            elems[1],
+            Err(CigarError::MissingCount(
+                'é',
+                error::ParsePosition {
+                    offset: 2,
+                    element: 1
+                }
+            ))
+        ));
+        assert!(matches!(
+            elems[2],
+            Err(CigarError::InvalidCharacter(
+                'µ',
+                error::ParsePosition {
+                    offset: 5,
+                    element: 2
+                }
+            ))
+        ));
+        assert_eq!(
+            elems[3].as_ref().unwrap(),
+            &CigarElement::new(2, CigarOp::Insertion)
+        );
+        assert_eq!(digit_run(b"0123456789012345:"), 16);
+        assert_eq!(digit_run(b"12345/789"), 5);
+    }
+
+    #[test]
+    fn test_cigar_iterator_length_overflow() {
+        let max = "4294967295M".parse::<Cigar>().unwrap();
+        assert_eq!(max.elements()[0].length, u32::MAX);
+        let elems: Vec<_> = CigarIterator::new("2S4294967296M3I").collect();
+        assert!(matches!(
+            &elems[1],
+            Err(CigarError::LengthOverflow(ParsePosition {
+                offset: 2,
+                element: 1
+            }))
+        ));
+        assert_eq!(
+            elems[2].as_ref().unwrap(),
+            &CigarElement::new(3, CigarOp::Insertion)
+        );
+        assert!("99999999999M".parse::<Cigar>().is_err());
+    }
+
+    #[test]
+    fn test_cigar_op_characters() {
+        for code in 0..9u8 {
+            let op = CigarOp::try_from(code).unwrap();
+            assert_eq!(CigarOp::try_from(char::from(op)), Ok(op));
+            assert_eq!(CigarOp::from_ascii(op.to_ascii()), Some(op));
+        }
+        assert_eq!(char::from(CigarOp::Equal), '=');
+        assert_eq!(CigarOp::Skip.to_ascii(), b'N');
+        assert_eq!(CigarOp::try_from('m'), Err('m'));
+        assert_eq!(CigarOp::from_ascii(b'Z'), None);
+    }
+
+    #[test]
+    fn test_cigar_round_trip() {
+        let cigar: Cigar = "3S10M2I5M".parse().unwrap();
+        assert_eq!(cigar.len(), 4);
+        assert_eq!(cigar.elements()[1], CigarElement::new(10, CigarOp::Match));
+        assert_eq!(cigar.to_string(), "3S10M2I5M");
+    }
+
+    #[test]
+    fn test_parse_optional_cigar() {
+        assert_eq!(parse_optional_cigar("*").unwrap(), None);
+        let cigar = parse_optional_cigar("4M1D2M").unwrap().unwrap();
+        assert_eq!(cigar.to_string(), "4M1D2M");
+        assert!(parse_optional_cigar("**").is_err());
+    }
+
+    #[test]
+    fn test_parse_into() {
+        let mut buffer = Vec::new();
+        assert_eq!(parse_into("4M1D2M", &mut buffer).unwrap(), 3);
+        assert_eq!(buffer[1], CigarElement::new(1, CigarOp::Deletion));
+        let capacity = buffer.capacity();
+        assert_eq!(parse_into("7S", &mut buffer).unwrap(), 1);
+        assert_eq!(buffer, vec![CigarElement::new(7, CigarOp::SoftClip)]);
+        assert_eq!(buffer.capacity(), capacity);
+        assert!(parse_into("3M2Q", &mut buffer).is_err());
+        assert_eq!(buffer, vec![CigarElement::new(3, CigarOp::Match)]);
+    }
+
+    #[test]
+    fn test_cigar_lengths() {
+        let cigar: Cigar = "3H2S5M2I3M4D1M1S".parse().unwrap();
+        assert_eq!(cigar.reference_length(), 13);
+        assert_eq!(cigar.query_length(), 14);
+        assert_eq!(cigar.query_length_with_hard_clips(), 17);
+        assert_eq!(cigar.alignment_end(100), 113);
+        assert_eq!((cigar.leading_clip(), cigar.trailing_clip()), (5, 1));
+
+        let cigar: Cigar = "4S".parse().unwrap();
+        assert_eq!((cigar.leading_clip(), cigar.trailing_clip()), (4, 0));
+        assert_eq!(Cigar::new().alignment_end(7), 7);
+    }
+
+    #[test]
+    fn test_cigar_clip_intervals() {
+        let cigar: Cigar = "3H2S5M2I3M4D1M1S".parse().unwrap();
+        assert_eq!(cigar.clipped_interval(100), (100, 113));
+        assert_eq!(cigar.unclipped_interval(100), (95, 114));
+        assert_eq!(cigar.unclipped_end(100), 114);
+        assert_eq!(cigar.unclipped_start(100), 95);
+        assert_eq!(cigar.unclipped_interval(2), (-3, 16));
+    }
+
+    #[test]
+    fn test_cigar_covered_intervals() {
+        let cigar: Cigar = "2S3=1X1=2X1D2=1I1X3N1X".parse().unwrap();
+        assert_eq!(
+            cigar.covered_intervals(10, &[CigarOp::Diff]),
+            vec![(13, 14), (15, 17), (20, 21), (24, 25)]
+        );
+        assert_eq!(
+            cigar.covered_intervals(10, &[CigarOp::Diff, CigarOp::Deletion, CigarOp::Insertion]),
+            vec![(13, 14), (15, 18), (20, 21), (24, 25)]
+        );
+        assert!(cigar.covered_intervals(10, &[CigarOp::SoftClip]).is_empty());
+    }
+
+    #[test]
+    fn test_cigar_reversed() {
+        let cigar: Cigar = "3S10M2I5M1H".parse().unwrap();
+        assert_eq!(cigar.reversed().to_string(), "1H5M2I10M3S");
+    }
+
+    #[test]
+    fn test_cigar_per_base_string() {
+        let cigar: Cigar = "2S4M2I1D".parse().unwrap();
+        assert_eq!(cigar.to_per_base_string(), "SSMMMMIID");
+        assert_eq!(Cigar::from_per_base_str("SSMMMMIID").unwrap(), cigar);
+        assert_eq!(Cigar::from_per_base_str("").unwrap(), Cigar::new());
+        assert!(matches!(
+            Cigar::from_per_base_str("MMIZ"),
+            Err(CigarError::InvalidCharacter(
+                'Z',
+                ParsePosition {
+                    offset: 3,
+                    element: 2
+                }
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_cigar_coordinate_mapping() {
+        let cigar: Cigar = "1H2S3M2I2M3D2M".parse().unwrap();
+        assert_eq!(cigar.read_to_reference(10, 2), Some(10));
+        assert_eq!(cigar.read_to_reference(10, 8), Some(14));
+        assert_eq!(cigar.read_to_reference(10, 5), None);
+        assert_eq!(cigar.read_to_reference(10, 11), None);
+        assert_eq!(cigar.reference_to_read(10, 18), Some(9));
+        assert_eq!(cigar.reference_to_read(10, 16), None);
+        assert_eq!(cigar.reference_to_read(10, 9), None);
+    }
+
+    #[test]
+    fn test_cigar_iterator_size_hint_and_fused() {
+        let mut iter = CigarIterator::new("10M5I");
+        assert_eq!(iter.size_hint(), (1, Some(5)));
+        iter.next();
+        assert_eq!(iter.size_hint(), (1, Some(2)));
+        iter.next();
+        assert_eq!(iter.size_hint(), (0, Some(0)));
+        assert!(iter.next().is_none());
+        assert!(iter.next().is_none());
+
+        let mut iter = CigarIterator::new("MM");
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+    }
 }
@@ -0,0 +1,306 @@
+//! Liftover of intervals through an alignment.
+//!
+//! A CIGAR aligning one sequence to another, such as an alt contig to the primary
+//! assembly or an assembly contig to the reference, defines a mapping between their
+//! coordinates. [`Liftover`] maps intervals of the query onto the reference (or, once
+//! [inverted](Liftover::inverse), the other way), splitting them at indels and
+//! reporting how much of each could not be mapped. [`BedRecord`] reads and writes the
+//! BED lines of such intervals.
+//!
+//! Query coordinates count from the start of the whole query sequence, including any
+//! clipped bases, and both sequences are taken to be on the same strand.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::Cigar;
+//! use cigar_utils::liftover::{BedRecord, Liftover};
+//!
+//! let cigar: Cigar = "2S10M3D10M".parse().unwrap();
+//! let liftover = Liftover::from_alignment(&cigar, 1000);
+//! let record: BedRecord = "alt1\t8\t16\tgene1".parse().unwrap();
+//! let lifted = liftover.lift_record(&record, "chr1");
+//! let lines: Vec<_> = lifted.pieces.iter().map(|p| p.to_string()).collect();
+//! assert_eq!(lines, vec!["chr1\t1006\t1010\tgene1", "chr1\t1013\t1017\tgene1"]);
+//! assert_eq!(lifted.unmapped_fraction(), 0.0);
+//! ```
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::chain::chain_blocks;
+use crate::error::{CigarError, parse_number};
+use crate::{Cigar, CigarOp};
+
+/// An ungapped block of the mapping: `size` bases from `from` onwards map to `to` onwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LiftBlock {
+    from: u64,
+    to: u64,
+    size: u64,
+}
+
+/// A mapping of coordinates between two aligned sequences.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Liftover {
+    blocks: Vec<LiftBlock>,
+}
+
+/// An interval mapped through a [`Liftover`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiftedInterval {
+    /// The half open intervals the mapped bases land on, one per ungapped block, in order.
+    pub pieces: Vec<(u64, u64)>,
+    /// The length of the original interval.
+    pub length: u64,
+}
+
+impl LiftedInterval {
+    /// The number of bases of the original interval which were mapped.
+    pub fn mapped(&self) -> u64 {
+        self.pieces.iter().map(|(start, end)| end - start).sum()
+    }
+
+    /// The fraction of the original interval which could not be mapped, zero for an
+    /// empty interval.
+    pub fn unmapped_fraction(&self) -> f64 {
+        if self.length == 0 {
+            0.0
+        } else {
+            (self.length - self.mapped()) as f64 / self.length as f64
+        }
+    }
+}
+
+impl Liftover {
+    /// The mapping from query to reference coordinates of a CIGAR alignment whose
+    /// aligned part starts at `reference_start`.
+    pub fn from_alignment(cigar: &Cigar, reference_start: u64) -> Liftover {
+        let leading = cigar
+            .iter()
+            .take_while(|e| !matches!(e.op, CigarOp::Match | CigarOp::Equal | CigarOp::Diff))
+            .map(|e| {
+                let reference = e.op.consumes_reference() as u64 * e.length as u64;
+                let query = (e.op.consumes_query() || e.op == CigarOp::HardClip) as u64;
+                (query * e.length as u64, reference)
+            })
+            .fold((0, 0), |(q, r), (dq, dr)| (q + dq, r + dr));
+        let (mut from, mut to) = (leading.0, reference_start + leading.1);
+        let mut blocks = Vec::new();
+        for block in chain_blocks(cigar) {
+            blocks.push(LiftBlock {
+                from,
+                to,
+                size: block.size,
+            });
+            from += block.size + block.dq;
+            to += block.size + block.dt;
+        }
+        Liftover { blocks }
+    }
+
+    /// The mapping in the opposite direction.
+    pub fn inverse(&self) -> Liftover {
+        Liftover {
+            blocks: self
+                .blocks
+                .iter()
+                .map(|b| LiftBlock {
+                    from: b.to,
+                    to: b.from,
+                    size: b.size,
+                })
+                .collect(),
+        }
+    }
+
+    /// The position a single position maps to, if it is aligned.
+    pub fn lift_position(&self, position: u64) -> Option<u64> {
+        let i = self.blocks.partition_point(|b| b.from + b.size <= position);
+        let block = self.blocks.get(i)?;
+        (block.from <= position).then(|| block.to + (position - block.from))
+    }
+
+    /// Map the half open interval `start..end`.
+    pub fn lift(&self, start: u64, end: u64) -> LiftedInterval {
+        let first = self.blocks.partition_point(|b| b.from + b.size <= start);
+        let last = self.blocks.partition_point(|b| b.from < end).max(first);
+        let pieces = self.blocks[first..last]
+            .iter()
+            .map(|b| {
+                let lo = start.max(b.from);
+                let hi = end.min(b.from + b.size);
+                (b.to + (lo - b.from), b.to + (hi - b.from))
+            })
+            .collect();
+        LiftedInterval {
+            pieces,
+            length: end.saturating_sub(start),
+        }
+    }
+
+    /// Map a BED record onto the chromosome `target`, giving a record for each piece.
+    pub fn lift_record(&self, record: &BedRecord, target: &str) -> LiftedBed {
+        let lifted = self.lift(record.start, record.end);
+        LiftedBed {
+            pieces: lifted
+                .pieces
+                .iter()
+                .map(|&(start, end)| BedRecord {
+                    chrom: target.to_string(),
+                    start,
+                    end,
+                    rest: record.rest.clone(),
+                })
+                .collect(),
+            length: lifted.length,
+            original: record.clone(),
+        }
+    }
+
+    /// Map a stream of BED records on the chromosome `source` onto the chromosome
+    /// `target`. Records on other chromosomes are left wholly unmapped.
+    pub fn lift_bed<'a, I>(
+        &'a self,
+        records: I,
+        source: &'a str,
+        target: &'a str,
+    ) -> impl Iterator<Item = LiftedBed> + 'a
+    where
+        I: IntoIterator<Item = BedRecord>,
+        I::IntoIter: 'a,
+    {
+        records.into_iter().map(move |record| {
+            if record.chrom == source {
+                self.lift_record(&record, target)
+            } else {
+                LiftedBed {
+                    pieces: Vec::new(),
+                    length: record.end.saturating_sub(record.start),
+                    original: record,
+                }
+            }
+        })
+    }
+}
+
+/// A BED record mapped through a [`Liftover`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiftedBed {
+    /// The original record.
+    pub original: BedRecord,
+    /// A record for each mapped piece, with the columns after the interval copied.
+    pub pieces: Vec<BedRecord>,
+    /// The length of the original interval.
+    pub length: u64,
+}
+
+impl LiftedBed {
+    /// The fraction of the original interval which could not be mapped, zero for an
+    /// empty interval.
+    pub fn unmapped_fraction(&self) -> f64 {
+        let mapped: u64 = self.pieces.iter().map(|p| p.end - p.start).sum();
+        if self.length == 0 {
+            0.0
+        } else {
+            (self.length - mapped) as f64 / self.length as f64
+        }
+    }
+}
+
+/// A BED line: a chromosome, a zero-based half open interval, and any further columns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BedRecord {
+    /// The chromosome name.
+    pub chrom: String,
+    /// The start of the interval.
+    pub start: u64,
+    /// The end of the interval.
+    pub end: u64,
+    /// The columns after the interval, tab separated, or empty.
+    pub rest: String,
+}
+
+/// The format name given in [`CigarError::InvalidFormat`] errors.
+const FORMAT: &str = "BED";
+
+impl FromStr for BedRecord {
+    type Err = CigarError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.trim_end_matches(['\r', '\n']).splitn(4, '\t');
+        let mut field = || {
+            fields
+                .next()
+                .ok_or_else(|| CigarError::invalid_format(FORMAT, "too few fields"))
+        };
+        let chrom = field()?.to_string();
+        let start = parse_number(FORMAT, field()?)?;
+        let end = parse_number(FORMAT, field()?)?;
+        if end < start {
+            return Err(CigarError::invalid_format(
+                FORMAT,
+                format!("interval {}-{} ends before it starts", start, end),
+            ));
+        }
+        let rest = fields.next().unwrap_or("").to_string();
+        Ok(BedRecord {
+            chrom,
+            start,
+            end,
+            rest,
+        })
+    }
+}
+
+impl Display for BedRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\t{}\t{}", self.chrom, self.start, self.end)?;
+        if !self.rest.is_empty() {
+            write!(f, "\t{}", self.rest)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lift_across_indels() {
+        let cigar: Cigar = "3H5M2I5M4D5M".parse().unwrap();
+        let liftover = Liftover::from_alignment(&cigar, 100);
+        assert_eq!(liftover.lift_position(3), Some(100));
+        assert_eq!(liftover.lift_position(8), None);
+        assert_eq!(liftover.lift_position(10), Some(105));
+
+        let lifted = liftover.lift(6, 22);
+        assert_eq!(lifted.pieces, vec![(103, 105), (105, 110), (114, 119)]);
+        assert_eq!(lifted.mapped(), 12);
+        assert_eq!(lifted.unmapped_fraction(), 0.25);
+
+        let inverse = liftover.inverse();
+        assert_eq!(inverse.lift_position(114), Some(15));
+        assert_eq!(inverse.lift(108, 116).pieces, vec![(13, 15), (15, 17)]);
+        assert_eq!(inverse.lift(111, 113).pieces, vec![]);
+    }
+
+    #[test]
+    fn test_lift_bed() {
+        let cigar: Cigar = "10M".parse().unwrap();
+        let liftover = Liftover::from_alignment(&cigar, 50);
+        let records: Vec<BedRecord> = ["alt\t5\t15\tx\t0", "other\t0\t5"]
+            .iter()
+            .map(|line| line.parse().unwrap())
+            .collect();
+        let lifted: Vec<_> = liftover.lift_bed(records, "alt", "chr2").collect();
+        assert_eq!(lifted[0].pieces[0].to_string(), "chr2\t55\t60\tx\t0");
+        assert_eq!(lifted[0].unmapped_fraction(), 0.5);
+        assert_eq!(lifted[1].unmapped_fraction(), 1.0);
+        assert!(matches!(
+            "chr1\t10\t5".parse::<BedRecord>(),
+            Err(CigarError::InvalidFormat("BED", _))
+        ));
+    }
+}
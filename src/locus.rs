@@ -0,0 +1,302 @@
+//! Allele breakdowns at a single locus.
+//!
+//! Collating a whole source to look at one position is wasteful. [`query_locus`] reads a
+//! coordinate-sorted source only as far as a small reference interval, and returns for
+//! each position in it the alleles the covering reads show: the reference base, each
+//! distinct substituted base, each distinct insertion and deletion with its bases, and
+//! deletions spanning the position, with the number of reads showing each. It is a
+//! targeted pileup, with no downsampling.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::locus::{Allele, query_position};
+//! use cigar_utils::sam::SamRecord;
+//!
+//! let records: Vec<std::io::Result<SamRecord>> = [
+//!     "r1\t0\tchr1\t1\t60\t4M\t*\t0\t0\tACGT\t*",
+//!     "r2\t0\tchr1\t1\t60\t4M\t*\t0\t0\tACTT\t*",
+//!     "r3\t0\tchr1\t2\t60\t1M1D1M\t*\t0\t0\tCT\t*",
+//! ]
+//! .iter()
+//! .map(|line| Ok(line.parse().unwrap()))
+//! .collect();
+//! let reference = vec![b"ACGT".to_vec()];
+//! let locus = query_position(records.into_iter(), &reference, 0, 2).unwrap();
+//! assert_eq!(locus.reference_base, b'G');
+//! assert_eq!(locus.count(&Allele::Reference), 1);
+//! assert_eq!(locus.count(&Allele::Substitution(b'T')), 1);
+//! assert_eq!(locus.count(&Allele::Deletion(b"G".to_vec())), 1);
+//! assert_eq!(locus.depth(), 3);
+//! ```
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use crate::CigarOp;
+use crate::error::CigarError;
+use crate::record::{CigarRecord, record_label};
+use crate::sequenced::SequencedElement;
+use crate::variants::ReferenceSequences;
+
+/// An allele observed at a reference position.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Allele {
+    /// An aligned base matching the reference.
+    Reference,
+    /// An aligned base differing from the reference.
+    Substitution(u8),
+    /// Bases inserted before the position.
+    Insertion(Vec<u8>),
+    /// A deletion starting at the position, with the deleted reference bases.
+    Deletion(Vec<u8>),
+    /// The position lies within a deletion starting before it.
+    Deleted,
+}
+
+impl Allele {
+    /// Whether the allele occupies the position itself, rather than lying before it.
+    /// Every read covering a position shows exactly one such allele there.
+    pub fn occupies_position(&self) -> bool {
+        !matches!(self, Allele::Insertion(_))
+    }
+}
+
+/// The alleles observed at a reference position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocusAlleles {
+    /// The chromosome ID of the position.
+    pub chrom_id: u32,
+    /// The reference position.
+    pub position: u64,
+    /// The reference base at the position.
+    pub reference_base: u8,
+    /// The number of reads showing each allele.
+    pub alleles: BTreeMap<Allele, usize>,
+}
+
+impl LocusAlleles {
+    /// The number of reads showing an allele.
+    pub fn count(&self, allele: &Allele) -> usize {
+        self.alleles.get(allele).copied().unwrap_or(0)
+    }
+
+    /// The number of reads covering the position with an aligned base or a deletion.
+    pub fn depth(&self) -> usize {
+        self.alleles
+            .iter()
+            .filter(|(allele, _)| allele.occupies_position())
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// The alleles other than the reference, with their counts, most frequent first.
+    pub fn variants(&self) -> Vec<(&Allele, usize)> {
+        let mut variants: Vec<_> = self
+            .alleles
+            .iter()
+            .filter(|(allele, _)| **allele != Allele::Reference)
+            .map(|(allele, count)| (allele, *count))
+            .collect();
+        variants.sort_by_key(|&(_, count)| Reverse(count));
+        variants
+    }
+}
+
+/// The alleles at each position of the reference interval `start..end` on a chromosome,
+/// from the reads of a coordinate-sorted source.
+///
+/// Records on earlier chromosomes are skipped without parsing their CIGARs, and reading
+/// stops at the first record starting beyond the interval. Records without a CIGAR or
+/// without bases are skipped. Fails on the first error from the source, or for a record
+/// whose events lie beyond its bases or the reference, and if the interval lies beyond
+/// the end of the reference.
+pub fn query_locus<S, R, E, Ref>(
+    source: S,
+    reference: &Ref,
+    chrom_id: u32,
+    start: u64,
+    end: u64,
+) -> std::result::Result<Vec<LocusAlleles>, CigarError>
+where
+    S: IntoIterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+    Ref: ReferenceSequences + ?Sized,
+{
+    let sequence = reference
+        .sequence(chrom_id)
+        .ok_or(CigarError::UnknownChromosome(chrom_id))?;
+    if end as usize > sequence.len() {
+        return Err(CigarError::ReferenceOutOfBounds(start, end));
+    }
+    let mut loci: Vec<LocusAlleles> = (start..end)
+        .map(|position| LocusAlleles {
+            chrom_id,
+            position,
+            reference_base: sequence[position as usize],
+            alleles: BTreeMap::new(),
+        })
+        .collect();
+    for record in source {
+        let record = record.map_err(|e| CigarError::External(Box::new(e)))?;
+        if record.chrom_id() < chrom_id || record.cigar().is_unavailable() {
+            continue;
+        }
+        if record.chrom_id() > chrom_id || record.reference_position() >= end {
+            break;
+        }
+        let Some(read) = record.sequence() else {
+            continue;
+        };
+        add_record(&mut loci, &record, read, sequence, start, end)
+            .map_err(|e| e.with_record(&record_label(&record)))?;
+    }
+    Ok(loci)
+}
+
+/// The alleles at a single reference position. See [`query_locus`].
+pub fn query_position<S, R, E, Ref>(
+    source: S,
+    reference: &Ref,
+    chrom_id: u32,
+    position: u64,
+) -> std::result::Result<LocusAlleles, CigarError>
+where
+    S: IntoIterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+    Ref: ReferenceSequences + ?Sized,
+{
+    let mut loci = query_locus(source, reference, chrom_id, position, position + 1)?;
+    Ok(loci.remove(0))
+}
+
+/// Add the alleles of a record to the positions of `start..end`.
+fn add_record<R: CigarRecord>(
+    loci: &mut [LocusAlleles],
+    record: &R,
+    read: &[u8],
+    reference: &[u8],
+    start: u64,
+    end: u64,
+) -> std::result::Result<(), CigarError> {
+    let mut add = |position: u64, allele: Allele| {
+        *loci[(position - start) as usize]
+            .alleles
+            .entry(allele)
+            .or_insert(0) += 1;
+    };
+    for elem in record.augmented() {
+        let elem = elem?;
+        if elem.reference_end() < start {
+            continue;
+        }
+        if elem.reference_position >= end {
+            break;
+        }
+        match elem.op {
+            CigarOp::Match | CigarOp::Equal | CigarOp::Diff => {
+                let first = elem.reference_position.max(start);
+                let last = elem.reference_end().min(end);
+                let offset = (first - elem.reference_position) as usize;
+                let read_start = elem.read_position as usize + offset;
+                let read_end = read_start + (last - first) as usize;
+                let bases = read
+                    .get(read_start..read_end)
+                    .ok_or(CigarError::SequenceTooShort(read_end, read.len()))?;
+                for (position, base) in (first..last).zip(bases) {
+                    let reference_base = reference[position as usize];
+                    if base.eq_ignore_ascii_case(&reference_base) {
+                        add(position, Allele::Reference);
+                    } else {
+                        add(position, Allele::Substitution(base.to_ascii_uppercase()));
+                    }
+                }
+            }
+            CigarOp::Insertion if elem.reference_position >= start => {
+                let sequenced = SequencedElement::new(elem, Some(read), None)?;
+                let bases = sequenced.sequence.expect("insertions have bases");
+                add(
+                    sequenced.element.reference_position,
+                    Allele::Insertion(bases),
+                );
+            }
+            CigarOp::Deletion => {
+                let sequenced = SequencedElement::new(elem, None, Some(reference))?;
+                let elem = sequenced.element;
+                if elem.reference_position >= start {
+                    let bases = sequenced.sequence.expect("deletions have bases");
+                    add(elem.reference_position, Allele::Deletion(bases));
+                }
+                for position in
+                    (elem.reference_position + 1).max(start)..elem.reference_end().min(end)
+                {
+                    add(position, Allele::Deleted);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sam::SamRecord;
+
+    fn records(lines: &[&str]) -> Vec<std::io::Result<SamRecord>> {
+        lines.iter().map(|line| Ok(line.parse().unwrap())).collect()
+    }
+
+    #[test]
+    fn test_query_locus() {
+        let reference = vec![b"TTTT".to_vec(), b"ACGTACGTAC".to_vec()];
+        let mut source = records(&[
+            "r0\t0\tchr0\t1\t60\t4M\t*\t0\t0\tTTTT\t*",
+            "r1\t0\tchr1\t1\t60\t3M2I3M\t*\t0\t0\tACGGGTAC\t*",
+            "r2\t0\tchr1\t2\t60\t2M2I1M3D2M\t*\t0\t0\tCGAATCG\t*",
+            "r3\t0\tchr1\t3\t60\t1M2I3M\t*\t0\t0\tgGGaAC\t*",
+            "r4\t0\tchr1\t4\t60\t4M\t*\t0\t0\t*\t*",
+            "r5\t0\tchr1\t9\t60\t2M\t*\t0\t0\tAC\t*",
+        ]);
+        for record in &mut source[1..] {
+            record.as_mut().unwrap().chrom_id = 1;
+        }
+        let loci = query_locus(source, &reference, 1, 3, 5).unwrap();
+        assert_eq!(loci.len(), 2);
+        let (first, second) = (&loci[0], &loci[1]);
+        assert_eq!((first.position, first.reference_base), (3, b'T'));
+        assert_eq!(first.count(&Allele::Reference), 2);
+        assert_eq!(first.count(&Allele::Insertion(b"GG".to_vec())), 2);
+        assert_eq!(first.count(&Allele::Insertion(b"AA".to_vec())), 1);
+        assert_eq!(first.count(&Allele::Substitution(b'A')), 1);
+        assert_eq!(first.depth(), 3);
+        assert_eq!(first.variants()[0], (&Allele::Insertion(b"GG".to_vec()), 2));
+        assert_eq!(second.count(&Allele::Deletion(b"ACG".to_vec())), 1);
+        assert_eq!(second.count(&Allele::Reference), 2);
+        assert_eq!(second.count(&Allele::Substitution(b'A')), 0);
+        assert_eq!(second.depth(), 3);
+    }
+
+    #[test]
+    fn test_query_locus_errors() {
+        let reference = vec![b"ACGT".to_vec()];
+        let source = records(&["r1\t0\tchr1\t1\t60\t4M\t*\t0\t0\tAC\t*"]);
+        assert!(matches!(
+            query_position(source, &reference, 0, 2).unwrap_err().root(),
+            CigarError::SequenceTooShort(3, 2)
+        ));
+        assert!(matches!(
+            query_position(records(&[]), &reference, 1, 2),
+            Err(CigarError::UnknownChromosome(1))
+        ));
+        assert!(matches!(
+            query_locus(records(&[]), &reference, 0, 2, 5),
+            Err(CigarError::ReferenceOutOfBounds(2, 5))
+        ));
+        let locus = query_position(records(&[]), &reference, 0, 3).unwrap();
+        assert_eq!((locus.reference_base, locus.depth()), (b'T', 0));
+    }
+}
@@ -0,0 +1,138 @@
+//! Long CIGARs.
+//!
+//! BAM records can hold at most 65535 CIGAR operations. Longer CIGARs (common with
+//! ultralong nanopore reads) are stored in a `CG:B,I` tag, and the CIGAR field holds a
+//! placeholder `kSmN`, where `k` is the read length and `m` the reference length. This
+//! module provides helpers to detect the placeholder and to convert between CIGAR
+//! elements and the BAM binary encoding used by the `CG` tag.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::{CigarElement, CigarOp};
+//! use cigar_utils::long_cigar::{decode_cg_tag, encode_cg_tag, is_placeholder, placeholder};
+//!
+//! let elements = vec![
+//!     CigarElement::new(10, CigarOp::Match),
+//!     CigarElement::new(2, CigarOp::Deletion),
+//!     CigarElement::new(5, CigarOp::Match),
+//! ];
+//! let cg = encode_cg_tag(&elements).unwrap();
+//! assert_eq!(decode_cg_tag(&cg).unwrap(), elements);
+//!
+//! let stub = placeholder(&elements);
+//! assert_eq!(CigarElement::cigar_string(stub.clone()), "15S17N");
+//! assert!(is_placeholder(&stub, 15));
+//! ```
+
+use crate::error::CigarError;
+use crate::validate::BAM_MAX_OPERATIONS;
+use crate::{CigarElement, CigarOp};
+
+/// The longest CIGAR element the BAM binary representation can hold.
+pub const BAM_MAX_ELEMENT_LENGTH: u32 = (1 << 28) - 1;
+
+/// Encode a CIGAR element in the BAM binary representation (`length << 4 | op`), giving
+/// [`CigarError::ElementTooLong`] if its length does not fit in 28 bits.
+pub fn encode_element(elem: &CigarElement) -> std::result::Result<u32, CigarError> {
+    if elem.length > BAM_MAX_ELEMENT_LENGTH {
+        return Err(CigarError::ElementTooLong(elem.length));
+    }
+    Ok((elem.length << 4) | u8::from(elem.op) as u32)
+}
+
+/// Decode a CIGAR element from the BAM binary representation.
+pub fn decode_element(value: u32) -> std::result::Result<CigarElement, CigarError> {
+    let code = (value & 0xf) as u8;
+    let op = CigarOp::try_from(code).map_err(CigarError::InvalidOpCode)?;
+    Ok(CigarElement::new(value >> 4, op))
+}
+
+/// Whether a CIGAR has too many elements to be stored in the BAM CIGAR field.
+pub fn needs_cg_tag(elements: &[CigarElement]) -> bool {
+    elements.len() > BAM_MAX_OPERATIONS
+}
+
+/// Whether a CIGAR is the `kSmN` placeholder for a read of the given length.
+pub fn is_placeholder(elements: &[CigarElement], read_length: usize) -> bool {
+    matches!(
+        elements,
+        [
+            CigarElement { length, op: CigarOp::SoftClip },
+            CigarElement { op: CigarOp::Skip, .. },
+        ] if *length as usize == read_length
+    )
+}
+
+/// The `kSmN` placeholder to store in the CIGAR field in place of the given CIGAR.
+pub fn placeholder(elements: &[CigarElement]) -> Vec<CigarElement> {
+    let sum = |f: fn(&CigarOp) -> bool| -> u32 {
+        elements.iter().filter(|e| f(&e.op)).map(|e| e.length).sum()
+    };
+    vec![
+        CigarElement::new(sum(CigarOp::consumes_query), CigarOp::SoftClip),
+        CigarElement::new(sum(CigarOp::consumes_reference), CigarOp::Skip),
+    ]
+}
+
+/// Decode the values of a `CG:B,I` tag into CIGAR elements.
+pub fn decode_cg_tag(values: &[u32]) -> std::result::Result<Vec<CigarElement>, CigarError> {
+    values.iter().map(|v| decode_element(*v)).collect()
+}
+
+/// Encode CIGAR elements as the values of a `CG:B,I` tag.
+pub fn encode_cg_tag(elements: &[CigarElement]) -> std::result::Result<Vec<u32>, CigarError> {
+    elements.iter().map(encode_element).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode_element() {
+        let elem = CigarElement::new(1234, CigarOp::Diff);
+        let value = encode_element(&elem).unwrap();
+        assert_eq!(value, (1234 << 4) | 8);
+        assert_eq!(decode_element(value).unwrap(), elem);
+
+        let longest = CigarElement::new(BAM_MAX_ELEMENT_LENGTH, CigarOp::Skip);
+        let value = encode_element(&longest).unwrap();
+        assert_eq!(decode_element(value).unwrap(), longest);
+        assert!(matches!(
+            encode_cg_tag(&[elem, CigarElement::new(1 << 28, CigarOp::Match)]),
+            Err(CigarError::ElementTooLong(268435456))
+        ));
+    }
+
+    #[test]
+    fn test_decode_invalid_op() {
+        assert!(matches!(
+            decode_cg_tag(&[(5 << 4) | 9]),
+            Err(CigarError::InvalidOpCode(9))
+        ));
+    }
+
+    #[test]
+    fn test_is_placeholder() {
+        let stub = vec![
+            CigarElement::new(100, CigarOp::SoftClip),
+            CigarElement::new(120, CigarOp::Skip),
+        ];
+        assert!(is_placeholder(&stub, 100));
+        assert!(!is_placeholder(&stub, 99));
+        let normal = vec![CigarElement::new(100, CigarOp::SoftClip)];
+        assert!(!is_placeholder(&normal, 100));
+    }
+
+    #[test]
+    fn test_needs_cg_tag() {
+        let mut elements = Vec::new();
+        for _ in 0..BAM_MAX_OPERATIONS / 2 + 1 {
+            elements.push(CigarElement::new(1, CigarOp::Match));
+            elements.push(CigarElement::new(1, CigarOp::Insertion));
+        }
+        assert!(needs_cg_tag(&elements));
+        assert!(!needs_cg_tag(&elements[..BAM_MAX_OPERATIONS]));
+    }
+}
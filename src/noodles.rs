@@ -0,0 +1,201 @@
+//! Alignment records from noodles.
+//!
+//! With the `noodles` feature enabled, this module adapts any source of
+//! [noodles](https://github.com/zaeleus/noodles) alignment records (BAM, CRAM or SAM)
+//! into a source for [collation](crate::collated), filtering records on flags, mapping
+//! quality and region along the way.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use cigar_utils::collated::CollatedAugmentedCigarIterator;
+//! use cigar_utils::noodles::{AlignmentSource, RecordFilter};
+//! use noodles_bam as bam;
+//!
+//! let mut reader = bam::io::reader::Builder::default().build_from_path("sample.bam")?;
+//! let header = reader.read_header()?;
+//! let source = AlignmentSource::new(&header, reader.records(), RecordFilter::default());
+//! for event in CollatedAugmentedCigarIterator::new(source) {
+//!     let (elem, count) = event?;
+//!     println!("{}:{} {}{} x{}", elem.chrom_id, elem.reference_position, elem.length, elem.op, count);
+//! }
+//! ```
+
+use std::cmp::Ordering;
+use std::io;
+
+use noodles_sam as sam;
+use noodles_sam::alignment::record::cigar::op::Kind;
+
+use crate::record::{CigarRecord, CigarRef, region_order};
+pub use crate::record::{DEFAULT_EXCLUDE_FLAGS, RecordFilter};
+use crate::{CigarElement, CigarOp, Strand};
+
+/// An alignment record read from noodles, holding what collation needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlignmentRecord {
+    /// The CIGAR of the alignment.
+    pub cigar: Vec<CigarElement>,
+    /// The chromosome ID of the alignment.
+    pub chrom_id: u32,
+    /// The 0-based reference position of the start of the alignment.
+    pub reference_position: u64,
+    /// The strand of the alignment.
+    pub strand: Strand,
+    /// The mapping quality, if known.
+    pub mapq: Option<u8>,
+    /// The SAM flags of the record.
+    pub flags: u16,
+}
+
+impl AlignmentRecord {
+    /// Convert a noodles alignment record.
+    ///
+    /// Returns `None` if the record has no reference sequence or position.
+    pub fn from_sam_record<R: sam::alignment::Record + ?Sized>(
+        header: &sam::Header,
+        record: &R,
+    ) -> io::Result<Option<AlignmentRecord>> {
+        let flags = record.flags()?;
+        let chrom_id = match record.reference_sequence_id(header).transpose()? {
+            Some(id) => u32::try_from(id).map_err(invalid_data)?,
+            None => return Ok(None),
+        };
+        let reference_position = match record.alignment_start().transpose()? {
+            Some(start) => (start.get() - 1) as u64,
+            None => return Ok(None),
+        };
+        let mapq = record.mapping_quality().transpose()?.map(|q| q.get());
+        let cigar = record.cigar();
+        let mut elements = Vec::with_capacity(cigar.len());
+        for op in cigar.iter() {
+            let op = op?;
+            let length = u32::try_from(op.len()).map_err(invalid_data)?;
+            elements.push(CigarElement::new(length, cigar_op(op.kind())));
+        }
+        let strand = if flags.is_reverse_complemented() {
+            Strand::Reverse
+        } else {
+            Strand::Forward
+        };
+        Ok(Some(AlignmentRecord {
+            cigar: elements,
+            chrom_id,
+            reference_position,
+            strand,
+            mapq,
+            flags: flags.bits(),
+        }))
+    }
+
+    fn reference_end(&self) -> u64 {
+        self.reference_position
+            + self
+                .cigar
+                .iter()
+                .filter(|e| e.op.consumes_reference())
+                .map(|e| e.length as u64)
+                .sum::<u64>()
+    }
+}
+
+impl CigarRecord for AlignmentRecord {
+    fn cigar(&self) -> CigarRef<'_> {
+        CigarRef::Elements(&self.cigar)
+    }
+
+    fn chrom_id(&self) -> u32 {
+        self.chrom_id
+    }
+
+    fn reference_position(&self) -> u64 {
+        self.reference_position
+    }
+
+    fn strand(&self) -> Option<Strand> {
+        Some(self.strand)
+    }
+
+    fn mapq(&self) -> Option<u8> {
+        self.mapq
+    }
+
+    fn flags(&self) -> Option<u16> {
+        Some(self.flags)
+    }
+}
+
+fn cigar_op(kind: Kind) -> CigarOp {
+    match kind {
+        Kind::Match => CigarOp::Match,
+        Kind::Insertion => CigarOp::Insertion,
+        Kind::Deletion => CigarOp::Deletion,
+        Kind::Skip => CigarOp::Skip,
+        Kind::SoftClip => CigarOp::SoftClip,
+        Kind::HardClip => CigarOp::HardClip,
+        Kind::Pad => CigarOp::Padding,
+        Kind::SequenceMatch => CigarOp::Equal,
+        Kind::SequenceMismatch => CigarOp::Diff,
+    }
+}
+
+fn invalid_data<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// A source of [`AlignmentRecord`]s for collation, read from a coordinate-sorted source
+/// of noodles alignment records.
+///
+/// Records without a position, or failing the [`RecordFilter`], are skipped. When a region
+/// is given, reading stops once the records have passed it.
+pub struct AlignmentSource<'h, I> {
+    header: &'h sam::Header,
+    records: I,
+    filter: RecordFilter,
+}
+
+impl<'h, I> AlignmentSource<'h, I> {
+    /// Create a new alignment source.
+    pub fn new(header: &'h sam::Header, records: I, filter: RecordFilter) -> Self {
+        AlignmentSource {
+            header,
+            records,
+            filter,
+        }
+    }
+}
+
+impl<'h, I, R> Iterator for AlignmentSource<'h, I>
+where
+    I: Iterator<Item = io::Result<R>>,
+    R: sam::alignment::Record,
+{
+    type Item = io::Result<AlignmentRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let record = match self.records.next()? {
+                Ok(record) => record,
+                Err(e) => return Some(Err(e)),
+            };
+            let record = match AlignmentRecord::from_sam_record(self.header, &record) {
+                Ok(Some(record)) => record,
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            };
+            if !self.filter.accepts(&record) {
+                continue;
+            }
+            if let Some(region) = self.filter.region {
+                let (_, start, _) = region;
+                match region_order(region, record.chrom_id, record.reference_position) {
+                    Ordering::Greater => return None,
+                    Ordering::Less => continue,
+                    Ordering::Equal if record.reference_end() <= start => continue,
+                    Ordering::Equal => {}
+                }
+            }
+            return Some(Ok(record));
+        }
+    }
+}
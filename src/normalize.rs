@@ -0,0 +1,146 @@
+//! Normalization of CIGAR operations.
+//!
+//! Insertions and deletions within repetitive sequence can be placed at several
+//! equivalent positions. Different aligners make different choices, so this module
+//! provides left-alignment of indels (as `bcftools norm` does for variants) to give
+//! a canonical representation.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::CigarElement;
+//! use cigar_utils::normalize::left_align_indels;
+//!
+//! let reference = b"GCAAAT";
+//! let seq = b"GCAAT";
+//! let cigar = "4M1D1M";
+//!
+//! let normalized = left_align_indels(0, cigar, &reference, &seq).unwrap();
+//! assert_eq!(CigarElement::cigar_string(normalized), "2M1D3M");
+//! ```
+
+use crate::builder::CigarBuilder;
+use crate::{CigarElement, CigarIterator, CigarOp, error::CigarError};
+
+/// Shift insertions and deletions as far left as the reference and read sequences allow.
+///
+/// An indel is moved left one base at a time while the base preceding it is the same as
+/// the last base of the inserted (or deleted) sequence, and while it is preceded by an
+/// aligned (`M`, `=` or `X`) element. At least one aligned base is always left before
+/// the indel, so it never becomes the first aligned element. Comparison of bases is
+/// case-insensitive. Adjacent elements with the same operation are merged in the result.
+pub fn left_align_indels<R: AsRef<[u8]>, S: AsRef<[u8]>>(
+    reference_position: usize,
+    cigar: &str,
+    reference: &R,
+    seq: &S,
+) -> std::result::Result<Vec<CigarElement>, CigarError> {
+    let reference = reference.as_ref();
+    let seq = seq.as_ref();
+    let mut elements = CigarIterator::new(cigar).collect::<Result<Vec<_>, _>>()?;
+    let mut reference_position = reference_position;
+    let mut read_position = 0;
+
+    let mut i = 0;
+    while i < elements.len() {
+        let op = elements[i].op;
+        let length = elements[i].length as usize;
+        if length > 0 && matches!(op, CigarOp::Insertion | CigarOp::Deletion) {
+            while i > 0
+                && is_aligned(elements[i - 1].op)
+                && (elements[i - 1].length > 1 || (i > 1 && is_aligned(elements[i - 2].op)))
+                && reference_position > 0
+                && read_position > 0
+            {
+                let shiftable = match op {
+                    CigarOp::Deletion => same_base(
+                        reference,
+                        reference_position - 1,
+                        reference_position + length - 1,
+                    ),
+                    _ => same_base(seq, read_position - 1, read_position + length - 1),
+                };
+                if !shiftable {
+                    break;
+                }
+                let moved = elements[i - 1].op;
+                elements[i - 1].length -= 1;
+                if i + 1 < elements.len() && elements[i + 1].op == moved {
+                    elements[i + 1].length += 1;
+                } else {
+                    elements.insert(i + 1, CigarElement::new(1, moved));
+                }
+                reference_position -= 1;
+                read_position -= 1;
+                if elements[i - 1].length == 0 {
+                    elements.remove(i - 1);
+                    i -= 1;
+                }
+            }
+        }
+        if op.consumes_reference() {
+            reference_position += length;
+        }
+        if op.consumes_query() {
+            read_position += length;
+        }
+        i += 1;
+    }
+
+    let mut builder = CigarBuilder::new();
+    for elem in elements.into_iter().filter(|e| e.length > 0) {
+        builder.push_element(elem)?;
+    }
+    Ok(builder.build().into_elements())
+}
+
+fn is_aligned(op: CigarOp) -> bool {
+    matches!(op, CigarOp::Match | CigarOp::Equal | CigarOp::Diff)
+}
+
+fn same_base(seq: &[u8], a: usize, b: usize) -> bool {
+    match (seq.get(a), seq.get(b)) {
+        (Some(x), Some(y)) => x.eq_ignore_ascii_case(y),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(reference: &[u8], seq: &[u8], cigar: &str) -> String {
+        CigarElement::cigar_string(left_align_indels(0, cigar, &reference, &seq).unwrap())
+    }
+
+    #[test]
+    fn test_left_align_deletion() {
+        assert_eq!(normalized(b"GCAAAT", b"GCAAT", "4M1D1M"), "2M1D3M");
+    }
+
+    #[test]
+    fn test_left_align_insertion() {
+        assert_eq!(normalized(b"GCAAT", b"GCAAAT", "4M1I1M"), "2M1I3M");
+    }
+
+    #[test]
+    fn test_left_align_dinucleotide_repeat() {
+        assert_eq!(normalized(b"GCACACT", b"GCACT", "4M2D1M"), "1M2D4M");
+    }
+
+    #[test]
+    fn test_left_align_already_normalized() {
+        assert_eq!(normalized(b"GCAAAT", b"GCAAT", "2M1D3M"), "2M1D3M");
+    }
+
+    #[test]
+    fn test_left_align_keeps_anchor() {
+        // The deletion could move further left, but one aligned base is kept before it.
+        assert_eq!(normalized(b"AAAAT", b"AAAAT", "1S2M1D1M"), "1S1M1D2M");
+    }
+
+    #[test]
+    fn test_left_align_eqx() {
+        assert_eq!(normalized(b"GCAAAT", b"GCAAT", "4=1D1="), "2=1D3=");
+    }
+}
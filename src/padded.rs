@@ -0,0 +1,371 @@
+//! Padded coordinates and multiple alignment views.
+//!
+//! In the padded SAM convention every base inserted by any read gets a column of its
+//! own, and the reference and the reads without the insertion are padded with `*` in
+//! those columns. A `P` operation in a CIGAR is such a pad: it fills an insertion column
+//! of another read, so that `1P1I` places an inserted base in the second column of the
+//! insertion rather than the first.
+//!
+//! A [`PaddedFrame`] collects the insertion columns of a set of alignments, converts
+//! between reference and padded coordinates, and projects the reference and each read
+//! onto the padded columns, giving a local multiple alignment view around indels.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::Cigar;
+//! use cigar_utils::padded::{PaddedFrame, PaddedPosition};
+//!
+//! let reads: Vec<(Cigar, &[u8])> = vec![
+//!     ("4M2I4M".parse().unwrap(), b"ACGTTTACGT"),
+//!     ("4M1P1I4M".parse().unwrap(), b"ACGTGACGT"),
+//!     ("8M".parse().unwrap(), b"ACGTACGT"),
+//! ];
+//! let frame = PaddedFrame::from_alignments(reads.iter().map(|(cigar, _)| (cigar, 0)));
+//! assert_eq!(frame.padded_reference(b"ACGTACGT"), b"ACGT**ACGT");
+//! let rows: Vec<_> = reads
+//!     .iter()
+//!     .map(|(cigar, read)| String::from_utf8(frame.row(cigar, 0, read)).unwrap())
+//!     .collect();
+//! assert_eq!(rows, vec!["ACGTTTACGT", "ACGT*GACGT", "ACGT**ACGT"]);
+//!
+//! assert_eq!(frame.to_padded(4), Some(6));
+//! assert_eq!(frame.to_unpadded(5), Some(PaddedPosition::Insertion { before: 4, offset: 1 }));
+//! ```
+
+use std::collections::BTreeMap;
+
+use crate::{Cigar, CigarOp};
+
+/// What a padded column holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaddedPosition {
+    /// A reference base.
+    Reference(u64),
+    /// A column inserted before the reference base `before`, at `offset` within the insertion.
+    Insertion {
+        /// The reference position the insertion precedes.
+        before: u64,
+        /// The offset of the column within the insertion.
+        offset: u32,
+    },
+}
+
+/// The padded columns of a reference window covered by a set of alignments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PaddedFrame {
+    span: Option<(u64, u64)>,
+    insertions: BTreeMap<u64, u32>,
+}
+
+/// The runs of insertions and pads of an alignment, with the reference positions they precede.
+fn insertion_runs(cigar: &Cigar, position: u64) -> Vec<(u64, u32)> {
+    let mut runs = Vec::new();
+    let mut reference_position = position;
+    let mut run = 0;
+    for elem in cigar.iter() {
+        match elem.op {
+            CigarOp::Insertion | CigarOp::Padding => run += elem.length,
+            op => {
+                if run > 0 {
+                    runs.push((reference_position, run));
+                    run = 0;
+                }
+                if op.consumes_reference() {
+                    reference_position += elem.length as u64;
+                }
+            }
+        }
+    }
+    if run > 0 {
+        runs.push((reference_position, run));
+    }
+    runs
+}
+
+impl PaddedFrame {
+    /// Create an empty frame.
+    pub fn new() -> Self {
+        PaddedFrame::default()
+    }
+
+    /// Create a frame covering a set of alignments, given as CIGARs and the reference
+    /// positions they start at.
+    pub fn from_alignments<'a, I>(alignments: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a Cigar, u64)>,
+    {
+        let mut frame = PaddedFrame::new();
+        for (cigar, position) in alignments {
+            frame.add(cigar, position);
+        }
+        frame
+    }
+
+    /// Add an alignment, widening the frame to cover it and adding its insertion columns.
+    pub fn add(&mut self, cigar: &Cigar, position: u64) {
+        let end = cigar.alignment_end(position);
+        self.span = Some(match self.span {
+            None => (position, end),
+            Some((start, stop)) => (start.min(position), stop.max(end)),
+        });
+        for (before, length) in insertion_runs(cigar, position) {
+            let width = self.insertions.entry(before).or_insert(0);
+            *width = (*width).max(length);
+        }
+    }
+
+    /// The reference interval covered by the frame, if any alignments have been added.
+    pub fn span(&self) -> Option<(u64, u64)> {
+        self.span
+    }
+
+    /// The number of padded columns.
+    pub fn width(&self) -> usize {
+        let Some((start, end)) = self.span else {
+            return 0;
+        };
+        (end - start) as usize + self.insertions.values().map(|&w| w as usize).sum::<usize>()
+    }
+
+    /// The number of insertion columns before the reference position `position`.
+    pub fn insertion_width(&self, position: u64) -> u32 {
+        self.insertions.get(&position).copied().unwrap_or(0)
+    }
+
+    /// The first column of the insertion before `position`, which is the column of
+    /// `position` itself if there is no insertion.
+    fn boundary_column(&self, position: u64) -> Option<usize> {
+        let (start, end) = self.span?;
+        if position < start || position > end {
+            return None;
+        }
+        let inserted: usize = self
+            .insertions
+            .range(..position)
+            .map(|(_, &w)| w as usize)
+            .sum();
+        Some((position - start) as usize + inserted)
+    }
+
+    /// The padded column of the reference position `position`.
+    pub fn to_padded(&self, position: u64) -> Option<usize> {
+        let (_, end) = self.span?;
+        if position >= end {
+            return None;
+        }
+        Some(self.boundary_column(position)? + self.insertion_width(position) as usize)
+    }
+
+    /// What the padded column `column` holds.
+    pub fn to_unpadded(&self, column: usize) -> Option<PaddedPosition> {
+        let (start, end) = self.span?;
+        let mut offset = 0;
+        let mut previous = start;
+        for (&before, &width) in self.insertions.iter() {
+            let bases = (before - previous) as usize;
+            if column < offset + bases {
+                return Some(PaddedPosition::Reference(
+                    previous + (column - offset) as u64,
+                ));
+            }
+            offset += bases;
+            if column < offset + width as usize {
+                return Some(PaddedPosition::Insertion {
+                    before,
+                    offset: (column - offset) as u32,
+                });
+            }
+            offset += width as usize;
+            previous = before;
+        }
+        let bases = (end - previous) as usize;
+        (column < offset + bases)
+            .then(|| PaddedPosition::Reference(previous + (column - offset) as u64))
+    }
+
+    /// The reference window of the frame with `*` in the insertion columns, given the
+    /// reference sequence starting at the first position of the frame.
+    pub fn padded_reference(&self, reference: &[u8]) -> Vec<u8> {
+        let Some((start, end)) = self.span else {
+            return Vec::new();
+        };
+        let mut padded = Vec::with_capacity(self.width());
+        for position in start..end {
+            padded.extend(std::iter::repeat_n(
+                b'*',
+                self.insertion_width(position) as usize,
+            ));
+            padded.push(
+                reference
+                    .get((position - start) as usize)
+                    .copied()
+                    .unwrap_or(b'N'),
+            );
+        }
+        padded.extend(std::iter::repeat_n(
+            b'*',
+            self.insertion_width(end) as usize,
+        ));
+        padded
+    }
+
+    /// Place a run of inserted bases (or pads, as `None`) before `position` in `row`.
+    ///
+    /// Runs within the alignment are placed from the start of the insertion columns and
+    /// the remaining columns padded; a run at the start of the alignment is placed
+    /// against the first aligned base, and one at the end after the last.
+    fn place_run(
+        &self,
+        row: &mut [u8],
+        run: &mut Vec<Option<u8>>,
+        position: u64,
+        started: bool,
+        followed: bool,
+    ) {
+        let Some(first) = self.boundary_column(position) else {
+            run.clear();
+            return;
+        };
+        let width = self.insertion_width(position) as usize;
+        let offset = if !started && followed {
+            width.saturating_sub(run.len())
+        } else {
+            0
+        };
+        for (k, base) in run.drain(..).enumerate() {
+            if offset + k < width {
+                row[first + offset + k] = base.unwrap_or(b'*');
+            }
+        }
+        if started && followed {
+            for cell in row[first..first + width].iter_mut() {
+                if *cell == b' ' {
+                    *cell = b'*';
+                }
+            }
+        }
+    }
+
+    /// Project a read onto the padded columns of the frame.
+    ///
+    /// The row has a character for each column: the read's bases, `*` for deletions and
+    /// pads, `-` for skipped regions, and spaces outside the alignment. Columns outside
+    /// the frame are dropped, and missing bases shown as `N`.
+    pub fn row(&self, cigar: &Cigar, position: u64, read: &[u8]) -> Vec<u8> {
+        let mut row = vec![b' '; self.width()];
+        let base = |q: usize| read.get(q).copied().unwrap_or(b'N');
+        let mut run = Vec::new();
+        let mut reference_position = position;
+        let mut read_position = 0;
+        let mut started = false;
+        for elem in cigar.iter() {
+            let length = elem.length as usize;
+            match elem.op {
+                CigarOp::Insertion => {
+                    run.extend((read_position..read_position + length).map(|q| Some(base(q))));
+                    read_position += length;
+                }
+                CigarOp::Padding => run.extend(std::iter::repeat_n(None, length)),
+                CigarOp::SoftClip | CigarOp::HardClip => {
+                    self.place_run(&mut row, &mut run, reference_position, started, false);
+                    if elem.op == CigarOp::SoftClip {
+                        read_position += length;
+                    }
+                }
+                op => {
+                    self.place_run(&mut row, &mut run, reference_position, started, true);
+                    started = true;
+                    for k in 0..elem.length as u64 {
+                        if k > 0 {
+                            self.place_run(&mut row, &mut run, reference_position + k, true, true);
+                        }
+                        let cell = match op {
+                            CigarOp::Deletion => b'*',
+                            CigarOp::Skip => b'-',
+                            _ => {
+                                read_position += 1;
+                                base(read_position - 1)
+                            }
+                        };
+                        if let Some(column) = self.to_padded(reference_position + k) {
+                            row[column] = cell;
+                        }
+                    }
+                    reference_position += elem.length as u64;
+                }
+            }
+        }
+        self.place_run(&mut row, &mut run, reference_position, started, false);
+        row
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cigar(s: &str) -> Cigar {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_padded_coordinates() {
+        let a = cigar("3M2I3M");
+        let b = cigar("2S4M1I1M1D2M");
+        let frame = PaddedFrame::from_alignments([(&a, 10), (&b, 11)]);
+        assert_eq!(frame.span(), Some((10, 19)));
+        assert_eq!(frame.width(), 12);
+        assert_eq!(frame.to_padded(10), Some(0));
+        assert_eq!(frame.to_padded(13), Some(5));
+        assert_eq!(frame.to_padded(16), Some(9));
+        assert_eq!(frame.to_padded(19), None);
+        assert_eq!(
+            frame.to_unpadded(3),
+            Some(PaddedPosition::Insertion {
+                before: 13,
+                offset: 0
+            })
+        );
+        assert_eq!(
+            frame.to_unpadded(7),
+            Some(PaddedPosition::Insertion {
+                before: 15,
+                offset: 0
+            })
+        );
+        assert_eq!(frame.to_unpadded(8), Some(PaddedPosition::Reference(15)));
+        assert_eq!(frame.to_unpadded(11), Some(PaddedPosition::Reference(18)));
+        assert_eq!(frame.to_unpadded(12), None);
+        for position in 10..19 {
+            let column = frame.to_padded(position).unwrap();
+            assert_eq!(
+                frame.to_unpadded(column),
+                Some(PaddedPosition::Reference(position))
+            );
+        }
+    }
+
+    #[test]
+    fn test_padded_rows() {
+        let a = cigar("3M2I3M");
+        let b = cigar("2S4M1I1M1D2M");
+        let c = cigar("1I2M");
+        let frame = PaddedFrame::from_alignments([(&a, 10), (&b, 11), (&c, 14)]);
+        assert_eq!(frame.padded_reference(b"ACGTACGTA"), b"ACG**T*A*CGTA");
+        let rows: Vec<String> = [
+            (&a, 10, &b"ACGGGTAC"[..]),
+            (&b, 11, b"TTCGTATCTA"),
+            (&c, 14, b"GAC"),
+        ]
+        .iter()
+        .map(|&(cigar, position, read)| {
+            String::from_utf8(frame.row(cigar, position, read)).unwrap()
+        })
+        .collect();
+        assert_eq!(
+            rows,
+            vec!["ACGGGT*A*C   ", " CG**T*ATC*TA", "      GA*C   "]
+        );
+    }
+}
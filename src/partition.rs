@@ -0,0 +1,271 @@
+//! Per-chromosome partitioning of collation sources.
+//!
+//! Events on different chromosomes never collate together, so a sorted multi-chromosome
+//! source can be split wherever the chromosome ID changes and each chromosome collated
+//! independently. [`ChromosomePartitions`] does the splitting, yielding a
+//! [`ChromosomePartition`] which reads the records of one chromosome from the source as
+//! they are wanted, and can be collated or used as a checkpoint boundary.
+//!
+//! A partition borrows the source, so only one is read at a time. To collate chromosomes
+//! concurrently, [`buffered`](ChromosomePartition::buffered) reads a partition into a
+//! [`BufferedPartition`], which holds its records in memory and can be sent to another
+//! thread.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::partition::ChromosomePartitions;
+//!
+//! let records = vec![
+//!     std::io::Result::Ok(("4M".to_string(), 0, 100)),
+//!     std::io::Result::Ok(("4M".to_string(), 0, 102)),
+//!     std::io::Result::Ok(("2M".to_string(), 1, 50)),
+//! ];
+//! let mut partitions = ChromosomePartitions::new(records.into_iter());
+//! let mut counts = Vec::new();
+//! while let Some(partition) = partitions.next_partition() {
+//!     counts.push((partition.chrom_id, partition.collated().count()));
+//! }
+//! assert_eq!(counts, vec![(0, 2), (1, 1)]);
+//! ```
+
+use std::iter::{FusedIterator, Peekable};
+
+use crate::collated::CollatedAugmentedCigarIterator;
+use crate::record::CigarRecord;
+
+/// The records of a source on one chromosome, read from the source as they are wanted.
+///
+/// This is an iterator over the records, with any errors read among them.
+pub struct ChromosomePartition<'a, S: Iterator> {
+    /// The chromosome ID of the records.
+    pub chrom_id: u32,
+    leading: std::vec::IntoIter<S::Item>,
+    source: &'a mut Peekable<S>,
+}
+
+impl<'a, S, R, E> ChromosomePartition<'a, S>
+where
+    S: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    /// A collated iterator over the records of the partition.
+    ///
+    /// The iterator may be configured like any other, with [`checked`] or
+    /// [`restrict`], for example.
+    ///
+    /// [`checked`]: CollatedAugmentedCigarIterator::checked
+    /// [`restrict`]: CollatedAugmentedCigarIterator::restrict
+    pub fn collated(self) -> CollatedAugmentedCigarIterator<Self, R, E> {
+        CollatedAugmentedCigarIterator::new(self)
+    }
+
+    /// Read the rest of the records of the partition into memory, so that they can be
+    /// collated apart from the source, on another thread for example.
+    pub fn buffered(self) -> BufferedPartition<R, E> {
+        BufferedPartition {
+            chrom_id: self.chrom_id,
+            records: self.collect(),
+        }
+    }
+}
+
+impl<'a, S, R, E> Iterator for ChromosomePartition<'a, S>
+where
+    S: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+{
+    type Item = std::result::Result<R, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(record) = self.leading.next() {
+            return Some(record);
+        }
+        match self.source.peek()? {
+            Ok(record) if record.chrom_id() != self.chrom_id => None,
+            _ => self.source.next(),
+        }
+    }
+}
+
+impl<'a, S, R, E> FusedIterator for ChromosomePartition<'a, S>
+where
+    S: FusedIterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+{
+}
+
+/// The records of a source on one chromosome, held in memory.
+#[derive(Debug)]
+pub struct BufferedPartition<R, E> {
+    /// The chromosome ID of the records.
+    pub chrom_id: u32,
+    /// The records, in source order, with any errors read among them.
+    pub records: Vec<std::result::Result<R, E>>,
+}
+
+impl<R, E> BufferedPartition<R, E>
+where
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    /// A collated iterator over the records of the partition, as for
+    /// [`ChromosomePartition::collated`].
+    pub fn collated(
+        self,
+    ) -> CollatedAugmentedCigarIterator<std::vec::IntoIter<std::result::Result<R, E>>, R, E> {
+        CollatedAugmentedCigarIterator::new(self.records.into_iter())
+    }
+}
+
+/// A splitting of a source of records into [per-chromosome
+/// partitions](ChromosomePartition).
+///
+/// A new partition starts at every change of chromosome ID, so an unsorted source may
+/// give several partitions for the same chromosome. Errors from the source are kept in
+/// the partition being read when they occur, so that they surface when it is collated.
+/// Records of a partition which are not read before the next partition is taken are
+/// skipped, errors among them included.
+pub struct ChromosomePartitions<S: Iterator> {
+    source: Peekable<S>,
+    current: Option<u32>,
+}
+
+impl<S, R, E> ChromosomePartitions<S>
+where
+    S: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+{
+    /// Create a new partitioning of a source.
+    pub fn new(source: S) -> Self {
+        ChromosomePartitions {
+            source: source.peekable(),
+            current: None,
+        }
+    }
+
+    /// The partition of the next chromosome, or `None` once the source is exhausted.
+    pub fn next_partition(&mut self) -> Option<ChromosomePartition<'_, S>> {
+        if let Some(chrom_id) = self.current {
+            while self
+                .source
+                .next_if(|record| !matches!(record, Ok(r) if r.chrom_id() != chrom_id))
+                .is_some()
+            {}
+        }
+        let mut leading = Vec::new();
+        while let Some(error) = self.source.next_if(|record| record.is_err()) {
+            leading.push(error);
+        }
+        let chrom_id = match self.source.peek() {
+            Some(Ok(record)) => record.chrom_id(),
+            _ if leading.is_empty() => return None,
+            _ => 0,
+        };
+        self.current = Some(chrom_id);
+        Some(ChromosomePartition {
+            chrom_id,
+            leading: leading.into_iter(),
+            source: &mut self.source,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collated::CollatedEvent;
+    use crate::error::CigarError;
+
+    fn records(
+        records: Vec<(&str, u32, u64)>,
+    ) -> impl Iterator<Item = std::io::Result<(String, u32, u64)>> {
+        records
+            .into_iter()
+            .map(|(cigar, chrom_id, position)| Ok((cigar.to_string(), chrom_id, position)))
+    }
+
+    #[test]
+    fn test_partitions_collate_concurrently() {
+        let source = records(vec![
+            ("2M1I2M", 0, 100),
+            ("5M", 0, 101),
+            ("3M", 2, 10),
+            ("1S2M", 2, 10),
+            ("4M", 3, 0),
+        ]);
+        let mut partitions = ChromosomePartitions::new(source);
+        let mut buffered = Vec::new();
+        while let Some(partition) = partitions.next_partition() {
+            buffered.push(partition.buffered());
+        }
+        let chroms: Vec<_> = buffered.iter().map(|p| p.chrom_id).collect();
+        assert_eq!(chroms, vec![0, 2, 3]);
+
+        let results: Vec<Vec<CollatedEvent>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = buffered
+                .into_iter()
+                .map(|partition| {
+                    scope.spawn(move || partition.collated().collect::<Result<Vec<_>, _>>())
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap().unwrap())
+                .collect()
+        });
+        let whole: Vec<CollatedEvent> = CollatedAugmentedCigarIterator::new(records(vec![
+            ("2M1I2M", 0, 100),
+            ("5M", 0, 101),
+            ("3M", 2, 10),
+            ("1S2M", 2, 10),
+            ("4M", 3, 0),
+        ]))
+        .collect::<Result<_, _>>()
+        .unwrap();
+        assert_eq!(results.concat(), whole);
+    }
+
+    #[test]
+    fn test_partitions_stream() {
+        let mut partitions = ChromosomePartitions::new(records(vec![
+            ("2M", 0, 1),
+            ("2M", 0, 2),
+            ("2M", 0, 3),
+            ("2M", 1, 1),
+            ("2M", 1, 2),
+        ]));
+        // Records left unread in one partition are skipped when the next is taken.
+        let mut first = partitions.next_partition().unwrap();
+        assert_eq!(first.next().unwrap().unwrap().2, 1);
+        let second = partitions.next_partition().unwrap();
+        assert_eq!(second.chrom_id, 1);
+        assert_eq!(second.count(), 2);
+        assert!(partitions.next_partition().is_none());
+    }
+
+    #[test]
+    fn test_partition_errors() {
+        let source = vec![
+            Err(std::io::Error::other("bad record")),
+            Ok(("2M".to_string(), 1, 5)),
+            Ok(("2M".to_string(), 4, 5)),
+        ];
+        let mut partitions = ChromosomePartitions::new(source.into_iter());
+        let first = partitions.next_partition().unwrap().buffered();
+        assert_eq!((first.chrom_id, first.records.len()), (1, 2));
+        assert!(matches!(
+            first.collated().next(),
+            Some(Err(CigarError::External(_)))
+        ));
+        assert_eq!(partitions.next_partition().unwrap().chrom_id, 4);
+        assert!(partitions.next_partition().is_none());
+
+        let only_errors = vec![Err::<(String, u32, u64), _>(std::io::Error::other("bad"))];
+        let mut partitions = ChromosomePartitions::new(only_errors.into_iter());
+        let partition = partitions.next_partition().unwrap();
+        assert!(partition.collated().next().unwrap().is_err());
+        assert!(partitions.next_partition().is_none());
+    }
+}
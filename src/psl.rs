@@ -0,0 +1,258 @@
+//! PSL alignment blocks.
+//!
+//! PSL, the format of BLAT and the UCSC genome browser, describes an alignment by its
+//! ungapped blocks: the `blockCount`, `blockSizes`, `qStarts` and `tStarts` fields.
+//! These functions convert between CIGARs and those blocks, and read and write the
+//! comma separated fields.
+//!
+//! For reverse strand alignments PSL query starts are positions on the reverse
+//! complement of the query, which is how SAM lays out reverse strand reads, so blocks
+//! are computed the same way for either strand.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::Cigar;
+//! use cigar_utils::psl::{PslBlocks, cigar_from_psl_blocks};
+//!
+//! let cigar: Cigar = "3S5M2I4M100N6M".parse().unwrap();
+//! let blocks = PslBlocks::from_alignment(&cigar, 1000);
+//! assert_eq!(
+//!     blocks.fields(),
+//!     ["3", "5,4,6,", "3,10,14,", "1000,1005,1109,"]
+//! );
+//!
+//! let parsed = PslBlocks::from_fields("3", "5,4,6,", "3,10,14,", "1000,1005,1109,").unwrap();
+//! let (cigar, position) = cigar_from_psl_blocks(&parsed, 20, Some(50)).unwrap();
+//! assert_eq!(cigar.to_string(), "3H5M2I4M100N6M");
+//! assert_eq!(position, 1000);
+//! ```
+
+use crate::error::{CigarError, element_length, parse_number};
+use crate::{Cigar, CigarElement, CigarOp};
+
+/// The ungapped blocks of a PSL alignment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PslBlocks {
+    /// The size of each block.
+    pub block_sizes: Vec<u64>,
+    /// The start of each block on the query.
+    pub query_starts: Vec<u64>,
+    /// The start of each block on the target (reference).
+    pub target_starts: Vec<u64>,
+}
+
+/// The format name given in [`CigarError::InvalidFormat`] errors.
+const FORMAT: &str = "PSL";
+
+fn parse_list(field: &str) -> std::result::Result<Vec<u64>, CigarError> {
+    field
+        .split(',')
+        .filter(|value| !value.is_empty())
+        .map(|value| parse_number(FORMAT, value.trim()))
+        .collect()
+}
+
+fn format_list(values: &[u64]) -> String {
+    values.iter().map(|v| format!("{},", v)).collect()
+}
+
+impl PslBlocks {
+    /// The blocks of an alignment starting at `reference_position`.
+    ///
+    /// Query positions count from the start of the read, including hard clipped bases.
+    pub fn from_alignment(cigar: &Cigar, reference_position: u64) -> PslBlocks {
+        let mut blocks = PslBlocks::default();
+        let mut query_position = 0;
+        let mut target_position = reference_position;
+        let mut contiguous = false;
+        for elem in cigar.iter() {
+            let length = elem.length as u64;
+            match elem.op {
+                CigarOp::Match | CigarOp::Equal | CigarOp::Diff => {
+                    match blocks.block_sizes.last_mut() {
+                        Some(size) if contiguous => *size += length,
+                        _ => {
+                            blocks.block_sizes.push(length);
+                            blocks.query_starts.push(query_position);
+                            blocks.target_starts.push(target_position);
+                        }
+                    }
+                    query_position += length;
+                    target_position += length;
+                    contiguous = true;
+                    continue;
+                }
+                CigarOp::Insertion | CigarOp::SoftClip | CigarOp::HardClip => {
+                    query_position += length
+                }
+                CigarOp::Deletion | CigarOp::Skip => target_position += length,
+                CigarOp::Padding => continue,
+            }
+            contiguous = false;
+        }
+        blocks
+    }
+
+    /// Parse the `blockCount`, `blockSizes`, `qStarts` and `tStarts` fields.
+    ///
+    /// The lists are comma separated, with an optional trailing comma, and must each have
+    /// `blockCount` values.
+    pub fn from_fields(
+        block_count: &str,
+        block_sizes: &str,
+        query_starts: &str,
+        target_starts: &str,
+    ) -> std::result::Result<PslBlocks, CigarError> {
+        let count: usize = block_count.trim().parse().map_err(|_| {
+            CigarError::invalid_format(FORMAT, format!("'{}' is not a block count", block_count))
+        })?;
+        let blocks = PslBlocks {
+            block_sizes: parse_list(block_sizes)?,
+            query_starts: parse_list(query_starts)?,
+            target_starts: parse_list(target_starts)?,
+        };
+        for list in [
+            &blocks.block_sizes,
+            &blocks.query_starts,
+            &blocks.target_starts,
+        ] {
+            if list.len() != count {
+                return Err(CigarError::LengthMismatch(count, list.len()));
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// The number of blocks.
+    pub fn block_count(&self) -> usize {
+        self.block_sizes.len()
+    }
+
+    /// The `blockCount`, `blockSizes`, `qStarts` and `tStarts` fields, with the lists
+    /// comma terminated as BLAT writes them.
+    pub fn fields(&self) -> [String; 4] {
+        [
+            self.block_count().to_string(),
+            format_list(&self.block_sizes),
+            format_list(&self.query_starts),
+            format_list(&self.target_starts),
+        ]
+    }
+}
+
+/// Convert PSL blocks into a CIGAR and reference position, for a query of `query_size` bases.
+///
+/// Query gaps between blocks become insertions and target gaps deletions, or skips if
+/// they are at least `min_intron` bases long; where a gap has both, the insertion comes
+/// first. The query outside the blocks becomes hard clips. Blocks must be in order
+/// and must not overlap.
+pub fn cigar_from_psl_blocks(
+    blocks: &PslBlocks,
+    query_size: u64,
+    min_intron: Option<u64>,
+) -> std::result::Result<(Cigar, u64), CigarError> {
+    for list in [&blocks.query_starts, &blocks.target_starts] {
+        if list.len() != blocks.block_count() {
+            return Err(CigarError::LengthMismatch(blocks.block_count(), list.len()));
+        }
+    }
+    let mut elements = Vec::new();
+    let mut push = |length: u64, op: CigarOp| {
+        if length > 0 {
+            elements.push(CigarElement::new(element_length(FORMAT, length)?, op));
+        }
+        Ok::<_, CigarError>(())
+    };
+    let mut previous: Option<(u64, u64)> = None;
+    for i in 0..blocks.block_count() {
+        let (size, query_start, target_start) = (
+            blocks.block_sizes[i],
+            blocks.query_starts[i],
+            blocks.target_starts[i],
+        );
+        match previous {
+            None => push(query_start, CigarOp::HardClip)?,
+            Some((query_end, target_end)) => {
+                if query_start < query_end || target_start < target_end {
+                    return Err(CigarError::invalid_format(
+                        FORMAT,
+                        format!("block {} overlaps the previous block", i),
+                    ));
+                }
+                push(query_start - query_end, CigarOp::Insertion)?;
+                let gap = target_start - target_end;
+                let op = match min_intron {
+                    Some(min) if gap >= min => CigarOp::Skip,
+                    _ => CigarOp::Deletion,
+                };
+                push(gap, op)?;
+            }
+        }
+        push(size, CigarOp::Match)?;
+        previous = Some((query_start + size, target_start + size));
+    }
+    if let Some((query_end, _)) = previous {
+        if query_end > query_size {
+            return Err(CigarError::LengthMismatch(
+                query_size as usize,
+                query_end as usize,
+            ));
+        }
+        push(query_size - query_end, CigarOp::HardClip)?;
+    }
+    let position = blocks.target_starts.first().copied().unwrap_or(0);
+    Ok((Cigar::from(elements), position))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks_from_alignment() {
+        let cigar: Cigar = "2H1S3=1X2M1I1D4M2S".parse().unwrap();
+        let blocks = PslBlocks::from_alignment(&cigar, 50);
+        assert_eq!(blocks.block_sizes, vec![6, 4]);
+        assert_eq!(blocks.query_starts, vec![3, 10]);
+        assert_eq!(blocks.target_starts, vec![50, 57]);
+        let (round_trip, position) = cigar_from_psl_blocks(&blocks, 16, None).unwrap();
+        assert_eq!(round_trip.to_string(), "3H6M1I1D4M2H");
+        assert_eq!(position, 50);
+    }
+
+    #[test]
+    fn test_from_fields_errors() {
+        assert!(matches!(
+            PslBlocks::from_fields("2", "5,4,", "0,5,", "10,"),
+            Err(CigarError::LengthMismatch(2, 1))
+        ));
+        assert!(matches!(
+            PslBlocks::from_fields("1", "5x,", "0,", "10,"),
+            Err(CigarError::InvalidFormat("PSL", _))
+        ));
+    }
+
+    #[test]
+    fn test_cigar_from_bad_blocks() {
+        let overlapping = PslBlocks::from_fields("2", "5,4,", "0,3,", "10,20,").unwrap();
+        assert!(matches!(
+            cigar_from_psl_blocks(&overlapping, 10, None),
+            Err(CigarError::InvalidFormat("PSL", _))
+        ));
+        let blocks = PslBlocks::from_fields("1", "5,", "2,", "10,").unwrap();
+        assert!(matches!(
+            cigar_from_psl_blocks(&blocks, 6, None),
+            Err(CigarError::LengthMismatch(6, 7))
+        ));
+        // Gaps and clips too long for a CIGAR element are not truncated.
+        let far = PslBlocks::from_fields("1", "10,", "5000000000,", "0,").unwrap();
+        assert!(matches!(
+            cigar_from_psl_blocks(&far, 5_000_000_010, None),
+            Err(CigarError::InvalidFormat("PSL", _))
+        ));
+        let (cigar, position) = cigar_from_psl_blocks(&PslBlocks::default(), 6, None).unwrap();
+        assert!(cigar.is_empty());
+        assert_eq!(position, 0);
+    }
+}
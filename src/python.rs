@@ -0,0 +1,219 @@
+//! Python bindings.
+//!
+//! With the `python` feature enabled, this module defines a `cigar_utils` Python
+//! extension module with PyO3, exposing the [`Cigar`] type with its lengths and
+//! coordinate mapping, expansion of alignment matches against the sequences, and
+//! collation of alignments. Build it with `maturin build --features python`.
+//!
+//! ```python
+//! import cigar_utils
+//!
+//! cigar = cigar_utils.Cigar("2S5M1I3M")
+//! assert cigar.reference_length() == 8
+//! assert cigar.read_to_reference(100, 8) == 105
+//! assert str(cigar_utils.expand(0, "4M", b"ACGT", b"ACTT")) == "2=1X1="
+//!
+//! records = [("2M1I", 1, 100), ("1D2M", 1, 102)]
+//! for chrom_id, position, length, op, count in cigar_utils.CollatedIterator(records):
+//!     print(chrom_id, position, length, op, count)
+//! ```
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyIterator;
+
+use crate::Cigar;
+use crate::collated::CollatedAugmentedCigarIterator;
+use crate::error::CigarError;
+use crate::expand::expand_cigar_operations;
+
+/// Convert a CIGAR error into a Python exception: errors raised by Python sources are
+/// passed back unchanged, and the rest become `ValueError`s.
+fn py_error(e: CigarError) -> PyErr {
+    match e {
+        CigarError::External(inner) => match inner.downcast::<PyErr>() {
+            Ok(e) => *e,
+            Err(inner) => PyValueError::new_err(inner.to_string()),
+        },
+        CigarError::Context(_, inner) if matches!(inner.root(), CigarError::External(_)) => {
+            py_error(*inner)
+        }
+        e => PyValueError::new_err(e.to_string()),
+    }
+}
+
+/// A CIGAR, parsed from its string form.
+#[pyclass(
+    name = "Cigar",
+    module = "cigar_utils",
+    eq,
+    frozen,
+    skip_from_py_object
+)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PyCigar {
+    inner: Cigar,
+}
+
+#[pymethods]
+impl PyCigar {
+    #[new]
+    fn new(text: &str) -> PyResult<Self> {
+        let inner = text.parse().map_err(py_error)?;
+        Ok(PyCigar { inner })
+    }
+
+    fn __str__(&self) -> String {
+        self.inner.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Cigar('{}')", self.inner)
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// The elements as (length, operation) pairs.
+    fn elements(&self) -> Vec<(u32, char)> {
+        self.inner
+            .iter()
+            .map(|e| (e.length, char::from(e.op)))
+            .collect()
+    }
+
+    /// The number of reference bases covered by the alignment.
+    fn reference_length(&self) -> u64 {
+        self.inner.reference_length()
+    }
+
+    /// The number of read bases in SEQ.
+    fn query_length(&self) -> usize {
+        self.inner.query_length()
+    }
+
+    /// The length of the original read, including hard clipped bases.
+    fn query_length_with_hard_clips(&self) -> usize {
+        self.inner.query_length_with_hard_clips()
+    }
+
+    /// The number of bases clipped from the start of the read.
+    fn leading_clip(&self) -> usize {
+        self.inner.leading_clip()
+    }
+
+    /// The number of bases clipped from the end of the read.
+    fn trailing_clip(&self) -> usize {
+        self.inner.trailing_clip()
+    }
+
+    /// The reference position following the alignment, for an alignment starting at `start`.
+    fn alignment_end(&self, start: u64) -> u64 {
+        self.inner.alignment_end(start)
+    }
+
+    /// The reference position aligned to a read position, or None.
+    fn read_to_reference(&self, start: u64, read_position: usize) -> Option<u64> {
+        self.inner.read_to_reference(start, read_position)
+    }
+
+    /// The read position aligned to a reference position, or None.
+    fn reference_to_read(&self, start: u64, position: u64) -> Option<usize> {
+        self.inner.reference_to_read(start, position)
+    }
+
+    /// The CIGAR with its elements in reverse order.
+    fn reversed(&self) -> PyCigar {
+        PyCigar {
+            inner: self.inner.reversed(),
+        }
+    }
+}
+
+/// Expand the alignment match (`M`) elements of a read aligned at `reference_position`
+/// into sequence match and mismatch elements.
+#[pyfunction]
+fn expand(
+    reference_position: usize,
+    cigar: &str,
+    reference: &[u8],
+    seq: &[u8],
+) -> PyResult<PyCigar> {
+    let elements =
+        expand_cigar_operations(reference_position, cigar, &reference, &seq).map_err(py_error)?;
+    Ok(PyCigar {
+        inner: Cigar::from(elements),
+    })
+}
+
+/// A source of collation records read from a Python iterator of
+/// (CIGAR string, chromosome ID, position) tuples.
+struct PySource {
+    records: Py<PyIterator>,
+}
+
+impl Iterator for PySource {
+    type Item = PyResult<(String, u32, u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Python::attach(|py| {
+            let record = self.records.bind(py).clone().next()?;
+            Some(record.and_then(|record| record.extract()))
+        })
+    }
+}
+
+/// A collated event as passed to Python: (chromosome ID, position, length, operation, count).
+type PyEvent = (u32, u64, u32, char, usize);
+
+/// Collate the CIGAR operations of coordinate-sorted (CIGAR string, chromosome ID,
+/// position) records, yielding (chromosome ID, position, length, operation, count)
+/// events.
+#[pyclass(name = "CollatedIterator", module = "cigar_utils", unsendable)]
+pub struct PyCollatedIterator {
+    inner: CollatedAugmentedCigarIterator<PySource, (String, u32, u64), PyErr>,
+}
+
+#[pymethods]
+impl PyCollatedIterator {
+    #[new]
+    #[pyo3(signature = (records, check_sorted = true))]
+    fn new(records: &Bound<'_, PyAny>, check_sorted: bool) -> PyResult<Self> {
+        let source = PySource {
+            records: records.try_iter()?.unbind(),
+        };
+        let mut inner = CollatedAugmentedCigarIterator::new(source);
+        if check_sorted {
+            inner = inner.checked();
+        }
+        Ok(PyCollatedIterator { inner })
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self) -> PyResult<Option<PyEvent>> {
+        match self.inner.next() {
+            None => Ok(None),
+            Some(Ok((elem, count))) => Ok(Some((
+                elem.chrom_id,
+                elem.reference_position,
+                elem.length,
+                char::from(elem.op),
+                count,
+            ))),
+            Some(Err(e)) => Err(py_error(e)),
+        }
+    }
+}
+
+/// The `cigar_utils` Python module.
+#[pymodule]
+fn cigar_utils(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyCigar>()?;
+    module.add_class::<PyCollatedIterator>()?;
+    module.add_function(wrap_pyfunction!(expand, module)?)?;
+    Ok(())
+}
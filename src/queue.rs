@@ -0,0 +1,254 @@
+//! The priority queue of events awaiting collation.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::augmented_cigar::AugmentedCigarElement;
+
+/// A queued event: an element and the tag of the record it came from.
+pub(crate) type QueuedEvent = (AugmentedCigarElement, usize);
+
+/// The number of reference positions covered by the buckets.
+const WINDOW: u64 = 1 << 12;
+
+/// A priority queue of events, specialised for coordinate-sorted input.
+///
+/// With sorted input, nearly every event lands a short distance ahead of the front of
+/// the queue. Such events are put in a ring of buckets, one per reference position of a
+/// window starting at the front, at constant cost; only the bucket at the front is kept
+/// sorted. Events on other chromosomes, behind the front or beyond the window go to a
+/// binary heap, and the smaller of the two heads is taken.
+pub(crate) struct EventQueue {
+    buckets: Vec<Vec<QueuedEvent>>,
+    chrom_id: u32,
+    base: u64,
+    bucketed: usize,
+    front_sorted: bool,
+    heap: BinaryHeap<Reverse<QueuedEvent>>,
+}
+
+impl EventQueue {
+    pub(crate) fn new() -> Self {
+        EventQueue {
+            buckets: Vec::new(),
+            chrom_id: 0,
+            base: 0,
+            bucketed: 0,
+            front_sorted: false,
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    /// The number of queued events.
+    pub(crate) fn len(&self) -> usize {
+        self.bucketed + self.heap.len()
+    }
+
+    /// Whether the queue is empty.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn slot(position: u64) -> usize {
+        (position % WINDOW) as usize
+    }
+
+    /// Add an event to the queue.
+    pub(crate) fn push(&mut self, event: QueuedEvent) {
+        let (chrom_id, position) = (event.0.chrom_id, event.0.reference_position);
+        if self.bucketed == 0 {
+            if self.buckets.is_empty() {
+                self.buckets.resize_with(WINDOW as usize, Vec::new);
+            }
+            self.chrom_id = chrom_id;
+            self.base = position;
+            self.front_sorted = false;
+        }
+        if chrom_id != self.chrom_id || position < self.base || position >= self.base + WINDOW {
+            self.heap.push(Reverse(event));
+            return;
+        }
+        let bucket = &mut self.buckets[Self::slot(position)];
+        if position == self.base && self.front_sorted {
+            // The front bucket is sorted in descending order, so its least event is last.
+            let i = bucket.partition_point(|e| *e > event);
+            bucket.insert(i, event);
+        } else {
+            bucket.push(event);
+        }
+        self.bucketed += 1;
+    }
+
+    /// Sort the front bucket, moving the front on past empty buckets first.
+    fn sort_front(&mut self) {
+        if self.bucketed == 0 || self.front_sorted {
+            return;
+        }
+        while self.buckets[Self::slot(self.base)].is_empty() {
+            self.base += 1;
+        }
+        let bucket = &mut self.buckets[Self::slot(self.base)];
+        bucket.sort_unstable_by(|a, b| b.cmp(a));
+        self.front_sorted = true;
+    }
+
+    /// Whether the least event is at the front of the buckets rather than in the heap.
+    fn front_is_least(&mut self) -> Option<bool> {
+        self.sort_front();
+        let bucketed = self
+            .buckets
+            .get(Self::slot(self.base))
+            .and_then(|bucket| bucket.last())
+            .filter(|_| self.bucketed > 0);
+        match (bucketed, self.heap.peek()) {
+            (None, None) => None,
+            (Some(_), None) => Some(true),
+            (None, Some(_)) => Some(false),
+            (Some(b), Some(Reverse(h))) => Some(b <= h),
+        }
+    }
+
+    /// The least event in the queue.
+    pub(crate) fn peek(&mut self) -> Option<&QueuedEvent> {
+        if self.front_is_least()? {
+            self.buckets[Self::slot(self.base)].last()
+        } else {
+            self.heap.peek().map(|Reverse(e)| e)
+        }
+    }
+
+    /// Remove the least event from the queue.
+    pub(crate) fn pop(&mut self) -> Option<QueuedEvent> {
+        if !self.front_is_least()? {
+            return self.heap.pop().map(|Reverse(e)| e);
+        }
+        let event = self.buckets[Self::slot(self.base)].pop();
+        self.bucketed -= 1;
+        if self.buckets[Self::slot(self.base)].is_empty() {
+            self.front_sorted = false;
+        }
+        event
+    }
+
+    /// Remove every event from the queue, in no particular order.
+    pub(crate) fn drain(&mut self) -> Vec<QueuedEvent> {
+        let mut events: Vec<_> = self.heap.drain().map(|Reverse(e)| e).collect();
+        for bucket in self.buckets.iter_mut() {
+            events.append(bucket);
+        }
+        self.bucketed = 0;
+        self.front_sorted = false;
+        events
+    }
+}
+
+impl Extend<QueuedEvent> for EventQueue {
+    fn extend<T: IntoIterator<Item = QueuedEvent>>(&mut self, iter: T) {
+        for event in iter {
+            self.push(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CigarOp;
+
+    fn event(chrom_id: u32, reference_position: u64, op: CigarOp, tag: usize) -> QueuedEvent {
+        let elem = AugmentedCigarElement {
+            length: 1,
+            op,
+            read_position: 0,
+            chrom_id,
+            reference_position,
+            leading_clip: false,
+        };
+        (elem, tag)
+    }
+
+    #[test]
+    fn test_event_queue_order() {
+        let events = vec![
+            event(1, 100, CigarOp::Match, 0),
+            event(1, 100, CigarOp::Deletion, 0),
+            event(1, 103, CigarOp::Match, 1),
+            event(1, 100 + WINDOW + 5, CigarOp::Match, 0),
+            event(2, 0, CigarOp::Match, 0),
+            event(1, 100, CigarOp::Insertion, 2),
+            event(0, 7, CigarOp::Match, 0),
+            event(1, 101, CigarOp::Match, 0),
+        ];
+        let mut queue = EventQueue::new();
+        queue.extend(events.iter().cloned());
+        assert_eq!(queue.len(), events.len());
+        let mut popped = vec![queue.pop().unwrap()];
+        // Events pushed at the front after it was sorted are kept in order.
+        let late = [
+            event(1, 100, CigarOp::Match, 5),
+            event(1, 102, CigarOp::Match, 5),
+        ];
+        queue.extend(late.iter().cloned());
+        while let Some(next) = queue.pop() {
+            popped.push(next);
+        }
+        let mut expected = events;
+        expected.extend(late);
+        expected.sort();
+        assert_eq!(popped, expected);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_event_queue_drain() {
+        let mut queue = EventQueue::new();
+        queue.push(event(1, 10, CigarOp::Match, 0));
+        queue.push(event(1, 5, CigarOp::Match, 0));
+        queue.push(event(1, 12, CigarOp::Match, 0));
+        assert_eq!(queue.peek().map(|e| e.0.reference_position), Some(5));
+        let mut drained = queue.drain();
+        drained.sort();
+        assert_eq!(drained.len(), 3);
+        assert!(queue.is_empty());
+        queue.extend(drained);
+        assert_eq!(queue.pop().map(|e| e.0.reference_position), Some(5));
+        assert_eq!(queue.pop().map(|e| e.0.reference_position), Some(10));
+    }
+
+    #[test]
+    fn test_event_queue_matches_heap() {
+        let mut state = 17u64;
+        let mut random = |n: u64| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) % n
+        };
+        let mut queue = EventQueue::new();
+        let mut heap = BinaryHeap::new();
+        let mut front = 0;
+        for i in 0..20000 {
+            if random(3) > 0 {
+                let chrom_id = (front / 50000) as u32;
+                let spread = if random(10) == 0 { 3 * WINDOW } else { 200 };
+                let position = front + random(spread);
+                let op = if random(2) == 0 {
+                    CigarOp::Match
+                } else {
+                    CigarOp::Insertion
+                };
+                let e = event(chrom_id, position, op, i);
+                queue.push(e.clone());
+                heap.push(Reverse(e));
+                front += random(4);
+            } else {
+                assert_eq!(queue.pop(), heap.pop().map(|Reverse(e)| e));
+            }
+            assert_eq!(queue.len(), heap.len());
+        }
+        while let Some(Reverse(e)) = heap.pop() {
+            assert_eq!(queue.pop(), Some(e));
+        }
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,215 @@
+//! Reconstruction of sequences from a CIGAR.
+//!
+//! Reference-based compression (as in CRAM) stores a read as its alignment and the
+//! differences from the reference. Given the [variant observations](crate::expand::VariantObservation)
+//! of a read, these functions rebuild the read from the reference, or the aligned segment
+//! of the reference from the read.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::Cigar;
+//! use cigar_utils::expand::variant_observations;
+//! use cigar_utils::reconstruct::{reconstruct_read, reconstruct_reference};
+//!
+//! let reference = b"ACGTACGT";
+//! let seq = b"AGTGTCG";
+//! let cigar: Cigar = "2M1I2M1D2M".parse().unwrap();
+//! let observations = variant_observations(0, &cigar.to_string(), &reference, &seq).unwrap();
+//!
+//! assert_eq!(reconstruct_read(0, &cigar, reference, &observations).unwrap(), seq);
+//! assert_eq!(reconstruct_reference(0, &cigar, seq, &observations).unwrap(), b"ACGTACG");
+//! ```
+
+use std::collections::HashMap;
+
+use crate::error::CigarError;
+use crate::expand::VariantObservation;
+use crate::{Cigar, CigarOp};
+
+/// Observations indexed the way reconstruction looks them up.
+struct Lookup<'a> {
+    mismatches: HashMap<usize, &'a VariantObservation>,
+    insertions: HashMap<usize, &'a [u8]>,
+    deletions: HashMap<usize, &'a [u8]>,
+}
+
+impl<'a> Lookup<'a> {
+    fn new(observations: &'a [VariantObservation]) -> Self {
+        let mut lookup = Lookup {
+            mismatches: HashMap::new(),
+            insertions: HashMap::new(),
+            deletions: HashMap::new(),
+        };
+        for observation in observations {
+            match observation {
+                VariantObservation::Mismatch {
+                    reference_position, ..
+                } => {
+                    lookup.mismatches.insert(*reference_position, observation);
+                }
+                VariantObservation::Insertion {
+                    read_position,
+                    sequence,
+                    ..
+                } => {
+                    lookup.insertions.insert(*read_position, sequence);
+                }
+                VariantObservation::Deletion {
+                    reference_position,
+                    sequence,
+                    ..
+                } => {
+                    lookup.deletions.insert(*reference_position, sequence);
+                }
+            }
+        }
+        lookup
+    }
+}
+
+/// Bases from an observation, or `N` for any not observed.
+fn observed(bases: Option<&&[u8]>, length: usize) -> impl Iterator<Item = u8> {
+    let bases: &[u8] = bases.copied().unwrap_or(&[]);
+    (0..length).map(move |i| bases.get(i).copied().unwrap_or(b'N'))
+}
+
+/// Rebuild a read aligned at `reference_position` from the reference and its observations.
+///
+/// Aligned bases are taken from the reference except where a mismatch was observed.
+/// Inserted and soft clipped bases are taken from insertion observations at their read
+/// position. Bases which cannot be recovered are `N`.
+pub fn reconstruct_read(
+    reference_position: usize,
+    cigar: &Cigar,
+    reference: &[u8],
+    observations: &[VariantObservation],
+) -> std::result::Result<Vec<u8>, CigarError> {
+    let lookup = Lookup::new(observations);
+    let mut read = Vec::new();
+    let mut position = reference_position;
+    for elem in cigar.iter() {
+        let length = elem.length as usize;
+        match elem.op {
+            CigarOp::Match | CigarOp::Equal | CigarOp::Diff => {
+                let end = position + length;
+                if end > reference.len() {
+                    return Err(CigarError::ReferenceOutOfBounds(
+                        position as u64,
+                        end as u64,
+                    ));
+                }
+                for (i, base) in reference[position..end].iter().enumerate() {
+                    match lookup.mismatches.get(&(position + i)) {
+                        Some(VariantObservation::Mismatch { read_base, .. }) => {
+                            read.push(*read_base)
+                        }
+                        _ => read.push(*base),
+                    }
+                }
+                position = end;
+            }
+            CigarOp::Insertion | CigarOp::SoftClip => {
+                read.extend(observed(lookup.insertions.get(&read.len()), length));
+            }
+            CigarOp::Deletion | CigarOp::Skip => position += length,
+            CigarOp::HardClip | CigarOp::Padding => {}
+        }
+    }
+    Ok(read)
+}
+
+/// Rebuild the segment of the reference covered by a read aligned at
+/// `reference_position`, from the read and its observations.
+///
+/// Aligned bases are taken from the read except where a mismatch was observed, and
+/// deleted or skipped bases from deletion observations. Bases which cannot be recovered
+/// are `N`.
+pub fn reconstruct_reference(
+    reference_position: usize,
+    cigar: &Cigar,
+    seq: &[u8],
+    observations: &[VariantObservation],
+) -> std::result::Result<Vec<u8>, CigarError> {
+    let lookup = Lookup::new(observations);
+    let mut segment = Vec::new();
+    let mut read_position = 0;
+    for elem in cigar.iter() {
+        let length = elem.length as usize;
+        let position = reference_position + segment.len();
+        match elem.op {
+            CigarOp::Match | CigarOp::Equal | CigarOp::Diff => {
+                let end = read_position + length;
+                if end > seq.len() {
+                    return Err(CigarError::SequenceTooShort(end, seq.len()));
+                }
+                for (i, base) in seq[read_position..end].iter().enumerate() {
+                    match lookup.mismatches.get(&(position + i)) {
+                        Some(VariantObservation::Mismatch { reference_base, .. }) => {
+                            segment.push(*reference_base)
+                        }
+                        _ => segment.push(*base),
+                    }
+                }
+                read_position = end;
+            }
+            CigarOp::Insertion | CigarOp::SoftClip => read_position += length,
+            CigarOp::Deletion | CigarOp::Skip => {
+                segment.extend(observed(lookup.deletions.get(&position), length));
+            }
+            CigarOp::HardClip | CigarOp::Padding => {}
+        }
+    }
+    Ok(segment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expand::variant_observations;
+
+    #[test]
+    fn test_round_trip() {
+        let reference = b"TTACGTACGTAC";
+        let seq = b"GGACTTACGAC";
+        let cigar: Cigar = "2S3M1I2M2D3M".parse().unwrap();
+        let observations = variant_observations(2, &cigar.to_string(), &reference, &seq).unwrap();
+        let mut with_clip = observations.clone();
+        with_clip.push(VariantObservation::Insertion {
+            reference_position: 2,
+            read_position: 0,
+            sequence: b"GG".to_vec(),
+        });
+        assert_eq!(
+            reconstruct_read(2, &cigar, reference, &with_clip).unwrap(),
+            seq
+        );
+        assert_eq!(
+            reconstruct_reference(2, &cigar, seq, &observations).unwrap(),
+            &reference[2..12]
+        );
+    }
+
+    #[test]
+    fn test_unrecovered_bases() {
+        let cigar: Cigar = "1S2M1I1D1M".parse().unwrap();
+        assert_eq!(reconstruct_read(0, &cigar, b"ACGT", &[]).unwrap(), b"NACNT");
+        assert_eq!(
+            reconstruct_reference(0, &cigar, b"CACGT", &[]).unwrap(),
+            b"ACNT"
+        );
+    }
+
+    #[test]
+    fn test_bounds() {
+        let cigar: Cigar = "5M".parse().unwrap();
+        assert!(matches!(
+            reconstruct_read(1, &cigar, b"ACGT", &[]),
+            Err(CigarError::ReferenceOutOfBounds(1, 6))
+        ));
+        assert!(matches!(
+            reconstruct_reference(0, &cigar, b"ACGT", &[]),
+            Err(CigarError::SequenceTooShort(5, 4))
+        ));
+    }
+}
@@ -0,0 +1,419 @@
+//! Alignment records.
+//!
+//! The [`CigarRecord`] trait describes what the collation machinery needs from an
+//! alignment record: its CIGAR, chromosome and position, and optionally its strand and
+//! mapping quality. Implementing it for a BAM or PAF record type lets such records be
+//! collated directly, without first copying the CIGAR into a `String`.
+
+use std::cmp::Ordering;
+
+use crate::augmented_cigar::AugmentedCigarIterator;
+use crate::{Cigar, CigarElement, Strand, UNAVAILABLE_CIGAR};
+
+/// A borrowed CIGAR, either as text or as parsed elements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CigarRef<'a> {
+    /// A CIGAR string.
+    Text(&'a str),
+    /// A sequence of parsed CIGAR elements.
+    Elements(&'a [CigarElement]),
+}
+
+impl<'a> CigarRef<'a> {
+    /// Whether the CIGAR is unavailable (`*` in SAM, or no elements).
+    pub fn is_unavailable(&self) -> bool {
+        match self {
+            CigarRef::Text(text) => *text == UNAVAILABLE_CIGAR,
+            CigarRef::Elements(elements) => elements.is_empty(),
+        }
+    }
+}
+
+impl<'a> From<(CigarRef<'a>, u32, u64)> for AugmentedCigarIterator<'a> {
+    fn from(value: (CigarRef<'a>, u32, u64)) -> Self {
+        let (cigar, chrom_id, reference_position) = value;
+        match cigar {
+            CigarRef::Text(text) => {
+                AugmentedCigarIterator::from((text, chrom_id, reference_position))
+            }
+            CigarRef::Elements(elements) => {
+                AugmentedCigarIterator::from((elements, chrom_id, reference_position))
+            }
+        }
+    }
+}
+
+/// Records which are unmapped, secondary, QC failures or duplicates, as excluded by
+/// default by `samtools mpileup`.
+pub const DEFAULT_EXCLUDE_FLAGS: u16 = 0x4 | 0x100 | 0x200 | 0x400;
+
+/// Conditions a record must meet to be passed on by a source of SAM records, such as
+/// [`SamSource`](crate::sam::SamSource).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordFilter {
+    /// The minimum mapping quality. Records with no mapping quality only pass if this is zero.
+    pub min_mapq: u8,
+    /// Records with any of these flags set are dropped.
+    pub exclude_flags: u16,
+    /// The half open reference interval `start..end` of a chromosome which records must
+    /// overlap, if any.
+    pub region: Option<(u32, u64, u64)>,
+}
+
+impl Default for RecordFilter {
+    fn default() -> Self {
+        RecordFilter {
+            min_mapq: 0,
+            exclude_flags: DEFAULT_EXCLUDE_FLAGS,
+            region: None,
+        }
+    }
+}
+
+impl RecordFilter {
+    /// Whether `record` passes the flag and mapping quality conditions of the filter.
+    ///
+    /// Records which do not report their flags are not excluded by them, and records
+    /// with no mapping quality (255 in SAM) only pass if the minimum is zero.
+    pub fn accepts(&self, record: &impl CigarRecord) -> bool {
+        record
+            .flags()
+            .is_none_or(|flags| flags & self.exclude_flags == 0)
+            && record.mapq().unwrap_or(0) >= self.min_mapq
+    }
+}
+
+/// Where a record starting at `position` of chromosome `chrom_id` lies relative to
+/// `region`, the half open interval `start..end` of a chromosome.
+///
+/// This is `Less` for a record on an earlier chromosome, `Greater` for one on a later
+/// chromosome or starting at or after `end`, so that no later record of a sorted source
+/// can overlap the region, and `Equal` otherwise.
+pub fn region_order(region: (u32, u64, u64), chrom_id: u32, position: u64) -> Ordering {
+    let (region_chrom_id, _, end) = region;
+    match chrom_id.cmp(&region_chrom_id) {
+        Ordering::Equal if position >= end => Ordering::Greater,
+        order => order,
+    }
+}
+
+/// A label for `record` in error messages: its read name if known, otherwise its
+/// chromosome ID and reference position.
+pub fn record_label(record: &impl CigarRecord) -> String {
+    match record.read_name() {
+        Some(name) => name.to_string(),
+        None => format!("{}:{}", record.chrom_id(), record.reference_position()),
+    }
+}
+
+/// An alignment record which can be collated.
+pub trait CigarRecord {
+    /// The CIGAR of the record.
+    fn cigar(&self) -> CigarRef<'_>;
+
+    /// The chromosome ID of the record.
+    fn chrom_id(&self) -> u32;
+
+    /// The reference position of the start of the alignment.
+    fn reference_position(&self) -> u64;
+
+    /// The strand of the alignment, if known.
+    fn strand(&self) -> Option<Strand> {
+        None
+    }
+
+    /// The mapping quality of the alignment, if known.
+    fn mapq(&self) -> Option<u8> {
+        None
+    }
+
+    /// The SAM flags of the record, if known.
+    fn flags(&self) -> Option<u16> {
+        None
+    }
+
+    /// The Phred base qualities of the bases in SEQ, without an ASCII offset, if known.
+    fn qualities(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// The bases of the read (SEQ), if known.
+    fn sequence(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// The ID of the sample the record belongs to, for collation broken down by sample.
+    fn sample(&self) -> usize {
+        0
+    }
+
+    /// The name of the read, if known, for pairing up mates.
+    fn read_name(&self) -> Option<&str> {
+        None
+    }
+
+    /// An iterator over the augmented CIGAR elements of the record.
+    fn augmented(&self) -> AugmentedCigarIterator<'_> {
+        AugmentedCigarIterator::from((self.cigar(), self.chrom_id(), self.reference_position()))
+    }
+}
+
+impl<R: CigarRecord + ?Sized> CigarRecord for &R {
+    fn cigar(&self) -> CigarRef<'_> {
+        (**self).cigar()
+    }
+
+    fn chrom_id(&self) -> u32 {
+        (**self).chrom_id()
+    }
+
+    fn reference_position(&self) -> u64 {
+        (**self).reference_position()
+    }
+
+    fn strand(&self) -> Option<Strand> {
+        (**self).strand()
+    }
+
+    fn mapq(&self) -> Option<u8> {
+        (**self).mapq()
+    }
+
+    fn flags(&self) -> Option<u16> {
+        (**self).flags()
+    }
+
+    fn qualities(&self) -> Option<&[u8]> {
+        (**self).qualities()
+    }
+
+    fn sequence(&self) -> Option<&[u8]> {
+        (**self).sequence()
+    }
+
+    fn sample(&self) -> usize {
+        (**self).sample()
+    }
+
+    fn read_name(&self) -> Option<&str> {
+        (**self).read_name()
+    }
+}
+
+/// A record tagged with the ID of the sample it belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sampled<R> {
+    /// The record.
+    pub record: R,
+    /// The sample ID.
+    pub sample: usize,
+}
+
+impl<R> Sampled<R> {
+    /// Tag a record with a sample ID.
+    pub fn new(record: R, sample: usize) -> Self {
+        Sampled { record, sample }
+    }
+}
+
+impl<R: CigarRecord> CigarRecord for Sampled<R> {
+    fn cigar(&self) -> CigarRef<'_> {
+        self.record.cigar()
+    }
+
+    fn chrom_id(&self) -> u32 {
+        self.record.chrom_id()
+    }
+
+    fn reference_position(&self) -> u64 {
+        self.record.reference_position()
+    }
+
+    fn strand(&self) -> Option<Strand> {
+        self.record.strand()
+    }
+
+    fn mapq(&self) -> Option<u8> {
+        self.record.mapq()
+    }
+
+    fn flags(&self) -> Option<u16> {
+        self.record.flags()
+    }
+
+    fn qualities(&self) -> Option<&[u8]> {
+        self.record.qualities()
+    }
+
+    fn sequence(&self) -> Option<&[u8]> {
+        self.record.sequence()
+    }
+
+    fn sample(&self) -> usize {
+        self.sample
+    }
+
+    fn read_name(&self) -> Option<&str> {
+        self.record.read_name()
+    }
+}
+
+impl CigarRecord for (String, u32, u64) {
+    fn cigar(&self) -> CigarRef<'_> {
+        CigarRef::Text(&self.0)
+    }
+
+    fn chrom_id(&self) -> u32 {
+        self.1
+    }
+
+    fn reference_position(&self) -> u64 {
+        self.2
+    }
+}
+
+impl CigarRecord for (&str, u32, u64) {
+    fn cigar(&self) -> CigarRef<'_> {
+        CigarRef::Text(self.0)
+    }
+
+    fn chrom_id(&self) -> u32 {
+        self.1
+    }
+
+    fn reference_position(&self) -> u64 {
+        self.2
+    }
+}
+
+impl CigarRecord for (Cigar, u32, u64) {
+    fn cigar(&self) -> CigarRef<'_> {
+        CigarRef::Elements(self.0.elements())
+    }
+
+    fn chrom_id(&self) -> u32 {
+        self.1
+    }
+
+    fn reference_position(&self) -> u64 {
+        self.2
+    }
+}
+
+impl CigarRecord for (Vec<CigarElement>, u32, u64) {
+    fn cigar(&self) -> CigarRef<'_> {
+        CigarRef::Elements(&self.0)
+    }
+
+    fn chrom_id(&self) -> u32 {
+        self.1
+    }
+
+    fn reference_position(&self) -> u64 {
+        self.2
+    }
+}
+
+impl CigarRecord for (&[CigarElement], u32, u64) {
+    fn cigar(&self) -> CigarRef<'_> {
+        CigarRef::Elements(self.0)
+    }
+
+    fn chrom_id(&self) -> u32 {
+        self.1
+    }
+
+    fn reference_position(&self) -> u64 {
+        self.2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CigarOp;
+
+    struct PafLike {
+        cigar: Vec<CigarElement>,
+        target: u32,
+        start: u64,
+        strand: Strand,
+    }
+
+    impl CigarRecord for PafLike {
+        fn cigar(&self) -> CigarRef<'_> {
+            CigarRef::Elements(&self.cigar)
+        }
+
+        fn chrom_id(&self) -> u32 {
+            self.target
+        }
+
+        fn reference_position(&self) -> u64 {
+            self.start
+        }
+
+        fn strand(&self) -> Option<Strand> {
+            Some(self.strand)
+        }
+    }
+
+    #[test]
+    fn test_cigar_record_custom() {
+        let record = PafLike {
+            cigar: vec![
+                CigarElement::new(3, CigarOp::Match),
+                CigarElement::new(1, CigarOp::Deletion),
+                CigarElement::new(2, CigarOp::Match),
+            ],
+            target: 4,
+            start: 20,
+            strand: Strand::Reverse,
+        };
+        assert_eq!(record.strand(), Some(Strand::Reverse));
+        assert_eq!(record.mapq(), None);
+        let elems: Vec<_> = record.augmented().collect::<Result<_, _>>().unwrap();
+        assert_eq!(elems.len(), 3);
+        assert_eq!(elems[2].chrom_id, 4);
+        assert_eq!(elems[2].reference_position, 24);
+    }
+
+    #[test]
+    fn test_cigar_record_unavailable() {
+        let record = ("*", 1, 100);
+        assert!(record.cigar().is_unavailable());
+        let record = ("5M".to_string(), 1, 100);
+        assert!(!record.cigar().is_unavailable());
+    }
+
+    #[test]
+    fn test_record_label() {
+        assert_eq!(record_label(&("5M", 1, 100)), "1:100");
+        let record: crate::sam::SamRecord =
+            "r1\t0\tchr1\t101\t60\t5M\t*\t0\t0\t*\t*".parse().unwrap();
+        assert_eq!(record_label(&record), "r1");
+    }
+
+    #[test]
+    fn test_record_filter() {
+        let filter = RecordFilter {
+            min_mapq: 20,
+            ..RecordFilter::default()
+        };
+        let parse = |line: &str| line.parse::<crate::sam::SamRecord>().unwrap();
+        assert!(filter.accepts(&parse("r1\t0\tchr1\t101\t60\t5M\t*\t0\t0\t*\t*")));
+        assert!(!filter.accepts(&parse("r1\t256\tchr1\t101\t60\t5M\t*\t0\t0\t*\t*")));
+        // A MAPQ of 255 is unknown, and only passes a minimum of zero.
+        let unknown = parse("r1\t0\tchr1\t101\t255\t5M\t*\t0\t0\t*\t*");
+        assert!(!filter.accepts(&unknown));
+        assert!(RecordFilter::default().accepts(&unknown));
+        // Records without flags or a mapping quality are not excluded by flags.
+        assert!(RecordFilter::default().accepts(&("5M", 1, 100)));
+
+        let region = (1, 100, 200);
+        assert_eq!(region_order(region, 0, 500), Ordering::Less);
+        assert_eq!(region_order(region, 1, 50), Ordering::Equal);
+        assert_eq!(region_order(region, 1, 200), Ordering::Greater);
+        assert_eq!(region_order(region, 2, 0), Ordering::Greater);
+    }
+}
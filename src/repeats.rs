@@ -0,0 +1,183 @@
+//! Repeat context of indels.
+//!
+//! Indel errors are far more common in homopolymers and short tandem repeats, especially
+//! with nanopore sequencing, so error analysis needs to know whether each indel lies in
+//! one. These functions find the repeat of the reference which an insertion or deletion
+//! lengthens or shortens, giving its unit and extent.
+//!
+//! An indel is in a repeat when its sequence is made of whole copies of a unit (of at
+//! most `max_unit` bases) and the reference next to it holds at least one more copy of
+//! that unit. Bases are compared ignoring case.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::repeats::{DEFAULT_MAX_UNIT, deletion_context, insertion_context};
+//!
+//! let reference = b"GATTTTCACACACAG";
+//! let context = deletion_context(reference, 3, 2, DEFAULT_MAX_UNIT).unwrap();
+//! assert!(context.is_homopolymer());
+//! assert_eq!((context.start, context.end, context.copies), (2, 6, 4));
+//!
+//! let context = insertion_context(reference, 14, b"CA", DEFAULT_MAX_UNIT).unwrap();
+//! assert_eq!(context.unit, b"CA");
+//! assert_eq!((context.start, context.end, context.copies), (6, 14, 4));
+//!
+//! assert!(insertion_context(reference, 1, b"C", DEFAULT_MAX_UNIT).is_none());
+//! ```
+
+use crate::expand::VariantObservation;
+
+/// The default longest repeat unit considered, as for short tandem repeats.
+pub const DEFAULT_MAX_UNIT: usize = 6;
+
+/// A repeat of the reference containing an indel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepeatContext {
+    /// The repeat unit, in the phase it has at the indel.
+    pub unit: Vec<u8>,
+    /// The reference position of the first base of the repeat.
+    pub start: usize,
+    /// The reference position following the last base of the repeat.
+    pub end: usize,
+    /// The number of whole copies of the unit in the repeat.
+    pub copies: usize,
+}
+
+impl RepeatContext {
+    /// Whether the repeat is a homopolymer run.
+    pub fn is_homopolymer(&self) -> bool {
+        self.unit.len() == 1
+    }
+
+    /// The number of reference bases in the repeat.
+    pub fn length(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+/// The periods of `sequence` which divide its length and are no more than `max_unit`, shortest first.
+fn units(sequence: &[u8], max_unit: usize) -> impl Iterator<Item = usize> + '_ {
+    (1..=max_unit.min(sequence.len())).filter(move |k| {
+        sequence.len().is_multiple_of(*k)
+            && (*k..sequence.len()).all(|i| sequence[i].eq_ignore_ascii_case(&sequence[i - k]))
+    })
+}
+
+/// The extent of the run of `unit` in the reference, in phase with the unit starting at `anchor`.
+fn run(reference: &[u8], anchor: usize, unit: &[u8]) -> (usize, usize) {
+    let k = unit.len();
+    let mut end = anchor;
+    while end < reference.len() && reference[end].eq_ignore_ascii_case(&unit[(end - anchor) % k]) {
+        end += 1;
+    }
+    let mut start = anchor;
+    while start > 0
+        && reference[start - 1].eq_ignore_ascii_case(&unit[(k - (anchor - start + 1) % k) % k])
+    {
+        start -= 1;
+    }
+    (start, end)
+}
+
+fn context(
+    reference: &[u8],
+    anchor: usize,
+    sequence: &[u8],
+    required: usize,
+    max_unit: usize,
+) -> Option<RepeatContext> {
+    if anchor > reference.len() {
+        return None;
+    }
+    units(sequence, max_unit).find_map(|k| {
+        let unit = &sequence[..k];
+        let (start, end) = run(reference, anchor, unit);
+        (end - start >= required + k).then(|| RepeatContext {
+            unit: unit.to_vec(),
+            start,
+            end,
+            copies: (end - start) / k,
+        })
+    })
+}
+
+/// Find the repeat of the reference shortened by deleting the `length` bases at `position`.
+///
+/// Returns `None` if the deletion is not in a repeat or extends beyond the reference.
+pub fn deletion_context(
+    reference: &[u8],
+    position: usize,
+    length: usize,
+    max_unit: usize,
+) -> Option<RepeatContext> {
+    let sequence = reference.get(position..position + length)?;
+    context(reference, position, sequence, length, max_unit)
+}
+
+/// Find the repeat of the reference lengthened by inserting `sequence` before `position`.
+///
+/// Returns `None` if the insertion is not in a repeat.
+pub fn insertion_context(
+    reference: &[u8],
+    position: usize,
+    sequence: &[u8],
+    max_unit: usize,
+) -> Option<RepeatContext> {
+    context(reference, position, sequence, 0, max_unit)
+}
+
+impl VariantObservation {
+    /// The repeat context of an insertion or deletion observation, if it is in a repeat.
+    ///
+    /// Mismatches have no repeat context.
+    pub fn repeat_context(&self, reference: &[u8], max_unit: usize) -> Option<RepeatContext> {
+        match self {
+            VariantObservation::Insertion {
+                reference_position,
+                sequence,
+                ..
+            } => insertion_context(reference, *reference_position, sequence, max_unit),
+            VariantObservation::Deletion {
+                reference_position,
+                sequence,
+                ..
+            } => deletion_context(reference, *reference_position, sequence.len(), max_unit),
+            VariantObservation::Mismatch { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expand::variant_observations;
+
+    #[test]
+    fn test_deletion_not_in_repeat() {
+        assert!(deletion_context(b"GCATG", 2, 1, DEFAULT_MAX_UNIT).is_none());
+        assert!(deletion_context(b"GCATG", 4, 3, DEFAULT_MAX_UNIT).is_none());
+        // A deleted copy of a dinucleotide repeat, with a partial copy at the start.
+        let context = deletion_context(b"GTGTGA", 1, 2, DEFAULT_MAX_UNIT).unwrap();
+        assert_eq!(context.unit, b"TG");
+        assert_eq!((context.start, context.end, context.copies), (0, 5, 2));
+    }
+
+    #[test]
+    fn test_shortest_unit_and_case() {
+        let context = insertion_context(b"cAaAaG", 1, b"AAAA", DEFAULT_MAX_UNIT).unwrap();
+        assert!(context.is_homopolymer());
+        assert_eq!((context.start, context.end, context.length()), (1, 5, 4));
+        assert!(insertion_context(b"CAAAAG", 1, b"AAAA", 0).is_none());
+    }
+
+    #[test]
+    fn test_observation_context() {
+        let reference = b"CGAAAATC";
+        let observations = variant_observations(0, "3M1D4M", &reference, &b"CGAAATC").unwrap();
+        let context = observations[0]
+            .repeat_context(reference, DEFAULT_MAX_UNIT)
+            .unwrap();
+        assert_eq!((context.start, context.end, context.copies), (2, 6, 4));
+    }
+}
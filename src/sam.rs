@@ -0,0 +1,352 @@
+//! Lightweight SAM text records.
+//!
+//! For collating SAM text without a full SAM library, [`SamRecord`] takes from a SAM
+//! line just what the collator needs: the reference name, position and CIGAR, plus the
+//! flags and mapping quality for filtering, the read name for pairing mates and the bases
+//! and their qualities.
+//! [`SamSource`] adapts a `BufRead` of SAM
+//! lines into a source for [collation](crate::collated), numbering chromosomes by the
+//! order of the `@SQ` header lines.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::io::Cursor;
+//! use cigar_utils::collated::CollatedAugmentedCigarIterator;
+//! use cigar_utils::record::RecordFilter;
+//! use cigar_utils::sam::SamSource;
+//!
+//! let sam = "@SQ\tSN:chr1\tLN:1000\n\
+//!            @SQ\tSN:chr2\tLN:1000\n\
+//!            r1\t0\tchr2\t11\t60\t4M\t*\t0\t0\tACGT\tIIII\n\
+//!            r2\t16\tchr2\t12\t60\t3M\t*\t0\t0\tCGT\tIII\n\
+//!            r3\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\tIIII\n";
+//! let source = SamSource::new(Cursor::new(sam), RecordFilter::default());
+//! let events: Vec<_> = CollatedAugmentedCigarIterator::new(source)
+//!     .collect::<Result<_, _>>()
+//!     .unwrap();
+//! assert_eq!(events.len(), 2);
+//! assert_eq!((events[0].0.chrom_id, events[0].0.reference_position), (1, 10));
+//! ```
+
+use std::cmp::Ordering;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+use crate::chrom_map::ChromMap;
+use crate::error::{CigarError, parse_number};
+use crate::record::{CigarRecord, CigarRef, RecordFilter, region_order};
+use crate::{Cigar, Strand};
+
+/// The fields of a SAM line needed for collation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SamRecord {
+    /// The read name (QNAME).
+    pub read_name: String,
+    /// The reference sequence name (RNAME), `*` if unmapped.
+    pub reference_name: String,
+    /// The chromosome ID of the reference sequence, assigned by the caller; zero when parsed.
+    pub chrom_id: u32,
+    /// The 0-based reference position of the start of the alignment.
+    pub reference_position: u64,
+    /// The CIGAR string, `*` if unavailable.
+    pub cigar: String,
+    /// The SAM flags.
+    pub flags: u16,
+    /// The mapping quality, if known (not 255).
+    pub mapq: Option<u8>,
+    /// The bases of the read (SEQ), if present and not `*`.
+    pub sequence: Option<Vec<u8>>,
+    /// The Phred base qualities (QUAL) without the ASCII offset, if present and not `*`.
+    pub qualities: Option<Vec<u8>>,
+}
+
+/// The format name given in [`CigarError::InvalidFormat`] errors.
+const FORMAT: &str = "SAM";
+
+impl SamRecord {
+    /// Whether the record is placed on a reference sequence.
+    pub fn is_placed(&self) -> bool {
+        self.reference_name != "*" && self.flags & 0x4 == 0
+    }
+
+    /// The reference position following the end of the alignment.
+    pub fn reference_end(&self) -> std::result::Result<u64, CigarError> {
+        if self.cigar == crate::UNAVAILABLE_CIGAR {
+            return Ok(self.reference_position);
+        }
+        let cigar: Cigar = self.cigar.parse()?;
+        Ok(cigar.alignment_end(self.reference_position))
+    }
+}
+
+impl FromStr for SamRecord {
+    type Err = CigarError;
+
+    /// Parse the first six fields of a tab separated SAM line, and the bases and their
+    /// qualities if present; the rest are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.trim_end_matches(['\r', '\n']).splitn(12, '\t');
+        let mut field = || {
+            fields
+                .next()
+                .ok_or_else(|| CigarError::invalid_format(FORMAT, "too few fields"))
+        };
+        let read_name = field()?.to_string();
+        let flags = parse_number(FORMAT, field()?)?;
+        let reference_name = field()?.to_string();
+        let position: u64 = parse_number(FORMAT, field()?)?;
+        let mapq: u8 = parse_number(FORMAT, field()?)?;
+        let cigar = field()?.to_string();
+        let sequence = match fields.nth(3) {
+            None | Some("*") => None,
+            Some(seq) => Some(seq.as_bytes().to_vec()),
+        };
+        let qualities = match fields.next() {
+            None | Some("*") => None,
+            Some(qual) => Some(
+                qual.bytes()
+                    .map(|q| q.checked_sub(33))
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or_else(|| {
+                        CigarError::invalid_format(
+                            FORMAT,
+                            format!("invalid base qualities '{}'", qual),
+                        )
+                    })?,
+            ),
+        };
+        Ok(SamRecord {
+            read_name,
+            reference_name,
+            chrom_id: 0,
+            reference_position: position.saturating_sub(1),
+            cigar,
+            flags,
+            mapq: (mapq != 255).then_some(mapq),
+            sequence,
+            qualities,
+        })
+    }
+}
+
+impl CigarRecord for SamRecord {
+    fn cigar(&self) -> CigarRef<'_> {
+        CigarRef::Text(&self.cigar)
+    }
+
+    fn chrom_id(&self) -> u32 {
+        self.chrom_id
+    }
+
+    fn reference_position(&self) -> u64 {
+        self.reference_position
+    }
+
+    fn strand(&self) -> Option<Strand> {
+        Some(if self.flags & 0x10 != 0 {
+            Strand::Reverse
+        } else {
+            Strand::Forward
+        })
+    }
+
+    fn mapq(&self) -> Option<u8> {
+        self.mapq
+    }
+
+    fn flags(&self) -> Option<u16> {
+        Some(self.flags)
+    }
+
+    fn qualities(&self) -> Option<&[u8]> {
+        self.qualities.as_deref()
+    }
+
+    fn sequence(&self) -> Option<&[u8]> {
+        self.sequence.as_deref()
+    }
+
+    fn read_name(&self) -> Option<&str> {
+        Some(&self.read_name)
+    }
+}
+
+fn invalid_data<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// A source of [`SamRecord`]s for collation, read from coordinate-sorted SAM text.
+///
+/// Chromosome IDs follow the order of the `@SQ` header lines; reference names missing
+/// from the header are numbered after them in order of appearance, and a name repeated
+/// in the header is an error. Unplaced records, and records failing the
+/// [`RecordFilter`], are skipped. When a region is given, reading stops once the records
+/// have passed it.
+pub struct SamSource<B> {
+    reader: B,
+    filter: RecordFilter,
+    line: String,
+    chroms: ChromMap,
+}
+
+impl<B: BufRead> SamSource<B> {
+    /// Create a new SAM source.
+    pub fn new(reader: B, filter: RecordFilter) -> Self {
+        SamSource {
+            reader,
+            filter,
+            line: String::new(),
+            chroms: ChromMap::new(),
+        }
+    }
+
+    /// The reference sequence names seen so far, indexed by chromosome ID.
+    pub fn reference_names(&self) -> &[String] {
+        self.chroms.names()
+    }
+
+    /// The reference sequences seen so far, with the lengths given by the `@SQ` header
+    /// lines, for writing names in place of chromosome IDs.
+    pub fn chrom_map(&self) -> &ChromMap {
+        &self.chroms
+    }
+}
+
+impl<B: BufRead> Iterator for SamSource<B> {
+    type Item = io::Result<SamRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e)),
+            }
+            if let Some(header) = self.line.strip_prefix("@SQ\t") {
+                let tag = |prefix: &str| {
+                    header
+                        .trim_end()
+                        .split('\t')
+                        .find_map(|tag| tag.strip_prefix(prefix))
+                };
+                let length = tag("LN:").and_then(|length| length.parse().ok());
+                match (tag("SN:"), length) {
+                    (Some(name), Some(length)) => {
+                        if let Err(e) = self.chroms.push(name, length) {
+                            return Some(Err(invalid_data(e)));
+                        }
+                    }
+                    (Some(name), None) => {
+                        self.chroms.insert(name);
+                    }
+                    (None, _) => {}
+                }
+                continue;
+            }
+            if self.line.starts_with('@') || self.line.trim().is_empty() {
+                continue;
+            }
+            let mut record: SamRecord = match self.line.parse() {
+                Ok(record) => record,
+                Err(e) => return Some(Err(invalid_data(e))),
+            };
+            if !record.is_placed() || !self.filter.accepts(&record) {
+                continue;
+            }
+            record.chrom_id = self.chroms.insert(&record.reference_name);
+            if let Some(region) = self.filter.region {
+                match region_order(region, record.chrom_id, record.reference_position) {
+                    Ordering::Greater => return None,
+                    Ordering::Less => continue,
+                    Ordering::Equal => {}
+                }
+                let (_, start, _) = region;
+                match record.reference_end() {
+                    Ok(reference_end) if reference_end <= start => continue,
+                    Ok(_) => {}
+                    Err(e) => return Some(Err(invalid_data(e))),
+                }
+            }
+            return Some(Ok(record));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_parse_line() {
+        let record: SamRecord = "r1\t16\tchr1\t100\t255\t5S20M\t=\t300\t0\tACGT\t*\tNM:i:0\n"
+            .parse()
+            .unwrap();
+        assert_eq!(record.read_name(), Some("r1"));
+        assert_eq!(record.reference_name, "chr1");
+        assert_eq!(record.reference_position, 99);
+        assert_eq!(record.cigar, "5S20M");
+        assert_eq!(record.mapq(), None);
+        assert_eq!(record.strand(), Some(Strand::Reverse));
+        assert_eq!(record.reference_end().unwrap(), 119);
+        assert_eq!(record.qualities(), None);
+        assert_eq!(record.sequence(), Some(&b"ACGT"[..]));
+        let record: SamRecord = "r1\t0\tchr1\t100\t60\t4M\t*\t0\t0\tACGT\t!+5I"
+            .parse()
+            .unwrap();
+        assert_eq!(record.qualities(), Some(&[0, 10, 20, 40][..]));
+        assert!(matches!(
+            "r1\t16\tchr1\t100".parse::<SamRecord>(),
+            Err(CigarError::InvalidFormat("SAM", _))
+        ));
+        assert!(matches!(
+            "r1\tx\tchr1\t100\t60\t4M".parse::<SamRecord>(),
+            Err(CigarError::InvalidFormat("SAM", _))
+        ));
+    }
+
+    #[test]
+    fn test_source_filters() {
+        let sam = "@HD\tVN:1.6\tSO:coordinate\n\
+                   @SQ\tSN:chr1\tLN:500\n\
+                   r1\t0\tchr1\t10\t5\t4M\t*\t0\t0\t*\t*\n\
+                   r2\t1024\tchr1\t20\t60\t4M\t*\t0\t0\t*\t*\n\
+                   r3\t0\tchr1\t30\t60\t4M\t*\t0\t0\t*\t*\n\
+                   r4\t0\tchrUn\t5\t60\t4M\t*\t0\t0\t*\t*\n";
+        let filter = RecordFilter {
+            min_mapq: 10,
+            ..RecordFilter::default()
+        };
+        let mut source = SamSource::new(Cursor::new(sam), filter);
+        let records: Vec<SamRecord> = source.by_ref().collect::<io::Result<_>>().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].reference_position, 29);
+        assert_eq!(records[1].chrom_id, 1);
+        assert_eq!(source.reference_names(), ["chr1", "chrUn"]);
+        let chroms = source.chrom_map();
+        assert_eq!((chroms.length(0), chroms.length(1)), (Some(500), None));
+
+        let repeated = "@SQ\tSN:chr1\tLN:500\n@SQ\tSN:chr1\tLN:500\n";
+        let mut source = SamSource::new(Cursor::new(repeated), RecordFilter::default());
+        assert!(source.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_source_region() {
+        let sam = "r1\t0\tchr1\t10\t60\t4M\t*\t0\t0\t*\t*\n\
+                   r2\t0\tchr1\t20\t60\t4M\t*\t0\t0\t*\t*\n\
+                   r3\t0\tchr1\t30\t60\t4M\t*\t0\t0\t*\t*\n\
+                   r4\t0\tchr1\t40\t60\tbad\t*\t0\t0\t*\t*\n";
+        let filter = RecordFilter {
+            region: Some((0, 15, 25)),
+            ..RecordFilter::default()
+        };
+        let records: Vec<SamRecord> = SamSource::new(Cursor::new(sam), filter)
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].reference_position, 19);
+    }
+}
@@ -0,0 +1,151 @@
+//! Affine-gap scoring of alignments.
+//!
+//! Aligners report a score for each alignment, computed under their own scoring scheme.
+//! [`score`] recomputes it from a CIGAR using sequence match (`=`) and mismatch (`X`)
+//! operations under a given [`Scoring`], for re-ranking candidate alignments or checking
+//! the scores aligners report. [`score_with_sequences`] resolves alignment match (`M`)
+//! elements against the reference and read first.
+//!
+//! Each run of consecutive insertion or deletion elements is one gap. Clipped bases,
+//! skipped reference (introns) and padding are not part of the alignment and do not
+//! score.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::Cigar;
+//! use cigar_utils::score::{Scoring, score};
+//!
+//! let cigar: Cigar = "2S10=1X2D5=".parse().unwrap();
+//! let scoring = Scoring {
+//!     match_score: 1,
+//!     mismatch: 3,
+//!     gap_open: 5,
+//!     gap_extend: 2,
+//! };
+//! assert_eq!(score(&cigar, &scoring).unwrap(), 15 - 3 - (5 + 2 * 2));
+//! ```
+
+use crate::error::CigarError;
+use crate::expand::ExpandedCigarIterator;
+use crate::{Cigar, CigarElement, CigarOp};
+
+/// An affine-gap scoring scheme.
+///
+/// A gap of length `k` costs `gap_open + k * gap_extend`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scoring {
+    /// The score of a matching pair of bases.
+    pub match_score: i32,
+    /// The penalty of a mismatching pair of bases.
+    pub mismatch: i32,
+    /// The penalty of opening a gap.
+    pub gap_open: i32,
+    /// The penalty of each base of a gap.
+    pub gap_extend: i32,
+}
+
+impl Default for Scoring {
+    /// Match 2, mismatch 4, gap open 4 and gap extend 2.
+    fn default() -> Self {
+        Scoring {
+            match_score: 2,
+            mismatch: 4,
+            gap_open: 4,
+            gap_extend: 2,
+        }
+    }
+}
+
+impl Scoring {
+    /// The score of a stream of elements using `=` and `X`.
+    fn accumulate(
+        &self,
+        elements: impl Iterator<Item = std::result::Result<CigarElement, CigarError>>,
+    ) -> std::result::Result<i32, CigarError> {
+        let mut score = 0;
+        let mut previous = None;
+        for (index, elem) in elements.enumerate() {
+            let elem = elem?;
+            let length = elem.length as i32;
+            match elem.op {
+                CigarOp::Equal => score += self.match_score * length,
+                CigarOp::Diff => score -= self.mismatch * length,
+                CigarOp::Insertion | CigarOp::Deletion => {
+                    if previous != Some(elem.op) {
+                        score -= self.gap_open;
+                    }
+                    score -= self.gap_extend * length;
+                }
+                CigarOp::Match => return Err(CigarError::UnresolvedMatch(index)),
+                CigarOp::Skip | CigarOp::SoftClip | CigarOp::HardClip | CigarOp::Padding => {}
+            }
+            previous = Some(elem.op);
+        }
+        Ok(score)
+    }
+}
+
+/// The score of an alignment from a CIGAR using sequence match (`=`) and mismatch (`X`)
+/// operations.
+///
+/// An alignment match (`M`) element gives [`CigarError::UnresolvedMatch`]; use
+/// [`score_with_sequences`] for such CIGARs.
+pub fn score(cigar: &Cigar, scoring: &Scoring) -> std::result::Result<i32, CigarError> {
+    scoring.accumulate(cigar.iter().cloned().map(Ok))
+}
+
+/// The score of a read aligned at `reference_position`, comparing the bases of
+/// alignment match (`M`) elements using the reference and read sequences.
+pub fn score_with_sequences<R: AsRef<[u8]>, S: AsRef<[u8]>>(
+    reference_position: usize,
+    cigar: &str,
+    reference: &R,
+    seq: &S,
+    scoring: &Scoring,
+) -> std::result::Result<i32, CigarError> {
+    scoring.accumulate(ExpandedCigarIterator::new(
+        reference_position,
+        cigar,
+        reference.as_ref(),
+        seq.as_ref(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score() {
+        let scoring = Scoring::default();
+        let cigar: Cigar = "3S4=1X2I3=2D1D5=".parse().unwrap();
+        assert_eq!(score(&cigar, &scoring).unwrap(), 24 - 4 - 8 - 10);
+        // An insertion next to a deletion is two gaps.
+        let cigar: Cigar = "2=2I3D2=".parse().unwrap();
+        assert_eq!(score(&cigar, &scoring).unwrap(), 8 - 8 - 10);
+        let cigar: Cigar = "2=3M".parse().unwrap();
+        assert!(matches!(
+            score(&cigar, &scoring),
+            Err(CigarError::UnresolvedMatch(1))
+        ));
+    }
+
+    #[test]
+    fn test_score_with_sequences() {
+        let scoring = Scoring::default();
+        assert_eq!(
+            score_with_sequences(0, "8M", b"ACGTACGT", b"ACGAACGT", &scoring).unwrap(),
+            14 - 4
+        );
+        assert_eq!(
+            score_with_sequences(2, "2M1D2M", b"TTACGTA", b"ACTA", &scoring).unwrap(),
+            8 - 6
+        );
+        // Padding takes no reference bases, so the bases either side of it still match.
+        assert_eq!(
+            score_with_sequences(0, "2M1P2M", b"ACGT", b"ACGT", &scoring).unwrap(),
+            score(&"4=".parse().unwrap(), &scoring).unwrap()
+        );
+    }
+}
@@ -0,0 +1,255 @@
+//! Searching CIGARs for patterns of operations.
+//!
+//! Scanning alignments for structural variant signatures (a long deletion with well
+//! aligned flanks, a large insertion, a long soft clip next to a short alignment) means
+//! walking the CIGAR with a small state machine. A [`CigarPattern`] describes the
+//! signature as a sequence of [`ElementMatcher`]s, each matching a run of elements with
+//! given operations and a total length within bounds, and [`CigarPattern::find`]
+//! returns every occurrence with its read and reference coordinates.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::{Cigar, CigarOp};
+//! use cigar_utils::search::{CigarPattern, ElementMatcher};
+//!
+//! // A deletion of at least 50bp flanked by at least 20 aligned bases on each side.
+//! let pattern = CigarPattern::new()
+//!     .then(ElementMatcher::aligned().at_least(20))
+//!     .then(ElementMatcher::op(CigarOp::Deletion).at_least(50))
+//!     .then(ElementMatcher::aligned().at_least(20));
+//!
+//! let cigar: Cigar = "5S12=1X10=80D25M3I4M".parse().unwrap();
+//! let matches = pattern.find(&cigar, 1000);
+//! assert_eq!(matches.len(), 1);
+//! let deletion = &matches[0].steps[1];
+//! assert_eq!((deletion.reference_start, deletion.reference_end), (1023, 1103));
+//! assert_eq!(deletion.read_start, 28);
+//! ```
+
+use std::ops::Range;
+
+use crate::{Cigar, CigarOp};
+
+/// A matcher for a run of consecutive CIGAR elements.
+///
+/// A run matches if every element in it has one of the matcher's operations and the
+/// total length of its elements lies within the matcher's bounds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElementMatcher {
+    ops: Vec<CigarOp>,
+    min_length: u32,
+    max_length: u32,
+}
+
+impl ElementMatcher {
+    /// Match runs of elements with any of the given operations, of any length.
+    pub fn ops<V: IntoIterator<Item = CigarOp>>(ops: V) -> Self {
+        ElementMatcher {
+            ops: ops.into_iter().collect(),
+            min_length: 0,
+            max_length: u32::MAX,
+        }
+    }
+
+    /// Match runs of elements with the given operation, of any length.
+    pub fn op(op: CigarOp) -> Self {
+        ElementMatcher::ops([op])
+    }
+
+    /// Match runs of aligned (`M`, `=` or `X`) elements, of any length.
+    pub fn aligned() -> Self {
+        ElementMatcher::ops([CigarOp::Match, CigarOp::Equal, CigarOp::Diff])
+    }
+
+    /// Require a total length of at least `length`.
+    pub fn at_least(mut self, length: u32) -> Self {
+        self.min_length = length;
+        self
+    }
+
+    /// Require a total length of at most `length`.
+    pub fn at_most(mut self, length: u32) -> Self {
+        self.max_length = length;
+        self
+    }
+
+    fn accepts_op(&self, op: CigarOp) -> bool {
+        self.ops.contains(&op)
+    }
+
+    fn accepts_length(&self, length: u64) -> bool {
+        self.min_length as u64 <= length && length <= self.max_length as u64
+    }
+}
+
+/// A sequence of [`ElementMatcher`]s to search for in CIGARs.
+///
+/// Each matcher matches a maximal run of consecutive elements with its operations,
+/// starting where the previous matcher's run ended. Runs are maximal, so a matcher for
+/// aligned bases followed by one for `=` alone never matches, as the first run takes
+/// every `=` element; and occurrences never start within a run of elements the first
+/// matcher accepts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CigarPattern {
+    steps: Vec<ElementMatcher>,
+}
+
+/// The elements of a CIGAR matched by a pattern, or by one of its matchers.
+///
+/// Intervals are half open. Read positions are positions in SEQ, so they include soft
+/// clipped bases but not hard clipped ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchSpan {
+    /// The indices of the matched elements in the CIGAR.
+    pub elements: Range<usize>,
+    /// The read position of the start of the span.
+    pub read_start: u32,
+    /// The read position of the end of the span.
+    pub read_end: u32,
+    /// The reference position of the start of the span.
+    pub reference_start: u64,
+    /// The reference position of the end of the span.
+    pub reference_end: u64,
+}
+
+/// An occurrence of a [`CigarPattern`] in a CIGAR.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternMatch {
+    /// The span of the whole occurrence.
+    pub span: MatchSpan,
+    /// The span matched by each matcher of the pattern, in order.
+    pub steps: Vec<MatchSpan>,
+}
+
+impl CigarPattern {
+    /// Create an empty pattern, which matches nothing.
+    pub fn new() -> Self {
+        CigarPattern::default()
+    }
+
+    /// Append a matcher to the pattern.
+    pub fn then(mut self, matcher: ElementMatcher) -> Self {
+        self.steps.push(matcher);
+        self
+    }
+
+    /// Every occurrence of the pattern in an alignment starting at the reference
+    /// position `position`, in order of their first element. Occurrences may overlap.
+    pub fn find(&self, cigar: &Cigar, position: u64) -> Vec<PatternMatch> {
+        let elements = cigar.elements();
+        // The read and reference positions at the start of each element, and the end.
+        let mut starts = Vec::with_capacity(elements.len() + 1);
+        let (mut read_position, mut reference_position) = (0, position);
+        for elem in elements {
+            starts.push((read_position, reference_position));
+            if elem.op.consumes_query() {
+                read_position += elem.length;
+            }
+            if elem.op.consumes_reference() {
+                reference_position += elem.length as u64;
+            }
+        }
+        starts.push((read_position, reference_position));
+        let span = |range: Range<usize>| MatchSpan {
+            read_start: starts[range.start].0,
+            read_end: starts[range.end].0,
+            reference_start: starts[range.start].1,
+            reference_end: starts[range.end].1,
+            elements: range,
+        };
+
+        let Some(first) = self.steps.first() else {
+            return Vec::new();
+        };
+        let mut matches = Vec::new();
+        for start in 0..elements.len() {
+            if start > 0 && first.accepts_op(elements[start - 1].op) {
+                continue;
+            }
+            let mut steps = Vec::with_capacity(self.steps.len());
+            let mut i = start;
+            for matcher in &self.steps {
+                let run = elements[i..]
+                    .iter()
+                    .take_while(|e| matcher.accepts_op(e.op))
+                    .count();
+                let length: u64 = elements[i..i + run].iter().map(|e| e.length as u64).sum();
+                if run == 0 || !matcher.accepts_length(length) {
+                    break;
+                }
+                steps.push(span(i..i + run));
+                i += run;
+            }
+            if steps.len() == self.steps.len() {
+                matches.push(PatternMatch {
+                    span: span(start..i),
+                    steps,
+                });
+            }
+        }
+        matches
+    }
+
+    /// Whether the pattern occurs in the CIGAR.
+    pub fn is_match(&self, cigar: &Cigar) -> bool {
+        !self.find(cigar, 0).is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_deletion_signature() {
+        let pattern = CigarPattern::new()
+            .then(ElementMatcher::aligned().at_least(20))
+            .then(ElementMatcher::op(CigarOp::Deletion).at_least(50))
+            .then(ElementMatcher::aligned().at_least(20));
+        let cigar: Cigar = "2H30M60D25M100D30M70D5=1X20=".parse().unwrap();
+        let matches = pattern.find(&cigar, 100);
+        assert_eq!(matches.len(), 3);
+        assert_eq!(matches[0].span.elements, 1..4);
+        assert_eq!(
+            (
+                matches[0].span.reference_start,
+                matches[0].span.reference_end
+            ),
+            (100, 215)
+        );
+        // Occurrences overlap where they share a flank.
+        assert_eq!(matches[1].span.elements, 3..6);
+        let last = &matches[2];
+        assert_eq!(last.span.elements, 5..10);
+        assert_eq!((last.span.read_start, last.span.read_end), (55, 111));
+        assert_eq!(last.steps[2].elements, 7..10);
+        assert!(!pattern.is_match(&"30M40D30M".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_find_runs() {
+        let pattern = CigarPattern::new()
+            .then(ElementMatcher::op(CigarOp::SoftClip).at_least(10))
+            .then(ElementMatcher::aligned().at_most(30));
+        let cigar: Cigar = "15S20M".parse().unwrap();
+        let matches = pattern.find(&cigar, 0);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(
+            (matches[0].steps[1].read_start, matches[0].span.read_end),
+            (15, 35)
+        );
+        assert!(!pattern.is_match(&"15S40M".parse().unwrap()));
+        assert!(!pattern.is_match(&"5S20M".parse().unwrap()));
+        // Occurrences start only at the start of a run the first matcher accepts.
+        let pattern = CigarPattern::new().then(ElementMatcher::aligned().at_least(5));
+        let matches = pattern.find(&"3=1X3=2I6M".parse().unwrap(), 0);
+        let runs: Vec<_> = matches.iter().map(|m| m.span.elements.clone()).collect();
+        assert_eq!(runs, vec![0..3, 4..5]);
+        assert!(
+            CigarPattern::new()
+                .find(&"10M".parse().unwrap(), 0)
+                .is_empty()
+        );
+    }
+}
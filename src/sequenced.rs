@@ -0,0 +1,309 @@
+//! Collation by sequence content.
+//!
+//! The [collator](crate::collated) counts events by their position, operation and length
+//! alone, so two different 2bp insertions at a position are counted as one event. A
+//! [`SequencedElement`] carries the inserted bases of an insertion, taken from the read,
+//! or the deleted bases of a deletion, taken from the reference, and a collator made
+//! [`sequenced`](CollatedAugmentedCigarIterator::sequenced) counts events with different
+//! bases apart.
+//!
+//! Records without bases (see [`CigarRecord::sequence`]), and deletions on chromosomes
+//! missing from the reference, give events with no sequence, which are counted together.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::CigarOp;
+//! use cigar_utils::collated::CollatedAugmentedCigarIterator;
+//! use cigar_utils::sam::SamRecord;
+//!
+//! let records: Vec<std::io::Result<SamRecord>> = [
+//!     "r1\t0\tchr1\t1\t60\t2M2I2M\t*\t0\t0\tACGTAC\t*",
+//!     "r2\t0\tchr1\t1\t60\t2M2I2M\t*\t0\t0\tACTTAC\t*",
+//!     "r3\t0\tchr1\t1\t60\t2M2I2M\t*\t0\t0\tACGTAC\t*",
+//! ]
+//! .iter()
+//! .map(|line| Ok(line.parse().unwrap()))
+//! .collect();
+//! let reference = vec![b"ACACGT".to_vec()];
+//! let insertions: Vec<_> = CollatedAugmentedCigarIterator::new(records.into_iter())
+//!     .sequenced(&reference)
+//!     .map(|event| event.unwrap())
+//!     .filter(|(e, _)| e.element.op == CigarOp::Insertion)
+//!     .map(|(e, count)| (e.sequence.unwrap(), count))
+//!     .collect();
+//! assert_eq!(insertions, vec![(b"GT".to_vec(), 2), (b"TT".to_vec(), 1)]);
+//! ```
+
+use std::iter::FusedIterator;
+
+use crate::CigarOp;
+use crate::augmented_cigar::AugmentedCigarElement;
+use crate::collated::CollatedAugmentedCigarIterator;
+use crate::error::CigarError;
+use crate::record::CigarRecord;
+use crate::variants::ReferenceSequences;
+
+/// An augmented CIGAR element with the bases of its event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequencedElement {
+    /// The element.
+    pub element: AugmentedCigarElement,
+    /// The inserted bases of an insertion or the deleted bases of a deletion, if known.
+    /// Always `None` for other operations.
+    pub sequence: Option<Vec<u8>>,
+}
+
+impl SequencedElement {
+    /// Attach the bases of an element, taking inserted bases from `read` and deleted
+    /// bases from `reference`, the sequence of the element's chromosome.
+    ///
+    /// Either may be `None` when not known, giving an element without a sequence. Fails
+    /// if the bases of the element lie beyond the end of the read or reference given.
+    pub fn new(
+        element: AugmentedCigarElement,
+        read: Option<&[u8]>,
+        reference: Option<&[u8]>,
+    ) -> std::result::Result<Self, CigarError> {
+        let sequence = match (element.op, read, reference) {
+            (CigarOp::Insertion, Some(read), _) => {
+                let start = element.read_position as usize;
+                let end = start + element.length as usize;
+                let bases = read
+                    .get(start..end)
+                    .ok_or(CigarError::SequenceTooShort(end, read.len()))?;
+                Some(bases.to_vec())
+            }
+            (CigarOp::Deletion, _, Some(reference)) => {
+                let (start, end) = (element.reference_position, element.reference_end());
+                let bases = reference
+                    .get(start as usize..end as usize)
+                    .ok_or(CigarError::ReferenceOutOfBounds(start, end))?;
+                Some(bases.to_vec())
+            }
+            _ => None,
+        };
+        Ok(SequencedElement { element, sequence })
+    }
+}
+
+/// A collated event with its bases, and the number of times it was seen.
+pub type SequencedEvent = (SequencedElement, usize);
+
+/// A collated iterator which counts events with different bases apart, created by
+/// [`CollatedAugmentedCigarIterator::sequenced`].
+///
+/// The events at a position, with the same operation and length, follow one another in
+/// order of their bases. A record whose insertions lie beyond its bases gives an error
+/// and is skipped, as does an event whose deleted bases lie beyond the reference.
+pub struct SequencedCollatedIterator<
+    'a,
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+    Ref: ReferenceSequences + ?Sized,
+> {
+    inner: CollatedAugmentedCigarIterator<Source, R, E>,
+    reference: &'a Ref,
+    ready: Vec<SequencedEvent>,
+}
+
+impl<'a, Source, R, E, Ref> SequencedCollatedIterator<'a, Source, R, E, Ref>
+where
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+    Ref: ReferenceSequences + ?Sized,
+{
+    pub(crate) fn new(
+        inner: CollatedAugmentedCigarIterator<Source, R, E>,
+        reference: &'a Ref,
+    ) -> Self {
+        SequencedCollatedIterator {
+            inner,
+            reference,
+            ready: Vec::new(),
+        }
+    }
+
+    /// Split the next collated event by the bases of its occurrences, leaving the events
+    /// in `ready` last first.
+    fn split_next(&mut self) -> Option<std::result::Result<(), CigarError>> {
+        let mut tags = Vec::new();
+        let (elem, _) = self.inner.pop_group(|tag| tags.push(tag))?;
+        let mut sequences: Vec<Option<Vec<u8>>> = if elem.op == CigarOp::Insertion {
+            tags.iter()
+                .map(|&tag| self.inner.take_inserted_bases(tag))
+                .collect()
+        } else {
+            let reference = self.reference.sequence(elem.chrom_id);
+            match SequencedElement::new(elem.clone(), None, reference) {
+                Ok(sequenced) => vec![sequenced.sequence; tags.len()],
+                Err(e) => return Some(Err(e)),
+            }
+        };
+        sequences.sort_unstable();
+        for sequence in sequences {
+            match self.ready.last_mut() {
+                Some((last, count)) if last.sequence == sequence => *count += 1,
+                _ => self.ready.push((
+                    SequencedElement {
+                        element: elem.clone(),
+                        sequence,
+                    },
+                    1,
+                )),
+            }
+        }
+        self.ready.reverse();
+        Some(Ok(()))
+    }
+}
+
+impl<Source, R, E, Ref> Iterator for SequencedCollatedIterator<'_, Source, R, E, Ref>
+where
+    Source: Iterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+    Ref: ReferenceSequences + ?Sized,
+{
+    type Item = std::result::Result<SequencedEvent, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ready.is_empty() {
+            if let Some(e) = self.inner.fill() {
+                return Some(Err(e));
+            }
+            if let Err(e) = self.split_next()? {
+                return Some(Err(e));
+            }
+        }
+        self.ready.pop().map(Ok)
+    }
+}
+
+impl<Source, R, E, Ref> FusedIterator for SequencedCollatedIterator<'_, Source, R, E, Ref>
+where
+    Source: FusedIterator<Item = std::result::Result<R, E>>,
+    R: CigarRecord,
+    E: std::error::Error + Send + Sync + 'static,
+    Ref: ReferenceSequences + ?Sized,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sam::SamRecord;
+
+    fn records(lines: &[&str]) -> impl Iterator<Item = std::io::Result<SamRecord>> + use<> {
+        lines
+            .iter()
+            .map(|line| Ok(line.parse().unwrap()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    fn indels(events: Vec<SequencedEvent>) -> Vec<(u64, CigarOp, Option<String>, usize)> {
+        events
+            .into_iter()
+            .filter(|(e, _)| matches!(e.element.op, CigarOp::Insertion | CigarOp::Deletion))
+            .map(|(e, count)| {
+                let bases = e.sequence.map(|s| String::from_utf8(s).unwrap());
+                (e.element.reference_position, e.element.op, bases, count)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_collate_by_sequence() {
+        let reference = vec![b"ACGTACGTAC".to_vec()];
+        let source = records(&[
+            "r1\t0\tchr1\t1\t60\t2M2I1M2D2M\t*\t0\t0\tACTTGAC\t*",
+            "r2\t0\tchr1\t1\t60\t2M2I1M2D2M\t*\t0\t0\tACGAGAC\t*",
+            "r3\t0\tchr1\t2\t60\t1M2I1M2D2M\t*\t0\t0\tCTTGAC\t*",
+            "r4\t0\tchr1\t3\t60\t1M2D2M\t*\t0\t0\tGAC\t*",
+            "r5\t0\tchr1\t3\t60\t3M\t*\t0\t0\t*\t*",
+        ]);
+        let events: Vec<_> = CollatedAugmentedCigarIterator::new(source)
+            .sequenced(&reference)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            indels(events),
+            vec![
+                (2, CigarOp::Insertion, Some("GA".to_string()), 1),
+                (2, CigarOp::Insertion, Some("TT".to_string()), 2),
+                (3, CigarOp::Deletion, Some("TA".to_string()), 4),
+            ]
+        );
+
+        // Without a reference, deletions have no bases but are still counted.
+        let source = records(&["r1\t0\tchr1\t1\t60\t1M1D1M\t*\t0\t0\t*\t*"]);
+        let events: Vec<_> = CollatedAugmentedCigarIterator::new(source)
+            .sequenced(&Vec::<Vec<u8>>::new())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(indels(events), vec![(1, CigarOp::Deletion, None, 1)]);
+    }
+
+    #[test]
+    fn test_sequenced_options() {
+        // Sequenced collation keeps the options of the collator, spilling included.
+        let reference = vec![b"ACGTACGTAC".to_vec()];
+        let lines = [
+            "r1\t0\tchr1\t1\t60\t2M2I1M2D2M\t*\t0\t0\tACTTGAC\t*",
+            "r2\t0\tchr1\t1\t60\t2M2I1M2D2M\t*\t0\t0\tACGAGAC\t*",
+            "r3\t0\tchr1\t2\t60\t1M2I1M2D2M\t*\t0\t0\tCTTGAC\t*",
+        ];
+        let events: Vec<_> = CollatedAugmentedCigarIterator::new(records(&lines))
+            .restrict(0, 3, 10)
+            .spill_to_disk(1)
+            .sequenced(&reference)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            indels(events),
+            vec![(3, CigarOp::Deletion, Some("TA".to_string()), 3)]
+        );
+        let events: Vec<_> = CollatedAugmentedCigarIterator::new(records(&lines))
+            .spill_to_disk(1)
+            .sequenced(&reference)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            indels(events),
+            vec![
+                (2, CigarOp::Insertion, Some("GA".to_string()), 1),
+                (2, CigarOp::Insertion, Some("TT".to_string()), 2),
+                (3, CigarOp::Deletion, Some("TA".to_string()), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sequenced_errors() {
+        let reference = vec![b"ACGT".to_vec()];
+        let source = records(&[
+            "r1\t0\tchr1\t1\t60\t1M2I1M\t*\t0\t0\tAC\t*",
+            "r2\t0\tchr1\t3\t60\t1M4D\t*\t0\t0\tG\t*",
+            "r3\t0\tchr1\t1\t60\t2M\t*\t0\t0\tAC\t*",
+        ]);
+        let results: Vec<_> = CollatedAugmentedCigarIterator::new(source)
+            .checked()
+            .sequenced(&reference)
+            .collect();
+        assert!(matches!(
+            results[0].as_ref().unwrap_err().root(),
+            CigarError::SequenceTooShort(3, 2)
+        ));
+        assert!(matches!(results[1], Err(CigarError::UnsortedInput { .. })));
+        assert_eq!(results[2].as_ref().unwrap().0.element.op, CigarOp::Match);
+        // Deleted bases are only looked up once the deletion is collated.
+        assert!(matches!(
+            results[3],
+            Err(CigarError::ReferenceOutOfBounds(3, 7))
+        ));
+        assert_eq!(results.len(), 4);
+    }
+}
@@ -0,0 +1,416 @@
+//! Simulation of random alignments.
+//!
+//! A [`Simulator`] generates random but valid CIGARs, and optionally reads aligned to a
+//! reference, from a configurable [`ErrorModel`]. The simulated alignments are useful for
+//! benchmarking the collator and testing downstream tools at scale.
+//!
+//! The simulator uses a small seeded pseudo-random generator, so the same seed and model
+//! always produce the same alignments. It is not suitable for anything needing
+//! statistical quality randomness.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::simulate::{ErrorModel, Simulator};
+//!
+//! let model = ErrorModel {
+//!     substitution_rate: 0.05,
+//!     eqx: true,
+//!     ..Default::default()
+//! };
+//! let mut simulator = Simulator::new(model, 42);
+//! let reference = simulator.reference(1000);
+//! let alignment = simulator.alignment(&reference, 100).unwrap();
+//!
+//! assert_eq!(alignment.read.len(), 100);
+//! let query_length: u32 = alignment
+//!     .cigar
+//!     .iter()
+//!     .filter(|e| e.op.consumes_query())
+//!     .map(|e| e.length)
+//!     .sum();
+//! assert_eq!(query_length, 100);
+//! ```
+
+use crate::{Cigar, CigarElement, CigarOp};
+
+const BASES: &[u8; 4] = b"ACGT";
+
+/// The rates of the events a [`Simulator`] generates.
+///
+/// Rates are probabilities between 0 and 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorModel {
+    /// The probability of an aligned base being a mismatch.
+    pub substitution_rate: f64,
+    /// The probability of an insertion following an aligned base.
+    pub insertion_rate: f64,
+    /// The probability of a deletion following an aligned base.
+    pub deletion_rate: f64,
+    /// The probability of extending an insertion or deletion by another base, so indel
+    /// lengths are geometrically distributed.
+    pub indel_extension: f64,
+    /// The probability of each end of a read being soft clipped.
+    pub soft_clip_probability: f64,
+    /// The probability of each end of a read being hard clipped.
+    pub hard_clip_probability: f64,
+    /// The longest clip generated.
+    pub max_clip_length: usize,
+    /// The probability of an intron following an aligned base.
+    pub intron_rate: f64,
+    /// The shortest intron generated.
+    pub min_intron_length: usize,
+    /// The longest intron generated.
+    pub max_intron_length: usize,
+    /// Whether aligned bases are reported as sequence matches and mismatches (`=`/`X`)
+    /// rather than alignment matches (`M`).
+    pub eqx: bool,
+}
+
+impl Default for ErrorModel {
+    fn default() -> Self {
+        ErrorModel {
+            substitution_rate: 0.01,
+            insertion_rate: 0.001,
+            deletion_rate: 0.001,
+            indel_extension: 0.3,
+            soft_clip_probability: 0.05,
+            hard_clip_probability: 0.0,
+            max_clip_length: 20,
+            intron_rate: 0.0,
+            min_intron_length: 50,
+            max_intron_length: 5000,
+            eqx: false,
+        }
+    }
+}
+
+/// A simulated read and its alignment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulatedAlignment {
+    /// The reference position of the first aligned base.
+    pub position: usize,
+    /// The CIGAR of the alignment.
+    pub cigar: Cigar,
+    /// The read sequence, including soft clipped bases.
+    pub read: Vec<u8>,
+}
+
+/// A generator of random CIGARs and alignments.
+#[derive(Debug, Clone)]
+pub struct Simulator {
+    model: ErrorModel,
+    state: u64,
+}
+
+impl Simulator {
+    /// Create a new simulator for an error model, seeding its random generator.
+    pub fn new(model: ErrorModel, seed: u64) -> Self {
+        Simulator { model, state: seed }
+    }
+
+    /// The error model of the simulator.
+    pub fn model(&self) -> &ErrorModel {
+        &self.model
+    }
+
+    /// The next value of the generator (SplitMix64).
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// True with probability `p`.
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+
+    /// A value between `low` and `high` inclusive.
+    fn range(&mut self, low: usize, high: usize) -> usize {
+        if high <= low {
+            return low;
+        }
+        low + (self.next_u64() % (high - low + 1) as u64) as usize
+    }
+
+    fn base(&mut self) -> u8 {
+        BASES[self.range(0, 3)]
+    }
+
+    /// A base other than `base`.
+    fn substitute(&mut self, base: u8) -> u8 {
+        loop {
+            let other = self.base();
+            if !other.eq_ignore_ascii_case(&base) {
+                return other;
+            }
+        }
+    }
+
+    fn indel_length(&mut self) -> usize {
+        let mut length = 1;
+        while self.chance(self.model.indel_extension) {
+            length += 1;
+        }
+        length
+    }
+
+    /// The length of a clip made with probability `p`, of at most `limit` bases, or zero.
+    fn clip_length(&mut self, p: f64, limit: usize) -> usize {
+        if limit > 0 && self.chance(p) {
+            self.range(1, self.model.max_clip_length.min(limit))
+        } else {
+            0
+        }
+    }
+
+    /// Generate a random reference sequence.
+    pub fn reference(&mut self, length: usize) -> Vec<u8> {
+        (0..length).map(|_| self.base()).collect()
+    }
+
+    /// Generate the events of an alignment, with aligned bases as `=` or `X`.
+    fn events(&mut self, read_length: usize) -> Vec<CigarElement> {
+        let mut elements = Vec::new();
+        if read_length == 0 {
+            return elements;
+        }
+        let hard = self.model.hard_clip_probability;
+        let soft = self.model.soft_clip_probability;
+        let leading_hard = self.clip_length(hard, usize::MAX);
+        let trailing_hard = self.clip_length(hard, usize::MAX);
+        let leading_soft = self.clip_length(soft, read_length - 1);
+        let trailing_soft = self.clip_length(soft, read_length - 1 - leading_soft);
+
+        push(&mut elements, CigarOp::HardClip, leading_hard);
+        push(&mut elements, CigarOp::SoftClip, leading_soft);
+        let mut remaining = read_length - leading_soft - trailing_soft;
+        while remaining > 0 {
+            let op = if self.chance(self.model.substitution_rate) {
+                CigarOp::Diff
+            } else {
+                CigarOp::Equal
+            };
+            push(&mut elements, op, 1);
+            remaining -= 1;
+            if remaining == 0 {
+                break;
+            }
+            if self.chance(self.model.intron_rate) {
+                let length = self.range(
+                    self.model.min_intron_length.max(1),
+                    self.model.max_intron_length,
+                );
+                push(&mut elements, CigarOp::Skip, length);
+            }
+            if self.chance(self.model.deletion_rate) {
+                let length = self.indel_length();
+                push(&mut elements, CigarOp::Deletion, length);
+            }
+            if remaining > 1 && self.chance(self.model.insertion_rate) {
+                let length = self.indel_length().min(remaining - 1);
+                push(&mut elements, CigarOp::Insertion, length);
+                remaining -= length;
+            }
+        }
+        push(&mut elements, CigarOp::SoftClip, trailing_soft);
+        push(&mut elements, CigarOp::HardClip, trailing_hard);
+        elements
+    }
+
+    /// Aligned bases as the model reports them.
+    fn report(&self, events: &[CigarElement]) -> Cigar {
+        if self.model.eqx {
+            return Cigar::from(events.to_vec());
+        }
+        let mut elements = Vec::new();
+        for elem in events {
+            let op = match elem.op {
+                CigarOp::Equal | CigarOp::Diff => CigarOp::Match,
+                op => op,
+            };
+            push(&mut elements, op, elem.length as usize);
+        }
+        Cigar::from(elements)
+    }
+
+    /// Generate a random valid CIGAR for a read of `read_length` bases.
+    ///
+    /// The elements consuming the query add up to `read_length`; any hard clips are in
+    /// addition to it.
+    pub fn cigar(&mut self, read_length: usize) -> Cigar {
+        let events = self.events(read_length);
+        self.report(&events)
+    }
+
+    /// Generate a random read of `read_length` bases aligned to `reference`.
+    ///
+    /// Returns `None` if the simulated alignment spans more of the reference than there is.
+    pub fn alignment(
+        &mut self,
+        reference: &[u8],
+        read_length: usize,
+    ) -> Option<SimulatedAlignment> {
+        let events = self.events(read_length);
+        let span: usize = events
+            .iter()
+            .filter(|e| e.op.consumes_reference())
+            .map(|e| e.length as usize)
+            .sum();
+        if span > reference.len() {
+            return None;
+        }
+        let position = self.range(0, reference.len() - span);
+        let mut read = Vec::with_capacity(read_length);
+        let mut reference_position = position;
+        for elem in events.iter() {
+            let length = elem.length as usize;
+            match elem.op {
+                CigarOp::Equal => {
+                    read.extend_from_slice(
+                        &reference[reference_position..reference_position + length],
+                    );
+                    reference_position += length;
+                }
+                CigarOp::Diff => {
+                    for i in 0..length {
+                        let base = self.substitute(reference[reference_position + i]);
+                        read.push(base);
+                    }
+                    reference_position += length;
+                }
+                CigarOp::Insertion | CigarOp::SoftClip => {
+                    for _ in 0..length {
+                        let base = self.base();
+                        read.push(base);
+                    }
+                }
+                CigarOp::Deletion | CigarOp::Skip => reference_position += length,
+                _ => {}
+            }
+        }
+        Some(SimulatedAlignment {
+            position,
+            cigar: self.report(&events),
+            read,
+        })
+    }
+}
+
+/// Append `length` of `op`, merging it with the previous element; zero lengths are dropped.
+fn push(elements: &mut Vec<CigarElement>, op: CigarOp, length: usize) {
+    if length == 0 {
+        return;
+    }
+    match elements.last_mut() {
+        Some(last) if last.op == op => last.length += length as u32,
+        _ => elements.push(CigarElement::new(length as u32, op)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate::{ValidationOptions, validate};
+
+    fn noisy() -> ErrorModel {
+        ErrorModel {
+            substitution_rate: 0.1,
+            insertion_rate: 0.05,
+            deletion_rate: 0.05,
+            soft_clip_probability: 0.5,
+            hard_clip_probability: 0.5,
+            intron_rate: 0.01,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cigars_are_valid() {
+        let mut simulator = Simulator::new(noisy(), 7);
+        for read_length in 0..200 {
+            let cigar = simulator.cigar(read_length);
+            assert!(
+                validate(
+                    &cigar,
+                    &ValidationOptions {
+                        forbid_adjacent_identical: true,
+                        read_length: Some(read_length),
+                        ..Default::default()
+                    }
+                )
+                .is_empty()
+            );
+            assert!(cigar.iter().all(|e| e.op != CigarOp::Equal));
+        }
+    }
+
+    #[test]
+    fn test_error_free_alignment() {
+        let model = ErrorModel {
+            substitution_rate: 0.0,
+            insertion_rate: 0.0,
+            deletion_rate: 0.0,
+            soft_clip_probability: 0.0,
+            ..Default::default()
+        };
+        let mut simulator = Simulator::new(model, 1);
+        let reference = simulator.reference(50);
+        let alignment = simulator.alignment(&reference, 20).unwrap();
+        assert_eq!(alignment.cigar.to_string(), "20M");
+        assert_eq!(
+            alignment.read,
+            &reference[alignment.position..alignment.position + 20]
+        );
+        assert!(simulator.alignment(&reference, 51).is_none());
+    }
+
+    #[test]
+    fn test_alignment_matches_cigar() {
+        let model = ErrorModel {
+            eqx: true,
+            ..noisy()
+        };
+        let mut simulator = Simulator::new(model, 3);
+        let reference = simulator.reference(100_000);
+        for _ in 0..50 {
+            let alignment = simulator.alignment(&reference, 150).unwrap();
+            assert_eq!(alignment.read.len(), 150);
+            let mut read_position = 0;
+            let mut position = alignment.position;
+            for elem in alignment.cigar.iter() {
+                let length = elem.length as usize;
+                for i in 0..length {
+                    match elem.op {
+                        CigarOp::Equal => {
+                            assert_eq!(alignment.read[read_position + i], reference[position + i])
+                        }
+                        CigarOp::Diff => {
+                            assert_ne!(alignment.read[read_position + i], reference[position + i])
+                        }
+                        _ => {}
+                    }
+                }
+                if elem.op.consumes_query() {
+                    read_position += length;
+                }
+                if elem.op.consumes_reference() {
+                    position += length;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_seed_determines_output() {
+        let mut a = Simulator::new(noisy(), 11);
+        let mut b = Simulator::new(noisy(), 11);
+        let mut c = Simulator::new(noisy(), 12);
+        let cigars: Vec<_> = (0..10).map(|_| a.cigar(100)).collect();
+        assert_eq!(cigars, (0..10).map(|_| b.cigar(100)).collect::<Vec<_>>());
+        assert_ne!(cigars, (0..10).map(|_| c.cigar(100)).collect::<Vec<_>>());
+    }
+}
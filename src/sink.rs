@@ -0,0 +1,293 @@
+//! Pluggable sinks for collated results.
+//!
+//! A [`CollatedSink`] receives each collated event together with its count and the depth
+//! of coverage at its position. [`collate_into`] drives a stream of collated events into
+//! a sink, taking the depths from its [summaries](crate::summary), so that a collation
+//! job is just a source and a sink, and the sink can be swapped for a [`CollatedTable`]
+//! in tests.
+//!
+//! [`TsvSink`] writes tab separated rows, [`BedSink`] writes each event as a BED
+//! interval scored by its frequency, and [`CollatedTable`] keeps the rows in memory.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::CigarOp;
+//! use cigar_utils::collated::CollatedAugmentedCigarIterator;
+//! use cigar_utils::sink::{CollatedTable, collate_into};
+//!
+//! let cigars = vec![
+//!     std::io::Result::Ok(("2M1D2M".to_string(), 1, 100)),
+//!     std::io::Result::Ok(("5M".to_string(), 1, 100)),
+//! ];
+//! let mut table = CollatedTable::new();
+//! collate_into(CollatedAugmentedCigarIterator::new(cigars.into_iter()), &mut table).unwrap();
+//! let deletion = table.rows.iter().find(|r| r.element.op == CigarOp::Deletion).unwrap();
+//! assert_eq!((deletion.element.reference_position, deletion.count, deletion.depth), (102, 1, 2));
+//! ```
+
+use std::io::Write;
+
+use crate::CigarOp;
+use crate::augmented_cigar::AugmentedCigarElement;
+use crate::chrom_map::ChromMap;
+use crate::collated::CollatedEvent;
+use crate::error::CigarError;
+use crate::summary::PositionSummaryIterator;
+use crate::writers::{BedWriter, ElementFormat, ElementWriter};
+
+/// A destination for collated events.
+pub trait CollatedSink {
+    /// Write a collated event, seen in `count` records, at a position covered by `depth`
+    /// records.
+    fn write_event(
+        &mut self,
+        element: &AugmentedCigarElement,
+        count: usize,
+        depth: usize,
+    ) -> std::result::Result<(), CigarError>;
+
+    /// Called once after the last event. The default does nothing.
+    fn flush(&mut self) -> std::result::Result<(), CigarError> {
+        Ok(())
+    }
+}
+
+impl<S: CollatedSink + ?Sized> CollatedSink for &mut S {
+    fn write_event(
+        &mut self,
+        element: &AugmentedCigarElement,
+        count: usize,
+        depth: usize,
+    ) -> std::result::Result<(), CigarError> {
+        (**self).write_event(element, count, depth)
+    }
+
+    fn flush(&mut self) -> std::result::Result<(), CigarError> {
+        (**self).flush()
+    }
+}
+
+/// Drive a stream of collated events into a sink, returning the number of events written.
+///
+/// The stream must be the unfiltered output of a collation, since the depth is computed
+/// from its match and deletion events, as for a
+/// [`PositionSummaryIterator`]. The events at a position are passed on once the depth
+/// there is known. The sink is flushed after the last event, and the first error from
+/// the stream or the sink ends the job.
+pub fn collate_into<I, S>(source: I, mut sink: S) -> std::result::Result<usize, CigarError>
+where
+    I: IntoIterator<Item = std::result::Result<CollatedEvent, CigarError>>,
+    S: CollatedSink,
+{
+    let mut positions = PositionSummaryIterator::new(source.into_iter());
+    let mut written = 0;
+    while let Some(position) = positions.next_position() {
+        let (depth, events) = position?;
+        for (elem, count) in events {
+            sink.write_event(elem, *count, depth)?;
+            written += 1;
+        }
+    }
+    sink.flush()?;
+    Ok(written)
+}
+
+/// A row of a [`CollatedTable`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollatedRow {
+    /// The collated element.
+    pub element: AugmentedCigarElement,
+    /// The number of records with the event.
+    pub count: usize,
+    /// The number of records covering the position with a match or deletion.
+    pub depth: usize,
+}
+
+/// A sink keeping the collated events in memory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CollatedTable {
+    /// The rows written, in order.
+    pub rows: Vec<CollatedRow>,
+}
+
+impl CollatedTable {
+    /// Create an empty table.
+    pub fn new() -> Self {
+        CollatedTable::default()
+    }
+}
+
+impl CollatedSink for CollatedTable {
+    fn write_event(
+        &mut self,
+        element: &AugmentedCigarElement,
+        count: usize,
+        depth: usize,
+    ) -> std::result::Result<(), CigarError> {
+        self.rows.push(CollatedRow {
+            element: element.clone(),
+            count,
+            depth,
+        });
+        Ok(())
+    }
+}
+
+/// A sink writing collated events as tab separated values, with a collated
+/// [`ElementWriter`] with [depths](ElementWriter::with_depths).
+///
+/// A header line is written before the first row, with the columns `chrom`, `position`,
+/// `op`, `length`, `read_position`, `count` and `depth`.
+pub struct TsvSink<W: Write> {
+    writer: ElementWriter<W>,
+}
+
+impl<W: Write> TsvSink<W> {
+    /// Create a new TSV sink.
+    pub fn new(out: W) -> Self {
+        TsvSink {
+            writer: ElementWriter::collated(out, ElementFormat::Tsv).with_depths(),
+        }
+    }
+
+    /// Write chromosome names in the `chrom` column, indexed by chromosome ID, in place
+    /// of the IDs.
+    pub fn with_chromosome_names(self, names: impl Into<ChromMap>) -> Self {
+        TsvSink {
+            writer: self.writer.with_chromosome_names(names),
+        }
+    }
+
+    /// Write the header, if no rows were written, and return the underlying writer.
+    pub fn finish(self) -> std::result::Result<W, CigarError> {
+        Ok(self.writer.finish()?)
+    }
+}
+
+impl<W: Write> CollatedSink for TsvSink<W> {
+    fn write_event(
+        &mut self,
+        element: &AugmentedCigarElement,
+        count: usize,
+        depth: usize,
+    ) -> std::result::Result<(), CigarError> {
+        Ok(self.writer.write_with_depth(element, count, depth)?)
+    }
+}
+
+/// A sink writing collated events as BED intervals.
+///
+/// Each event becomes the reference interval it spans, which is empty for insertions and
+/// clips, named by its length and operation (such as `2D`) and scored by the fraction of
+/// covering records with the event, scaled to 0 to 1000. Events at positions without
+/// coverage are scored 0.
+pub struct BedSink<W: Write> {
+    writer: BedWriter<W>,
+    ops: Option<Vec<CigarOp>>,
+}
+
+impl<W: Write> BedSink<W> {
+    /// Create a new BED sink, with the names of the chromosomes indexed by ID.
+    pub fn new(out: W, names: impl Into<ChromMap>) -> Self {
+        BedSink {
+            writer: BedWriter::new(out, names),
+            ops: None,
+        }
+    }
+
+    /// Only write events with the given operations, such as the indels.
+    pub fn with_ops(mut self, ops: &[CigarOp]) -> Self {
+        self.ops = Some(ops.to_vec());
+        self
+    }
+
+    /// Return the underlying writer.
+    pub fn finish(self) -> std::result::Result<W, CigarError> {
+        Ok(self.writer.finish()?)
+    }
+}
+
+impl<W: Write> CollatedSink for BedSink<W> {
+    fn write_event(
+        &mut self,
+        element: &AugmentedCigarElement,
+        count: usize,
+        depth: usize,
+    ) -> std::result::Result<(), CigarError> {
+        if let Some(ops) = &self.ops
+            && !ops.contains(&element.op)
+        {
+            return Ok(());
+        }
+        let score = if depth == 0 {
+            0
+        } else {
+            ((count * 1000) as f64 / depth as f64).round().min(1000.0) as u32
+        };
+        self.writer.write(
+            element.chrom_id,
+            element.reference_position,
+            element.reference_end(),
+            &format!("{}{}", element.length, element.op),
+            score,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collated::collated;
+
+    #[test]
+    fn test_collate_into_table() {
+        let mut table = CollatedTable::new();
+        let written = collate_into(
+            collated(vec![("4M", 0, 100), ("2M", 0, 102), ("2M1I2M", 0, 102)]),
+            &mut table,
+        )
+        .unwrap();
+        assert_eq!(written, table.rows.len());
+        let rows: Vec<_> = table
+            .rows
+            .iter()
+            .map(|r| (r.element.reference_position, r.element.op, r.count, r.depth))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (100, CigarOp::Match, 1, 1),
+                (102, CigarOp::Match, 2, 3),
+                (104, CigarOp::Match, 1, 1),
+                (104, CigarOp::Insertion, 1, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_text_sinks() {
+        let names = vec!["chr1".to_string()];
+        let mut tsv = TsvSink::new(Vec::new()).with_chromosome_names(names.clone());
+        collate_into(collated(vec![("2M1D2M", 0, 10), ("5M", 0, 10)]), &mut tsv).unwrap();
+        let text = String::from_utf8(tsv.finish().unwrap()).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            "chrom\tposition\top\tlength\tread_position\tcount\tdepth"
+        );
+        assert!(lines.contains(&"chr1\t12\tD\t1\t2\t1\t2"));
+
+        let mut bed = BedSink::new(Vec::new(), names).with_ops(&[CigarOp::Deletion]);
+        collate_into(collated(vec![("2M1D2M", 0, 10), ("5M", 0, 10)]), &mut bed).unwrap();
+        let text = String::from_utf8(bed.finish().unwrap()).unwrap();
+        assert_eq!(text, "chr1\t12\t13\t1D\t500\n");
+
+        let mut bed = BedSink::new(Vec::new(), ChromMap::new());
+        assert!(matches!(
+            collate_into(collated(vec![("2M", 0, 10)]), &mut bed),
+            Err(CigarError::External(_))
+        ));
+    }
+}
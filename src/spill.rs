@@ -0,0 +1,142 @@
+//! Sorted runs of collation events spilled to disk.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::CigarOp;
+use crate::augmented_cigar::AugmentedCigarElement;
+
+static RUN_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+const RECORD_SIZE: usize = 4 + 1 + 1 + 4 + 4 + 8 + 8;
+
+/// A sorted run of (element, sample) pairs in a temporary file, read back in order.
+pub(crate) struct SpillRun {
+    path: PathBuf,
+    reader: BufReader<File>,
+    head: Option<(AugmentedCigarElement, usize)>,
+    remaining: usize,
+}
+
+impl SpillRun {
+    /// Write a sorted run of events to a new file in `dir`.
+    pub(crate) fn create(dir: &Path, events: &[(AugmentedCigarElement, usize)]) -> Result<Self> {
+        let path = dir.join(format!(
+            "cigar-utils-spill-{}-{}.bin",
+            std::process::id(),
+            RUN_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for (elem, sample) in events {
+            writer.write_all(&elem.length.to_le_bytes())?;
+            writer.write_all(&[u8::from(elem.op), elem.leading_clip as u8])?;
+            writer.write_all(&elem.read_position.to_le_bytes())?;
+            writer.write_all(&elem.chrom_id.to_le_bytes())?;
+            writer.write_all(&elem.reference_position.to_le_bytes())?;
+            writer.write_all(&(*sample as u64).to_le_bytes())?;
+        }
+        writer.flush()?;
+        drop(writer);
+        let mut run = SpillRun {
+            reader: BufReader::new(File::open(&path)?),
+            path,
+            head: None,
+            remaining: events.len(),
+        };
+        run.advance()?;
+        Ok(run)
+    }
+
+    /// The next event of the run, if any.
+    pub(crate) fn head(&self) -> Option<&(AugmentedCigarElement, usize)> {
+        self.head.as_ref()
+    }
+
+    /// The number of events of the run not yet taken.
+    pub(crate) fn len(&self) -> usize {
+        self.remaining
+    }
+
+    /// Take the next event of the run and read the one after it.
+    pub(crate) fn take(&mut self) -> Result<Option<(AugmentedCigarElement, usize)>> {
+        let head = self.head.take();
+        if head.is_some() {
+            self.remaining -= 1;
+        }
+        self.advance()?;
+        Ok(head)
+    }
+
+    fn advance(&mut self) -> Result<()> {
+        let mut buf = [0u8; RECORD_SIZE];
+        match self.reader.read_exact(&mut buf) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                self.head = None;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(buf[i..i + 8].try_into().unwrap());
+        let op = CigarOp::try_from(buf[4]).map_err(|code| {
+            std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid operation code {} in spill file", code),
+            )
+        })?;
+        self.head = Some((
+            AugmentedCigarElement {
+                length: u32_at(0),
+                op,
+                read_position: u32_at(6),
+                chrom_id: u32_at(10),
+                reference_position: u64_at(14),
+                leading_clip: buf[5] != 0,
+            },
+            u64_at(22) as usize,
+        ));
+        Ok(())
+    }
+}
+
+impl Drop for SpillRun {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spill_run_round_trip() {
+        let events: Vec<_> = (0..3)
+            .map(|i| {
+                (
+                    AugmentedCigarElement {
+                        length: 10 + i,
+                        op: CigarOp::Skip,
+                        read_position: i,
+                        chrom_id: 2,
+                        reference_position: 5_000_000_000 + i as u64,
+                        leading_clip: i == 1,
+                    },
+                    i as usize,
+                )
+            })
+            .collect();
+        let mut run = SpillRun::create(&std::env::temp_dir(), &events).unwrap();
+        let path = run.path.clone();
+        let mut read = Vec::new();
+        while let Some(event) = run.take().unwrap() {
+            read.push(event);
+        }
+        assert_eq!(read, events);
+        drop(run);
+        assert!(!path.exists());
+    }
+}
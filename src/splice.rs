@@ -0,0 +1,460 @@
+//! Spliced alignments.
+//!
+//! RNA-seq alignments use the skip (`N`) operation to represent introns. This module
+//! splits such alignments into their exon blocks, with reference and read intervals
+//! suitable for building BED12 `blockSizes`/`blockStarts` fields, and extracts the
+//! splice junctions between them, either from a single read or with the read support
+//! counted by [collation](crate::collated). Junctions can be annotated with the
+//! dinucleotide motif of the intron, and the strand it implies, as STAR reports them.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::splice::{ExonBlockIterator, bed12_blocks};
+//!
+//! let blocks: Vec<_> = ExonBlockIterator::new("2S10M100N5M1D5M", 1000)
+//!     .collect::<Result<_, _>>()
+//!     .unwrap();
+//! assert_eq!(blocks.len(), 2);
+//! assert_eq!((blocks[0].reference_start, blocks[0].reference_end), (1000, 1010));
+//! assert_eq!((blocks[1].reference_start, blocks[1].reference_end), (1110, 1121));
+//!
+//! let (sizes, starts) = bed12_blocks(&blocks);
+//! assert_eq!(sizes, vec![10, 11]);
+//! assert_eq!(starts, vec![0, 110]);
+//! ```
+
+use std::fmt::Display;
+
+use crate::collated::CollatedEvent;
+use crate::error::CigarError;
+use crate::variants::ReferenceSequences;
+use crate::{CigarElement, CigarIterator, CigarOp, Strand};
+
+/// A contiguous block of an alignment between skip (`N`) operations.
+///
+/// Intervals are half open. Read positions are positions in SEQ, so they include
+/// soft clipped bases.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExonBlock {
+    /// The reference position of the start of the block.
+    pub reference_start: u64,
+    /// The reference position of the end of the block.
+    pub reference_end: u64,
+    /// The read position of the start of the block.
+    pub read_start: u32,
+    /// The read position of the end of the block.
+    pub read_end: u32,
+}
+
+/// An iterator over the exon blocks of a CIGAR string.
+pub struct ExonBlockIterator<'a> {
+    inner: CigarIterator<'a>,
+    read_position: u32,
+    reference_position: u64,
+    current: Option<ExonBlock>,
+}
+
+impl<'a> ExonBlockIterator<'a> {
+    /// Create a new exon block iterator for an alignment starting at the given reference position.
+    pub fn new(cigar: &'a str, reference_position: u64) -> Self {
+        ExonBlockIterator {
+            inner: CigarIterator::new(cigar),
+            read_position: 0,
+            reference_position,
+            current: None,
+        }
+    }
+}
+
+impl<'a> Iterator for ExonBlockIterator<'a> {
+    type Item = std::result::Result<ExonBlock, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for elem in self.inner.by_ref() {
+            let CigarElement { length, op } = match elem {
+                Ok(elem) => elem,
+                Err(e) => return Some(Err(e)),
+            };
+            match op {
+                CigarOp::Skip => {
+                    self.reference_position += length as u64;
+                    if let Some(block) = self.current.take() {
+                        return Some(Ok(block));
+                    }
+                }
+                CigarOp::SoftClip => {
+                    self.read_position += length;
+                }
+                CigarOp::HardClip | CigarOp::Padding => {}
+                _ => {
+                    let block = self.current.get_or_insert(ExonBlock {
+                        reference_start: self.reference_position,
+                        reference_end: self.reference_position,
+                        read_start: self.read_position,
+                        read_end: self.read_position,
+                    });
+                    if op.consumes_reference() {
+                        self.reference_position += length as u64;
+                    }
+                    if op.consumes_query() {
+                        self.read_position += length;
+                    }
+                    block.reference_end = self.reference_position;
+                    block.read_end = self.read_position;
+                }
+            }
+        }
+        self.current.take().map(Ok)
+    }
+}
+
+/// Compute BED12 `blockSizes` and `blockStarts` from a sequence of exon blocks.
+///
+/// Block starts are relative to the start of the first block.
+pub fn bed12_blocks(blocks: &[ExonBlock]) -> (Vec<u64>, Vec<u64>) {
+    let origin = blocks.first().map(|b| b.reference_start).unwrap_or(0);
+    let sizes = blocks
+        .iter()
+        .map(|b| b.reference_end - b.reference_start)
+        .collect();
+    let starts = blocks.iter().map(|b| b.reference_start - origin).collect();
+    (sizes, starts)
+}
+
+/// A splice junction: an intron between two exon blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpliceJunction {
+    /// The chromosome ID of the junction.
+    pub chrom_id: u32,
+    /// The reference position of the first base of the intron, following the donor
+    /// exon on the forward strand.
+    pub start: u64,
+    /// The reference position following the last base of the intron, the first base of
+    /// the acceptor exon on the forward strand.
+    pub end: u64,
+    /// The number of reads spanning the junction.
+    pub support: usize,
+}
+
+impl SpliceJunction {
+    /// The length of the intron.
+    pub fn length(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// The dinucleotide motif of the intron, from the chromosome's reference sequence.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cigar_utils::Strand;
+    /// use cigar_utils::splice::{IntronMotif, SpliceJunction};
+    ///
+    /// let reference = vec![b"AAAGTCCCCCAGAA".to_vec()];
+    /// let junction = SpliceJunction { chrom_id: 0, start: 3, end: 12, support: 1 };
+    /// let motif = junction.motif(&reference).unwrap();
+    /// assert_eq!(motif, IntronMotif::GtAg);
+    /// assert_eq!((motif.star_code(), motif.strand()), (1, Some(Strand::Forward)));
+    /// assert_eq!(motif.to_string(), "GT/AG");
+    /// ```
+    pub fn motif<R: ReferenceSequences + ?Sized>(
+        &self,
+        reference: &R,
+    ) -> std::result::Result<IntronMotif, CigarError> {
+        let sequence = reference
+            .sequence(self.chrom_id)
+            .ok_or(CigarError::UnknownChromosome(self.chrom_id))?;
+        IntronMotif::classify(sequence, self.start, self.end)
+    }
+}
+
+/// The dinucleotide motif at the ends of an intron, as classified by STAR.
+///
+/// Motifs are named by their forward strand sequence; the reverse complement of a
+/// canonical motif implies the intron is on the reverse strand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IntronMotif {
+    /// Any other motif.
+    NonCanonical,
+    /// GT-AG, canonical on the forward strand.
+    GtAg,
+    /// CT-AC, canonical on the reverse strand.
+    CtAc,
+    /// GC-AG, semi-canonical on the forward strand.
+    GcAg,
+    /// CT-GC, semi-canonical on the reverse strand.
+    CtGc,
+    /// AT-AC, the minor spliceosome motif on the forward strand.
+    AtAc,
+    /// GT-AT, the minor spliceosome motif on the reverse strand.
+    GtAt,
+}
+
+impl IntronMotif {
+    /// Classify the intron `start..end` of a reference sequence. Bases are compared
+    /// ignoring case.
+    pub fn classify(
+        reference: &[u8],
+        start: u64,
+        end: u64,
+    ) -> std::result::Result<IntronMotif, CigarError> {
+        if end < start + 4 || end > reference.len() as u64 {
+            return Err(CigarError::ReferenceOutOfBounds(start, end));
+        }
+        let (start, end) = (start as usize, end as usize);
+        let donor = reference[start..start + 2].to_ascii_uppercase();
+        let acceptor = reference[end - 2..end].to_ascii_uppercase();
+        Ok(match (&donor[..], &acceptor[..]) {
+            (b"GT", b"AG") => IntronMotif::GtAg,
+            (b"CT", b"AC") => IntronMotif::CtAc,
+            (b"GC", b"AG") => IntronMotif::GcAg,
+            (b"CT", b"GC") => IntronMotif::CtGc,
+            (b"AT", b"AC") => IntronMotif::AtAc,
+            (b"GT", b"AT") => IntronMotif::GtAt,
+            _ => IntronMotif::NonCanonical,
+        })
+    }
+
+    /// The motif code used in STAR's `SJ.out.tab`.
+    pub fn star_code(&self) -> u8 {
+        match self {
+            IntronMotif::NonCanonical => 0,
+            IntronMotif::GtAg => 1,
+            IntronMotif::CtAc => 2,
+            IntronMotif::GcAg => 3,
+            IntronMotif::CtGc => 4,
+            IntronMotif::AtAc => 5,
+            IntronMotif::GtAt => 6,
+        }
+    }
+
+    /// The strand of the intron implied by the motif, if it is not non-canonical.
+    pub fn strand(&self) -> Option<Strand> {
+        match self {
+            IntronMotif::NonCanonical => None,
+            IntronMotif::GtAg | IntronMotif::GcAg | IntronMotif::AtAc => Some(Strand::Forward),
+            IntronMotif::CtAc | IntronMotif::CtGc | IntronMotif::GtAt => Some(Strand::Reverse),
+        }
+    }
+}
+
+impl Display for IntronMotif {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            IntronMotif::NonCanonical => "non-canonical",
+            IntronMotif::GtAg => "GT/AG",
+            IntronMotif::CtAc => "CT/AC",
+            IntronMotif::GcAg => "GC/AG",
+            IntronMotif::CtGc => "CT/GC",
+            IntronMotif::AtAc => "AT/AC",
+            IntronMotif::GtAt => "GT/AT",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// An iterator over the splice junctions of a single alignment.
+///
+/// Junctions are the gaps between consecutive [exon blocks](ExonBlockIterator), so
+/// adjacent skips form a single junction, and skips at the ends of the alignment, which
+/// have no exon on one side, are not junctions. Each junction has a support of 1.
+///
+/// # Example
+///
+/// ```rust
+/// use cigar_utils::splice::SpliceJunctionIterator;
+///
+/// let junctions: Vec<_> = SpliceJunctionIterator::new("2S10M100N5M1D5M30N5M", 3, 1000)
+///     .collect::<Result<_, _>>()
+///     .unwrap();
+/// assert_eq!(junctions.len(), 2);
+/// assert_eq!((junctions[0].chrom_id, junctions[0].start, junctions[0].end), (3, 1010, 1110));
+/// assert_eq!((junctions[1].start, junctions[1].end), (1121, 1151));
+/// ```
+pub struct SpliceJunctionIterator<'a> {
+    blocks: ExonBlockIterator<'a>,
+    chrom_id: u32,
+    previous: Option<ExonBlock>,
+}
+
+impl<'a> SpliceJunctionIterator<'a> {
+    /// Create a new splice junction iterator for an alignment starting at the given
+    /// reference position.
+    pub fn new(cigar: &'a str, chrom_id: u32, reference_position: u64) -> Self {
+        SpliceJunctionIterator {
+            blocks: ExonBlockIterator::new(cigar, reference_position),
+            chrom_id,
+            previous: None,
+        }
+    }
+}
+
+impl<'a> Iterator for SpliceJunctionIterator<'a> {
+    type Item = std::result::Result<SpliceJunction, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let block = match self.blocks.next()? {
+                Ok(block) => block,
+                Err(e) => return Some(Err(e)),
+            };
+            if let Some(previous) = self.previous.replace(block.clone()) {
+                return Some(Ok(SpliceJunction {
+                    chrom_id: self.chrom_id,
+                    start: previous.reference_end,
+                    end: block.reference_start,
+                    support: 1,
+                }));
+            }
+        }
+    }
+}
+
+/// An iterator over the splice junctions of a stream of collated events, with the
+/// number of reads supporting each.
+///
+/// Each collated skip event is a junction. Unlike [`SpliceJunctionIterator`], skips at
+/// the ends of alignments are not distinguished, so adjacent skips within a read give
+/// separate junctions.
+///
+/// # Example
+///
+/// ```rust
+/// use cigar_utils::collated::CollatedAugmentedCigarIterator;
+/// use cigar_utils::splice::CollatedJunctionIterator;
+///
+/// let cigars = vec![
+///     std::io::Result::Ok(("5M100N5M".to_string(), 0, 10)),
+///     std::io::Result::Ok(("3M100N8M".to_string(), 0, 12)),
+///     std::io::Result::Ok(("4M50N6M".to_string(), 0, 12)),
+/// ];
+/// let collated = CollatedAugmentedCigarIterator::new(cigars.into_iter());
+/// let junctions: Vec<_> = CollatedJunctionIterator::new(collated)
+///     .collect::<Result<_, _>>()
+///     .unwrap();
+/// assert_eq!(junctions.len(), 2);
+/// assert_eq!((junctions[0].start, junctions[0].end, junctions[0].support), (15, 115, 2));
+/// assert_eq!((junctions[1].start, junctions[1].end, junctions[1].support), (16, 66, 1));
+/// ```
+pub struct CollatedJunctionIterator<I> {
+    inner: I,
+}
+
+impl<I> CollatedJunctionIterator<I> {
+    /// Create a new junction iterator over collated events.
+    pub fn new(inner: I) -> Self {
+        CollatedJunctionIterator { inner }
+    }
+}
+
+impl<I> Iterator for CollatedJunctionIterator<I>
+where
+    I: Iterator<Item = std::result::Result<CollatedEvent, CigarError>>,
+{
+    type Item = std::result::Result<SpliceJunction, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (elem, count) = match self.inner.next()? {
+                Ok(event) => event,
+                Err(e) => return Some(Err(e)),
+            };
+            if elem.op == CigarOp::Skip {
+                return Some(Ok(SpliceJunction {
+                    chrom_id: elem.chrom_id,
+                    start: elem.reference_position,
+                    end: elem.reference_end(),
+                    support: count,
+                }));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exon_blocks_unspliced() {
+        let blocks: Vec<_> = ExonBlockIterator::new("3S10M2I5M", 50)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            blocks,
+            vec![ExonBlock {
+                reference_start: 50,
+                reference_end: 65,
+                read_start: 3,
+                read_end: 20,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_exon_blocks_spliced() {
+        let blocks: Vec<_> = ExonBlockIterator::new("5M20N3M1I2M30N4M", 100)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let summary: Vec<_> = blocks
+            .iter()
+            .map(|b| (b.reference_start, b.reference_end, b.read_start, b.read_end))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![(100, 105, 0, 5), (125, 130, 5, 11), (160, 164, 11, 15)]
+        );
+    }
+
+    #[test]
+    fn test_exon_blocks_error() {
+        let mut iter = ExonBlockIterator::new("5M20N3Z", 100);
+        assert!(matches!(iter.next(), Some(Ok(_))));
+        assert!(matches!(
+            iter.next(),
+            Some(Err(CigarError::InvalidCharacter('Z', _)))
+        ));
+    }
+
+    #[test]
+    fn test_splice_junctions_at_ends() {
+        let junctions: Vec<_> = SpliceJunctionIterator::new("10N5M20N10N5M3N", 1, 0)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            junctions,
+            vec![SpliceJunction {
+                chrom_id: 1,
+                start: 15,
+                end: 45,
+                support: 1,
+            }]
+        );
+        assert_eq!(junctions[0].length(), 30);
+    }
+
+    #[test]
+    fn test_intron_motifs() {
+        let classify = |s: &[u8]| IntronMotif::classify(s, 0, s.len() as u64).unwrap();
+        assert_eq!(classify(b"ctaaaac"), IntronMotif::CtAc);
+        assert_eq!(classify(b"GCAAAG"), IntronMotif::GcAg);
+        assert_eq!(classify(b"GTAAAT").strand(), Some(Strand::Reverse));
+        assert_eq!(classify(b"GGAAAG"), IntronMotif::NonCanonical);
+        assert_eq!(classify(b"GGAAAG").strand(), None);
+        assert!(matches!(
+            IntronMotif::classify(b"GTAG", 0, 5),
+            Err(CigarError::ReferenceOutOfBounds(0, 5))
+        ));
+        let junction = SpliceJunction {
+            chrom_id: 2,
+            start: 0,
+            end: 4,
+            support: 1,
+        };
+        assert!(matches!(
+            junction.motif(&vec![b"GTAG"]),
+            Err(CigarError::UnknownChromosome(2))
+        ));
+    }
+}
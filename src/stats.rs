@@ -0,0 +1,604 @@
+//! Alignment and CIGAR statistics.
+//!
+//! Identity has several definitions in common use, and they are easy to get subtly
+//! wrong. [`AlignmentStats`] counts the matching and mismatching bases and the indels of
+//! an alignment, and derives the usual measures from them:
+//!
+//! - BLAST identity: matching bases over alignment columns, where every inserted or
+//!   deleted base is a column.
+//! - Gap-compressed identity: as BLAST identity, but with each indel counted once
+//!   whatever its length, so long-read indel errors do not dominate.
+//! - Mismatch rate, and insertion and deletion rates per alignment column.
+//!
+//! Clipped bases, skipped reference (introns) and padding are not part of the alignment
+//! and do not count.
+//!
+//! For the shape of a CIGAR rather than its errors, [`CigarStats`] summarizes the
+//! operations, aligned lengths and clips in a single pass, [`IndelHistogram`]
+//! accumulates the distribution of indel lengths over many alignments, and
+//! [`CycleErrorProfile`] accumulates error rates by sequencing cycle.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::Cigar;
+//! use cigar_utils::stats::stats;
+//!
+//! let cigar: Cigar = "2S6=1X4I3=2D".parse().unwrap();
+//! let stats = stats(&cigar).unwrap();
+//! assert_eq!(stats.blast_identity(), Some(9.0 / 16.0));
+//! assert_eq!(stats.gap_compressed_identity(), Some(9.0 / 12.0));
+//! assert_eq!(stats.mismatch_rate(), Some(0.1));
+//! ```
+
+use crate::error::CigarError;
+use crate::expand::ExpandedCigarIterator;
+use crate::{Cigar, CigarElement, CigarOp, Strand};
+
+/// Counts of the matches, mismatches and indels of an alignment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AlignmentStats {
+    /// The number of matching bases.
+    pub matches: usize,
+    /// The number of mismatching bases.
+    pub mismatches: usize,
+    /// The number of insertions.
+    pub insertions: usize,
+    /// The number of inserted bases.
+    pub inserted_bases: usize,
+    /// The number of deletions.
+    pub deletions: usize,
+    /// The number of deleted bases.
+    pub deleted_bases: usize,
+}
+
+impl AlignmentStats {
+    /// The number of alignment columns: aligned, inserted and deleted bases.
+    pub fn columns(&self) -> usize {
+        self.matches + self.mismatches + self.inserted_bases + self.deleted_bases
+    }
+
+    /// The BLAST identity: matching bases over alignment columns.
+    pub fn blast_identity(&self) -> Option<f64> {
+        ratio(self.matches, self.columns())
+    }
+
+    /// The gap-compressed identity: as [`blast_identity`](Self::blast_identity), but with
+    /// each insertion or deletion counted as a single column.
+    pub fn gap_compressed_identity(&self) -> Option<f64> {
+        ratio(
+            self.matches,
+            self.matches + self.mismatches + self.insertions + self.deletions,
+        )
+    }
+
+    /// The fraction of aligned bases which are mismatches.
+    pub fn mismatch_rate(&self) -> Option<f64> {
+        ratio(self.mismatches, self.matches + self.mismatches)
+    }
+
+    /// Inserted bases per alignment column.
+    pub fn insertion_rate(&self) -> Option<f64> {
+        ratio(self.inserted_bases, self.columns())
+    }
+
+    /// Deleted bases per alignment column.
+    pub fn deletion_rate(&self) -> Option<f64> {
+        ratio(self.deleted_bases, self.columns())
+    }
+
+    fn accumulate<I>(elements: I) -> std::result::Result<AlignmentStats, CigarError>
+    where
+        I: Iterator<Item = std::result::Result<CigarElement, CigarError>>,
+    {
+        let mut stats = AlignmentStats::default();
+        for (index, elem) in elements.enumerate() {
+            let elem = elem?;
+            let length = elem.length as usize;
+            match elem.op {
+                CigarOp::Equal => stats.matches += length,
+                CigarOp::Diff => stats.mismatches += length,
+                CigarOp::Insertion => {
+                    stats.insertions += 1;
+                    stats.inserted_bases += length;
+                }
+                CigarOp::Deletion => {
+                    stats.deletions += 1;
+                    stats.deleted_bases += length;
+                }
+                CigarOp::Match => return Err(CigarError::UnresolvedMatch(index)),
+                CigarOp::Skip | CigarOp::SoftClip | CigarOp::HardClip | CigarOp::Padding => {}
+            }
+        }
+        Ok(stats)
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> Option<f64> {
+    if denominator == 0 {
+        None
+    } else {
+        Some(numerator as f64 / denominator as f64)
+    }
+}
+
+/// Compute the statistics of an alignment from a CIGAR using sequence match (`=`) and
+/// mismatch (`X`) operations.
+///
+/// An alignment match (`M`) element gives [`CigarError::UnresolvedMatch`]; use
+/// [`stats_with_sequences`] for such CIGARs.
+pub fn stats(cigar: &Cigar) -> std::result::Result<AlignmentStats, CigarError> {
+    AlignmentStats::accumulate(cigar.iter().cloned().map(Ok))
+}
+
+/// Compute the statistics of a read aligned at `reference_position`, comparing the
+/// bases of alignment match (`M`) elements using the reference and read sequences.
+pub fn stats_with_sequences<R: AsRef<[u8]>, S: AsRef<[u8]>>(
+    reference_position: usize,
+    cigar: &str,
+    reference: &R,
+    seq: &S,
+) -> std::result::Result<AlignmentStats, CigarError> {
+    AlignmentStats::accumulate(ExpandedCigarIterator::new(
+        reference_position,
+        cigar,
+        reference.as_ref(),
+        seq.as_ref(),
+    ))
+}
+
+/// A summary of the operations of a CIGAR.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CigarStats {
+    counts: [usize; 9],
+    bases: [u64; 9],
+    /// The number of elements.
+    pub elements: usize,
+    /// The number of read bases aligned to the reference or inserted (excluding clips).
+    pub aligned_query_length: u64,
+    /// The number of reference bases covered by the alignment.
+    pub aligned_reference_length: u64,
+    /// The number of bases soft or hard clipped from the start of the read.
+    pub leading_clip: u64,
+    /// The number of bases soft or hard clipped from the end of the read.
+    pub trailing_clip: u64,
+    /// The longest insertion or deletion, if any; the first of equal lengths.
+    pub largest_indel: Option<CigarElement>,
+}
+
+impl CigarStats {
+    /// Summarize a CIGAR.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cigar_utils::{Cigar, CigarElement, CigarOp};
+    /// use cigar_utils::stats::CigarStats;
+    ///
+    /// let cigar: Cigar = "3H2S5M2I3M4D1M1S".parse().unwrap();
+    /// let stats = CigarStats::new(&cigar);
+    /// assert_eq!(stats.count(CigarOp::Match), 3);
+    /// assert_eq!(stats.bases(CigarOp::Match), 9);
+    /// assert_eq!((stats.aligned_query_length, stats.aligned_reference_length), (11, 13));
+    /// assert_eq!((stats.leading_clip, stats.trailing_clip), (5, 1));
+    /// assert_eq!(stats.largest_indel, Some(CigarElement::new(4, CigarOp::Deletion)));
+    /// ```
+    pub fn new(cigar: &Cigar) -> Self {
+        let mut stats = CigarStats {
+            elements: cigar.len(),
+            ..Default::default()
+        };
+        let mut leading = true;
+        for elem in cigar.iter() {
+            let length = elem.length as u64;
+            let code = u8::from(elem.op) as usize;
+            stats.counts[code] += 1;
+            stats.bases[code] += length;
+            match elem.op {
+                CigarOp::SoftClip | CigarOp::HardClip => {
+                    if leading {
+                        stats.leading_clip += length;
+                    } else {
+                        stats.trailing_clip += length;
+                    }
+                    continue;
+                }
+                CigarOp::Insertion | CigarOp::Deletion
+                    if stats
+                        .largest_indel
+                        .as_ref()
+                        .is_none_or(|largest| elem.length > largest.length) =>
+                {
+                    stats.largest_indel = Some(elem.clone());
+                }
+                _ => {}
+            }
+            leading = false;
+            // Clips only count as trailing if nothing else follows them.
+            stats.trailing_clip = 0;
+            if elem.op.consumes_query() {
+                stats.aligned_query_length += length;
+            }
+            if elem.op.consumes_reference() {
+                stats.aligned_reference_length += length;
+            }
+        }
+        stats
+    }
+
+    /// The number of elements with the given operation.
+    pub fn count(&self, op: CigarOp) -> usize {
+        self.counts[u8::from(op) as usize]
+    }
+
+    /// The total length of the elements with the given operation.
+    pub fn bases(&self, op: CigarOp) -> u64 {
+        self.bases[u8::from(op) as usize]
+    }
+}
+
+/// Histograms of insertion and deletion lengths.
+///
+/// Lengths are binned into bins of `bin_width` lengths starting at 1, up to
+/// `max_length`; longer indels are counted in a final overflow bin. Histograms with the
+/// same binning can be merged, to combine counts from several threads or samples.
+///
+/// # Example
+///
+/// ```rust
+/// use cigar_utils::stats::IndelHistogram;
+///
+/// let mut histogram = IndelHistogram::new(2, 4);
+/// histogram.add_cigar(&"5M1I5M3D5M2I5M10D".parse().unwrap());
+/// assert_eq!(histogram.insertions(), &[2, 0, 0]);
+/// assert_eq!(histogram.deletions(), &[0, 1, 1]);
+/// assert_eq!(histogram.bin_range(1), (3, 4));
+/// assert_eq!(histogram.bin_range(2), (5, u32::MAX));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndelHistogram {
+    bin_width: u32,
+    max_length: u32,
+    insertions: Vec<u64>,
+    deletions: Vec<u64>,
+}
+
+impl IndelHistogram {
+    /// Create an empty histogram with the given binning.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bin_width` is zero.
+    pub fn new(bin_width: u32, max_length: u32) -> Self {
+        assert!(bin_width > 0, "bin width must be positive");
+        let bins = max_length.div_ceil(bin_width) as usize + 1;
+        IndelHistogram {
+            bin_width,
+            max_length,
+            insertions: vec![0; bins],
+            deletions: vec![0; bins],
+        }
+    }
+
+    /// The counts of insertions in each bin, ending with the overflow bin.
+    pub fn insertions(&self) -> &[u64] {
+        &self.insertions
+    }
+
+    /// The counts of deletions in each bin, ending with the overflow bin.
+    pub fn deletions(&self) -> &[u64] {
+        &self.deletions
+    }
+
+    /// The inclusive range of lengths counted in a bin.
+    pub fn bin_range(&self, bin: usize) -> (u32, u32) {
+        let first = bin as u32 * self.bin_width + 1;
+        if bin + 1 == self.insertions.len() {
+            (first.min(self.max_length + 1), u32::MAX)
+        } else {
+            (first, (first + self.bin_width - 1).min(self.max_length))
+        }
+    }
+
+    fn bin(&self, length: u32) -> usize {
+        if length > self.max_length {
+            self.insertions.len() - 1
+        } else {
+            (length.max(1) - 1) as usize / self.bin_width as usize
+        }
+    }
+
+    /// Count `count` occurrences of an event, such as a collated event. Operations other
+    /// than insertions and deletions are ignored.
+    pub fn add_event(&mut self, op: CigarOp, length: u32, count: u64) {
+        let bin = self.bin(length);
+        match op {
+            CigarOp::Insertion => self.insertions[bin] += count,
+            CigarOp::Deletion => self.deletions[bin] += count,
+            _ => {}
+        }
+    }
+
+    /// Count the insertions and deletions of a CIGAR.
+    pub fn add_cigar(&mut self, cigar: &Cigar) {
+        for elem in cigar.iter() {
+            self.add_event(elem.op, elem.length, 1);
+        }
+    }
+
+    /// Add the counts of another histogram to this one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the histograms have different binning.
+    pub fn merge(&mut self, other: &IndelHistogram) {
+        assert!(
+            self.bin_width == other.bin_width && self.max_length == other.max_length,
+            "histograms must have the same binning"
+        );
+        for (count, other_count) in self.insertions.iter_mut().zip(&other.insertions) {
+            *count += other_count;
+        }
+        for (count, other_count) in self.deletions.iter_mut().zip(&other.deletions) {
+            *count += other_count;
+        }
+    }
+}
+
+/// Counts of errors by position within the read in sequencing order (the cycle), as for
+/// a per-base error plot.
+///
+/// Each aligned or inserted base counts towards its cycle, and each mismatched or
+/// inserted base as an error there. A deletion is counted at the cycle of the read base
+/// sequenced just after it. Hard clipped bases are counted in the cycle numbering, but
+/// clipped bases are not themselves counted. Reads on the reverse strand are sequenced
+/// from the end of their alignment.
+///
+/// # Example
+///
+/// ```rust
+/// use cigar_utils::Strand;
+/// use cigar_utils::expand::expand_cigar_operations;
+/// use cigar_utils::stats::CycleErrorProfile;
+///
+/// let expanded = expand_cigar_operations(0, "1S3M1D2M", &b"ACGTACG", &b"TAGGCG").unwrap();
+/// let mut profile = CycleErrorProfile::default();
+/// profile.add(&expanded, Strand::Forward).unwrap();
+/// assert_eq!(profile.cycles(), 6);
+/// assert_eq!(profile.mismatch_rate(2), Some(1.0));
+/// assert_eq!(profile.deletion_rate(4), Some(1.0));
+/// assert_eq!(profile.mismatch_rate(0), None);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CycleErrorProfile {
+    bases: Vec<u64>,
+    mismatches: Vec<u64>,
+    insertions: Vec<u64>,
+    deletions: Vec<u64>,
+}
+
+impl CycleErrorProfile {
+    /// The number of cycles seen, the length of the longest read.
+    pub fn cycles(&self) -> usize {
+        self.bases.len()
+    }
+
+    /// The number of aligned or inserted bases at a cycle.
+    pub fn bases(&self, cycle: usize) -> u64 {
+        self.bases.get(cycle).copied().unwrap_or(0)
+    }
+
+    /// The fraction of bases at a cycle which are mismatches.
+    pub fn mismatch_rate(&self, cycle: usize) -> Option<f64> {
+        self.rate(&self.mismatches, cycle)
+    }
+
+    /// The fraction of bases at a cycle which are insertions.
+    pub fn insertion_rate(&self, cycle: usize) -> Option<f64> {
+        self.rate(&self.insertions, cycle)
+    }
+
+    /// The number of deletions per base at a cycle.
+    pub fn deletion_rate(&self, cycle: usize) -> Option<f64> {
+        self.rate(&self.deletions, cycle)
+    }
+
+    fn rate(&self, counts: &[u64], cycle: usize) -> Option<f64> {
+        ratio(
+            counts.get(cycle).copied().unwrap_or(0) as usize,
+            self.bases(cycle) as usize,
+        )
+    }
+
+    /// Add the elements of an expanded CIGAR (using `=` and `X` rather than `M`) for a
+    /// read aligned on `strand`.
+    ///
+    /// An alignment match (`M`) element gives [`CigarError::UnresolvedMatch`], and the
+    /// profile is left unchanged.
+    pub fn add(
+        &mut self,
+        expanded: &[CigarElement],
+        strand: Strand,
+    ) -> std::result::Result<(), CigarError> {
+        if let Some(index) = expanded.iter().position(|e| e.op == CigarOp::Match) {
+            return Err(CigarError::UnresolvedMatch(index));
+        }
+        let read_length = expanded
+            .iter()
+            .filter(|e| e.op.consumes_query() || e.op == CigarOp::HardClip)
+            .map(|e| e.length as usize)
+            .sum::<usize>();
+        if read_length > self.bases.len() {
+            for counts in [
+                &mut self.bases,
+                &mut self.mismatches,
+                &mut self.insertions,
+                &mut self.deletions,
+            ] {
+                counts.resize(read_length, 0);
+            }
+        }
+        let cycle = |position: usize| match strand {
+            Strand::Forward => position,
+            Strand::Reverse => read_length - 1 - position,
+        };
+
+        let mut read_position = 0;
+        for elem in expanded {
+            let length = elem.length as usize;
+            match elem.op {
+                CigarOp::Equal | CigarOp::Diff | CigarOp::Insertion => {
+                    for position in read_position..read_position + length {
+                        let c = cycle(position);
+                        self.bases[c] += 1;
+                        match elem.op {
+                            CigarOp::Diff => self.mismatches[c] += 1,
+                            CigarOp::Insertion => self.insertions[c] += 1,
+                            _ => {}
+                        }
+                    }
+                    read_position += length;
+                }
+                CigarOp::Deletion if read_length > 0 => {
+                    // The base sequenced after the deletion follows it in the read on the
+                    // forward strand, and precedes it on the reverse strand.
+                    let position = match strand {
+                        Strand::Forward => read_position.min(read_length - 1),
+                        Strand::Reverse => read_position.max(1) - 1,
+                    };
+                    self.deletions[cycle(position)] += 1;
+                }
+                CigarOp::SoftClip | CigarOp::HardClip => read_position += length,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the counts of another profile to this one.
+    pub fn merge(&mut self, other: &CycleErrorProfile) {
+        for (counts, other_counts) in [
+            (&mut self.bases, &other.bases),
+            (&mut self.mismatches, &other.mismatches),
+            (&mut self.insertions, &other.insertions),
+            (&mut self.deletions, &other.deletions),
+        ] {
+            if other_counts.len() > counts.len() {
+                counts.resize(other_counts.len(), 0);
+            }
+            for (count, other_count) in counts.iter_mut().zip(other_counts) {
+                *count += other_count;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_counts() {
+        let cigar: Cigar = "5H3=1X2I1N2=1D3I1=2S".parse().unwrap();
+        let stats = stats(&cigar).unwrap();
+        assert_eq!(
+            stats,
+            AlignmentStats {
+                matches: 6,
+                mismatches: 1,
+                insertions: 2,
+                inserted_bases: 5,
+                deletions: 1,
+                deleted_bases: 1,
+            }
+        );
+        assert_eq!(stats.columns(), 13);
+        assert_eq!(stats.insertion_rate(), Some(5.0 / 13.0));
+        assert_eq!(stats.deletion_rate(), Some(1.0 / 13.0));
+    }
+
+    #[test]
+    fn test_stats_with_sequences() {
+        let stats = stats_with_sequences(0, "3M1I2M1D2M", b"ACGTACGT", b"ACCTTAGT").unwrap();
+        assert_eq!((stats.matches, stats.mismatches), (6, 1));
+        assert_eq!((stats.insertions, stats.deletions), (1, 1));
+        assert_eq!(stats.blast_identity(), Some(6.0 / 9.0));
+    }
+
+    #[test]
+    fn test_stats_unresolved_and_empty() {
+        let cigar: Cigar = "2=3M".parse().unwrap();
+        assert!(matches!(stats(&cigar), Err(CigarError::UnresolvedMatch(1))));
+        let stats = stats(&"10S".parse().unwrap()).unwrap();
+        assert_eq!(stats.blast_identity(), None);
+        assert_eq!(stats.mismatch_rate(), None);
+    }
+
+    #[test]
+    fn test_cigar_stats_clips() {
+        let stats = CigarStats::new(&"4S2H".parse().unwrap());
+        assert_eq!((stats.leading_clip, stats.trailing_clip), (6, 0));
+        assert_eq!(stats.aligned_query_length, 0);
+        assert_eq!(stats.largest_indel, None);
+
+        let stats = CigarStats::new(&"2S3M1S4M2S1H".parse().unwrap());
+        assert_eq!((stats.leading_clip, stats.trailing_clip), (2, 3));
+        assert_eq!(stats.count(CigarOp::SoftClip), 3);
+        assert_eq!(stats.bases(CigarOp::Deletion), 0);
+    }
+
+    #[test]
+    fn test_cigar_stats_largest_indel() {
+        let stats = CigarStats::new(&"5M3I2M3D1M1N2M".parse().unwrap());
+        assert_eq!(stats.elements, 7);
+        assert_eq!(
+            stats.largest_indel,
+            Some(CigarElement::new(3, CigarOp::Insertion))
+        );
+        assert_eq!(stats.aligned_reference_length, 14);
+    }
+
+    #[test]
+    fn test_indel_histogram_merge() {
+        let mut a = IndelHistogram::new(1, 3);
+        a.add_cigar(&"2M1I2M3I2M".parse().unwrap());
+        let mut b = IndelHistogram::new(1, 3);
+        b.add_event(CigarOp::Insertion, 4, 5);
+        b.add_event(CigarOp::Deletion, 1, 2);
+        b.add_event(CigarOp::Skip, 100, 1);
+        a.merge(&b);
+        assert_eq!(a.insertions(), &[1, 0, 1, 5]);
+        assert_eq!(a.deletions(), &[2, 0, 0, 0]);
+        assert_eq!(a.bin_range(0), (1, 1));
+        assert_eq!(a.bin_range(3), (4, u32::MAX));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_indel_histogram_merge_different_binning() {
+        let mut a = IndelHistogram::new(1, 3);
+        a.merge(&IndelHistogram::new(2, 3));
+    }
+
+    #[test]
+    fn test_cycle_profile_reverse_strand() {
+        let expanded: Cigar = "2H2=1I1D1X".parse().unwrap();
+        let mut profile = CycleErrorProfile::default();
+        profile.add(expanded.elements(), Strand::Reverse).unwrap();
+        // Read positions 2..6 are cycles 3..0; the deletion is sequenced before position 4.
+        assert_eq!(profile.cycles(), 6);
+        assert_eq!(profile.mismatch_rate(0), Some(1.0));
+        assert_eq!(profile.insertion_rate(1), Some(1.0));
+        assert_eq!(profile.deletion_rate(1), Some(1.0));
+        assert_eq!(profile.bases(4), 0);
+
+        let mut merged = CycleErrorProfile::default();
+        merged.merge(&profile);
+        merged.merge(&profile);
+        assert_eq!(merged.bases(3), 2);
+        assert!(matches!(
+            merged.add(&[CigarElement::new(3, CigarOp::Match)], Strand::Forward),
+            Err(CigarError::UnresolvedMatch(0))
+        ));
+    }
+}
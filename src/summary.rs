@@ -0,0 +1,607 @@
+//! Per-position summaries of collated events.
+//!
+//! A [`PositionSummary`] gathers the collated events starting at a reference position
+//! together with the depth of coverage there, so that counts can be turned into
+//! frequencies without joining the collated stream against a separate depth track.
+//! The depth is computed from the collated events themselves, in the same pass.
+//!
+//! Summaries are only produced where events start. For dense tracks,
+//! [`with_gaps`](PositionSummaryIterator::with_gaps) also yields the covered intervals
+//! between them as [`CoverageGap`]s, so every covered position is accounted for.
+//!
+//! A [`PositionEventTable`] holds the summaries of a whole collation in memory, for
+//! querying by position, ranking events, and merging the tables of parallel runs.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::CigarOp;
+//! use cigar_utils::collated::CollatedAugmentedCigarIterator;
+//!
+//! let cigars = vec![
+//!     std::io::Result::Ok(("2M1D2M".to_string(), 1, 100)),
+//!     std::io::Result::Ok(("5M".to_string(), 1, 100)),
+//! ];
+//! let summaries: Vec<_> = CollatedAugmentedCigarIterator::new(cigars.into_iter())
+//!     .summarized()
+//!     .collect::<Result<_, _>>()
+//!     .unwrap();
+//! let at_102 = summaries.iter().find(|s| s.position == 102).unwrap();
+//! assert_eq!(at_102.depth, 2);
+//! assert_eq!(at_102.fraction(CigarOp::Deletion, 1), Some(0.5));
+//! ```
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+    iter::Peekable,
+};
+
+use crate::CigarOp;
+use crate::collated::CollatedEvent;
+use crate::error::CigarError;
+
+/// The number of times an event was seen at a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventCount {
+    /// The operation of the event.
+    pub op: CigarOp,
+    /// The length of the event.
+    pub length: u32,
+    /// The number of records with the event.
+    pub count: usize,
+}
+
+/// The events starting at a reference position, with the depth of coverage there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PositionSummary {
+    /// The chromosome ID of the position.
+    pub chrom_id: u32,
+    /// The reference position.
+    pub position: u64,
+    /// The number of records covering the position with a match or deletion.
+    pub depth: usize,
+    /// The events starting at the position, in collation order.
+    pub events: Vec<EventCount>,
+}
+
+impl PositionSummary {
+    /// The fraction of covering records with the given event, or `None` if the depth is zero.
+    pub fn fraction(&self, op: CigarOp, length: u32) -> Option<f64> {
+        let count = self
+            .events
+            .iter()
+            .filter(|e| e.op == op && e.length == length)
+            .map(|e| e.count)
+            .sum::<usize>();
+        self.fraction_of(count)
+    }
+
+    /// The fraction of covering records with each event at the position.
+    pub fn fractions(&self) -> impl Iterator<Item = (EventCount, Option<f64>)> + '_ {
+        self.events.iter().map(|e| (*e, self.fraction_of(e.count)))
+    }
+
+    fn fraction_of(&self, count: usize) -> Option<f64> {
+        if self.depth == 0 {
+            None
+        } else {
+            Some(count as f64 / self.depth as f64)
+        }
+    }
+}
+
+/// A covered reference interval where no events start, with its depth of coverage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoverageGap {
+    /// The chromosome ID of the interval.
+    pub chrom_id: u32,
+    /// The start of the half open interval.
+    pub start: u64,
+    /// The end of the half open interval.
+    pub end: u64,
+    /// The number of records covering the interval with a match or deletion.
+    pub depth: usize,
+}
+
+/// A record of a [gap-filled](PositionSummaryIterator::with_gaps) summary stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoverageRecord {
+    /// The events starting at a position.
+    Events(PositionSummary),
+    /// A covered interval without events.
+    Gap(CoverageGap),
+}
+
+/// An iterator over per-position summaries of a stream of collated events.
+///
+/// The stream must be the unfiltered output of a collation, since the depth is computed
+/// from its match and deletion events. Only positions where at least one event starts
+/// are reported.
+pub struct PositionSummaryIterator<I: Iterator> {
+    inner: Peekable<I>,
+    covering: BinaryHeap<Reverse<(u32, u64, usize)>>,
+    depth: usize,
+    events: Vec<CollatedEvent>,
+}
+
+impl<I: Iterator> PositionSummaryIterator<I> {
+    /// Create a new summary iterator over a stream of collated events.
+    pub fn new(inner: I) -> Self {
+        PositionSummaryIterator {
+            inner: inner.peekable(),
+            covering: BinaryHeap::new(),
+            depth: 0,
+            events: Vec::new(),
+        }
+    }
+
+    /// Also yield the covered intervals between summaries, split wherever the depth
+    /// changes, so that consumers building dense tracks need no second pass.
+    ///
+    /// Uncovered intervals are skipped.
+    pub fn with_gaps(self) -> GapFilledSummaryIterator<I> {
+        GapFilledSummaryIterator {
+            summaries: self,
+            cursor: None,
+        }
+    }
+}
+
+impl<I> PositionSummaryIterator<I>
+where
+    I: Iterator<Item = std::result::Result<CollatedEvent, CigarError>>,
+{
+    fn add(&mut self, event: CollatedEvent) {
+        let (elem, count) = &event;
+        if elem.op.consumes_reference() && elem.op != CigarOp::Skip {
+            self.depth += count;
+            self.covering
+                .push(Reverse((elem.chrom_id, elem.reference_end(), *count)));
+        }
+        self.events.push(event);
+    }
+
+    /// The collated events at the next position where events start, with the depth there.
+    pub(crate) fn next_position(
+        &mut self,
+    ) -> Option<std::result::Result<(usize, &[CollatedEvent]), CigarError>> {
+        let first = match self.inner.next()? {
+            Ok(event) => event,
+            Err(e) => return Some(Err(e)),
+        };
+        let key = (first.0.chrom_id, first.0.reference_position);
+        while let Some(Reverse((chrom_id, end, count))) = self.covering.peek() {
+            if (*chrom_id, *end) > key {
+                break;
+            }
+            self.depth -= count;
+            self.covering.pop();
+        }
+
+        self.events.clear();
+        self.add(first);
+        while let Some(Ok((elem, _))) = self.inner.peek() {
+            if (elem.chrom_id, elem.reference_position) != key {
+                break;
+            }
+            if let Some(Ok(event)) = self.inner.next() {
+                self.add(event);
+            }
+        }
+        Some(Ok((self.depth, &self.events)))
+    }
+}
+
+impl<I> Iterator for PositionSummaryIterator<I>
+where
+    I: Iterator<Item = std::result::Result<CollatedEvent, CigarError>>,
+{
+    type Item = std::result::Result<PositionSummary, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (depth, events) = match self.next_position()? {
+            Ok(position) => position,
+            Err(e) => return Some(Err(e)),
+        };
+        let (first, _) = &events[0];
+        Some(Ok(PositionSummary {
+            chrom_id: first.chrom_id,
+            position: first.reference_position,
+            depth,
+            events: events
+                .iter()
+                .map(|(elem, count)| EventCount {
+                    op: elem.op,
+                    length: elem.length,
+                    count: *count,
+                })
+                .collect(),
+        }))
+    }
+}
+
+/// An iterator over per-position summaries and the covered gaps between them, created
+/// by [`PositionSummaryIterator::with_gaps`].
+pub struct GapFilledSummaryIterator<I: Iterator> {
+    summaries: PositionSummaryIterator<I>,
+    cursor: Option<(u32, u64)>,
+}
+
+impl<I> Iterator for GapFilledSummaryIterator<I>
+where
+    I: Iterator<Item = std::result::Result<CollatedEvent, CigarError>>,
+{
+    type Item = std::result::Result<CoverageRecord, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = match self.summaries.inner.peek() {
+            Some(Ok((elem, _))) => Some((elem.chrom_id, elem.reference_position)),
+            _ => None,
+        };
+        if let Some((chrom_id, position)) = self.cursor
+            && next != self.cursor
+        {
+            let summaries = &mut self.summaries;
+            while let Some(Reverse((c, end, count))) = summaries.covering.peek() {
+                if (*c, *end) > (chrom_id, position) {
+                    break;
+                }
+                summaries.depth -= count;
+                summaries.covering.pop();
+            }
+            if summaries.depth > 0
+                && let Some(Reverse((_, covered_end, _))) = summaries.covering.peek()
+            {
+                let end = match next {
+                    Some((c, p)) if c == chrom_id => p.min(*covered_end),
+                    _ => *covered_end,
+                };
+                self.cursor = Some((chrom_id, end));
+                return Some(Ok(CoverageRecord::Gap(CoverageGap {
+                    chrom_id,
+                    start: position,
+                    end,
+                    depth: summaries.depth,
+                })));
+            }
+        }
+        let summary = match self.summaries.next()? {
+            Ok(summary) => summary,
+            Err(e) => return Some(Err(e)),
+        };
+        self.cursor = Some((summary.chrom_id, summary.position + 1));
+        Some(Ok(CoverageRecord::Events(summary)))
+    }
+}
+
+/// The event counts and depth at a position of a [`PositionEventTable`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PositionEvents {
+    /// The number of records covering the position with a match or deletion.
+    pub depth: usize,
+    /// The number of records with each event, keyed by operation and length.
+    pub counts: BTreeMap<(CigarOp, u32), usize>,
+}
+
+impl PositionEvents {
+    /// The number of records with the given event.
+    pub fn count(&self, op: CigarOp, length: u32) -> usize {
+        self.counts.get(&(op, length)).copied().unwrap_or(0)
+    }
+
+    /// The fraction of covering records with the given event, or `None` if the depth is zero.
+    pub fn frequency(&self, op: CigarOp, length: u32) -> Option<f64> {
+        (self.depth > 0).then(|| self.count(op, length) as f64 / self.depth as f64)
+    }
+
+    /// The `n` most frequent events, most frequent first, with ties in operation and
+    /// length order.
+    pub fn top(&self, n: usize) -> Vec<EventCount> {
+        let mut events: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(&(op, length), &count)| EventCount { op, length, count })
+            .collect();
+        events.sort_by_key(|e| Reverse(e.count));
+        events.truncate(n);
+        events
+    }
+}
+
+/// A table of the events at each position of a collation, with the depth there.
+///
+/// Events with the same operation and length at a position are counted together, so
+/// clips at the start and end of reads are not told apart. The table also keeps the
+/// depth of coverage at every covered position, as the changes in depth along each
+/// chromosome, so that the depths at event positions stay right when tables of
+/// different records are merged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PositionEventTable {
+    positions: BTreeMap<(u32, u64), PositionEvents>,
+    coverage: BTreeMap<(u32, u64), i64>,
+}
+
+impl PositionEventTable {
+    /// Create an empty table.
+    pub fn new() -> Self {
+        PositionEventTable::default()
+    }
+
+    /// Fold a stream of collated events into a table. As for a
+    /// [`PositionSummaryIterator`], the stream must be the unfiltered output of a
+    /// collation for the depths to be right.
+    pub fn from_collated<I>(events: I) -> std::result::Result<Self, CigarError>
+    where
+        I: IntoIterator<Item = std::result::Result<CollatedEvent, CigarError>>,
+    {
+        let mut table = PositionEventTable::new();
+        for record in PositionSummaryIterator::new(events.into_iter()).with_gaps() {
+            match record? {
+                CoverageRecord::Events(summary) => table.add(&summary),
+                CoverageRecord::Gap(gap) => table.add_gap(&gap),
+            }
+        }
+        Ok(table)
+    }
+
+    /// Add the events of a summary, summing counts and depths with any already at its
+    /// position.
+    pub fn add(&mut self, summary: &PositionSummary) {
+        let (chrom_id, position) = (summary.chrom_id, summary.position);
+        self.cover(chrom_id, position, position + 1, summary.depth);
+        let entry = self.positions.entry((chrom_id, position)).or_default();
+        entry.depth += summary.depth;
+        for event in &summary.events {
+            *entry.counts.entry((event.op, event.length)).or_insert(0) += event.count;
+        }
+    }
+
+    /// Add the depth of a covered interval without events, summing it with the depths
+    /// already there.
+    pub fn add_gap(&mut self, gap: &CoverageGap) {
+        self.cover(gap.chrom_id, gap.start, gap.end, gap.depth);
+        for (_, events) in self
+            .positions
+            .range_mut((gap.chrom_id, gap.start)..(gap.chrom_id, gap.end.max(gap.start)))
+        {
+            events.depth += gap.depth;
+        }
+    }
+
+    /// Record a depth of `depth` over `start..end` of chromosome `chrom_id`.
+    fn cover(&mut self, chrom_id: u32, start: u64, end: u64, depth: usize) {
+        if depth == 0 || end <= start {
+            return;
+        }
+        self.change_depth((chrom_id, start), depth as i64);
+        self.change_depth((chrom_id, end), -(depth as i64));
+    }
+
+    /// Add `change` to the change in depth at a position.
+    fn change_depth(&mut self, key: (u32, u64), change: i64) {
+        let entry = self.coverage.entry(key).or_insert(0);
+        *entry += change;
+        if *entry == 0 {
+            self.coverage.remove(&key);
+        }
+    }
+
+    /// Merge in the table of another run over different records, such as another shard
+    /// or sample, summing counts and depths position by position.
+    ///
+    /// The depth at each position is the sum of the depths there in both tables, whether
+    /// or not events start there in both.
+    pub fn merge(&mut self, other: PositionEventTable) {
+        for (key, events) in other.positions {
+            let entry = self.positions.entry(key).or_default();
+            for (event, count) in events.counts {
+                *entry.counts.entry(event).or_insert(0) += count;
+            }
+        }
+        for (key, change) in other.coverage {
+            self.change_depth(key, change);
+        }
+        // The changes in depth along each chromosome sum to zero, so a running total over
+        // all of them gives the depth at each position.
+        let mut depth = 0;
+        let mut changes = self.coverage.iter().peekable();
+        for (key, events) in self.positions.iter_mut() {
+            while let Some((_, change)) = changes.next_if(|(at, _)| *at <= key) {
+                depth += change;
+            }
+            events.depth = depth as usize;
+        }
+    }
+
+    /// The depth of coverage at a position, whether or not events start there.
+    pub fn depth(&self, chrom_id: u32, position: u64) -> usize {
+        self.coverage
+            .range((chrom_id, 0)..=(chrom_id, position))
+            .map(|(_, change)| change)
+            .sum::<i64>() as usize
+    }
+
+    /// The events at a position.
+    pub fn get(&self, chrom_id: u32, position: u64) -> Option<&PositionEvents> {
+        self.positions.get(&(chrom_id, position))
+    }
+
+    /// The number of positions in the table.
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Whether the table has no positions.
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// The positions and their events, in coordinate order.
+    pub fn iter(&self) -> impl Iterator<Item = ((u32, u64), &PositionEvents)> + '_ {
+        self.positions.iter().map(|(key, events)| (*key, events))
+    }
+
+    /// The positions and their events within the half open interval `start..end` of
+    /// chromosome `chrom_id`, in coordinate order.
+    pub fn range(
+        &self,
+        chrom_id: u32,
+        start: u64,
+        end: u64,
+    ) -> impl Iterator<Item = ((u32, u64), &PositionEvents)> + '_ {
+        self.positions
+            .range((chrom_id, start)..(chrom_id, end.max(start)))
+            .map(|(key, events)| (*key, events))
+    }
+
+    /// The `n` events with the highest counts across the table, as chromosome ID,
+    /// position and count, highest first and ties in coordinate order. Matches are left
+    /// out, since they would otherwise dominate.
+    pub fn top_events(&self, n: usize) -> Vec<(u32, u64, EventCount)> {
+        let mut events: Vec<_> = self
+            .positions
+            .iter()
+            .flat_map(|(&(chrom_id, position), events)| {
+                events
+                    .counts
+                    .iter()
+                    .filter(|((op, _), _)| !matches!(op, CigarOp::Match | CigarOp::Equal))
+                    .map(move |(&(op, length), &count)| {
+                        (chrom_id, position, EventCount { op, length, count })
+                    })
+            })
+            .collect();
+        events.sort_by_key(|(_, _, e)| Reverse(e.count));
+        events.truncate(n);
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collated::collated;
+
+    fn summaries(cigars: Vec<(&str, u32, u64)>) -> Vec<PositionSummary> {
+        collated(cigars)
+            .summarized()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn event_table(cigars: Vec<(&str, u32, u64)>) -> PositionEventTable {
+        PositionEventTable::from_collated(collated(cigars)).unwrap()
+    }
+
+    #[test]
+    fn test_summary_depth() {
+        let result = summaries(vec![("4M", 1, 100), ("2M", 1, 102), ("2M", 1, 104)]);
+        let depths: Vec<_> = result.iter().map(|s| (s.position, s.depth)).collect();
+        assert_eq!(depths, vec![(100, 1), (102, 2), (104, 1)]);
+    }
+
+    #[test]
+    fn test_summary_fractions() {
+        let result = summaries(vec![
+            ("2M1I2M", 1, 100),
+            ("2M1I2M", 1, 100),
+            ("4M", 1, 100),
+            ("4M", 1, 100),
+        ]);
+        let at_102 = result.iter().find(|s| s.position == 102).unwrap();
+        assert_eq!(at_102.depth, 4);
+        assert_eq!(at_102.fraction(CigarOp::Insertion, 1), Some(0.5));
+        assert_eq!(at_102.fraction(CigarOp::Deletion, 1), Some(0.0));
+        let fractions: Vec<_> = at_102.fractions().map(|(e, f)| (e.op, f)).collect();
+        assert_eq!(
+            fractions,
+            vec![(CigarOp::Match, Some(0.5)), (CigarOp::Insertion, Some(0.5))]
+        );
+    }
+
+    #[test]
+    fn test_summary_chromosomes() {
+        let result = summaries(vec![("5M", 1, 100), ("1I4M", 2, 100)]);
+        assert_eq!(result.len(), 2);
+        assert_eq!((result[1].chrom_id, result[1].depth), (2, 1));
+        assert_eq!(result[1].events.len(), 2);
+    }
+
+    #[test]
+    fn test_summary_zero_depth() {
+        let result = summaries(vec![("2M2I", 1, 100)]);
+        assert_eq!(result[1].position, 102);
+        assert_eq!(result[1].depth, 0);
+        assert_eq!(result[1].fraction(CigarOp::Insertion, 2), None);
+    }
+
+    #[test]
+    fn test_summary_with_gaps() {
+        let records = vec![("4M", 1, 100), ("2M", 1, 102), ("3M", 1, 110), ("2M", 2, 5)];
+        let result: Vec<_> = collated(records)
+            .summarized()
+            .with_gaps()
+            .map(|record| match record.unwrap() {
+                CoverageRecord::Events(s) => (s.chrom_id, s.position, s.position + 1, s.depth),
+                CoverageRecord::Gap(g) => (g.chrom_id, g.start, g.end, g.depth),
+            })
+            .collect();
+        assert_eq!(
+            result,
+            vec![
+                (1, 100, 101, 1),
+                (1, 101, 102, 1),
+                (1, 102, 103, 2),
+                (1, 103, 104, 2),
+                (1, 110, 111, 1),
+                (1, 111, 113, 1),
+                (2, 5, 6, 1),
+                (2, 6, 7, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_position_event_table() {
+        let mut table = event_table(vec![("2M1D2M", 1, 100), ("5M", 1, 100), ("2M2I3M", 1, 100)]);
+        assert_eq!(table.len(), 3);
+        let at_102 = table.get(1, 102).unwrap();
+        assert_eq!(at_102.depth, 3);
+        assert_eq!(at_102.frequency(CigarOp::Deletion, 1), Some(1.0 / 3.0));
+        assert_eq!(at_102.top(1)[0].op, CigarOp::Match);
+
+        table.merge(event_table(vec![("2M1D2M", 1, 100), ("3M", 2, 0)]));
+        let at_102 = table.get(1, 102).unwrap();
+        assert_eq!((at_102.depth, at_102.count(CigarOp::Deletion, 1)), (4, 2));
+        let keys: Vec<_> = table.iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![(1, 100), (1, 102), (1, 103), (2, 0)]);
+        assert_eq!(table.range(1, 101, 103).count(), 1);
+
+        let top = table.top_events(2);
+        assert_eq!(
+            (top[0].1, top[0].2.op, top[0].2.count),
+            (102, CigarOp::Deletion, 2)
+        );
+        assert_eq!((top[1].1, top[1].2.op), (102, CigarOp::Insertion));
+    }
+
+    #[test]
+    fn test_position_event_table_merge_depths() {
+        // The second shard has no event at 102, but still covers it.
+        let mut table = event_table(vec![("2M1D2M", 1, 100)]);
+        table.merge(event_table(vec![("5M", 1, 100)]));
+        let at_102 = table.get(1, 102).unwrap();
+        assert_eq!(at_102.depth, 2);
+        assert_eq!(at_102.frequency(CigarOp::Deletion, 1), Some(0.5));
+        assert_eq!(table.get(1, 103).unwrap().depth, 2);
+        assert_eq!(table.depth(1, 101), 2);
+        assert_eq!(table.depth(1, 105), 0);
+
+        // Merging the other way round gives the same table.
+        let mut reversed = event_table(vec![("5M", 1, 100)]);
+        reversed.merge(event_table(vec![("2M1D2M", 1, 100)]));
+        assert_eq!(reversed, table);
+        assert_eq!(table, event_table(vec![("2M1D2M", 1, 100), ("5M", 1, 100)]));
+    }
+}
@@ -0,0 +1,521 @@
+//! Supplementary alignments.
+//!
+//! Aligners split reads spanning large deletions or introns into a primary alignment and
+//! one or more supplementary alignments (recorded in the `SA` tag). When the pieces are
+//! co-linear they can be stitched back together into a single alignment.
+//!
+//! When they are not, as for chimeric reads and fusions, [`read_layout`] describes how
+//! the read is laid out across its alignments instead: the part of the read each aligns,
+//! the parts left unaligned, and where alignments overlap in the read.
+//! [`parse_sa_tag`] reads the supplementary alignments from an `SA` tag.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::Strand;
+//! use cigar_utils::supplementary::{AlignmentSegment, stitch};
+//!
+//! let primary = AlignmentSegment::new("50M50S".parse().unwrap(), 1, 1000, Strand::Forward);
+//! let supplementary = AlignmentSegment::new("50S50M".parse().unwrap(), 1, 1150, Strand::Forward);
+//!
+//! let stitched = stitch(&primary, &[supplementary], 1000).unwrap().unwrap();
+//! assert_eq!(stitched.cigar.to_string(), "50M100D50M");
+//! assert_eq!(stitched.reference_position, 1000);
+//! ```
+
+use crate::builder::CigarBuilder;
+use crate::chrom_map::ChromMap;
+use crate::error::{CigarError, parse_number};
+use crate::trim::trim_read;
+use crate::{Cigar, CigarOp, Strand};
+
+/// One alignment of a read: a CIGAR with its chromosome, position and strand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlignmentSegment {
+    /// The CIGAR of the alignment.
+    pub cigar: Cigar,
+    /// The chromosome ID of the alignment.
+    pub chrom_id: u32,
+    /// The reference position of the alignment.
+    pub reference_position: u64,
+    /// The strand of the alignment.
+    pub strand: Strand,
+}
+
+impl AlignmentSegment {
+    /// Create a new alignment segment.
+    pub fn new(cigar: Cigar, chrom_id: u32, reference_position: u64, strand: Strand) -> Self {
+        AlignmentSegment {
+            cigar,
+            chrom_id,
+            reference_position,
+            strand,
+        }
+    }
+
+    /// The aligned interval of the read, including hard clipped bases, and the full read length.
+    fn read_span(&self) -> (u32, u32, u32) {
+        let mut start = 0;
+        let mut aligned = 0;
+        let mut total = 0;
+        let mut seen_aligned = false;
+        for elem in self.cigar.iter() {
+            match elem.op {
+                CigarOp::SoftClip | CigarOp::HardClip => {
+                    if !seen_aligned {
+                        start += elem.length;
+                    }
+                    total += elem.length;
+                }
+                op if op.consumes_query() => {
+                    seen_aligned = true;
+                    aligned += elem.length;
+                    total += elem.length;
+                }
+                _ => {}
+            }
+        }
+        (start, start + aligned, total)
+    }
+
+    /// The aligned interval of the read in the orientation it was sequenced, including
+    /// hard clipped bases, and the full read length.
+    fn sequenced_span(&self) -> (u32, u32, u32) {
+        let (start, end, total) = self.read_span();
+        match self.strand {
+            Strand::Forward => (start, end, total),
+            Strand::Reverse => (total - end, total - start, total),
+        }
+    }
+
+    fn reference_end(&self) -> u64 {
+        self.reference_position
+            + self
+                .cigar
+                .iter()
+                .filter(|e| e.op.consumes_reference())
+                .map(|e| e.length as u64)
+                .sum::<u64>()
+    }
+
+    fn leading_hard_clip(&self) -> u32 {
+        match self.cigar.elements().first() {
+            Some(e) if e.op == CigarOp::HardClip => e.length,
+            _ => 0,
+        }
+    }
+}
+
+/// Stitch a primary alignment and its supplementary alignments into a single alignment.
+///
+/// Returns `None` if the alignments are not co-linear: on the same chromosome and strand,
+/// with the read and reference intervals in the same order, and with reference gaps that
+/// fit in a CIGAR element. Gaps in the read between
+/// segments become insertions, and gaps in the reference become deletions, or skips if
+/// they are longer than `max_deletion`. Where segments overlap in the read, the later
+/// segment is trimmed.
+pub fn stitch(
+    primary: &AlignmentSegment,
+    supplementary: &[AlignmentSegment],
+    max_deletion: u32,
+) -> std::result::Result<Option<AlignmentSegment>, CigarError> {
+    let (_, _, read_length) = primary.read_span();
+    let mut segments = vec![primary.clone()];
+    for segment in supplementary {
+        if segment.chrom_id != primary.chrom_id || segment.strand != primary.strand {
+            return Ok(None);
+        }
+        let (_, _, length) = segment.read_span();
+        if length != read_length {
+            return Err(CigarError::LengthMismatch(
+                read_length as usize,
+                length as usize,
+            ));
+        }
+        segments.push(segment.clone());
+    }
+    segments.sort_by_key(|s| s.read_span().0);
+
+    let mut builder = CigarBuilder::new();
+    let mut previous: Option<(u32, u64)> = None;
+    for (i, segment) in segments.iter().enumerate() {
+        let mut segment = segment.clone();
+        if let Some((previous_read_end, _)) = previous {
+            let (read_start, read_end, _) = segment.read_span();
+            if read_end <= previous_read_end {
+                return Ok(None);
+            }
+            if read_start < previous_read_end {
+                let hard = segment.leading_hard_clip();
+                let seq_length: u32 = segment
+                    .cigar
+                    .iter()
+                    .filter(|e| e.op.consumes_query())
+                    .map(|e| e.length)
+                    .sum();
+                let (cigar, offset) = trim_read(
+                    &segment.cigar,
+                    (previous_read_end - hard) as usize,
+                    seq_length as usize,
+                )?;
+                segment.cigar = cigar;
+                segment.reference_position += offset as u64;
+            }
+        }
+
+        let (read_start, read_end, _) = segment.read_span();
+        let elements = segment.cigar.elements();
+        let first = elements.iter().position(|e| !is_clip(e.op));
+        let last = elements.iter().rposition(|e| !is_clip(e.op));
+        let (first, last) = match (first, last) {
+            (Some(first), Some(last)) => (first, last + 1),
+            _ => return Ok(None),
+        };
+
+        match previous {
+            None => {
+                for elem in &elements[..first] {
+                    builder.push_element(elem.clone())?;
+                }
+            }
+            Some((previous_read_end, previous_reference_end)) => {
+                if segment.reference_position < previous_reference_end {
+                    return Ok(None);
+                }
+                let read_gap = read_start - previous_read_end;
+                let reference_gap =
+                    match u32::try_from(segment.reference_position - previous_reference_end) {
+                        Ok(gap) => gap,
+                        Err(_) => return Ok(None),
+                    };
+                if read_gap > 0 {
+                    builder.push(CigarOp::Insertion, read_gap)?;
+                }
+                if reference_gap > 0 {
+                    let op = if reference_gap > max_deletion {
+                        CigarOp::Skip
+                    } else {
+                        CigarOp::Deletion
+                    };
+                    builder.push(op, reference_gap)?;
+                }
+            }
+        }
+        for elem in &elements[first..last] {
+            builder.push_element(elem.clone())?;
+        }
+        if i + 1 == segments.len() {
+            for elem in &elements[last..] {
+                builder.push_element(elem.clone())?;
+            }
+        }
+        previous = Some((read_end, segment.reference_end()));
+    }
+
+    let first = &segments[0];
+    Ok(Some(AlignmentSegment::new(
+        builder.build(),
+        first.chrom_id,
+        first.reference_position,
+        first.strand,
+    )))
+}
+
+fn is_clip(op: CigarOp) -> bool {
+    matches!(op, CigarOp::SoftClip | CigarOp::HardClip)
+}
+
+/// The format name given in [`CigarError::InvalidFormat`] errors.
+const FORMAT: &str = "SA";
+
+/// Parse the alignments of an `SA` tag value, such as `chr2,1001,-,30S70M,60,0;`,
+/// numbering chromosomes by the map. Positions are converted to 0-based.
+pub fn parse_sa_tag(
+    value: &str,
+    chroms: &ChromMap,
+) -> std::result::Result<Vec<AlignmentSegment>, CigarError> {
+    value
+        .split(';')
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let fields: Vec<&str> = entry.split(',').collect();
+            let [name, position, strand, cigar, ..] = fields[..] else {
+                return Err(CigarError::invalid_format(
+                    FORMAT,
+                    format!("'{}' has too few fields", entry),
+                ));
+            };
+            let chrom_id = chroms.chrom_id(name).ok_or_else(|| {
+                CigarError::invalid_format(FORMAT, format!("unknown reference sequence '{}'", name))
+            })?;
+            let position: u64 = parse_number(FORMAT, position)?;
+            let strand = match strand {
+                "+" => Strand::Forward,
+                "-" => Strand::Reverse,
+                _ => {
+                    return Err(CigarError::invalid_format(
+                        FORMAT,
+                        format!("'{}' is not a strand", strand),
+                    ));
+                }
+            };
+            Ok(AlignmentSegment::new(
+                cigar.parse()?,
+                chrom_id,
+                position.saturating_sub(1),
+                strand,
+            ))
+        })
+        .collect()
+}
+
+/// Where one alignment of a read lies, in the read and on the reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutSegment {
+    /// The index of the alignment: 0 for the primary, and 1 onwards for the supplementary
+    /// alignments in the order given.
+    pub index: usize,
+    /// The start of the aligned part of the read, in the orientation it was sequenced.
+    pub read_start: u32,
+    /// The end of the aligned part of the read, in the orientation it was sequenced.
+    pub read_end: u32,
+    /// The chromosome ID of the alignment.
+    pub chrom_id: u32,
+    /// The reference position of the start of the alignment.
+    pub reference_start: u64,
+    /// The reference position following the end of the alignment.
+    pub reference_end: u64,
+    /// The strand of the alignment.
+    pub strand: Strand,
+}
+
+/// A part of a read aligned by two alignments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadOverlap {
+    /// The index of the alignment aligning the earlier part of the read.
+    pub first: usize,
+    /// The index of the other alignment.
+    pub second: usize,
+    /// The start of the overlap in the read.
+    pub read_start: u32,
+    /// The end of the overlap in the read.
+    pub read_end: u32,
+}
+
+/// The layout of a read across its alignments. Read coordinates are in the orientation
+/// the read was sequenced, and include hard clipped bases.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadLayout {
+    /// The length of the read.
+    pub read_length: u32,
+    /// The alignments, in order of their start in the read.
+    pub segments: Vec<LayoutSegment>,
+    /// The parts of the read aligned by no alignment, in order.
+    pub unaligned: Vec<(u32, u32)>,
+    /// The parts of the read aligned by more than one alignment, in order.
+    pub overlaps: Vec<ReadOverlap>,
+}
+
+impl ReadLayout {
+    /// The number of read bases aligned by at least one alignment.
+    pub fn aligned_length(&self) -> u32 {
+        self.read_length - self.unaligned.iter().map(|(s, e)| e - s).sum::<u32>()
+    }
+
+    /// Whether the alignments lie on more than one chromosome or strand.
+    pub fn is_chimeric(&self) -> bool {
+        self.segments
+            .windows(2)
+            .any(|pair| (pair[0].chrom_id, pair[0].strand) != (pair[1].chrom_id, pair[1].strand))
+    }
+}
+
+/// The layout of a read across a primary alignment and its supplementary alignments.
+///
+/// Fails with [`CigarError::LengthMismatch`] if the alignments imply different read
+/// lengths, counting hard clipped bases.
+pub fn read_layout(
+    primary: &AlignmentSegment,
+    supplementary: &[AlignmentSegment],
+) -> std::result::Result<ReadLayout, CigarError> {
+    let (_, _, read_length) = primary.read_span();
+    let mut segments = Vec::with_capacity(supplementary.len() + 1);
+    for (index, segment) in std::iter::once(primary).chain(supplementary).enumerate() {
+        let (read_start, read_end, length) = segment.sequenced_span();
+        if length != read_length {
+            return Err(CigarError::LengthMismatch(
+                read_length as usize,
+                length as usize,
+            ));
+        }
+        segments.push(LayoutSegment {
+            index,
+            read_start,
+            read_end,
+            chrom_id: segment.chrom_id,
+            reference_start: segment.reference_position,
+            reference_end: segment.reference_end(),
+            strand: segment.strand,
+        });
+    }
+    segments.sort_by_key(|s| (s.read_start, s.read_end));
+
+    let mut unaligned = Vec::new();
+    let mut covered = 0;
+    for segment in segments.iter().filter(|s| s.read_end > s.read_start) {
+        if segment.read_start > covered {
+            unaligned.push((covered, segment.read_start));
+        }
+        covered = covered.max(segment.read_end);
+    }
+    if covered < read_length {
+        unaligned.push((covered, read_length));
+    }
+
+    let mut overlaps = Vec::new();
+    for (i, a) in segments.iter().enumerate() {
+        for b in &segments[i + 1..] {
+            let (start, end) = (b.read_start, a.read_end.min(b.read_end));
+            if start < end {
+                overlaps.push(ReadOverlap {
+                    first: a.index,
+                    second: b.index,
+                    read_start: start,
+                    read_end: end,
+                });
+            }
+        }
+    }
+    overlaps.sort_by_key(|o| (o.read_start, o.read_end));
+
+    Ok(ReadLayout {
+        read_length,
+        segments,
+        unaligned,
+        overlaps,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(cigar: &str, chrom_id: u32, pos: u64) -> AlignmentSegment {
+        AlignmentSegment::new(cigar.parse().unwrap(), chrom_id, pos, Strand::Forward)
+    }
+
+    #[test]
+    fn test_stitch_skip() {
+        let primary = segment("50M50S", 1, 1000);
+        let supplementary = segment("50S50M", 1, 1200);
+        let stitched = stitch(&primary, &[supplementary], 100).unwrap().unwrap();
+        assert_eq!(stitched.cigar.to_string(), "50M150N50M");
+    }
+
+    #[test]
+    fn test_stitch_read_gap() {
+        let primary = segment("50M50S", 1, 1000);
+        let supplementary = segment("55H45M", 1, 1100);
+        let stitched = stitch(&primary, &[supplementary], 100).unwrap().unwrap();
+        assert_eq!(stitched.cigar.to_string(), "50M5I50D45M");
+    }
+
+    #[test]
+    fn test_stitch_read_overlap() {
+        let primary = segment("60M40S", 1, 1000);
+        let supplementary = segment("50S50M", 1, 1100);
+        let stitched = stitch(&primary, &[supplementary], 100).unwrap().unwrap();
+        assert_eq!(stitched.cigar.to_string(), "60M50D40M");
+    }
+
+    #[test]
+    fn test_stitch_supplementary_first() {
+        let primary = segment("50S50M", 1, 1100);
+        let supplementary = segment("10S40M50H", 1, 1000);
+        let stitched = stitch(&primary, &[supplementary], 100).unwrap().unwrap();
+        assert_eq!(stitched.cigar.to_string(), "10S40M60D50M");
+        assert_eq!(stitched.reference_position, 1000);
+    }
+
+    #[test]
+    fn test_stitch_not_colinear() {
+        let primary = segment("50M50S", 1, 1000);
+        assert!(
+            stitch(&primary, &[segment("50S50M", 2, 1200)], 100)
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            stitch(&primary, &[segment("50S50M", 1, 900)], 100)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_read_layout() {
+        let primary = segment("30M70S", 1, 1000);
+        let reverse = AlignmentSegment::new("25H45M30H".parse().unwrap(), 2, 5000, Strand::Reverse);
+        let layout = read_layout(&primary, &[segment("80S20M", 1, 2000), reverse]).unwrap();
+        let spans: Vec<_> = layout
+            .segments
+            .iter()
+            .map(|s| (s.index, s.read_start, s.read_end, s.reference_end))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![(0, 0, 30, 1030), (2, 30, 75, 5045), (1, 80, 100, 2020)]
+        );
+        assert_eq!(layout.unaligned, vec![(75, 80)]);
+        assert!(layout.overlaps.is_empty());
+        assert_eq!(layout.aligned_length(), 95);
+        assert!(layout.is_chimeric());
+
+        let layout = read_layout(&primary, &[segment("28S20M52S", 1, 1500)]).unwrap();
+        assert_eq!(
+            layout.overlaps,
+            vec![ReadOverlap {
+                first: 0,
+                second: 1,
+                read_start: 28,
+                read_end: 30
+            }]
+        );
+        assert_eq!(layout.unaligned, vec![(48, 100)]);
+        assert!(!layout.is_chimeric());
+        assert!(matches!(
+            read_layout(&primary, &[segment("50M", 1, 0)]),
+            Err(CigarError::LengthMismatch(100, 50))
+        ));
+    }
+
+    #[test]
+    fn test_parse_sa_tag() {
+        let mut chroms = ChromMap::new();
+        chroms.push("chr1", 10000).unwrap();
+        chroms.push("chr2", 10000).unwrap();
+        let segments = parse_sa_tag(
+            "chr2,5001,-,25H45M30H,60,0;chr1,2001,+,80S20M,30,1;",
+            &chroms,
+        )
+        .unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(
+            (
+                segments[0].chrom_id,
+                segments[0].reference_position,
+                segments[0].strand
+            ),
+            (1, 5000, Strand::Reverse)
+        );
+        assert_eq!(segments[1].cigar.to_string(), "80S20M");
+        assert!(matches!(
+            parse_sa_tag("chr3,1,+,10M,60,0", &chroms),
+            Err(CigarError::InvalidFormat("SA", _))
+        ));
+        assert!(matches!(
+            parse_sa_tag("chr1,1,?,10M,60,0", &chroms),
+            Err(CigarError::InvalidFormat("SA", _))
+        ));
+    }
+}
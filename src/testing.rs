@@ -0,0 +1,192 @@
+//! Property testing support (`testing` feature).
+//!
+//! Provides [`proptest`] strategies and [`Arbitrary`] implementations for [`CigarOp`],
+//! [`CigarElement`] and [`Cigar`], so downstream crates can property-test their CIGAR
+//! handling against this crate's parser and printer.
+//!
+//! Generated elements always have a non-zero length. Generated CIGARs are valid by the
+//! rules of the SAM specification (see [`validate`](crate::validate::validate)): hard
+//! clips appear only at the ends, and soft clips only have hard clips between them and
+//! the ends.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::Cigar;
+//! use cigar_utils::testing::CigarParameters;
+//! use proptest::prelude::*;
+//!
+//! proptest! {
+//!     fn round_trip(cigar in any_with::<Cigar>(CigarParameters { max_elements: 5, ..Default::default() })) {
+//!         prop_assert_eq!(cigar.to_string().parse::<Cigar>().unwrap(), cigar);
+//!     }
+//! }
+//! # round_trip();
+//! ```
+
+use proptest::prelude::*;
+
+use crate::{Cigar, CigarElement, CigarOp};
+
+/// Every CIGAR operation.
+const OPERATIONS: [CigarOp; 9] = [
+    CigarOp::Match,
+    CigarOp::Insertion,
+    CigarOp::Deletion,
+    CigarOp::Skip,
+    CigarOp::SoftClip,
+    CigarOp::HardClip,
+    CigarOp::Padding,
+    CigarOp::Equal,
+    CigarOp::Diff,
+];
+
+/// The operations allowed between the clips of a CIGAR.
+const ALIGNED_OPERATIONS: [CigarOp; 7] = [
+    CigarOp::Match,
+    CigarOp::Insertion,
+    CigarOp::Deletion,
+    CigarOp::Skip,
+    CigarOp::Padding,
+    CigarOp::Equal,
+    CigarOp::Diff,
+];
+
+/// The default longest element generated.
+pub const DEFAULT_MAX_LENGTH: u32 = 1000;
+
+/// The default largest number of elements between the clips of a generated CIGAR.
+pub const DEFAULT_MAX_ELEMENTS: usize = 20;
+
+/// Parameters for generating CIGARs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CigarParameters {
+    /// The longest element generated.
+    pub max_length: u32,
+    /// The largest number of elements between the clips (at least one is generated).
+    pub max_elements: usize,
+    /// Whether to generate leading and trailing soft and hard clips.
+    pub clips: bool,
+}
+
+impl Default for CigarParameters {
+    fn default() -> Self {
+        CigarParameters {
+            max_length: DEFAULT_MAX_LENGTH,
+            max_elements: DEFAULT_MAX_ELEMENTS,
+            clips: true,
+        }
+    }
+}
+
+/// A strategy generating any CIGAR operation.
+pub fn cigar_op() -> impl Strategy<Value = CigarOp> {
+    proptest::sample::select(&OPERATIONS[..])
+}
+
+/// A strategy generating any operation other than a clip.
+pub fn aligned_op() -> impl Strategy<Value = CigarOp> {
+    proptest::sample::select(&ALIGNED_OPERATIONS[..])
+}
+
+/// A strategy generating elements of `op` with lengths from 1 to `max_length`.
+pub fn element_of(
+    op: impl Strategy<Value = CigarOp>,
+    max_length: u32,
+) -> impl Strategy<Value = CigarElement> {
+    (1..=max_length.max(1), op).prop_map(|(length, op)| CigarElement::new(length, op))
+}
+
+/// An optional clip of `op`.
+fn clip(op: CigarOp, parameters: &CigarParameters) -> BoxedStrategy<Option<CigarElement>> {
+    if parameters.clips {
+        proptest::option::of(element_of(Just(op), parameters.max_length)).boxed()
+    } else {
+        Just(None).boxed()
+    }
+}
+
+/// A strategy generating valid CIGARs.
+pub fn cigar(parameters: CigarParameters) -> impl Strategy<Value = Cigar> {
+    let body = proptest::collection::vec(
+        element_of(aligned_op(), parameters.max_length),
+        1..=parameters.max_elements.max(1),
+    );
+    (
+        clip(CigarOp::HardClip, &parameters),
+        clip(CigarOp::SoftClip, &parameters),
+        body,
+        clip(CigarOp::SoftClip, &parameters),
+        clip(CigarOp::HardClip, &parameters),
+    )
+        .prop_map(
+            |(leading_hard, leading_soft, body, trailing_soft, trailing_hard)| {
+                let mut elements = Vec::new();
+                elements.extend(leading_hard);
+                elements.extend(leading_soft);
+                elements.extend(body);
+                elements.extend(trailing_soft);
+                elements.extend(trailing_hard);
+                Cigar::from(elements)
+            },
+        )
+}
+
+impl Arbitrary for CigarOp {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<CigarOp>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        cigar_op().boxed()
+    }
+}
+
+impl Arbitrary for CigarElement {
+    /// The longest element generated.
+    type Parameters = u32;
+    type Strategy = BoxedStrategy<CigarElement>;
+
+    fn arbitrary_with(max_length: Self::Parameters) -> Self::Strategy {
+        let max_length = if max_length == 0 {
+            DEFAULT_MAX_LENGTH
+        } else {
+            max_length
+        };
+        element_of(cigar_op(), max_length).boxed()
+    }
+}
+
+impl Arbitrary for Cigar {
+    type Parameters = CigarParameters;
+    type Strategy = BoxedStrategy<Cigar>;
+
+    fn arbitrary_with(parameters: Self::Parameters) -> Self::Strategy {
+        cigar(parameters).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate::{ValidationOptions, validate};
+
+    proptest! {
+        #[test]
+        fn test_element_round_trip(elem in any::<CigarElement>()) {
+            let cigar: Cigar = elem.to_string().parse().unwrap();
+            prop_assert_eq!(cigar.elements(), &[elem]);
+        }
+
+        #[test]
+        fn test_cigars_are_valid(cigar in any::<Cigar>()) {
+            prop_assert!(validate(&cigar, &ValidationOptions::default()).is_empty());
+            prop_assert_eq!(cigar.to_string().parse::<Cigar>().unwrap(), cigar);
+        }
+
+        #[test]
+        fn test_parameters(cigar in any_with::<Cigar>(CigarParameters { max_length: 3, max_elements: 2, clips: false })) {
+            prop_assert!((1..=2).contains(&cigar.len()));
+            prop_assert!(cigar.iter().all(|e| e.length <= 3 && !matches!(e.op, CigarOp::SoftClip | CigarOp::HardClip)));
+        }
+    }
+}
@@ -0,0 +1,401 @@
+//! Tracepoint encoding of alignments.
+//!
+//! Storing full CIGARs for long noisy reads (such as all-vs-all nanopore overlaps) is
+//! expensive. Tracepoints, as used by DALIGNER, divide the reference into segments at
+//! fixed spacing and record for each segment only the number of read bases aligned to
+//! it and the number of differences. Given the sequences, an alignment is rebuilt by
+//! aligning each segment independently, which is cheap because segments are short.
+//!
+//! Reconstruction is lossless up to the placement of edits within a segment: the
+//! rebuilt alignment has the same segment boundaries, and no more differences in each
+//! segment, than the original.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::tracepoints::{decode_tracepoints, encode_tracepoints};
+//!
+//! let reference = b"ACGTACGTACGTACGTACGT";
+//! let seq = b"ACGTACTTACGTAACGTACGT";
+//! let tracepoints = encode_tracepoints(0, "6M1X5M1I8M", reference, seq, 8).unwrap();
+//! assert_eq!(tracepoints.trace(), vec![1, 8, 1, 9, 0, 4]);
+//!
+//! let cigar = decode_tracepoints(&tracepoints, reference, seq).unwrap();
+//! assert_eq!(cigar.to_string(), "6=1X5=1I8=");
+//! ```
+
+use crate::builder::CigarBuilder;
+use crate::error::CigarError;
+use crate::expand::ExpandedCigarIterator;
+use crate::{Cigar, CigarOp};
+
+/// The alignment of one reference segment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Tracepoint {
+    /// The number of differences (mismatched, inserted and deleted bases).
+    pub diffs: u32,
+    /// The number of read bases aligned to the segment.
+    pub query_length: u32,
+}
+
+/// An alignment encoded as tracepoints.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tracepoints {
+    /// The reference spacing of segment boundaries.
+    pub spacing: usize,
+    /// The reference position of the first aligned base.
+    pub reference_start: usize,
+    /// The reference position following the last aligned base.
+    pub reference_end: usize,
+    /// The read position of the first aligned base (the length of any leading soft clip).
+    pub query_start: usize,
+    /// The alignment of each segment, in reference order.
+    pub points: Vec<Tracepoint>,
+}
+
+impl Tracepoints {
+    /// The reference interval of each segment: boundaries fall on multiples of the spacing.
+    pub fn segments(&self) -> Vec<(usize, usize)> {
+        let mut segments = Vec::new();
+        let mut start = self.reference_start;
+        while start < self.reference_end {
+            let end = ((start / self.spacing + 1) * self.spacing).min(self.reference_end);
+            segments.push((start, end));
+            start = end;
+        }
+        segments
+    }
+
+    /// The tracepoints as a flat trace of (differences, read bases) pairs, as DALIGNER
+    /// stores them.
+    pub fn trace(&self) -> Vec<u32> {
+        self.points
+            .iter()
+            .flat_map(|p| [p.diffs, p.query_length])
+            .collect()
+    }
+
+    /// Rebuild tracepoints from a flat trace and the alignment's coordinates.
+    ///
+    /// Fails if the spacing is zero, or the trace does not have one pair of values for
+    /// each segment.
+    pub fn from_trace(
+        spacing: usize,
+        reference_start: usize,
+        reference_end: usize,
+        query_start: usize,
+        trace: &[u32],
+    ) -> std::result::Result<Tracepoints, CigarError> {
+        check_spacing(spacing)?;
+        if !trace.len().is_multiple_of(2) {
+            return Err(CigarError::invalid_format(
+                "tracepoint",
+                "trace must be pairs of values",
+            ));
+        }
+        let tracepoints = Tracepoints {
+            spacing,
+            reference_start,
+            reference_end,
+            query_start,
+            points: trace
+                .chunks(2)
+                .map(|pair| Tracepoint {
+                    diffs: pair[0],
+                    query_length: pair[1],
+                })
+                .collect(),
+        };
+        let segments = tracepoints.segments().len();
+        if segments != tracepoints.points.len() {
+            return Err(CigarError::LengthMismatch(
+                segments,
+                tracepoints.points.len(),
+            ));
+        }
+        Ok(tracepoints)
+    }
+}
+
+fn check_spacing(spacing: usize) -> std::result::Result<(), CigarError> {
+    if spacing == 0 {
+        return Err(CigarError::invalid_format(
+            "tracepoint",
+            "spacing must be positive",
+        ));
+    }
+    Ok(())
+}
+
+/// Encode a read aligned at `reference_position` as tracepoints with the given reference
+/// spacing.
+///
+/// Alignment matches are resolved against the sequences to count mismatches. Insertions
+/// belong to the segment of the reference base following them, and skips are counted
+/// as deletions. Clips are not encoded, other than the read position of the first
+/// aligned base. Fails if `spacing` is zero.
+pub fn encode_tracepoints<R: AsRef<[u8]>, S: AsRef<[u8]>>(
+    reference_position: usize,
+    cigar: &str,
+    reference: &R,
+    seq: &S,
+    spacing: usize,
+) -> std::result::Result<Tracepoints, CigarError> {
+    check_spacing(spacing)?;
+    let mut tracepoints = Tracepoints {
+        spacing,
+        reference_start: reference_position,
+        reference_end: reference_position,
+        query_start: 0,
+        points: vec![Tracepoint::default()],
+    };
+    let mut aligned = false;
+    let mut position = reference_position;
+    let (reference, seq) = (reference.as_ref(), seq.as_ref());
+    for elem in ExpandedCigarIterator::new(reference_position, cigar, reference, seq) {
+        let elem = elem?;
+        let mut length = elem.length as usize;
+        if elem.op == CigarOp::SoftClip {
+            if !aligned {
+                tracepoints.query_start += length;
+            }
+            continue;
+        }
+        if elem.op == CigarOp::Insertion {
+            let point = tracepoints.points.last_mut().expect("a current segment");
+            point.diffs += elem.length;
+            point.query_length += elem.length;
+            aligned = true;
+            continue;
+        }
+        if !elem.op.consumes_reference() {
+            continue;
+        }
+        aligned = true;
+        while length > 0 {
+            let boundary = (position / spacing + 1) * spacing;
+            let take = length.min(boundary - position);
+            let point = tracepoints.points.last_mut().expect("a current segment");
+            if elem.op != CigarOp::Equal {
+                point.diffs += take as u32;
+            }
+            if elem.op.consumes_query() {
+                point.query_length += take as u32;
+            }
+            position += take;
+            length -= take;
+            if position == boundary {
+                tracepoints.points.push(Tracepoint::default());
+            }
+        }
+    }
+    tracepoints.reference_end = position;
+    // The segment opened at the final boundary has no reference bases, so fold it, and
+    // any insertion after the boundary, into the last segment.
+    if tracepoints.points.len() > tracepoints.segments().len() && tracepoints.points.len() > 1 {
+        let trailing = tracepoints.points.pop().expect("a trailing segment");
+        let point = tracepoints.points.last_mut().expect("a last segment");
+        point.diffs += trailing.diffs;
+        point.query_length += trailing.query_length;
+    }
+    Ok(tracepoints)
+}
+
+/// Globally align a read segment to a reference segment with the fewest edits, appending
+/// the alignment to `builder`.
+fn align_segment(
+    reference: &[u8],
+    query: &[u8],
+    builder: &mut CigarBuilder,
+) -> std::result::Result<(), CigarError> {
+    let (n, m) = (reference.len(), query.len());
+    let width = m + 1;
+    let mut costs = vec![0u32; (n + 1) * width];
+    for i in 0..=n {
+        for j in 0..=m {
+            costs[i * width + j] = match (i, j) {
+                (0, _) => j as u32,
+                (_, 0) => i as u32,
+                _ => {
+                    let diagonal = costs[(i - 1) * width + j - 1]
+                        + u32::from(!reference[i - 1].eq_ignore_ascii_case(&query[j - 1]));
+                    let deletion = costs[(i - 1) * width + j] + 1;
+                    let insertion = costs[i * width + j - 1] + 1;
+                    diagonal.min(deletion).min(insertion)
+                }
+            };
+        }
+    }
+    let mut ops = Vec::with_capacity(n + m);
+    let (mut i, mut j) = (n, m);
+    while i > 0 || j > 0 {
+        let cost = costs[i * width + j];
+        if i > 0 && j > 0 {
+            let matched = reference[i - 1].eq_ignore_ascii_case(&query[j - 1]);
+            if cost == costs[(i - 1) * width + j - 1] + u32::from(!matched) {
+                ops.push(if matched {
+                    CigarOp::Equal
+                } else {
+                    CigarOp::Diff
+                });
+                (i, j) = (i - 1, j - 1);
+                continue;
+            }
+        }
+        if i > 0 && cost == costs[(i - 1) * width + j] + 1 {
+            ops.push(CigarOp::Deletion);
+            i -= 1;
+        } else {
+            ops.push(CigarOp::Insertion);
+            j -= 1;
+        }
+    }
+    for op in ops.into_iter().rev() {
+        builder.push(op, 1)?;
+    }
+    Ok(())
+}
+
+/// Rebuild the CIGAR of an alignment from its tracepoints and sequences.
+///
+/// Each segment is aligned with the fewest edits, giving sequence match and mismatch
+/// (`=`/`X`) elements. Read bases before and after the aligned part are soft clipped.
+pub fn decode_tracepoints<R: AsRef<[u8]>, S: AsRef<[u8]>>(
+    tracepoints: &Tracepoints,
+    reference: &R,
+    seq: &S,
+) -> std::result::Result<Cigar, CigarError> {
+    let (reference, seq) = (reference.as_ref(), seq.as_ref());
+    if tracepoints.reference_end > reference.len() {
+        return Err(CigarError::ReferenceOutOfBounds(
+            tracepoints.reference_start as u64,
+            tracepoints.reference_end as u64,
+        ));
+    }
+    let segments = tracepoints.segments();
+    if segments.len() != tracepoints.points.len() {
+        return Err(CigarError::LengthMismatch(
+            segments.len(),
+            tracepoints.points.len(),
+        ));
+    }
+    let query_end = tracepoints.query_start
+        + tracepoints
+            .points
+            .iter()
+            .map(|p| p.query_length as usize)
+            .sum::<usize>();
+    if query_end > seq.len() {
+        return Err(CigarError::SequenceTooShort(query_end, seq.len()));
+    }
+    let mut builder = CigarBuilder::new();
+    if tracepoints.query_start > 0 {
+        builder.push(CigarOp::SoftClip, tracepoints.query_start as u32)?;
+    }
+    let mut query_position = tracepoints.query_start;
+    for ((start, end), point) in segments.into_iter().zip(tracepoints.points.iter()) {
+        let query_next = query_position + point.query_length as usize;
+        align_segment(
+            &reference[start..end],
+            &seq[query_position..query_next],
+            &mut builder,
+        )?;
+        query_position = query_next;
+    }
+    if seq.len() > query_end {
+        builder.push(CigarOp::SoftClip, (seq.len() - query_end) as u32)?;
+    }
+    Ok(builder.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segments() {
+        let tracepoints =
+            Tracepoints::from_trace(10, 15, 42, 0, &[0, 5, 1, 10, 0, 10, 2, 2]).unwrap();
+        assert_eq!(
+            tracepoints.segments(),
+            vec![(15, 20), (20, 30), (30, 40), (40, 42)]
+        );
+        assert!(matches!(
+            Tracepoints::from_trace(10, 15, 42, 0, &[0, 5, 1]),
+            Err(CigarError::InvalidFormat("tracepoint", _))
+        ));
+        assert!(matches!(
+            Tracepoints::from_trace(10, 15, 42, 0, &[0, 5]),
+            Err(CigarError::LengthMismatch(4, 1))
+        ));
+    }
+
+    #[test]
+    fn test_round_trip_with_clips_and_deletion() {
+        let reference = b"TTTTACGTACGGACGTACGATT";
+        let seq = b"GGACGTACGACGTAGGATCC";
+        let cigar = "2S9M1D7M2S";
+        let tracepoints = encode_tracepoints(4, cigar, reference, seq, 5).unwrap();
+        assert_eq!(tracepoints.query_start, 2);
+        assert_eq!(
+            (tracepoints.reference_start, tracepoints.reference_end),
+            (4, 21)
+        );
+        let diffs: u32 = tracepoints.points.iter().map(|p| p.diffs).sum();
+        assert_eq!(diffs, 4);
+
+        let decoded = decode_tracepoints(&tracepoints, reference, seq).unwrap();
+        let total = |cigar: &Cigar| {
+            cigar
+                .iter()
+                .filter(|e| !matches!(e.op, CigarOp::Equal | CigarOp::SoftClip))
+                .map(|e| e.length)
+                .sum::<u32>()
+        };
+        assert!(total(&decoded) <= diffs);
+        assert_eq!(decoded.reference_length(), 17);
+        assert_eq!(decoded.query_length(), seq.len());
+        assert_eq!(decoded.leading_clip(), 2);
+    }
+
+    #[test]
+    fn test_round_trip_trailing_insertion() {
+        // An insertion after the last boundary belongs to the last segment.
+        for (cigar, seq, expected) in [
+            ("4M1I", &b"ACGTA"[..], "4=1I"),
+            ("4M2I", &b"ACGTAA"[..], "4=2I"),
+        ] {
+            let tracepoints = encode_tracepoints(0, cigar, b"ACGT", &seq, 2).unwrap();
+            assert_eq!(tracepoints.points.len(), tracepoints.segments().len());
+            let restored = Tracepoints::from_trace(
+                2,
+                tracepoints.reference_start,
+                tracepoints.reference_end,
+                tracepoints.query_start,
+                &tracepoints.trace(),
+            )
+            .unwrap();
+            assert_eq!(restored, tracepoints);
+            let decoded = decode_tracepoints(&tracepoints, b"ACGT", &seq).unwrap();
+            assert_eq!(decoded.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let tracepoints = encode_tracepoints(0, "4M", b"ACGT", b"ACGT", 2).unwrap();
+        assert!(matches!(
+            decode_tracepoints(&tracepoints, b"ACG", b"ACGT"),
+            Err(CigarError::ReferenceOutOfBounds(0, 4))
+        ));
+        assert!(matches!(
+            decode_tracepoints(&tracepoints, b"ACGT", b"ACG"),
+            Err(CigarError::SequenceTooShort(4, 3))
+        ));
+        assert!(matches!(
+            Tracepoints::from_trace(0, 0, 4, 0, &[0, 4]),
+            Err(CigarError::InvalidFormat("tracepoint", _))
+        ));
+        assert!(encode_tracepoints(0, "4M", b"ACGT", b"ACGT", 0).is_err());
+    }
+}
@@ -0,0 +1,328 @@
+//! Trimming alignments.
+//!
+//! When bases are cut from a read (adapter or primer trimming, for example) the CIGAR
+//! must be rewritten so that the trimmed bases are soft clipped and the alignment
+//! position accounts for any reference bases that are no longer aligned.
+//! [`trim_read`] trims by read coordinates, [`trim_primers`] by the reference
+//! intervals of amplicon primers, as `ivar trim` and `samtools ampliconclip` do, and
+//! [`trim_qualities`] by base quality, cutting low quality read ends after alignment
+//! as ancient DNA and amplicon workflows do.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::Cigar;
+//! use cigar_utils::trim::trim_read;
+//!
+//! let cigar: Cigar = "3M2D5M".parse().unwrap();
+//! let (trimmed, reference_offset) = trim_read(&cigar, 3, 8).unwrap();
+//! assert_eq!(trimmed.to_string(), "3S5M");
+//! assert_eq!(reference_offset, 5);
+//! ```
+
+use crate::builder::CigarBuilder;
+use crate::error::CigarError;
+use crate::validate::check_query_length;
+use crate::{Cigar, CigarElement, CigarOp};
+
+/// Trim an alignment to the read interval `read_start..read_end`.
+///
+/// Read coordinates are positions in SEQ, so they include soft clipped bases but not
+/// hard clipped ones. Aligned bases and insertions outside the interval become soft
+/// clips, and deletions and skips outside it are dropped. The kept region is further
+/// trimmed so that it begins and ends with an aligned (`M`, `=` or `X`) element. Hard
+/// clips are preserved.
+///
+/// Returns the trimmed CIGAR and the number of reference bases by which the alignment
+/// start moves to the right, to be added to the original alignment position.
+pub fn trim_read(
+    cigar: &Cigar,
+    read_start: usize,
+    read_end: usize,
+) -> std::result::Result<(Cigar, usize), CigarError> {
+    let query_length: usize = cigar
+        .iter()
+        .filter(|e| e.op.consumes_query())
+        .map(|e| e.length as usize)
+        .sum();
+    if read_start > read_end || read_end > query_length {
+        return Err(CigarError::InvalidRange(read_start, read_end));
+    }
+
+    let mut leading_hard = 0;
+    let mut trailing_hard = 0;
+    let mut leading_soft = 0;
+    let mut trailing_soft = 0;
+    let mut reference_offset = 0;
+    let mut middle: Vec<CigarElement> = Vec::new();
+
+    let mut read_position = 0;
+    for elem in cigar.iter() {
+        let length = elem.length as usize;
+        if elem.op == CigarOp::HardClip {
+            if read_position == 0 && middle.is_empty() && leading_soft == 0 {
+                leading_hard += elem.length;
+            } else {
+                trailing_hard += elem.length;
+            }
+            continue;
+        }
+        if !elem.op.consumes_query() {
+            if read_position <= read_start && middle.is_empty() {
+                if elem.op.consumes_reference() {
+                    reference_offset += length;
+                }
+            } else if read_position < read_end {
+                middle.push(elem.clone());
+            }
+            continue;
+        }
+
+        let start = read_position;
+        let end = read_position + length;
+        read_position = end;
+
+        let before = start.max(read_start.min(end)) - start;
+        let after = end - end.min(read_end.max(start));
+        let kept = length - before - after;
+
+        leading_soft += before;
+        if elem.op.consumes_reference() {
+            reference_offset += before;
+        }
+        if kept > 0 {
+            middle.push(CigarElement::new(kept as u32, elem.op));
+        }
+        trailing_soft += after;
+    }
+
+    // The kept region must start and end with aligned bases.
+    let first = middle.iter().position(|e| is_aligned(e.op));
+    let last = middle.iter().rposition(|e| is_aligned(e.op));
+    let (first, last) = match (first, last) {
+        (Some(first), Some(last)) => (first, last + 1),
+        _ => (middle.len(), middle.len()),
+    };
+    for elem in &middle[..first] {
+        if elem.op.consumes_query() {
+            leading_soft += elem.length as usize;
+        }
+        if elem.op.consumes_reference() {
+            reference_offset += elem.length as usize;
+        }
+    }
+    for elem in &middle[last..] {
+        if elem.op.consumes_query() {
+            trailing_soft += elem.length as usize;
+        }
+    }
+
+    let mut builder = CigarBuilder::new();
+    push_nonzero(&mut builder, CigarOp::HardClip, leading_hard)?;
+    push_nonzero(&mut builder, CigarOp::SoftClip, leading_soft as u32)?;
+    for elem in &middle[first..last] {
+        builder.push_element(elem.clone())?;
+    }
+    push_nonzero(&mut builder, CigarOp::SoftClip, trailing_soft as u32)?;
+    push_nonzero(&mut builder, CigarOp::HardClip, trailing_hard)?;
+    Ok((builder.build(), reference_offset))
+}
+
+/// Soft clip the bases at the ends of an alignment at `position` which lie in primers,
+/// given as half open reference intervals.
+///
+/// A primer is trimmed from the start of the alignment if it contains the first aligned
+/// base, and from the end if it contains the last; the bases aligned to it, and any
+/// inserted among them, are soft clipped as by [`trim_read`]. Where several primers
+/// contain an end, the one reaching furthest into the alignment is used. If the primers
+/// cover the whole alignment, every base is soft clipped.
+///
+/// Returns the trimmed CIGAR and the new alignment position.
+pub fn trim_primers(
+    cigar: &Cigar,
+    position: u64,
+    primers: &[(u64, u64)],
+) -> std::result::Result<(Cigar, u64), CigarError> {
+    let end = cigar.alignment_end(position);
+    if end == position {
+        return Ok((cigar.clone(), position));
+    }
+    let left = primers
+        .iter()
+        .filter(|&&(start, stop)| start <= position && position < stop)
+        .map(|&(_, stop)| stop)
+        .max()
+        .unwrap_or(position);
+    let right = primers
+        .iter()
+        .filter(|&&(start, stop)| start < end && end <= stop)
+        .map(|&(start, _)| start)
+        .min()
+        .unwrap_or(end);
+
+    // The read interval of the bases aligned within left..right.
+    let mut kept: Option<(usize, usize)> = None;
+    let (mut read_position, mut reference_position) = (0, position);
+    for elem in cigar.iter() {
+        let length = elem.length as usize;
+        if is_aligned(elem.op) {
+            let lo = reference_position.max(left);
+            let hi = (reference_position + length as u64).min(right);
+            if lo < hi {
+                let first = read_position + (lo - reference_position) as usize;
+                let last = read_position + (hi - reference_position) as usize;
+                kept = Some((kept.map_or(first, |(first, _)| first), last));
+            }
+        }
+        if elem.op.consumes_query() {
+            read_position += length;
+        }
+        if elem.op.consumes_reference() {
+            reference_position += length as u64;
+        }
+    }
+    let (read_start, read_end) = kept.unwrap_or((0, 0));
+    let (trimmed, offset) = trim_read(cigar, read_start, read_end)?;
+    Ok((trimmed, position + offset as u64))
+}
+
+/// Soft clip the low quality ends of an alignment at `position`, given the Phred base
+/// qualities of SEQ without an ASCII offset.
+///
+/// Each end of the read is trimmed up to the first window of `window` bases whose mean
+/// quality reaches `threshold`, and then past any bases below `threshold` at the edge
+/// of that window, in the manner of a sliding window trimmer. The bases trimmed are
+/// soft clipped as by [`trim_read`]. Reads shorter than the window are judged by their
+/// mean quality. If no window reaches the threshold, every base is soft clipped.
+///
+/// Gives [`CigarError::LengthMismatch`] if the qualities do not match the query
+/// length of the CIGAR. Returns the trimmed CIGAR and the new alignment position.
+pub fn trim_qualities(
+    cigar: &Cigar,
+    position: u64,
+    qualities: &[u8],
+    threshold: u8,
+    window: usize,
+) -> std::result::Result<(Cigar, u64), CigarError> {
+    check_query_length(cigar, qualities.len())?;
+    if qualities.is_empty() {
+        return Ok((cigar.clone(), position));
+    }
+    let window = window.clamp(1, qualities.len());
+    let passes = |bases: &[u8]| {
+        let total: u64 = bases.iter().map(|&q| q as u64).sum();
+        total >= threshold as u64 * bases.len() as u64
+    };
+    let (read_start, read_end) = match qualities.windows(window).position(passes) {
+        Some(first) => {
+            let last = qualities.len() - qualities.windows(window).rev().position(passes).unwrap();
+            let good = |&q: &u8| q >= threshold;
+            let start = first + qualities[first..].iter().position(good).unwrap_or(0);
+            let end = qualities[..last]
+                .iter()
+                .rposition(good)
+                .map_or(last, |i| i + 1);
+            if start < end { (start, end) } else { (0, 0) }
+        }
+        None => (0, 0),
+    };
+    let (trimmed, offset) = trim_read(cigar, read_start, read_end)?;
+    Ok((trimmed, position + offset as u64))
+}
+
+fn is_aligned(op: CigarOp) -> bool {
+    matches!(op, CigarOp::Match | CigarOp::Equal | CigarOp::Diff)
+}
+
+fn push_nonzero(
+    builder: &mut CigarBuilder,
+    op: CigarOp,
+    length: u32,
+) -> std::result::Result<(), CigarError> {
+    if length > 0 {
+        builder.push(op, length)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trimmed(cigar: &str, read_start: usize, read_end: usize) -> (String, usize) {
+        let cigar: Cigar = cigar.parse().unwrap();
+        let (cigar, offset) = trim_read(&cigar, read_start, read_end).unwrap();
+        (cigar.to_string(), offset)
+    }
+
+    #[test]
+    fn test_trim_both_ends() {
+        assert_eq!(trimmed("10M", 2, 8), ("2S6M2S".to_string(), 2));
+    }
+
+    #[test]
+    fn test_trim_drops_deletion_at_boundary() {
+        assert_eq!(trimmed("3M2D5M", 3, 8), ("3S5M".to_string(), 5));
+    }
+
+    #[test]
+    fn test_trim_insertion_becomes_clip() {
+        assert_eq!(trimmed("2S3M1I4M", 0, 6), ("2S3M5S".to_string(), 0));
+    }
+
+    #[test]
+    fn test_trim_preserves_hard_clips() {
+        assert_eq!(trimmed("1H2S8M3H", 4, 10), ("1H4S6M3H".to_string(), 2));
+    }
+
+    #[test]
+    fn test_trim_invalid_range() {
+        let cigar: Cigar = "10M".parse().unwrap();
+        assert!(matches!(
+            trim_read(&cigar, 4, 11),
+            Err(CigarError::InvalidRange(4, 11))
+        ));
+    }
+
+    #[test]
+    fn test_trim_primers() {
+        let primers = [(90, 105), (140, 160), (300, 320)];
+        let cigar: Cigar = "50M".parse().unwrap();
+        let (trimmed, position) = trim_primers(&cigar, 100, &primers).unwrap();
+        assert_eq!(
+            (trimmed.to_string(), position),
+            ("5S35M10S".to_string(), 105)
+        );
+
+        // Bases inserted within a primer are clipped with it.
+        let cigar: Cigar = "2S3M2I5M1D10M".parse().unwrap();
+        let (trimmed, position) = trim_primers(&cigar, 102, &primers).unwrap();
+        assert_eq!(
+            (trimmed.to_string(), position),
+            ("7S5M1D10M".to_string(), 105)
+        );
+
+        // Alignments not starting or ending in a primer are unchanged.
+        let (trimmed, position) = trim_primers(&cigar, 200, &primers).unwrap();
+        assert_eq!((trimmed, position), (cigar.clone(), 200));
+    }
+
+    #[test]
+    fn test_trim_qualities() {
+        let cigar: Cigar = "2S6M1I3M".parse().unwrap();
+        let qualities = [2, 2, 5, 30, 8, 30, 30, 30, 30, 30, 10, 2];
+        let (trimmed, position) = trim_qualities(&cigar, 100, &qualities, 20, 3).unwrap();
+        assert_eq!(
+            (trimmed.to_string(), position),
+            ("3S5M1I1M2S".to_string(), 101)
+        );
+
+        // With no window reaching the threshold, everything is clipped.
+        let (trimmed, _) = trim_qualities(&cigar, 100, &[5; 12], 20, 3).unwrap();
+        assert_eq!(trimmed.to_string(), "12S");
+        assert!(matches!(
+            trim_qualities(&cigar, 100, &[30; 11], 20, 3),
+            Err(CigarError::LengthMismatch(12, 11))
+        ));
+    }
+}
@@ -0,0 +1,402 @@
+//! Validation of CIGARs.
+//!
+//! The SAM specification places structural constraints on CIGARs beyond what the parser
+//! enforces. [`validate`] checks a CIGAR against these rules and reports every violation
+//! found, rather than stopping at the first.
+//!
+//! Converters often produce CIGARs which disagree with the length of SEQ.
+//! [`check_query_length`] reports the disagreement, [`adjustable_elements`] which
+//! elements to adjust, and [`repair_query_length`] makes the CIGAR agree by growing or
+//! shrinking its terminal soft clips.
+//!
+//! Alignments must also lie within their chromosome. [`check_reference_length`] checks
+//! an alignment against the length of its chromosome, which a
+//! [`ChromMap`](crate::chrom_map::ChromMap) read from a FASTA index (`.fai`) or SAM header
+//! provides by chromosome ID.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::Cigar;
+//! use cigar_utils::validate::{ValidationOptions, Violation, validate};
+//!
+//! let cigar: Cigar = "5M2H3S5M0I".parse().unwrap();
+//! let options = ValidationOptions {
+//!     read_length: Some(20),
+//!     ..Default::default()
+//! };
+//! let violations = validate(&cigar, &options);
+//! assert_eq!(
+//!     violations,
+//!     vec![
+//!         Violation::MisplacedHardClip(1),
+//!         Violation::MisplacedSoftClip(2),
+//!         Violation::ZeroLength(4),
+//!         Violation::ReadLengthMismatch(13, 20),
+//!     ]
+//! );
+//! ```
+
+use std::fmt::Display;
+
+use crate::error::CigarError;
+use crate::{Cigar, CigarElement, CigarOp};
+
+/// The maximum number of CIGAR operations that can be stored in a BAM record.
+pub const BAM_MAX_OPERATIONS: usize = 65535;
+
+/// Options controlling which rules [`validate`] checks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationOptions {
+    /// Report adjacent elements with the same operation.
+    pub forbid_adjacent_identical: bool,
+    /// The maximum number of elements allowed, if any (see [`BAM_MAX_OPERATIONS`]).
+    pub max_operations: Option<usize>,
+    /// The read (SEQ) length the CIGAR must be consistent with, if known.
+    pub read_length: Option<usize>,
+}
+
+/// A violation of a CIGAR rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// A hard clip at the given element index which is not the first or last element.
+    MisplacedHardClip(usize),
+    /// A soft clip at the given element index with something other than hard clips between it and the ends.
+    MisplacedSoftClip(usize),
+    /// An element at the given index with the same operation as the previous element.
+    AdjacentIdentical(usize),
+    /// An element at the given index with a length of zero.
+    ZeroLength(usize),
+    /// More elements than allowed (count, maximum).
+    TooManyOperations(usize, usize),
+    /// The query length implied by the CIGAR differs from the read length (implied, read length).
+    ReadLengthMismatch(usize, usize),
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::MisplacedHardClip(index) => {
+                write!(f, "Hard clip at element {} is not at either end", index)
+            }
+            Violation::MisplacedSoftClip(index) => write!(
+                f,
+                "Soft clip at element {} is not separated from the end by hard clips only",
+                index
+            ),
+            Violation::AdjacentIdentical(index) => write!(
+                f,
+                "Element {} has the same operation as the previous element",
+                index
+            ),
+            Violation::ZeroLength(index) => write!(f, "Element {} has length zero", index),
+            Violation::TooManyOperations(count, max) => {
+                write!(f, "CIGAR has {} elements (maximum {})", count, max)
+            }
+            Violation::ReadLengthMismatch(implied, actual) => write!(
+                f,
+                "CIGAR implies a read length of {} but the read length is {}",
+                implied, actual
+            ),
+        }
+    }
+}
+
+/// Check a CIGAR against the SAM specification rules selected by the options.
+///
+/// Clip placement and zero length elements are always checked. Violations are returned
+/// in the order of the elements they concern, followed by whole-CIGAR violations.
+pub fn validate(cigar: &Cigar, options: &ValidationOptions) -> Vec<Violation> {
+    let elements = cigar.elements();
+    let n = elements.len();
+    let mut violations = Vec::new();
+
+    for (i, elem) in elements.iter().enumerate() {
+        match elem.op {
+            CigarOp::HardClip if i != 0 && i + 1 != n => {
+                violations.push(Violation::MisplacedHardClip(i));
+            }
+            CigarOp::SoftClip => {
+                let leading = elements[..i].iter().all(|e| e.op == CigarOp::HardClip);
+                let trailing = elements[i + 1..].iter().all(|e| e.op == CigarOp::HardClip);
+                if !leading && !trailing {
+                    violations.push(Violation::MisplacedSoftClip(i));
+                }
+            }
+            _ => {}
+        }
+        if options.forbid_adjacent_identical && i > 0 && elements[i - 1].op == elem.op {
+            violations.push(Violation::AdjacentIdentical(i));
+        }
+        if elem.length == 0 {
+            violations.push(Violation::ZeroLength(i));
+        }
+    }
+
+    if let Some(max) = options.max_operations
+        && n > max
+    {
+        violations.push(Violation::TooManyOperations(n, max));
+    }
+    if let Some(read_length) = options.read_length {
+        let implied: usize = elements
+            .iter()
+            .filter(|e| e.op.consumes_query())
+            .map(|e| e.length as usize)
+            .sum();
+        if implied != read_length {
+            violations.push(Violation::ReadLengthMismatch(implied, read_length));
+        }
+    }
+
+    violations
+}
+
+/// Check that an alignment of `cigar` at `position` on chromosome `chrom_id` ends within
+/// the chromosome's `length`, giving [`CigarError::BeyondChromosomeEnd`] with the
+/// overhang if it does not.
+pub fn check_reference_length(
+    cigar: &Cigar,
+    chrom_id: u32,
+    position: u64,
+    length: u64,
+) -> std::result::Result<(), CigarError> {
+    check_alignment_end(chrom_id, cigar.alignment_end(position), length)
+}
+
+pub(crate) fn check_alignment_end(
+    chrom_id: u32,
+    end: u64,
+    length: u64,
+) -> std::result::Result<(), CigarError> {
+    if end > length {
+        return Err(CigarError::BeyondChromosomeEnd {
+            chrom_id,
+            end,
+            length,
+            overhang: end - length,
+        });
+    }
+    Ok(())
+}
+
+/// An end of a read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReadEnd {
+    /// The start of the read, at the left of the alignment.
+    Start,
+    /// The end of the read, at the right of the alignment.
+    End,
+}
+
+/// The read length implied by `cigar`, checked against the length of SEQ.
+///
+/// Gives [`CigarError::LengthMismatch`] with the implied and actual lengths if they
+/// differ; [`adjustable_elements`] gives the elements to adjust.
+pub fn check_query_length(
+    cigar: &Cigar,
+    read_length: usize,
+) -> std::result::Result<usize, CigarError> {
+    let implied = cigar.query_length();
+    if implied != read_length {
+        return Err(CigarError::LengthMismatch(implied, read_length));
+    }
+    Ok(implied)
+}
+
+/// The indices of the elements to adjust where the read length implied by `cigar` is
+/// wrong: the outermost elements consuming read bases at each end of the read, which
+/// are the soft clips of a clipped read.
+pub fn adjustable_elements(cigar: &Cigar) -> Vec<usize> {
+    let elements = cigar.elements();
+    let first = elements.iter().position(|e| e.op.consumes_query());
+    let last = elements.iter().rposition(|e| e.op.consumes_query());
+    let mut adjustable: Vec<usize> = first.into_iter().chain(last).collect();
+    adjustable.dedup();
+    adjustable
+}
+
+/// Make `cigar` agree with a read of `read_length` bases by adjusting its soft clips.
+///
+/// A read longer than the CIGAR implies grows the soft clip at the given end, adding one
+/// (inside any hard clip) if there is none. A shorter read shrinks the soft clip at the
+/// given end, then the one at the other end, dropping them once empty. Fails with the
+/// mismatch from [`check_query_length`] if the soft clips are too short to absorb the
+/// difference, or the CIGAR is empty.
+pub fn repair_query_length(
+    cigar: &Cigar,
+    read_length: usize,
+    end: ReadEnd,
+) -> std::result::Result<Cigar, CigarError> {
+    let mismatch = match check_query_length(cigar, read_length) {
+        Ok(_) => return Ok(cigar.clone()),
+        Err(e) => e,
+    };
+    let mut elements = cigar.elements().to_vec();
+    if elements.is_empty() {
+        return Err(mismatch);
+    }
+    let implied = cigar.query_length();
+    // The index of the soft clip at an end, or where one would go.
+    let clip_index = |elements: &[CigarElement], end: ReadEnd| match end {
+        ReadEnd::Start => elements
+            .iter()
+            .position(|e| e.op != CigarOp::HardClip)
+            .unwrap_or(elements.len()),
+        ReadEnd::End => elements
+            .iter()
+            .rposition(|e| e.op != CigarOp::HardClip)
+            .unwrap_or(0),
+    };
+    if read_length > implied {
+        let Ok(grow) = u32::try_from(read_length - implied) else {
+            return Err(mismatch);
+        };
+        let i = clip_index(&elements, end);
+        match elements.get_mut(i) {
+            Some(e) if e.op == CigarOp::SoftClip => {
+                let Some(length) = e.length.checked_add(grow) else {
+                    return Err(mismatch);
+                };
+                e.length = length;
+            }
+            _ => {
+                let at = if end == ReadEnd::Start { i } else { i + 1 };
+                elements.insert(at, CigarElement::new(grow, CigarOp::SoftClip));
+            }
+        }
+    } else {
+        let mut shrink = implied - read_length;
+        let other = match end {
+            ReadEnd::Start => ReadEnd::End,
+            ReadEnd::End => ReadEnd::Start,
+        };
+        for end in [end, other] {
+            let i = clip_index(&elements, end);
+            if let Some(e) = elements.get_mut(i).filter(|e| e.op == CigarOp::SoftClip) {
+                let taken = shrink.min(e.length as usize);
+                e.length -= taken as u32;
+                shrink -= taken;
+                if e.length == 0 {
+                    elements.remove(i);
+                }
+            }
+        }
+        if shrink > 0 {
+            return Err(mismatch);
+        }
+    }
+    Ok(Cigar::from(elements))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn violations(cigar: &str, options: &ValidationOptions) -> Vec<Violation> {
+        validate(&cigar.parse().unwrap(), options)
+    }
+
+    #[test]
+    fn test_validate_valid() {
+        let options = ValidationOptions {
+            forbid_adjacent_identical: true,
+            max_operations: Some(BAM_MAX_OPERATIONS),
+            read_length: Some(19),
+        };
+        assert!(violations("2H3S10M1I5M4H", &options).is_empty());
+    }
+
+    #[test]
+    fn test_validate_clips() {
+        let options = ValidationOptions::default();
+        assert_eq!(
+            violations("5M2S5M1H1S", &options),
+            vec![
+                Violation::MisplacedSoftClip(1),
+                Violation::MisplacedHardClip(3)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_adjacent_identical() {
+        let mut options = ValidationOptions::default();
+        assert!(violations("5M5M", &options).is_empty());
+        options.forbid_adjacent_identical = true;
+        assert_eq!(
+            violations("5M5M", &options),
+            vec![Violation::AdjacentIdentical(1)]
+        );
+    }
+
+    #[test]
+    fn test_validate_operation_count() {
+        let options = ValidationOptions {
+            max_operations: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            violations("1M1I1M", &options),
+            vec![Violation::TooManyOperations(3, 2)]
+        );
+    }
+
+    #[test]
+    fn test_check_reference_length() {
+        let cigar: Cigar = "5S10M2D3M4S".parse().unwrap();
+        assert!(check_reference_length(&cigar, 0, 985, 1000).is_ok());
+        assert!(matches!(
+            check_reference_length(&cigar, 3, 990, 1000),
+            Err(CigarError::BeyondChromosomeEnd {
+                chrom_id: 3,
+                end: 1005,
+                length: 1000,
+                overhang: 5
+            })
+        ));
+    }
+
+    #[test]
+    fn test_check_query_length() {
+        let cigar: Cigar = "2H3S10M2I5M4S".parse().unwrap();
+        assert_eq!(check_query_length(&cigar, 24).unwrap(), 24);
+        assert!(matches!(
+            check_query_length(&cigar, 20),
+            Err(CigarError::LengthMismatch(24, 20))
+        ));
+        assert_eq!(adjustable_elements(&cigar), vec![1, 5]);
+        assert_eq!(adjustable_elements(&"5M".parse().unwrap()), vec![0]);
+        assert!(adjustable_elements(&"5D".parse().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_repair_query_length() {
+        let cigar: Cigar = "2H3S10M2I5M4S".parse().unwrap();
+        let repaired =
+            |length, end| repair_query_length(&cigar, length, end).map(|c| c.to_string());
+        assert_eq!(repaired(24, ReadEnd::Start).unwrap(), "2H3S10M2I5M4S");
+        assert_eq!(repaired(26, ReadEnd::End).unwrap(), "2H3S10M2I5M6S");
+        assert_eq!(repaired(26, ReadEnd::Start).unwrap(), "2H5S10M2I5M4S");
+        assert_eq!(repaired(19, ReadEnd::End).unwrap(), "2H2S10M2I5M");
+        assert!(matches!(
+            repaired(16, ReadEnd::Start),
+            Err(CigarError::LengthMismatch(24, 16))
+        ));
+
+        let unclipped: Cigar = "10M1H".parse().unwrap();
+        let grown = |end| {
+            repair_query_length(&unclipped, 12, end)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(grown(ReadEnd::Start), "2S10M1H");
+        assert_eq!(grown(ReadEnd::End), "10M2S1H");
+        assert!(repair_query_length(&Cigar::new(), 3, ReadEnd::End).is_err());
+        let full: Cigar = "4294967290S10M".parse().unwrap();
+        assert!(matches!(
+            repair_query_length(&full, 4294967310, ReadEnd::Start),
+            Err(CigarError::LengthMismatch(4294967300, 4294967310))
+        ));
+    }
+}
@@ -0,0 +1,266 @@
+//! Variant records from collated events.
+//!
+//! Collation is often run to discover candidate variants. This module converts the
+//! insertion, deletion and mismatch events of per-position [summaries](crate::summary)
+//! into VCF-style variant records, with the reference and alternate alleles taken from
+//! the reference sequence and indels anchored on the preceding reference base as the
+//! VCF specification requires. The records can be written as VCF text with
+//! [`VcfWriter`](crate::writers::VcfWriter).
+//!
+//! Collated events do not carry the read bases, so inserted and mismatched bases are
+//! reported as `N`. Indels are anchored where they were collated; use
+//! [`left_align_indels`](crate::normalize::left_align_indels) on the input CIGARs for
+//! fully normalized positions.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::collated::CollatedAugmentedCigarIterator;
+//! use cigar_utils::variants::VariantIterator;
+//!
+//! let reference = vec![b"ACGTACGT".to_vec()];
+//! let cigars = vec![
+//!     std::io::Result::Ok(("3M2D3M".to_string(), 0, 0)),
+//!     std::io::Result::Ok(("8M".to_string(), 0, 0)),
+//! ];
+//! let summaries = CollatedAugmentedCigarIterator::new(cigars.into_iter()).summarized();
+//! let variants: Vec<_> = VariantIterator::new(summaries, &reference)
+//!     .collect::<Result<_, _>>()
+//!     .unwrap();
+//! assert_eq!(variants.len(), 1);
+//! assert_eq!(variants[0].position, 2);
+//! assert_eq!(variants[0].reference, b"GTA");
+//! assert_eq!(variants[0].alternate, b"G");
+//! assert_eq!((variants[0].count, variants[0].depth), (1, 2));
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::CigarOp;
+use crate::error::CigarError;
+use crate::summary::PositionSummary;
+
+/// A set of reference sequences, looked up by chromosome ID.
+pub trait ReferenceSequences {
+    /// The sequence of the given chromosome, if there is one.
+    fn sequence(&self, chrom_id: u32) -> Option<&[u8]>;
+}
+
+impl<T: AsRef<[u8]>> ReferenceSequences for [T] {
+    fn sequence(&self, chrom_id: u32) -> Option<&[u8]> {
+        self.get(chrom_id as usize).map(|s| s.as_ref())
+    }
+}
+
+impl<T: AsRef<[u8]>> ReferenceSequences for Vec<T> {
+    fn sequence(&self, chrom_id: u32) -> Option<&[u8]> {
+        self.as_slice().sequence(chrom_id)
+    }
+}
+
+impl<T: AsRef<[u8]>> ReferenceSequences for HashMap<u32, T> {
+    fn sequence(&self, chrom_id: u32) -> Option<&[u8]> {
+        self.get(&chrom_id).map(|s| s.as_ref())
+    }
+}
+
+/// A variant in VCF style.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariantRecord {
+    /// The chromosome ID of the variant.
+    pub chrom_id: u32,
+    /// The reference position of the first base of the reference allele.
+    pub position: u64,
+    /// The reference allele.
+    pub reference: Vec<u8>,
+    /// The alternate allele.
+    pub alternate: Vec<u8>,
+    /// The number of records supporting the variant.
+    pub count: usize,
+    /// The depth of coverage at the position of the event.
+    pub depth: usize,
+}
+
+impl VariantRecord {
+    /// Build the variant record for an insertion, deletion or mismatch event.
+    ///
+    /// Returns `None` for other operations.
+    pub fn from_event(
+        reference: &[u8],
+        chrom_id: u32,
+        position: u64,
+        op: CigarOp,
+        length: u32,
+        count: usize,
+        depth: usize,
+    ) -> std::result::Result<Option<VariantRecord>, CigarError> {
+        let length = length as u64;
+        let bases = |start: u64, end: u64| {
+            if end > reference.len() as u64 {
+                Err(CigarError::ReferenceOutOfBounds(start, end))
+            } else {
+                Ok(reference[start as usize..end as usize].to_vec())
+            }
+        };
+        let unknown = vec![b'N'; length as usize];
+        let (position, ref_allele, alt_allele) = match op {
+            CigarOp::Diff => (position, bases(position, position + length)?, unknown),
+            CigarOp::Deletion if position > 0 => {
+                let ref_allele = bases(position - 1, position + length)?;
+                let alt_allele = ref_allele[..1].to_vec();
+                (position - 1, ref_allele, alt_allele)
+            }
+            CigarOp::Deletion => {
+                // At the start of the chromosome the anchor is the following base.
+                let ref_allele = bases(0, length + 1)?;
+                let alt_allele = ref_allele[length as usize..].to_vec();
+                (0, ref_allele, alt_allele)
+            }
+            CigarOp::Insertion if position > 0 => {
+                let ref_allele = bases(position - 1, position)?;
+                let mut alt_allele = ref_allele.clone();
+                alt_allele.extend(unknown);
+                (position - 1, ref_allele, alt_allele)
+            }
+            CigarOp::Insertion => {
+                let ref_allele = bases(0, 1)?;
+                let mut alt_allele = unknown;
+                alt_allele.extend(&ref_allele);
+                (0, ref_allele, alt_allele)
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(VariantRecord {
+            chrom_id,
+            position,
+            reference: ref_allele,
+            alternate: alt_allele,
+            count,
+            depth,
+        }))
+    }
+}
+
+/// An iterator over the variant records of a stream of per-position summaries.
+pub struct VariantIterator<'a, I, R: ?Sized> {
+    inner: I,
+    reference: &'a R,
+    pending: VecDeque<VariantRecord>,
+}
+
+impl<'a, I, R: ReferenceSequences + ?Sized> VariantIterator<'a, I, R> {
+    /// Create a new variant iterator, taking alleles from the given reference sequences.
+    pub fn new(inner: I, reference: &'a R) -> Self {
+        VariantIterator {
+            inner,
+            reference,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<'a, I, R> Iterator for VariantIterator<'a, I, R>
+where
+    I: Iterator<Item = std::result::Result<PositionSummary, CigarError>>,
+    R: ReferenceSequences + ?Sized,
+{
+    type Item = std::result::Result<VariantRecord, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let summary = match self.inner.next()? {
+                Ok(summary) => summary,
+                Err(e) => return Some(Err(e)),
+            };
+            let reference = match self.reference.sequence(summary.chrom_id) {
+                Some(reference) => reference,
+                None => return Some(Err(CigarError::UnknownChromosome(summary.chrom_id))),
+            };
+            for event in &summary.events {
+                match VariantRecord::from_event(
+                    reference,
+                    summary.chrom_id,
+                    summary.position,
+                    event.op,
+                    event.length,
+                    event.count,
+                    summary.depth,
+                ) {
+                    Ok(Some(record)) => self.pending.push_back(record),
+                    Ok(None) => {}
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(op: CigarOp, position: u64, length: u32) -> (u64, String, String) {
+        let r = VariantRecord::from_event(b"ACGTACGT", 0, position, op, length, 1, 1)
+            .unwrap()
+            .unwrap();
+        (
+            r.position,
+            String::from_utf8(r.reference).unwrap(),
+            String::from_utf8(r.alternate).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_variant_alleles() {
+        assert_eq!(
+            record(CigarOp::Diff, 2, 2),
+            (2, "GT".to_string(), "NN".to_string())
+        );
+        assert_eq!(
+            record(CigarOp::Deletion, 3, 2),
+            (2, "GTA".to_string(), "G".to_string())
+        );
+        assert_eq!(
+            record(CigarOp::Insertion, 4, 2),
+            (3, "T".to_string(), "TNN".to_string())
+        );
+    }
+
+    #[test]
+    fn test_variant_alleles_at_start() {
+        assert_eq!(
+            record(CigarOp::Deletion, 0, 2),
+            (0, "ACG".to_string(), "G".to_string())
+        );
+        assert_eq!(
+            record(CigarOp::Insertion, 0, 1),
+            (0, "A".to_string(), "NA".to_string())
+        );
+    }
+
+    #[test]
+    fn test_variant_errors() {
+        assert!(matches!(
+            VariantRecord::from_event(b"ACGT", 0, 3, CigarOp::Deletion, 4, 1, 1),
+            Err(CigarError::ReferenceOutOfBounds(2, 7))
+        ));
+        assert!(
+            VariantRecord::from_event(b"ACGT", 0, 0, CigarOp::Match, 4, 1, 1)
+                .unwrap()
+                .is_none()
+        );
+        let reference: HashMap<u32, &[u8]> = HashMap::new();
+        let summaries = vec![Ok(PositionSummary {
+            chrom_id: 3,
+            position: 0,
+            depth: 0,
+            events: Vec::new(),
+        })];
+        let mut iter = VariantIterator::new(summaries.into_iter(), &reference);
+        assert!(matches!(
+            iter.next(),
+            Some(Err(CigarError::UnknownChromosome(3)))
+        ));
+    }
+}
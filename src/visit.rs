@@ -0,0 +1,247 @@
+//! Visiting the elements of an alignment.
+//!
+//! Code which treats each kind of operation differently usually walks the augmented
+//! elements of an alignment and matches on their [`CigarOp`]. A [`CigarVisitor`] has a
+//! callback for each kind of operation instead, given the element with its read and
+//! reference positions, and [`walk`] calls them in order along an alignment. Every
+//! callback does nothing by default, so a visitor implements only those it needs.
+//!
+//! A closure taking an [`AugmentedCigarElement`] is itself a visitor of every element,
+//! and [`on_op`] and [`on_ops`] make one visiting only elements with given operations.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::Cigar;
+//! use cigar_utils::augmented_cigar::AugmentedCigarElement;
+//! use cigar_utils::visit::{CigarVisitor, walk};
+//!
+//! #[derive(Default)]
+//! struct IndelSites {
+//!     sites: Vec<(char, u64, u32)>,
+//! }
+//!
+//! impl CigarVisitor for IndelSites {
+//!     fn on_insertion(&mut self, elem: &AugmentedCigarElement) {
+//!         self.sites.push(('I', elem.reference_position, elem.read_position));
+//!     }
+//!
+//!     fn on_deletion(&mut self, elem: &AugmentedCigarElement) {
+//!         self.sites.push(('D', elem.reference_position, elem.read_position));
+//!     }
+//! }
+//!
+//! let cigar: Cigar = "3S5M2I4M3D6M".parse().unwrap();
+//! let mut sites = IndelSites::default();
+//! walk(&cigar, 100, &mut sites);
+//! assert_eq!(sites.sites, vec![('I', 105, 8), ('D', 109, 14)]);
+//! ```
+
+use crate::augmented_cigar::{AugmentedCigarElement, AugmentedCigarIterator};
+use crate::error::CigarError;
+use crate::{Cigar, CigarOp};
+
+/// Callbacks for each kind of CIGAR operation along an alignment.
+///
+/// Each callback is given the element with its read and reference positions, and does
+/// nothing by default. The sequence match (`=`) and mismatch (`X`) callbacks default to
+/// [`on_match`](CigarVisitor::on_match), so a visitor of aligned bases need only
+/// implement that.
+pub trait CigarVisitor {
+    /// An alignment match (`M`) element.
+    fn on_match(&mut self, elem: &AugmentedCigarElement) {
+        let _ = elem;
+    }
+
+    /// A sequence match (`=`) element.
+    fn on_equal(&mut self, elem: &AugmentedCigarElement) {
+        self.on_match(elem);
+    }
+
+    /// A sequence mismatch (`X`) element.
+    fn on_mismatch(&mut self, elem: &AugmentedCigarElement) {
+        self.on_match(elem);
+    }
+
+    /// An insertion (`I`) element.
+    fn on_insertion(&mut self, elem: &AugmentedCigarElement) {
+        let _ = elem;
+    }
+
+    /// A deletion (`D`) element.
+    fn on_deletion(&mut self, elem: &AugmentedCigarElement) {
+        let _ = elem;
+    }
+
+    /// A skipped region (`N`) element.
+    fn on_skip(&mut self, elem: &AugmentedCigarElement) {
+        let _ = elem;
+    }
+
+    /// A soft (`S`) or hard (`H`) clip element.
+    fn on_clip(&mut self, elem: &AugmentedCigarElement) {
+        let _ = elem;
+    }
+
+    /// A padding (`P`) element.
+    fn on_padding(&mut self, elem: &AugmentedCigarElement) {
+        let _ = elem;
+    }
+
+    /// Visit an element, calling the callback for its operation.
+    fn visit(&mut self, elem: &AugmentedCigarElement) {
+        match elem.op {
+            CigarOp::Match => self.on_match(elem),
+            CigarOp::Equal => self.on_equal(elem),
+            CigarOp::Diff => self.on_mismatch(elem),
+            CigarOp::Insertion => self.on_insertion(elem),
+            CigarOp::Deletion => self.on_deletion(elem),
+            CigarOp::Skip => self.on_skip(elem),
+            CigarOp::SoftClip | CigarOp::HardClip => self.on_clip(elem),
+            CigarOp::Padding => self.on_padding(elem),
+        }
+    }
+}
+
+/// A closure is a visitor of every element.
+impl<F: FnMut(&AugmentedCigarElement)> CigarVisitor for F {
+    fn visit(&mut self, elem: &AugmentedCigarElement) {
+        self(elem)
+    }
+}
+
+/// A visitor calling a closure for the elements with given operations, created by
+/// [`on_op`] or [`on_ops`].
+pub struct OpCallback<F> {
+    ops: Vec<CigarOp>,
+    callback: F,
+}
+
+impl<F: FnMut(&AugmentedCigarElement)> CigarVisitor for OpCallback<F> {
+    fn visit(&mut self, elem: &AugmentedCigarElement) {
+        if self.ops.contains(&elem.op) {
+            (self.callback)(elem)
+        }
+    }
+}
+
+/// A visitor calling `callback` for the elements with operation `op`.
+pub fn on_op<F: FnMut(&AugmentedCigarElement)>(op: CigarOp, callback: F) -> OpCallback<F> {
+    on_ops([op], callback)
+}
+
+/// A visitor calling `callback` for the elements with any of the operations `ops`.
+pub fn on_ops<V, F>(ops: V, callback: F) -> OpCallback<F>
+where
+    V: IntoIterator<Item = CigarOp>,
+    F: FnMut(&AugmentedCigarElement),
+{
+    OpCallback {
+        ops: ops.into_iter().collect(),
+        callback,
+    }
+}
+
+/// Visit the elements of an alignment starting at the reference position `start`, in
+/// order. Read positions index SEQ, as for an [`AugmentedCigarIterator`], and the
+/// chromosome ID of the elements is 0.
+pub fn walk<V: CigarVisitor + ?Sized>(cigar: &Cigar, start: u64, visitor: &mut V) {
+    for elem in AugmentedCigarIterator::from((cigar.elements(), 0, start)) {
+        visitor.visit(&elem.expect("parsed elements are valid"));
+    }
+}
+
+/// Visit a stream of augmented elements in order, such as an
+/// [`AugmentedCigarIterator`] on a chromosome, or one
+/// [expanding](AugmentedCigarIterator::expanded) `M` into `=` and `X`. Stops at the
+/// first error.
+pub fn walk_augmented<I, V>(elements: I, visitor: &mut V) -> std::result::Result<(), CigarError>
+where
+    I: IntoIterator<Item = std::result::Result<AugmentedCigarElement, CigarError>>,
+    V: CigarVisitor + ?Sized,
+{
+    for elem in elements {
+        visitor.visit(&elem?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Counts {
+        aligned: u32,
+        mismatched: u32,
+        clipped: u32,
+        skipped: u64,
+    }
+
+    impl CigarVisitor for Counts {
+        fn on_match(&mut self, elem: &AugmentedCigarElement) {
+            self.aligned += elem.length;
+        }
+
+        fn on_mismatch(&mut self, elem: &AugmentedCigarElement) {
+            self.mismatched += elem.length;
+            self.on_match(elem);
+        }
+
+        fn on_clip(&mut self, elem: &AugmentedCigarElement) {
+            self.clipped += elem.length;
+        }
+
+        fn on_skip(&mut self, elem: &AugmentedCigarElement) {
+            self.skipped += elem.reference_end() - elem.reference_position;
+        }
+    }
+
+    #[test]
+    fn test_walk() {
+        let cigar: Cigar = "2H3S4=1X2M100N5=1D3S".parse().unwrap();
+        let mut counts = Counts::default();
+        walk(&cigar, 0, &mut counts);
+        assert_eq!(
+            (
+                counts.aligned,
+                counts.mismatched,
+                counts.clipped,
+                counts.skipped
+            ),
+            (12, 1, 8, 100)
+        );
+
+        let mut ends = Vec::new();
+        walk(&cigar, 50, &mut |elem: &AugmentedCigarElement| {
+            ends.push(elem.reference_end())
+        });
+        assert_eq!(ends.len(), cigar.len());
+        assert_eq!(ends.last(), Some(&163));
+    }
+
+    #[test]
+    fn test_walk_callbacks() {
+        let mut deletions = Vec::new();
+        let mut visitor = on_op(CigarOp::Deletion, |elem: &AugmentedCigarElement| {
+            deletions.push(elem.reference_position)
+        });
+        walk(&"3M2D4M1D1M".parse().unwrap(), 10, &mut visitor);
+        assert_eq!(deletions, vec![13, 19]);
+
+        let mut read_positions = Vec::new();
+        let elements = AugmentedCigarIterator::expanded("4M", 0, 0, b"ACGT", b"ACCT");
+        walk_augmented(
+            elements,
+            &mut on_ops([CigarOp::Diff], |elem: &AugmentedCigarElement| {
+                read_positions.push(elem.read_position)
+            }),
+        )
+        .unwrap();
+        assert_eq!(read_positions, vec![2]);
+        let mut count = 0;
+        let failing = AugmentedCigarIterator::from(("4M1Q", 0, 0));
+        assert!(walk_augmented(failing, &mut |_: &AugmentedCigarElement| count += 1).is_err());
+        assert_eq!(count, 1);
+    }
+}
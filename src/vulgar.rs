@@ -0,0 +1,381 @@
+//! Exonerate VULGAR alignments.
+//!
+//! Exonerate reports alignments as VULGAR strings ("Verbose Useful Labelled Gapped
+//! Alignment Report"): a header giving the query and target intervals and the score,
+//! followed by triples of an operation and the query and target lengths it covers. These
+//! functions convert between VULGAR triples and CIGARs, so exonerate output can be
+//! handled alongside SAM.
+//!
+//! For protein queries the query lengths of VULGAR triples are in amino acids, so
+//! conversion to a CIGAR takes a `query_unit` (3 for protein, 1 for nucleotide queries)
+//! by which query lengths are multiplied. Aligned blocks take their length from the
+//! target.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::vulgar::{VulgarRecord, cigar_from_vulgar, vulgar_from_cigar};
+//!
+//! let record: VulgarRecord =
+//!     "vulgar: prot 0 20 . chr1 100 172 + 90 M 8 24 5 0 2 I 0 8 3 0 2 M 12 36"
+//!         .parse()
+//!         .unwrap();
+//! let cigar = cigar_from_vulgar(&record.triples, 3).unwrap();
+//! assert_eq!(cigar.to_string(), "24M12N36M");
+//!
+//! let triples = vulgar_from_cigar(&"5M2I3M".parse().unwrap());
+//! assert_eq!(
+//!     triples.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(" "),
+//!     "M 5 5 G 2 0 M 3 3"
+//! );
+//! ```
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::builder::CigarBuilder;
+use crate::error::{CigarError, parse_number};
+use crate::{Cigar, CigarOp, Strand};
+
+/// A VULGAR operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VulgarOp {
+    /// An aligned block (M).
+    Match,
+    /// An aligned block of whole codons (C).
+    Codon,
+    /// A gap in the query or target (G).
+    Gap,
+    /// A non-equivalenced (unaligned) region (N).
+    NonEquivalenced,
+    /// A 5' splice site (5).
+    FivePrimeSplice,
+    /// A 3' splice site (3).
+    ThreePrimeSplice,
+    /// An intron (I).
+    Intron,
+    /// A codon split by an intron (S).
+    SplitCodon,
+    /// A frameshift (F).
+    Frameshift,
+}
+
+impl From<VulgarOp> for char {
+    fn from(op: VulgarOp) -> char {
+        match op {
+            VulgarOp::Match => 'M',
+            VulgarOp::Codon => 'C',
+            VulgarOp::Gap => 'G',
+            VulgarOp::NonEquivalenced => 'N',
+            VulgarOp::FivePrimeSplice => '5',
+            VulgarOp::ThreePrimeSplice => '3',
+            VulgarOp::Intron => 'I',
+            VulgarOp::SplitCodon => 'S',
+            VulgarOp::Frameshift => 'F',
+        }
+    }
+}
+
+impl TryFrom<char> for VulgarOp {
+    type Error = char;
+
+    fn try_from(value: char) -> Result<Self, Self::Error> {
+        match value {
+            'M' => Ok(VulgarOp::Match),
+            'C' => Ok(VulgarOp::Codon),
+            'G' => Ok(VulgarOp::Gap),
+            'N' => Ok(VulgarOp::NonEquivalenced),
+            '5' => Ok(VulgarOp::FivePrimeSplice),
+            '3' => Ok(VulgarOp::ThreePrimeSplice),
+            'I' => Ok(VulgarOp::Intron),
+            'S' => Ok(VulgarOp::SplitCodon),
+            'F' => Ok(VulgarOp::Frameshift),
+            _ => Err(value),
+        }
+    }
+}
+
+/// A VULGAR triple: an operation with the query and target lengths it covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VulgarTriple {
+    /// The operation.
+    pub op: VulgarOp,
+    /// The length on the query, in query units.
+    pub query_length: u32,
+    /// The length on the target.
+    pub target_length: u32,
+}
+
+impl VulgarTriple {
+    /// Create a new triple.
+    pub fn new(op: VulgarOp, query_length: u32, target_length: u32) -> Self {
+        VulgarTriple {
+            op,
+            query_length,
+            target_length,
+        }
+    }
+}
+
+impl Display for VulgarTriple {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            char::from(self.op),
+            self.query_length,
+            self.target_length
+        )
+    }
+}
+
+/// A VULGAR alignment record.
+///
+/// Coordinates are exonerate's in-between coordinates; on the reverse strand the start
+/// is greater than the end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VulgarRecord {
+    /// The query name.
+    pub query_name: String,
+    /// The start of the alignment on the query.
+    pub query_start: u64,
+    /// The end of the alignment on the query.
+    pub query_end: u64,
+    /// The query strand, or `None` if it has none (as for proteins).
+    pub query_strand: Option<Strand>,
+    /// The target name.
+    pub target_name: String,
+    /// The start of the alignment on the target.
+    pub target_start: u64,
+    /// The end of the alignment on the target.
+    pub target_end: u64,
+    /// The target strand, or `None` if it has none.
+    pub target_strand: Option<Strand>,
+    /// The alignment score.
+    pub score: i64,
+    /// The operations of the alignment.
+    pub triples: Vec<VulgarTriple>,
+}
+
+/// The format name given in [`CigarError::InvalidFormat`] errors.
+const FORMAT: &str = "VULGAR";
+
+fn parse_strand(field: &str) -> std::result::Result<Option<Strand>, CigarError> {
+    match field {
+        "+" => Ok(Some(Strand::Forward)),
+        "-" => Ok(Some(Strand::Reverse)),
+        "." => Ok(None),
+        _ => Err(CigarError::invalid_format(
+            FORMAT,
+            format!("'{}' is not a strand", field),
+        )),
+    }
+}
+
+fn strand_str(strand: Option<Strand>) -> &'static str {
+    match strand {
+        Some(Strand::Forward) => "+",
+        Some(Strand::Reverse) => "-",
+        None => ".",
+    }
+}
+
+/// Parse a sequence of VULGAR triples, such as `"M 10 10 G 0 2 M 5 5"`.
+pub fn parse_vulgar_triples(s: &str) -> std::result::Result<Vec<VulgarTriple>, CigarError> {
+    let fields: Vec<&str> = s.split_whitespace().collect();
+    if !fields.len().is_multiple_of(3) {
+        return Err(CigarError::invalid_format(
+            FORMAT,
+            "operations must be triples",
+        ));
+    }
+    fields
+        .chunks(3)
+        .map(|triple| {
+            let mut chars = triple[0].chars();
+            let op = match (chars.next(), chars.next()) {
+                (Some(c), None) => VulgarOp::try_from(c).map_err(|c| {
+                    CigarError::invalid_format(FORMAT, format!("unknown operation '{}'", c))
+                })?,
+                _ => {
+                    return Err(CigarError::invalid_format(
+                        FORMAT,
+                        format!("unknown operation '{}'", triple[0]),
+                    ));
+                }
+            };
+            Ok(VulgarTriple::new(
+                op,
+                parse_number(FORMAT, triple[1])?,
+                parse_number(FORMAT, triple[2])?,
+            ))
+        })
+        .collect()
+}
+
+impl FromStr for VulgarRecord {
+    type Err = CigarError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_prefix("vulgar:").unwrap_or(s);
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() < 9 {
+            return Err(CigarError::invalid_format(FORMAT, "too few fields"));
+        }
+        Ok(VulgarRecord {
+            query_name: fields[0].to_string(),
+            query_start: parse_number(FORMAT, fields[1])?,
+            query_end: parse_number(FORMAT, fields[2])?,
+            query_strand: parse_strand(fields[3])?,
+            target_name: fields[4].to_string(),
+            target_start: parse_number(FORMAT, fields[5])?,
+            target_end: parse_number(FORMAT, fields[6])?,
+            target_strand: parse_strand(fields[7])?,
+            score: parse_number(FORMAT, fields[8])?,
+            triples: parse_vulgar_triples(&fields[9..].join(" "))?,
+        })
+    }
+}
+
+impl Display for VulgarRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "vulgar: {} {} {} {} {} {} {} {} {}",
+            self.query_name,
+            self.query_start,
+            self.query_end,
+            strand_str(self.query_strand),
+            self.target_name,
+            self.target_start,
+            self.target_end,
+            strand_str(self.target_strand),
+            self.score
+        )?;
+        for triple in self.triples.iter() {
+            write!(f, " {}", triple)?;
+        }
+        Ok(())
+    }
+}
+
+/// Convert VULGAR triples into a CIGAR, with query lengths multiplied by `query_unit`.
+///
+/// Aligned blocks (`M`, `C` and `S`) become alignment matches of their target length.
+/// Gaps and frameshifts become insertions and deletions, splice sites and introns
+/// become skips, and non-equivalenced regions an insertion followed by a deletion.
+/// Frameshift lengths are taken to be in bases whatever the query unit.
+pub fn cigar_from_vulgar(
+    triples: &[VulgarTriple],
+    query_unit: u32,
+) -> std::result::Result<Cigar, CigarError> {
+    let mut builder = CigarBuilder::new();
+    let mut push = |op: CigarOp, length: u32| -> std::result::Result<(), CigarError> {
+        if length > 0 {
+            builder.push(op, length)?;
+        }
+        Ok(())
+    };
+    for triple in triples {
+        let query_length = triple.query_length * query_unit;
+        match triple.op {
+            VulgarOp::Match | VulgarOp::Codon | VulgarOp::SplitCodon => {
+                push(CigarOp::Match, triple.target_length)?
+            }
+            VulgarOp::Gap | VulgarOp::NonEquivalenced => {
+                push(CigarOp::Insertion, query_length)?;
+                push(CigarOp::Deletion, triple.target_length)?;
+            }
+            VulgarOp::Frameshift => {
+                push(CigarOp::Insertion, triple.query_length)?;
+                push(CigarOp::Deletion, triple.target_length)?;
+            }
+            VulgarOp::FivePrimeSplice | VulgarOp::ThreePrimeSplice | VulgarOp::Intron => {
+                push(CigarOp::Skip, triple.target_length)?
+            }
+        }
+    }
+    Ok(builder.build())
+}
+
+/// Convert the aligned part of a CIGAR into VULGAR triples, for a nucleotide query.
+///
+/// Alignment and sequence matches and mismatches become `M`, insertions and deletions
+/// `G`. Skips become introns, with splice sites of two bases at either end when the
+/// skip is long enough to hold them. Clips and padding are not represented.
+pub fn vulgar_from_cigar(cigar: &Cigar) -> Vec<VulgarTriple> {
+    let mut triples: Vec<VulgarTriple> = Vec::new();
+    for elem in cigar.iter() {
+        let length = elem.length;
+        let triple = match elem.op {
+            CigarOp::Match | CigarOp::Equal | CigarOp::Diff => {
+                VulgarTriple::new(VulgarOp::Match, length, length)
+            }
+            CigarOp::Insertion => VulgarTriple::new(VulgarOp::Gap, length, 0),
+            CigarOp::Deletion => VulgarTriple::new(VulgarOp::Gap, 0, length),
+            CigarOp::Skip if length > 4 => {
+                triples.push(VulgarTriple::new(VulgarOp::FivePrimeSplice, 0, 2));
+                triples.push(VulgarTriple::new(VulgarOp::Intron, 0, length - 4));
+                VulgarTriple::new(VulgarOp::ThreePrimeSplice, 0, 2)
+            }
+            CigarOp::Skip => VulgarTriple::new(VulgarOp::Intron, 0, length),
+            CigarOp::SoftClip | CigarOp::HardClip | CigarOp::Padding => continue,
+        };
+        match triples.last_mut() {
+            Some(last) if last.op == VulgarOp::Match && triple.op == VulgarOp::Match => {
+                last.query_length += triple.query_length;
+                last.target_length += triple.target_length;
+            }
+            _ => triples.push(triple),
+        }
+    }
+    triples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_round_trip() {
+        let text = "vulgar: q1 10 0 - t1 200 218 + 37 M 3 3 G 2 0 N 1 4 F 0 1 M 4 4";
+        let record: VulgarRecord = text.parse().unwrap();
+        assert_eq!(record.query_strand, Some(Strand::Reverse));
+        assert_eq!(record.triples.len(), 5);
+        assert_eq!(record.to_string(), text);
+        let cigar = cigar_from_vulgar(&record.triples, 1).unwrap();
+        assert_eq!(cigar.to_string(), "3M3I5D4M");
+    }
+
+    #[test]
+    fn test_cigar_to_vulgar() {
+        let cigar: Cigar = "2S3=1X2M1D4M3N2M1H".parse().unwrap();
+        let triples = vulgar_from_cigar(&cigar);
+        assert_eq!(
+            triples,
+            vec![
+                VulgarTriple::new(VulgarOp::Match, 6, 6),
+                VulgarTriple::new(VulgarOp::Gap, 0, 1),
+                VulgarTriple::new(VulgarOp::Match, 4, 4),
+                VulgarTriple::new(VulgarOp::Intron, 0, 3),
+                VulgarTriple::new(VulgarOp::Match, 2, 2),
+            ]
+        );
+        assert_eq!(
+            cigar_from_vulgar(&triples, 1).unwrap().to_string(),
+            "6M1D4M3N2M"
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(
+            parse_vulgar_triples("M 1 1 G 2"),
+            Err(CigarError::InvalidFormat("VULGAR", _))
+        ));
+        assert!(parse_vulgar_triples("Q 1 1").is_err());
+        assert!(parse_vulgar_triples("M x 1").is_err());
+        assert!("q 0 1 + t 0 1 ?".parse::<VulgarRecord>().is_err());
+    }
+}
@@ -0,0 +1,168 @@
+//! Windowed aggregation of collated events.
+//!
+//! For QC and coarse hotspot scans it is often enough to know how many events of each
+//! kind fall in fixed-size windows of the reference. [`WindowIterator`] groups a stream
+//! of collated events by the window containing their reference position.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::CigarOp;
+//! use cigar_utils::collated::CollatedAugmentedCigarIterator;
+//! use cigar_utils::window::WindowIterator;
+//!
+//! let cigars = vec![
+//!     std::io::Result::Ok(("50M2I50M".to_string(), 1, 0)),
+//!     std::io::Result::Ok(("120M".to_string(), 1, 10)),
+//! ];
+//! let collated = CollatedAugmentedCigarIterator::new(cigars.into_iter());
+//! let windows: Vec<_> = WindowIterator::new(collated, 100)
+//!     .collect::<Result<_, _>>()
+//!     .unwrap();
+//! assert_eq!(windows.len(), 1);
+//! assert_eq!(windows[0].count(CigarOp::Match), 3);
+//! assert_eq!(windows[0].count(CigarOp::Insertion), 1);
+//! assert_eq!(windows[0].bases(CigarOp::Insertion), 2);
+//! ```
+
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+
+use crate::CigarOp;
+use crate::collated::CollatedEvent;
+use crate::error::CigarError;
+
+/// The totals for one operation within a window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpTotals {
+    /// The number of events.
+    pub events: usize,
+    /// The number of bases in the events, counting each occurrence.
+    pub bases: u64,
+}
+
+/// The totals of the events starting within a window of the reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowSummary {
+    /// The chromosome ID of the window.
+    pub chrom_id: u32,
+    /// The first reference position of the window.
+    pub start: u64,
+    /// The reference position just past the end of the window.
+    pub end: u64,
+    /// The totals for each operation seen in the window.
+    pub totals: BTreeMap<CigarOp, OpTotals>,
+}
+
+impl WindowSummary {
+    /// The number of events with the given operation in the window.
+    pub fn count(&self, op: CigarOp) -> usize {
+        self.totals.get(&op).map(|t| t.events).unwrap_or(0)
+    }
+
+    /// The number of bases in events with the given operation in the window.
+    pub fn bases(&self, op: CigarOp) -> u64 {
+        self.totals.get(&op).map(|t| t.bases).unwrap_or(0)
+    }
+}
+
+/// An iterator grouping a stream of collated events into fixed-size reference windows.
+///
+/// Windows are aligned to multiples of the window size, and an event belongs to the
+/// window containing its reference position. Windows with no events are not reported.
+pub struct WindowIterator<I: Iterator> {
+    inner: Peekable<I>,
+    size: u64,
+}
+
+impl<I: Iterator> WindowIterator<I> {
+    /// Create a new window iterator with windows of `size` reference positions. A size
+    /// of zero is taken as one.
+    pub fn new(inner: I, size: u64) -> Self {
+        WindowIterator {
+            inner: inner.peekable(),
+            size: size.max(1),
+        }
+    }
+}
+
+impl<I> Iterator for WindowIterator<I>
+where
+    I: Iterator<Item = std::result::Result<CollatedEvent, CigarError>>,
+{
+    type Item = std::result::Result<WindowSummary, CigarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (elem, count) = match self.inner.next()? {
+            Ok(event) => event,
+            Err(e) => return Some(Err(e)),
+        };
+        let start = elem.reference_position - elem.reference_position % self.size;
+        let mut summary = WindowSummary {
+            chrom_id: elem.chrom_id,
+            start,
+            end: start + self.size,
+            totals: BTreeMap::new(),
+        };
+        let mut add = |op: CigarOp, length: u32, count: usize| {
+            let totals = summary.totals.entry(op).or_default();
+            totals.events += count;
+            totals.bases += length as u64 * count as u64;
+        };
+        add(elem.op, elem.length, count);
+        while let Some(Ok((next, _))) = self.inner.peek() {
+            if next.chrom_id != summary.chrom_id || next.reference_position >= summary.end {
+                break;
+            }
+            if let Some(Ok((next, count))) = self.inner.next() {
+                add(next.op, next.length, count);
+            }
+        }
+        Some(Ok(summary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collated::collated;
+
+    fn windows(cigars: Vec<(&str, u32, u64)>, size: u64) -> Vec<WindowSummary> {
+        WindowIterator::new(collated(cigars), size)
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_windows_boundaries() {
+        let result = windows(vec![("5M1D5M", 1, 5), ("5M", 1, 35)], 10);
+        let bounds: Vec<_> = result.iter().map(|w| (w.start, w.end)).collect();
+        assert_eq!(bounds, vec![(0, 10), (10, 20), (30, 40)]);
+        assert_eq!(result[0].count(CigarOp::Match), 1);
+        assert_eq!(result[1].count(CigarOp::Deletion), 1);
+        assert_eq!(result[1].count(CigarOp::Match), 1);
+    }
+
+    #[test]
+    fn test_windows_chromosomes() {
+        let result = windows(vec![("5M", 1, 5), ("5M", 2, 5)], 100);
+        assert_eq!(result.len(), 2);
+        assert_eq!((result[0].chrom_id, result[1].chrom_id), (1, 2));
+    }
+
+    #[test]
+    fn test_windows_zero_size() {
+        let result = windows(vec![("2M", 1, 5)], 0);
+        let bounds: Vec<_> = result.iter().map(|w| (w.start, w.end)).collect();
+        assert_eq!(bounds, vec![(5, 6)]);
+    }
+
+    #[test]
+    fn test_windows_counts_occurrences() {
+        let result = windows(vec![("3M", 1, 0), ("3M", 1, 0), ("2S3M", 1, 1)], 10);
+        assert_eq!(result[0].count(CigarOp::Match), 3);
+        assert_eq!(result[0].bases(CigarOp::Match), 9);
+        assert_eq!(result[0].bases(CigarOp::SoftClip), 2);
+        assert_eq!(result[0].count(CigarOp::Insertion), 0);
+    }
+}
@@ -0,0 +1,739 @@
+//! Writers for genome browser tracks.
+//!
+//! These sinks turn values over reference intervals, such as [depth runs](crate::depth),
+//! [window totals](crate::window) or collated counts, into bedGraph or fixed-step wiggle
+//! text which can be loaded straight into a genome browser such as IGV, and
+//! [variant records](crate::variants) into VCF text, and
+//! [hotspot intervals](crate::hotspots) into BED. Chromosome IDs are turned into
+//! names with a [`ChromMap`], or a list of names indexed by ID. Aligned reads can also
+//! be written as MAF alignment blocks for comparative genomics tools, and streams of
+//! augmented or collated elements as TSV or JSON lines with [`ElementWriter`].
+//!
+//! # Example
+//!
+//! ```rust
+//! use cigar_utils::depth::DepthIterator;
+//! use cigar_utils::writers::BedGraphWriter;
+//!
+//! let records = vec![
+//!     std::io::Result::Ok(("4M".to_string(), 0, 100)),
+//!     std::io::Result::Ok(("2M".to_string(), 0, 102)),
+//! ];
+//! let mut writer = BedGraphWriter::new(Vec::new(), vec!["chr1".to_string()]);
+//! for run in DepthIterator::new(records.into_iter()) {
+//!     let run = run.unwrap();
+//!     writer.write(run.chrom_id, run.start, run.end, run.depth).unwrap();
+//! }
+//! let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+//! assert_eq!(text, "chr1\t100\t102\t1\nchr1\t102\t104\t2\n");
+//! ```
+
+use std::fmt::Display;
+use std::io::{Error, ErrorKind, Result, Write};
+
+use crate::alignment::to_alignment_strings;
+use crate::augmented_cigar::AugmentedCigarElement;
+use crate::chrom_map::ChromMap;
+use crate::hotspots::Hotspot;
+use crate::variants::VariantRecord;
+use crate::{Cigar, Strand};
+
+fn chrom_name(chroms: &ChromMap, chrom_id: u32) -> Result<&str> {
+    chroms.name(chrom_id).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("no name for chromosome ID {}", chrom_id),
+        )
+    })
+}
+
+/// A writer of bedGraph text.
+///
+/// Intervals must be written in sorted order. Adjacent intervals on the same chromosome
+/// with equal values are merged into a single line.
+pub struct BedGraphWriter<W: Write, V> {
+    out: W,
+    names: ChromMap,
+    pending: Option<(u32, u64, u64, V)>,
+}
+
+impl<W: Write, V: Display + PartialEq> BedGraphWriter<W, V> {
+    /// Create a new bedGraph writer, with the names of the chromosomes indexed by ID.
+    pub fn new(out: W, names: impl Into<ChromMap>) -> Self {
+        BedGraphWriter {
+            out,
+            names: names.into(),
+            pending: None,
+        }
+    }
+
+    /// Write a value over the half open reference interval `start..end`.
+    pub fn write(&mut self, chrom_id: u32, start: u64, end: u64, value: V) -> Result<()> {
+        if let Some((c, _, e, v)) = self.pending.as_mut()
+            && *c == chrom_id
+            && *e == start
+            && *v == value
+        {
+            *e = end;
+            return Ok(());
+        }
+        self.flush_pending()?;
+        self.pending = Some((chrom_id, start, end, value));
+        Ok(())
+    }
+
+    /// Write any pending interval and return the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.flush_pending()?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn flush_pending(&mut self) -> Result<()> {
+        if let Some((chrom_id, start, end, value)) = self.pending.take() {
+            let name = chrom_name(&self.names, chrom_id)?;
+            writeln!(self.out, "{}\t{}\t{}\t{}", name, start, end, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// A writer of fixed-step wiggle text.
+///
+/// Each value covers `step` reference positions. Intervals must be written in sorted
+/// order, with lengths that are multiples of the step; a new `fixedStep` block is started
+/// whenever an interval does not follow on from the previous one.
+pub struct WiggleWriter<W: Write> {
+    out: W,
+    names: ChromMap,
+    step: u64,
+    next: Option<(u32, u64)>,
+}
+
+impl<W: Write> WiggleWriter<W> {
+    /// Create a new wiggle writer with the given step, and the names of the chromosomes
+    /// indexed by ID.
+    pub fn new(out: W, names: impl Into<ChromMap>, step: u64) -> Self {
+        WiggleWriter {
+            out,
+            names: names.into(),
+            step: step.max(1),
+            next: None,
+        }
+    }
+
+    /// Write a value over the half open reference interval `start..end`.
+    pub fn write<V: Display>(
+        &mut self,
+        chrom_id: u32,
+        start: u64,
+        end: u64,
+        value: V,
+    ) -> Result<()> {
+        if self.next != Some((chrom_id, start)) {
+            let name = chrom_name(&self.names, chrom_id)?;
+            // Wiggle positions are 1-based.
+            writeln!(
+                self.out,
+                "fixedStep chrom={} start={} step={} span={}",
+                name,
+                start + 1,
+                self.step,
+                self.step
+            )?;
+        }
+        let mut position = start;
+        while position < end {
+            writeln!(self.out, "{}", value)?;
+            position += self.step;
+        }
+        self.next = Some((chrom_id, position));
+        Ok(())
+    }
+
+    /// Return the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// A writer of VCF text.
+///
+/// The header is written when the writer is created. Each record becomes one line, with
+/// the depth and supporting count in the `DP` and `SUP` INFO fields.
+pub struct VcfWriter<W: Write> {
+    out: W,
+    names: ChromMap,
+}
+
+impl<W: Write> VcfWriter<W> {
+    /// Create a new VCF writer and write the header, with the names of the chromosomes
+    /// indexed by ID. The `##contig` lines give the chromosome lengths where known.
+    pub fn new(mut out: W, names: impl Into<ChromMap>) -> Result<Self> {
+        let names = names.into();
+        writeln!(out, "##fileformat=VCFv4.2")?;
+        for (chrom_id, name) in names.names().iter().enumerate() {
+            match names.length(chrom_id as u32) {
+                Some(length) => writeln!(out, "##contig=<ID={},length={}>", name, length)?,
+                None => writeln!(out, "##contig=<ID={}>", name)?,
+            }
+        }
+        writeln!(
+            out,
+            "##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth of coverage\">"
+        )?;
+        writeln!(
+            out,
+            "##INFO=<ID=SUP,Number=1,Type=Integer,Description=\"Number of supporting records\">"
+        )?;
+        writeln!(out, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO")?;
+        Ok(VcfWriter { out, names })
+    }
+
+    /// Write a variant record.
+    pub fn write(&mut self, record: &VariantRecord) -> Result<()> {
+        let name = chrom_name(&self.names, record.chrom_id)?;
+        // VCF positions are 1-based.
+        writeln!(
+            self.out,
+            "{}\t{}\t.\t{}\t{}\t.\t.\tDP={};SUP={}",
+            name,
+            record.position + 1,
+            String::from_utf8_lossy(&record.reference),
+            String::from_utf8_lossy(&record.alternate),
+            record.depth,
+            record.count
+        )
+    }
+
+    /// Return the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// A writer of BED text, with a name and score for each interval.
+pub struct BedWriter<W: Write> {
+    out: W,
+    names: ChromMap,
+}
+
+impl<W: Write> BedWriter<W> {
+    /// Create a new BED writer, with the names of the chromosomes indexed by ID.
+    pub fn new(out: W, names: impl Into<ChromMap>) -> Self {
+        BedWriter {
+            out,
+            names: names.into(),
+        }
+    }
+
+    /// Write the half open reference interval `start..end` with a name and a score,
+    /// which BED expects to be between 0 and 1000.
+    pub fn write(
+        &mut self,
+        chrom_id: u32,
+        start: u64,
+        end: u64,
+        name: &str,
+        score: u32,
+    ) -> Result<()> {
+        let chrom = chrom_name(&self.names, chrom_id)?;
+        writeln!(
+            self.out,
+            "{}\t{}\t{}\t{}\t{}",
+            chrom, start, end, name, score
+        )
+    }
+
+    /// Write a hotspot interval, named by its number of events and scored by its
+    /// highest event fraction, scaled to 0 to 1000.
+    pub fn write_hotspot(&mut self, hotspot: &Hotspot) -> Result<()> {
+        let score = (hotspot.max_fraction * 1000.0).round().clamp(0.0, 1000.0) as u32;
+        self.write(
+            hotspot.chrom_id,
+            hotspot.start,
+            hotspot.end,
+            &format!("events={}", hotspot.events),
+            score,
+        )
+    }
+
+    /// Return the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// A read aligned to a reference, to be written as a MAF alignment block.
+#[derive(Debug, Clone)]
+pub struct MafRecord<'a> {
+    /// The name of the reference sequence.
+    pub reference_name: &'a str,
+    /// The whole reference sequence.
+    pub reference: &'a [u8],
+    /// The reference position of the first aligned base.
+    pub reference_position: usize,
+    /// The name of the read.
+    pub query_name: &'a str,
+    /// The read sequence (SEQ), as laid out in the alignment.
+    pub query: &'a [u8],
+    /// The strand of the read.
+    pub query_strand: Strand,
+    /// The CIGAR of the alignment.
+    pub cigar: &'a Cigar,
+    /// The alignment score, if any.
+    pub score: Option<f64>,
+}
+
+/// A writer of MAF (multiple alignment format) text.
+///
+/// The header is written when the writer is created. Each record becomes an alignment
+/// block of two sequence lines, with the gapped rows generated as for
+/// [alignment strings](crate::alignment::to_alignment_strings). Clipped bases are not
+/// part of the block, and skipped reference bases are shown as deletions. The read's
+/// size includes its hard clipped bases.
+pub struct MafWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> MafWriter<W> {
+    /// Create a new MAF writer and write the header.
+    pub fn new(mut out: W) -> Result<Self> {
+        writeln!(out, "##maf version=1")?;
+        Ok(MafWriter { out })
+    }
+
+    /// Write an alignment block.
+    pub fn write(&mut self, record: &MafRecord) -> Result<()> {
+        let rows = to_alignment_strings(
+            record.reference_position,
+            &record.cigar.to_string(),
+            &record.reference,
+            &record.query,
+        )
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        match record.score {
+            Some(score) => writeln!(self.out, "a score={}", score)?,
+            None => writeln!(self.out, "a")?,
+        }
+        writeln!(
+            self.out,
+            "s {} {} {} + {} {}",
+            record.reference_name,
+            record.reference_position,
+            record.cigar.reference_length(),
+            record.reference.len(),
+            rows.reference
+        )?;
+        let strand = match record.query_strand {
+            Strand::Forward => '+',
+            Strand::Reverse => '-',
+        };
+        writeln!(
+            self.out,
+            "s {} {} {} {} {} {}",
+            record.query_name,
+            record.cigar.leading_clip(),
+            rows.query.bytes().filter(|b| *b != b'-').count(),
+            strand,
+            record.cigar.query_length_with_hard_clips(),
+            rows.query
+        )?;
+        writeln!(self.out)
+    }
+
+    /// Return the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// The text format written by an [`ElementWriter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElementFormat {
+    /// Tab separated values, with a header line of the column names.
+    Tsv,
+    /// One JSON object per line, keyed by the column names.
+    JsonLines,
+}
+
+/// A writer of augmented CIGAR elements, or collated `(element, count)` pairs, as TSV
+/// or JSON lines.
+///
+/// Each element becomes one row with the columns:
+///
+/// | column          | contents                                                    |
+/// |-----------------|-------------------------------------------------------------|
+/// | `chrom`         | the chromosome name, or the chromosome ID if no names given |
+/// | `position`      | the 0-based reference position                              |
+/// | `op`            | the operation character, such as `M` or `I`                 |
+/// | `length`        | the length of the operation                                 |
+/// | `read_position` | the read position of the operation                          |
+/// | `count`         | the number of records with the event (collated only)        |
+/// | `depth`         | the depth of coverage at the position (with depths only)    |
+///
+/// The TSV header is written before the first row. In JSON lines, `chrom` is a string
+/// when chromosome names are given, and a number otherwise.
+pub struct ElementWriter<W: Write> {
+    out: W,
+    format: ElementFormat,
+    names: Option<ChromMap>,
+    counts: bool,
+    depths: bool,
+    started: bool,
+}
+
+impl<W: Write> ElementWriter<W> {
+    /// Create a new writer of augmented CIGAR elements.
+    pub fn new(out: W, format: ElementFormat) -> Self {
+        ElementWriter {
+            out,
+            format,
+            names: None,
+            counts: false,
+            depths: false,
+            started: false,
+        }
+    }
+
+    /// Create a new writer of collated `(element, count)` pairs, with a `count` column.
+    pub fn collated(out: W, format: ElementFormat) -> Self {
+        ElementWriter {
+            counts: true,
+            ..ElementWriter::new(out, format)
+        }
+    }
+
+    /// Write chromosome names in the `chrom` column, indexed by chromosome ID, in place
+    /// of the IDs.
+    pub fn with_chromosome_names(mut self, names: impl Into<ChromMap>) -> Self {
+        self.names = Some(names.into());
+        self
+    }
+
+    /// Add a `depth` column after the others, for the depth of coverage at each position,
+    /// as given to [`write_with_depth`](Self::write_with_depth).
+    pub fn with_depths(mut self) -> Self {
+        self.depths = true;
+        self
+    }
+
+    /// Write an augmented CIGAR element.
+    ///
+    /// For a collated writer, the count is written as 1, and any depth as 0.
+    pub fn write(&mut self, element: &AugmentedCigarElement) -> Result<()> {
+        self.write_row(element, 1, 0)
+    }
+
+    /// Write a collated element with its count. Any depth is written as 0.
+    pub fn write_collated(&mut self, element: &AugmentedCigarElement, count: usize) -> Result<()> {
+        self.write_row(element, count, 0)
+    }
+
+    /// Write a collated element with its count and the depth of coverage at its position.
+    pub fn write_with_depth(
+        &mut self,
+        element: &AugmentedCigarElement,
+        count: usize,
+        depth: usize,
+    ) -> Result<()> {
+        self.write_row(element, count, depth)
+    }
+
+    /// Write the header, if no rows were written, and return the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.start()?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn start(&mut self) -> Result<()> {
+        if !self.started && self.format == ElementFormat::Tsv {
+            write!(self.out, "chrom\tposition\top\tlength\tread_position")?;
+            if self.counts {
+                write!(self.out, "\tcount")?;
+            }
+            if self.depths {
+                write!(self.out, "\tdepth")?;
+            }
+            writeln!(self.out)?;
+        }
+        self.started = true;
+        Ok(())
+    }
+
+    fn write_row(
+        &mut self,
+        element: &AugmentedCigarElement,
+        count: usize,
+        depth: usize,
+    ) -> Result<()> {
+        self.start()?;
+        let name = match &self.names {
+            Some(names) => Some(chrom_name(names, element.chrom_id)?),
+            None => None,
+        };
+        let op = char::from(element.op);
+        match self.format {
+            ElementFormat::Tsv => {
+                match name {
+                    Some(name) => write!(self.out, "{}", name)?,
+                    None => write!(self.out, "{}", element.chrom_id)?,
+                }
+                write!(
+                    self.out,
+                    "\t{}\t{}\t{}\t{}",
+                    element.reference_position, op, element.length, element.read_position
+                )?;
+                if self.counts {
+                    write!(self.out, "\t{}", count)?;
+                }
+                if self.depths {
+                    write!(self.out, "\t{}", depth)?;
+                }
+            }
+            ElementFormat::JsonLines => {
+                match name {
+                    Some(name) => write!(self.out, "{{\"chrom\":\"{}\"", json_escape(name))?,
+                    None => write!(self.out, "{{\"chrom\":{}", element.chrom_id)?,
+                }
+                write!(
+                    self.out,
+                    ",\"position\":{},\"op\":\"{}\",\"length\":{},\"read_position\":{}",
+                    element.reference_position, op, element.length, element.read_position
+                )?;
+                if self.counts {
+                    write!(self.out, ",\"count\":{}", count)?;
+                }
+                if self.depths {
+                    write!(self.out, ",\"depth\":{}", depth)?;
+                }
+                write!(self.out, "}}")?;
+            }
+        }
+        writeln!(self.out)
+    }
+}
+
+/// Escape a string for inclusion in a JSON string literal.
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CigarOp;
+
+    fn names() -> Vec<String> {
+        vec!["chr1".to_string(), "chr2".to_string()]
+    }
+
+    #[test]
+    fn test_bedgraph_merges_equal_values() {
+        let mut writer = BedGraphWriter::new(Vec::new(), names());
+        writer.write(0, 10, 20, 3).unwrap();
+        writer.write(0, 20, 25, 3).unwrap();
+        writer.write(0, 30, 35, 3).unwrap();
+        writer.write(1, 35, 40, 3).unwrap();
+        writer.write(1, 40, 41, 1).unwrap();
+        let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(
+            text,
+            "chr1\t10\t25\t3\nchr1\t30\t35\t3\nchr2\t35\t40\t3\nchr2\t40\t41\t1\n"
+        );
+    }
+
+    #[test]
+    fn test_bedgraph_unknown_chromosome() {
+        let mut writer = BedGraphWriter::new(Vec::new(), names());
+        writer.write(5, 10, 20, 0.5).unwrap();
+        assert_eq!(writer.finish().unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_wiggle_blocks() {
+        let mut writer = WiggleWriter::new(Vec::new(), names(), 10);
+        writer.write(0, 0, 20, 4).unwrap();
+        writer.write(0, 20, 30, 5).unwrap();
+        writer.write(0, 50, 60, 1).unwrap();
+        let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(
+            text,
+            "fixedStep chrom=chr1 start=1 step=10 span=10\n4\n4\n5\n\
+             fixedStep chrom=chr1 start=51 step=10 span=10\n1\n"
+        );
+    }
+
+    #[test]
+    fn test_vcf() {
+        let mut writer = VcfWriter::new(Vec::new(), names()).unwrap();
+        writer
+            .write(&VariantRecord {
+                chrom_id: 1,
+                position: 9,
+                reference: b"AC".to_vec(),
+                alternate: b"A".to_vec(),
+                count: 3,
+                depth: 10,
+            })
+            .unwrap();
+        let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[0], "##fileformat=VCFv4.2");
+        assert_eq!(lines[1], "##contig=<ID=chr1>");
+        assert_eq!(lines[5], "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO");
+        assert_eq!(lines[6], "chr2\t10\t.\tAC\tA\t.\t.\tDP=10;SUP=3");
+
+        let mut chroms = ChromMap::from(names());
+        chroms.push("chrM", 16569).unwrap();
+        let writer = VcfWriter::new(Vec::new(), chroms).unwrap();
+        let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert!(text.contains("##contig=<ID=chr2>\n##contig=<ID=chrM,length=16569>\n"));
+    }
+
+    #[test]
+    fn test_bed_hotspots() {
+        let mut writer = BedWriter::new(Vec::new(), names());
+        writer
+            .write_hotspot(&Hotspot {
+                chrom_id: 1,
+                start: 10,
+                end: 14,
+                max_fraction: 0.4567,
+                events: 12,
+            })
+            .unwrap();
+        let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(text, "chr2\t10\t14\tevents=12\t457\n");
+    }
+
+    #[test]
+    fn test_maf() {
+        let cigar: Cigar = "2H1S3M1I2M1D2M".parse().unwrap();
+        let mut writer = MafWriter::new(Vec::new()).unwrap();
+        writer
+            .write(&MafRecord {
+                reference_name: "chr1",
+                reference: b"TTACGTACGTTT",
+                reference_position: 2,
+                query_name: "read1",
+                query: b"GACCTTAGT",
+                query_strand: Strand::Reverse,
+                cigar: &cigar,
+                score: Some(12.5),
+            })
+            .unwrap();
+        let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(
+            text,
+            "##maf version=1\n\
+             a score=12.5\n\
+             s chr1 2 8 + 12 ACG-TACGT\n\
+             s read1 3 8 - 11 ACCTTA-GT\n\n"
+        );
+    }
+
+    #[test]
+    fn test_maf_errors() {
+        let cigar: Cigar = "10M".parse().unwrap();
+        let mut writer = MafWriter::new(Vec::new()).unwrap();
+        let record = MafRecord {
+            reference_name: "chr1",
+            reference: b"ACGT",
+            reference_position: 0,
+            query_name: "read1",
+            query: b"ACGTACGTAC",
+            query_strand: Strand::Forward,
+            cigar: &cigar,
+            score: None,
+        };
+        assert_eq!(
+            writer.write(&record).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    fn element(
+        chrom_id: u32,
+        reference_position: u64,
+        op: crate::CigarOp,
+    ) -> AugmentedCigarElement {
+        AugmentedCigarElement {
+            length: 2,
+            op,
+            read_position: 3,
+            chrom_id,
+            reference_position,
+            leading_clip: false,
+        }
+    }
+
+    #[test]
+    fn test_element_tsv() {
+        let mut writer =
+            ElementWriter::collated(Vec::new(), ElementFormat::Tsv).with_chromosome_names(names());
+        writer
+            .write_collated(&element(1, 10, CigarOp::Insertion), 4)
+            .unwrap();
+        let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(
+            text,
+            "chrom\tposition\top\tlength\tread_position\tcount\nchr2\t10\tI\t2\t3\t4\n"
+        );
+
+        let writer = ElementWriter::new(Vec::new(), ElementFormat::Tsv);
+        let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(text, "chrom\tposition\top\tlength\tread_position\n");
+    }
+
+    #[test]
+    fn test_element_json_lines() {
+        let mut writer = ElementWriter::new(Vec::new(), ElementFormat::JsonLines);
+        writer.write(&element(0, 5, CigarOp::Match)).unwrap();
+        let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(
+            text,
+            "{\"chrom\":0,\"position\":5,\"op\":\"M\",\"length\":2,\"read_position\":3}\n"
+        );
+
+        let mut writer = ElementWriter::collated(Vec::new(), ElementFormat::JsonLines)
+            .with_chromosome_names(vec!["chr\"1".to_string()]);
+        writer
+            .write_collated(&element(0, 5, CigarOp::Deletion), 2)
+            .unwrap();
+        assert!(writer.write(&element(1, 5, CigarOp::Match)).is_err());
+        let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(
+            text,
+            "{\"chrom\":\"chr\\\"1\",\"position\":5,\"op\":\"D\",\"length\":2,\"read_position\":3,\"count\":2}\n"
+        );
+    }
+
+    #[test]
+    fn test_element_depths() {
+        let mut writer =
+            ElementWriter::collated(Vec::new(), ElementFormat::JsonLines).with_depths();
+        writer
+            .write_with_depth(&element(0, 5, CigarOp::Deletion), 2, 7)
+            .unwrap();
+        let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(
+            text,
+            "{\"chrom\":0,\"position\":5,\"op\":\"D\",\"length\":2,\"read_position\":3,\"count\":2,\"depth\":7}\n"
+        );
+    }
+}